@@ -0,0 +1,20 @@
+// Clears the current viewport by drawing a full-screen triangle clipped by the
+// scissor rectangle. Render pass load ops always clear the whole attachment,
+// which would wipe views that were already drawn into the same target.
+struct Clear {
+    color: vec4<f32>,
+    depth: f32,
+}
+@group(0) @binding(0)
+var<uniform> clear: Clear;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, clear.depth, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return clear.color;
+}
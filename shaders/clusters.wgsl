@@ -0,0 +1,99 @@
+// Assigns point lights to the clusters of one view: its viewport split
+// into screen tiles, and the depth between the near and far planes into
+// slices growing exponentially, so clusters are roughly cubes at every
+// distance. One invocation per cluster tests every light's sphere against
+// the cluster's view-space bounds.
+struct Clusters {
+    view: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    // Pixel rectangle of the view in its target.
+    viewport: vec4<f32>,
+    // Tiles across, tiles down and depth slices.
+    grid: vec3<u32>,
+    light_count: u32,
+    near: f32,
+    far: f32,
+    // Stride of each cluster's run in `cluster_indices`.
+    max_per_cluster: u32,
+}
+
+struct PointLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    // Its cube of point shadows, or -1 without one.
+    shadow: i32,
+}
+
+@group(0) @binding(0)
+var<uniform> clusters: Clusters;
+@group(0) @binding(1)
+var<storage, read> point_lights: array<PointLight>;
+@group(0) @binding(2)
+var<storage, read_write> cluster_counts: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> cluster_indices: array<u32>;
+
+// The view-space point at `depth` in front of the eye that projects to
+// `ndc`, found on the line through two unprojected points so it works for
+// orthographic, perspective and infinite projections alike.
+fn view_point(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let a = clusters.inv_proj * vec4<f32>(ndc, 0.25, 1.0);
+    let b = clusters.inv_proj * vec4<f32>(ndc, 0.75, 1.0);
+    let p0 = a.xyz / a.w;
+    let p1 = b.xyz / b.w;
+    let t = (-depth - p0.z) / (p1.z - p0.z);
+    return p0 + (p1 - p0) * t;
+}
+
+fn slice_depth(slice: u32) -> f32 {
+    return clusters.near * pow(clusters.far / clusters.near, f32(slice) / f32(clusters.grid.z));
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn cs_assign(@builtin(global_invocation_id) id: vec3<u32>) {
+    let grid = clusters.grid;
+    let index = id.x;
+    if (index >= grid.x * grid.y * grid.z) {
+        return;
+    }
+    let tile = vec2<u32>(index % grid.x, (index / grid.x) % grid.y);
+    let slice = index / (grid.x * grid.y);
+
+    // Screen tile rows run top to bottom, NDC bottom to top.
+    let low = vec2<f32>(tile) / vec2<f32>(grid.xy);
+    let high = vec2<f32>(tile + 1u) / vec2<f32>(grid.xy);
+    let ndc_low = vec2<f32>(low.x * 2.0 - 1.0, 1.0 - high.y * 2.0);
+    let ndc_high = vec2<f32>(high.x * 2.0 - 1.0, 1.0 - low.y * 2.0);
+    let near_depth = slice_depth(slice);
+    let far_depth = slice_depth(slice + 1u);
+
+    var bounds_min = vec3<f32>(1e30);
+    var bounds_max = vec3<f32>(-1e30);
+    for (var corner = 0u; corner < 8u; corner = corner + 1u) {
+        let ndc = vec2<f32>(
+            select(ndc_low.x, ndc_high.x, (corner & 1u) != 0u),
+            select(ndc_low.y, ndc_high.y, (corner & 2u) != 0u)
+        );
+        let depth = select(near_depth, far_depth, (corner & 4u) != 0u);
+        let p = view_point(ndc, depth);
+        bounds_min = min(bounds_min, p);
+        bounds_max = max(bounds_max, p);
+    }
+
+    var count = 0u;
+    let first = index * clusters.max_per_cluster;
+    for (var i = 0u; i < clusters.light_count; i = i + 1u) {
+        let light = point_lights[i];
+        let center = (clusters.view * vec4<f32>(light.position, 1.0)).xyz;
+        let offset = center - clamp(center, bounds_min, bounds_max);
+        if (dot(offset, offset) <= light.radius * light.radius) {
+            cluster_indices[first + count] = i;
+            count = count + 1u;
+            if (count == clusters.max_per_cluster) {
+                break;
+            }
+        }
+    }
+    cluster_counts[index] = count;
+}
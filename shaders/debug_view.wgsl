@@ -0,0 +1,162 @@
+// Replacement fragment outputs for inspecting meshes. Shares the vertex
+// inputs and the first three bind groups with shader.wgsl.
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+
+struct DebugView {
+    mode: u32,
+    depth_range: f32,
+}
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0)@binding(1)
+var s_diffuse: sampler;
+@group(0)@binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+@group(2) @binding(0)
+var<uniform> light: Light;
+
+@group(3) @binding(0)
+var<uniform> debug: DebugView;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
+    // Passed through so the camera stays a vertex-only binding, as in
+    // shader.wgsl.
+    @location(5) view_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = normal_matrix * model.tangent;
+    out.world_bitangent = normal_matrix * model.bitangent;
+    out.view_position = camera.view_pos.xyz;
+    return out;
+}
+
+fn encode_direction(v: vec3<f32>) -> vec4<f32> {
+    return vec4<f32>(normalize(v) * 0.5 + 0.5, 1.0);
+}
+
+// Blue for magnified texels through green and yellow to red at mip 5+.
+fn mip_color(uv: vec2<f32>) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_diffuse));
+    let dx = dpdx(uv * size);
+    let dy = dpdy(uv * size);
+    let level = clamp(0.5 * log2(max(dot(dx, dx), dot(dy, dy))), 0.0, 5.0) / 5.0;
+    let low = mix(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(0.0, 1.0, 0.0), clamp(level * 2.0, 0.0, 1.0));
+    let color = mix(low, vec3<f32>(1.0, 0.0, 0.0), clamp(level * 2.0 - 1.0, 0.0, 1.0));
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Sampled up front because implicit derivatives need uniform control flow.
+    let albedo = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let mip = mip_color(in.tex_coords);
+
+    // Cases are the `DebugViewMode` discriminants.
+    switch debug.mode {
+        case 1u: {
+            return encode_direction(in.world_normal);
+        }
+        case 2u: {
+            return encode_direction(in.world_tangent);
+        }
+        case 3u: {
+            return encode_direction(in.world_bitangent);
+        }
+        case 4u: {
+            return vec4<f32>(fract(in.tex_coords), 0.0, 1.0);
+        }
+        case 5u: {
+            return albedo;
+        }
+        case 6u: {
+            // Shading with a white surface and the interpolated vertex
+            // normal, so broken normals or tangents show up as seams.
+            let normal = normalize(in.world_normal);
+            let light_dir = normalize(light.position - in.world_position);
+            let view_dir = normalize(in.view_position - in.world_position);
+            let half_dir = normalize(view_dir + light_dir);
+            let diffuse = max(dot(normal, light_dir), 0.0);
+            let specular = pow(max(dot(normal, half_dir), 0.0), 32.0);
+            return vec4<f32>(light.color * (0.1 + diffuse + specular), 1.0);
+        }
+        case 7u: {
+            return mip;
+        }
+        case 9u: {
+            let distance = length(in.view_position - in.world_position);
+            return vec4<f32>(vec3<f32>(1.0 - clamp(distance / debug.depth_range, 0.0, 1.0)), 1.0);
+        }
+        default: {
+            return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+        }
+    }
+}
+
+// Every fragment adds a little heat, so bright areas were shaded many times.
+@fragment
+fn fs_overdraw(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(0.1, 0.04, 0.01, 1.0);
+}
@@ -0,0 +1,153 @@
+// Projects decals into the G-buffer, see `decals.rs`. Each decal is drawn
+// as the inside faces of its box, so it covers every pixel whose surface
+// could be inside once, even with the camera in the box. The surface's
+// position comes back from the G-buffer's depth, and its place in the box
+// gives the decal's texture coordinates. Built with gbuffer.wgsl in front.
+struct Frame {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    // The depth of pixels nothing was drawn to.
+    far_depth: f32,
+    // Pixel rectangle of the view in its target.
+    viewport: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+@group(0) @binding(1)
+var t_depth: texture_depth_2d;
+@group(0) @binding(2)
+var t_material: texture_2d<f32>;
+// The G-buffer's normals as they were before the decal pass, which draws
+// onto them.
+@group(0) @binding(3)
+var t_surface_normal: texture_2d<f32>;
+
+@group(1) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(1) @binding(1)
+var s_albedo: sampler;
+@group(1) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(3)
+var s_normal: sampler;
+
+struct DecalInput {
+    // From the unit box around the origin to the world.
+    @location(0) model_matrix_0: vec4<f32>,
+    @location(1) model_matrix_1: vec4<f32>,
+    @location(2) model_matrix_2: vec4<f32>,
+    @location(3) model_matrix_3: vec4<f32>,
+    @location(4) inv_model_matrix_0: vec4<f32>,
+    @location(5) inv_model_matrix_1: vec4<f32>,
+    @location(6) inv_model_matrix_2: vec4<f32>,
+    @location(7) inv_model_matrix_3: vec4<f32>,
+    // Opacity, then the cosines of the angles between the surface and the
+    // decal at which it starts and finishes fading out.
+    @location(8) fade: vec4<f32>,
+    // The surface layers the decal lands on, one bit each.
+    @location(9) layers: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) inv_model_matrix_0: vec4<f32>,
+    @location(1) inv_model_matrix_1: vec4<f32>,
+    @location(2) inv_model_matrix_2: vec4<f32>,
+    @location(3) inv_model_matrix_3: vec4<f32>,
+    @location(4) fade: vec4<f32>,
+    @location(5) @interpolate(flat) layers: u32,
+    // The world directions of the texture's u axis and of the side the
+    // decal is projected from.
+    @location(6) right: vec3<f32>,
+    @location(7) up: vec3<f32>,
+}
+
+// The 14 corners of a triangle strip covering the unit box, wound
+// counter-clockwise from outside.
+fn box_corner(index: u32) -> vec3<f32> {
+    let bit = 1u << index;
+    return vec3<f32>(
+        select(-0.5, 0.5, (0x287au & bit) != 0u),
+        select(-0.5, 0.5, (0x02afu & bit) != 0u),
+        select(-0.5, 0.5, (0x31e3u & bit) != 0u)
+    );
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, decal: DecalInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        decal.model_matrix_0,
+        decal.model_matrix_1,
+        decal.model_matrix_2,
+        decal.model_matrix_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = frame.view_proj * model_matrix * vec4<f32>(box_corner(index), 1.0);
+    out.inv_model_matrix_0 = decal.inv_model_matrix_0;
+    out.inv_model_matrix_1 = decal.inv_model_matrix_1;
+    out.inv_model_matrix_2 = decal.inv_model_matrix_2;
+    out.inv_model_matrix_3 = decal.inv_model_matrix_3;
+    out.fade = decal.fade;
+    out.layers = decal.layers;
+    out.right = normalize(decal.model_matrix_0.xyz);
+    out.up = normalize(decal.model_matrix_1.xyz);
+    return out;
+}
+
+struct DecalOutput {
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+}
+
+// The normal target is only written by the pipeline of decals with a
+// normal map, which blends it here rather than in its encoded form.
+@fragment
+fn fs_main(in: VertexOutput) -> DecalOutput {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, pixel, 0);
+    if (depth == frame.far_depth) {
+        discard;
+    }
+    let uv = (in.clip_position.xy - frame.viewport.xy) / frame.viewport.zw;
+    let world = frame.inv_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world_position = world.xyz / world.w;
+    let normal = decode_octahedral(textureLoad(t_surface_normal, pixel, 0).xy);
+    let layer = u32(textureLoad(t_material, pixel, 0).b * 255.0 + 0.5);
+    if (((in.layers >> layer) & 1u) == 0u) {
+        discard;
+    }
+    let inv_model_matrix = mat4x4<f32>(
+        in.inv_model_matrix_0,
+        in.inv_model_matrix_1,
+        in.inv_model_matrix_2,
+        in.inv_model_matrix_3,
+    );
+    let local = (inv_model_matrix * vec4<f32>(world_position, 1.0)).xyz;
+    if (any(abs(local) > vec3<f32>(0.5))) {
+        discard;
+    }
+
+    let facing = dot(normal, in.up);
+    let angle_fade = clamp((facing - in.fade.z) / max(in.fade.y - in.fade.z, 1e-4), 0.0, 1.0);
+    // Seen from the side it is projected from, the box's x axis runs to
+    // the right and its -z axis up the texture.
+    let tex_coords = local.xz + 0.5;
+    let albedo = textureSampleLevel(t_albedo, s_albedo, tex_coords, 0.0);
+    let alpha = albedo.a * in.fade.x * angle_fade;
+    if (alpha <= 0.0) {
+        discard;
+    }
+
+    let tangent_normal = textureSampleLevel(t_normal, s_normal, tex_coords, 0.0).xyz * 2.0 - 1.0;
+    let tangent = normalize(in.right - normal * dot(normal, in.right));
+    let bitangent = cross(normal, tangent);
+    let decal_normal = normalize(
+        tangent * tangent_normal.x + bitangent * tangent_normal.y + normal * tangent_normal.z
+    );
+
+    var out: DecalOutput;
+    out.albedo = vec4<f32>(albedo.rgb, alpha);
+    out.normal = vec4<f32>(encode_octahedral(normalize(mix(normal, decal_normal, alpha))), 0.0, 0.0);
+    return out;
+}
@@ -0,0 +1,123 @@
+// Lights the G-buffer `fs_gbuffer` in shader.wgsl wrote, with the same
+// terms as its forward `shade`, and copies the G-buffer's depth into the
+// view so later forward draws are hidden behind the scene.
+struct Frame {
+    inv_view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    // The depth of pixels nothing was drawn to.
+    far_depth: f32,
+    // Pixel rectangle of the view in its target.
+    viewport: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+@group(0) @binding(1)
+var t_albedo: texture_2d<f32>;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var t_material: texture_2d<f32>;
+@group(0) @binding(4)
+var t_depth: texture_depth_2d;
+
+// Group 3 of shader.wgsl. The lights are in group 2, as there, from
+// lighting.wgsl.
+struct Environment {
+    intensity: f32,
+    max_lod: f32,
+}
+
+@group(1) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(1) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(1) @binding(2)
+var t_brdf_lut: texture_2d<f32>;
+@group(1) @binding(3)
+var s_environment: sampler;
+@group(1) @binding(4)
+var<uniform> environment: Environment;
+@group(1) @binding(5)
+var t_occlusion: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// As in shader.wgsl.
+fn ambient_light(
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    albedo: vec3<f32>,
+    roughness: f32,
+    metallic: f32,
+) -> vec3<f32> {
+    let n_dot_v = max(dot(normal, view_dir), 0.0);
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let fresnel = f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+
+    let irradiance = textureSampleLevel(t_irradiance, s_environment, normal, 0.0).rgb;
+    let diffuse = (1.0 - fresnel) * (1.0 - metallic) * irradiance * albedo;
+
+    let reflected = reflect(-view_dir, normal);
+    let prefiltered = textureSampleLevel(
+        t_prefiltered,
+        s_environment,
+        reflected,
+        roughness * environment.max_lod
+    ).rgb;
+    let brdf = textureSampleLevel(
+        t_brdf_lut,
+        s_environment,
+        vec2<f32>(n_dot_v, roughness),
+        0.0
+    ).rg;
+    let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    return (diffuse + specular) * environment.intensity;
+}
+
+struct LightingOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> LightingOutput {
+    let pixel = vec2<i32>(position.xy);
+    let depth = textureLoad(t_depth, pixel, 0);
+    if (depth == frame.far_depth) {
+        discard;
+    }
+    let albedo = textureLoad(t_albedo, pixel, 0).rgb;
+    let normal = decode_octahedral(textureLoad(t_normal, pixel, 0).xy);
+    let surface = textureLoad(t_material, pixel, 0);
+
+    let uv = (vec2<f32>(pixel) + 0.5 - frame.viewport.xy) / frame.viewport.zw;
+    let world = frame.inv_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world_position = world.xyz / world.w;
+
+    let light_dir = normalize(light.position - world_position);
+    let view_dir = normalize(frame.eye - world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let diffuse_color = light.color * max(dot(normal, light_dir), 0.0);
+    let specular_color = light.color * pow(max(dot(normal, half_dir), 0.0), 32.0);
+    let light_color = (diffuse_color + specular_color) * light_visibility(world_position, normal);
+
+    let occlusion_size = vec2<i32>(textureDimensions(t_occlusion));
+    let occlusion = textureLoad(t_occlusion, min(pixel, occlusion_size - 1), 0).r;
+    let ambient_color = ambient_light(normal, view_dir, albedo, surface.r, surface.g) * occlusion;
+    let point_color = point_light(position.xy, world_position, normal, view_dir);
+    let sun_color = sun_light(world_position, normal, view_dir);
+
+    var out: LightingOutput;
+    out.color = vec4<f32>(
+        ambient_color + (light_color + point_color + sun_color) * albedo,
+        1.0
+    );
+    out.depth = depth;
+    return out;
+}
@@ -0,0 +1,64 @@
+// egui meshes. Positions are in points, colors are premultiplied sRGBA.
+struct Screen {
+    size_in_points: vec2<f32>,
+}
+@group(0) @binding(0)
+var<uniform> screen: Screen;
+
+@group(1) @binding(0)
+var t_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var s_texture: sampler;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+fn linear_from_srgb(srgb: vec3<f32>) -> vec3<f32> {
+    let cutoff = srgb < vec3<f32>(0.04045);
+    let lower = srgb / vec3<f32>(12.92);
+    let higher = pow((srgb + vec3<f32>(0.055)) / vec3<f32>(1.055), vec3<f32>(2.4));
+    return select(higher, lower, cutoff);
+}
+
+fn srgb_from_linear(rgb: vec3<f32>) -> vec3<f32> {
+    let cutoff = rgb < vec3<f32>(0.0031308);
+    let lower = rgb * vec3<f32>(12.92);
+    let higher = vec3<f32>(1.055) * pow(rgb, vec3<f32>(1.0 / 2.4)) - vec3<f32>(0.055);
+    return select(higher, lower, cutoff);
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(
+        in.position.x / screen.size_in_points.x * 2.0 - 1.0,
+        1.0 - in.position.y / screen.size_in_points.y * 2.0,
+        0.0,
+        1.0,
+    );
+    out.uv = in.uv;
+    out.color = vec4<f32>(linear_from_srgb(in.color.rgb), in.color.a);
+    return out;
+}
+
+// For sRGB targets, which convert the linear result on write.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color * textureSample(t_texture, s_texture, in.uv);
+}
+
+// For linear targets, which store what the shader returns.
+@fragment
+fn fs_gamma(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = in.color * textureSample(t_texture, s_texture, in.uv);
+    return vec4<f32>(srgb_from_linear(color.rgb), color.a);
+}
@@ -0,0 +1,218 @@
+// Fogs one view after its opaque scene, reading the distance of each pixel
+// from the view's depth. Light scattered in the atmosphere between the eye
+// and the surface is added first, then the fog on top, with the share of
+// the surface that still shows through in alpha.
+struct Fog {
+    inv_view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    // The depth of pixels nothing was drawn to.
+    far_depth: f32,
+    // Pixel rectangle of the view in its target.
+    viewport: vec4<f32>,
+    color: vec3<f32>,
+    // 0 for none, then linear, exponential and exponential squared.
+    falloff: u32,
+    // Where linear fog starts and ends.
+    start: f32,
+    end: f32,
+    // Of exponential and exponential squared fog.
+    density: f32,
+    // Whether pixels nothing was drawn to get the fog and sky, or are left
+    // to the views below.
+    background: u32,
+    height_base: f32,
+    // Zero without height fog.
+    height_density: f32,
+    height_falloff: f32,
+    // Whether the atmosphere scatters sunlight.
+    scattering: u32,
+    // Where the sun shines.
+    sun_direction: vec3<f32>,
+    planet_radius: f32,
+    sun_radiance: vec3<f32>,
+    atmosphere_radius: f32,
+    rayleigh: vec3<f32>,
+    rayleigh_height: f32,
+    mie: f32,
+    mie_height: f32,
+    mie_g: f32,
+    // Meters per world unit.
+    world_scale: f32,
+    // The world height of the planet's surface.
+    ground_height: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> fog: Fog;
+@group(0) @binding(1)
+var t_depth: texture_depth_2d;
+
+let PI: f32 = 3.14159265359;
+// How far away the background is taken to be.
+let BACKGROUND_DISTANCE: f32 = 1e6;
+let VIEW_SAMPLES: i32 = 16;
+let SUN_SAMPLES: i32 = 4;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = fog.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// How much of a surface `surface_distance` away along `direction` shows
+// through the distance and height fog.
+fn fog_transmittance(direction: vec3<f32>, surface_distance: f32) -> f32 {
+    var transmittance = 1.0;
+    if (fog.falloff == 1u) {
+        transmittance = clamp((fog.end - surface_distance) / max(fog.end - fog.start, 1e-4), 0.0, 1.0);
+    } else if (fog.falloff == 2u) {
+        transmittance = exp(-fog.density * surface_distance);
+    } else if (fog.falloff == 3u) {
+        let optical_depth = fog.density * surface_distance;
+        transmittance = exp(-optical_depth * optical_depth);
+    }
+
+    if (fog.height_density > 0.0) {
+        // The density falls off exponentially with height, which
+        // integrates in closed form along the ray.
+        let rise = fog.height_falloff * direction.y * surface_distance;
+        var spread = 1.0;
+        if (abs(rise) > 1e-4) {
+            spread = (1.0 - exp(-rise)) / rise;
+        }
+        let density = fog.height_density * exp(-fog.height_falloff * (fog.eye.y - fog.height_base));
+        transmittance = transmittance * exp(-density * surface_distance * spread);
+    }
+    return transmittance;
+}
+
+// Where a ray from `origin` inside the sphere of `radius` around the
+// planet's centre leaves it.
+fn sphere_exit(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> f32 {
+    let b = dot(origin, direction);
+    let c = dot(origin, origin) - radius * radius;
+    return -b + sqrt(max(b * b - c, 0.0));
+}
+
+// Where a ray from `origin` above the ground hits it, or -1.
+fn ground_hit(origin: vec3<f32>, direction: vec3<f32>) -> f32 {
+    let b = dot(origin, direction);
+    let c = dot(origin, origin) - fog.planet_radius * fog.planet_radius;
+    let discriminant = b * b - c;
+    if (discriminant < 0.0 || b > 0.0) {
+        return -1.0;
+    }
+    return -b - sqrt(discriminant);
+}
+
+// Rayleigh and Mie optical depth from `origin` to the top of the
+// atmosphere towards the sun, or a huge one if the ground is in the way.
+fn sun_optical_depth(origin: vec3<f32>) -> vec2<f32> {
+    if (ground_hit(origin, -fog.sun_direction) >= 0.0) {
+        return vec2<f32>(1e9);
+    }
+    let ray_length = sphere_exit(origin, -fog.sun_direction, fog.atmosphere_radius);
+    let step_length = ray_length / f32(SUN_SAMPLES);
+    var depth = vec2<f32>(0.0);
+    for (var i = 0; i < SUN_SAMPLES; i = i + 1) {
+        let position = origin - fog.sun_direction * (f32(i) + 0.5) * step_length;
+        let height = length(position) - fog.planet_radius;
+        depth += exp(-height / vec2<f32>(fog.rayleigh_height, fog.mie_height)) * step_length;
+    }
+    return depth;
+}
+
+struct Scattering {
+    inscattered: vec3<f32>,
+    transmittance: vec3<f32>,
+}
+
+// Single scattering of sunlight by air molecules (Rayleigh) and aerosols
+// (Mie) over `ray_length` meters from `origin` along `direction`.
+fn scatter(origin: vec3<f32>, direction: vec3<f32>, ray_length: f32) -> Scattering {
+    let step_length = ray_length / f32(VIEW_SAMPLES);
+    let mie_extinction = fog.mie * 1.1;
+    var view_depth = vec2<f32>(0.0);
+    var rayleigh_sum = vec3<f32>(0.0);
+    var mie_sum = vec3<f32>(0.0);
+    for (var i = 0; i < VIEW_SAMPLES; i = i + 1) {
+        let position = origin + direction * (f32(i) + 0.5) * step_length;
+        let height = length(position) - fog.planet_radius;
+        let densities = exp(-height / vec2<f32>(fog.rayleigh_height, fog.mie_height)) * step_length;
+        view_depth += densities;
+
+        let depth = view_depth + sun_optical_depth(position);
+        let attenuation = exp(-(fog.rayleigh * depth.x + vec3<f32>(mie_extinction * depth.y)));
+        rayleigh_sum += densities.x * attenuation;
+        mie_sum += densities.y * attenuation;
+    }
+
+    let mu = dot(direction, -fog.sun_direction);
+    let g = fog.mie_g;
+    let rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+    let mie_phase = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + mu * mu))
+        / ((2.0 + g * g) * pow(1.0 + g * g - 2.0 * g * mu, 1.5));
+
+    var out: Scattering;
+    out.inscattered = fog.sun_radiance
+        * (rayleigh_sum * fog.rayleigh * rayleigh_phase + mie_sum * fog.mie * mie_phase);
+    out.transmittance = exp(-(fog.rayleigh * view_depth.x + vec3<f32>(mie_extinction * view_depth.y)));
+    return out;
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let depth = textureLoad(t_depth, pixel, 0);
+    let background = depth == fog.far_depth;
+    if (background && fog.background == 0u) {
+        discard;
+    }
+
+    let uv = (position.xy - fog.viewport.xy) / fog.viewport.zw;
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    var direction: vec3<f32>;
+    var surface_distance: f32;
+    if (background) {
+        // Two points along the pixel's ray, the first the nearer one
+        // unless depth is reversed.
+        var ray = unproject(ndc, 0.75) - unproject(ndc, 0.25);
+        if (fog.far_depth < 0.5) {
+            ray = -ray;
+        }
+        direction = normalize(ray);
+        surface_distance = BACKGROUND_DISTANCE;
+    } else {
+        let offset = unproject(ndc, depth) - fog.eye;
+        surface_distance = length(offset);
+        direction = offset / max(surface_distance, 1e-6);
+    }
+
+    var color = vec3<f32>(0.0);
+    var transmittance = 1.0;
+    if (fog.scattering != 0u) {
+        let altitude = max((fog.eye.y - fog.ground_height) * fog.world_scale, 1.0);
+        let origin = vec3<f32>(0.0, fog.planet_radius + altitude, 0.0);
+        var ray_length = surface_distance * fog.world_scale;
+        if (background) {
+            ray_length = sphere_exit(origin, direction, fog.atmosphere_radius);
+            let ground = ground_hit(origin, direction);
+            if (ground >= 0.0) {
+                ray_length = ground;
+            }
+        }
+        let scattering = scatter(origin, direction, ray_length);
+        color = scattering.inscattered;
+        // The sky replaces the background entirely.
+        transmittance = select(dot(scattering.transmittance, vec3<f32>(1.0 / 3.0)), 0.0, background);
+    }
+
+    let fogged = fog_transmittance(direction, surface_distance);
+    color = color * fogged + fog.color * (1.0 - fogged);
+    return vec4<f32>(color, transmittance * fogged);
+}
@@ -0,0 +1,30 @@
+// The layout of the G-buffer, see `deferred.rs`, for the shaders writing
+// or reading it. Each is built with this file in front.
+
+// The unit vector `n` folded onto an octahedron and flattened into the
+// square from -1 to 1, which two channels store evenly.
+fn encode_octahedral(n: vec3<f32>) -> vec2<f32> {
+    let p = n.xy / (abs(n.x) + abs(n.y) + abs(n.z));
+    if (n.z >= 0.0) {
+        return p;
+    }
+    let signs = select(vec2<f32>(-1.0), vec2<f32>(1.0), p >= vec2<f32>(0.0));
+    return (1.0 - abs(p.yx)) * signs;
+}
+
+// The inverse of `encode_octahedral`.
+fn decode_octahedral(e: vec2<f32>) -> vec3<f32> {
+    let z = 1.0 - abs(e.x) - abs(e.y);
+    if (z >= 0.0) {
+        return normalize(vec3<f32>(e, z));
+    }
+    let signs = select(vec2<f32>(-1.0), vec2<f32>(1.0), e >= vec2<f32>(0.0));
+    return normalize(vec3<f32>((1.0 - abs(e.yx)) * signs, z));
+}
+
+// What the G-buffer pass writes, see `deferred.rs`.
+struct GBufferOutput {
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) material: vec4<f32>,
+}
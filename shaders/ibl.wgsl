@@ -0,0 +1,233 @@
+// Precomputes image-based lighting from an equirectangular environment map:
+// its mip chain, a diffuse irradiance cubemap, a specular cubemap
+// prefiltered for increasing roughness, and the split-sum BRDF lookup table.
+struct Params {
+    // The roughness a prefiltered level is computed for.
+    roughness: f32,
+    sample_count: u32,
+    // Average solid angle of a texel at the source's full resolution.
+    texel_solid_angle: f32,
+    max_lod: f32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+@group(1) @binding(0)
+var t_destination: texture_storage_2d_array<rgba16float, write>;
+
+@group(2) @binding(0)
+var<uniform> params: Params;
+
+let PI: f32 = 3.14159265359;
+
+// The direction through `uv`, from -1 to 1 with v pointing down, on cube
+// face `face` in wgpu's +X, -X, +Y, -Y, +Z, -Z order.
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    var direction: vec3<f32>;
+    if (face == 0u) {
+        direction = vec3<f32>(1.0, -uv.y, -uv.x);
+    } else if (face == 1u) {
+        direction = vec3<f32>(-1.0, -uv.y, uv.x);
+    } else if (face == 2u) {
+        direction = vec3<f32>(uv.x, 1.0, uv.y);
+    } else if (face == 3u) {
+        direction = vec3<f32>(uv.x, -1.0, -uv.y);
+    } else if (face == 4u) {
+        direction = vec3<f32>(uv.x, -uv.y, 1.0);
+    } else {
+        direction = vec3<f32>(-uv.x, -uv.y, -1.0);
+    }
+    return normalize(direction);
+}
+
+fn sample_environment(direction: vec3<f32>, lod: f32) -> vec3<f32> {
+    let uv = vec2<f32>(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI
+    );
+    return textureSampleLevel(t_source, s_source, uv, clamp(lod, 0.0, params.max_lod)).rgb;
+}
+
+// The level whose texels cover about the solid angle one of
+// `params.sample_count` samples with probability density `pdf` stands
+// for, which keeps few samples from aliasing.
+fn sample_lod(pdf: f32) -> f32 {
+    let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf + 1e-4);
+    return 0.5 * log2(sample_solid_angle / params.texel_solid_angle) + 1.0;
+}
+
+fn radical_inverse(bits_in: u32) -> f32 {
+    var bits = bits_in;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), radical_inverse(i));
+}
+
+// Turns `local`, relative to +Z, into a direction relative to `normal`.
+fn to_world(local: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(normal.y) > 0.999) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return tangent * local.x + bitangent * local.y + normal * local.z;
+}
+
+// A half vector distributed like the GGX normal distribution.
+fn importance_sample_ggx(xi: vec2<f32>, normal: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), normal);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a2 = roughness * roughness * roughness * roughness;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    // The remapping of the roughness Karis suggests for image-based lighting.
+    let k = roughness * roughness / 2.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+// The invocation's texel, or nothing if it is outside the destination.
+fn destination_uv(id: vec3<u32>) -> vec2<f32> {
+    let size = vec2<f32>(textureDimensions(t_destination));
+    return (vec2<f32>(id.xy) + 0.5) / size;
+}
+
+fn outside(id: vec3<u32>) -> bool {
+    let size = vec2<u32>(textureDimensions(t_destination));
+    return id.x >= size.x || id.y >= size.y;
+}
+
+// One level of the source's mip chain from the level above, bound as the
+// source. Linear filtering at the centre of a texel averages the 2x2 texels
+// above it.
+@compute @workgroup_size(8, 8, 1)
+fn cs_downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (outside(id)) {
+        return;
+    }
+    let color = textureSampleLevel(t_source, s_source, destination_uv(id), 0.0);
+    textureStore(t_destination, vec2<i32>(id.xy), 0, color);
+}
+
+// Radiance averaged over the hemisphere around each direction, weighted by
+// the cosine. Multiplied by the albedo, it is the diffuse light reflected.
+@compute @workgroup_size(8, 8, 1)
+fn cs_irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (outside(id)) {
+        return;
+    }
+    let normal = cube_direction(id.z, destination_uv(id) * 2.0 - 1.0);
+
+    var sum = vec3<f32>(0.0);
+    for (var i = 0u; i < params.sample_count; i = i + 1u) {
+        // Cosine-weighted, so every sample counts the same.
+        let xi = hammersley(i, params.sample_count);
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = sqrt(1.0 - xi.y);
+        let sin_theta = sqrt(xi.y);
+        let local = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+        sum += sample_environment(to_world(local, normal), sample_lod(cos_theta / PI));
+    }
+    let irradiance = sum / f32(params.sample_count);
+    textureStore(t_destination, vec2<i32>(id.xy), i32(id.z), vec4<f32>(irradiance, 1.0));
+}
+
+// The environment convolved with the GGX lobe of `params.roughness`,
+// assuming the view direction equals the normal.
+@compute @workgroup_size(8, 8, 1)
+fn cs_prefilter(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (outside(id)) {
+        return;
+    }
+    let normal = cube_direction(id.z, destination_uv(id) * 2.0 - 1.0);
+    if (params.roughness == 0.0) {
+        let color = sample_environment(normal, 0.0);
+        textureStore(t_destination, vec2<i32>(id.xy), i32(id.z), vec4<f32>(color, 1.0));
+        return;
+    }
+
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i = i + 1u) {
+        let half_vector = importance_sample_ggx(
+            hammersley(i, params.sample_count),
+            normal,
+            params.roughness
+        );
+        let light = 2.0 * dot(normal, half_vector) * half_vector - normal;
+        let n_dot_l = dot(normal, light);
+        if (n_dot_l > 0.0) {
+            // With the view along the normal, n·h equals v·h and the pdf of
+            // the light direction reduces to D / 4.
+            let n_dot_h = max(dot(normal, half_vector), 0.0);
+            let pdf = distribution_ggx(n_dot_h, params.roughness) / 4.0;
+            sum += sample_environment(light, sample_lod(pdf)) * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    let color = sum / max(weight, 1e-4);
+    textureStore(t_destination, vec2<i32>(id.xy), i32(id.z), vec4<f32>(color, 1.0));
+}
+
+// Scale and bias to the Fresnel reflectance at normal incidence, by n·v
+// along X and roughness along Y.
+@compute @workgroup_size(8, 8, 1)
+fn cs_brdf_lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (outside(id)) {
+        return;
+    }
+    let uv = destination_uv(id);
+    let n_dot_v = uv.x;
+    let roughness = uv.y;
+    let view = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let normal = vec3<f32>(0.0, 0.0, 1.0);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < params.sample_count; i = i + 1u) {
+        let half_vector = importance_sample_ggx(
+            hammersley(i, params.sample_count),
+            normal,
+            roughness
+        );
+        let light = 2.0 * dot(view, half_vector) * half_vector - view;
+        let n_dot_l = max(light.z, 0.0);
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(half_vector.z, 0.0);
+            let v_dot_h = max(dot(view, half_vector), 0.0);
+            let visibility = geometry_smith(n_dot_v, n_dot_l, roughness) * v_dot_h
+                / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    let count = f32(params.sample_count);
+    textureStore(
+        t_destination,
+        vec2<i32>(id.xy),
+        0,
+        vec4<f32>(scale / count, bias / count, 0.0, 1.0)
+    );
+}
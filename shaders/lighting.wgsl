@@ -0,0 +1,247 @@
+// The main light, the clustered point lights and the shadows of both and
+// of the sun, which shader.wgsl, deferred.wgsl and terrain.wgsl all light
+// with. Each is built with gbuffer.wgsl and this file in front, see
+// `light.rs`.
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+
+@group(2) @binding(0)
+var<uniform> light: Light;
+
+// Point lights binned into the view's clusters, see `clusters.rs`.
+struct Clusters {
+    view: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    viewport: vec4<f32>,
+    grid: vec3<u32>,
+    light_count: u32,
+    near: f32,
+    far: f32,
+    max_per_cluster: u32,
+}
+
+struct PointLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    // Its cube of point shadows, or -1 without one.
+    shadow: i32,
+}
+
+@group(2) @binding(1)
+var<uniform> clusters: Clusters;
+@group(2) @binding(2)
+var<storage, read> point_lights: array<PointLight>;
+@group(2) @binding(3)
+var<storage, read> cluster_counts: array<u32>;
+@group(2) @binding(4)
+var<storage, read> cluster_indices: array<u32>;
+
+// The sun and its cascaded shadow map, see `shadows.rs`.
+struct Shadow {
+    // Light space of each cascade.
+    cascades: array<mat4x4<f32>, 4>,
+    // The view depth at which each cascade ends.
+    splits: vec4<f32>,
+    // The world size of a shadow map texel in each cascade.
+    texel_sizes: vec4<f32>,
+    // The camera's view matrix, to measure depth with.
+    view: mat4x4<f32>,
+    // Where the sun shines, and zero without one.
+    direction: vec3<f32>,
+    // Zero without shadows.
+    cascade_count: u32,
+    color: vec3<f32>,
+    // Fraction of each cascade over which it fades into the next.
+    blend: f32,
+    depth_bias: f32,
+    // In texels.
+    normal_bias: f32,
+}
+
+@group(2) @binding(5)
+var t_shadow: texture_depth_2d_array;
+@group(2) @binding(6)
+var s_shadow: sampler_comparison;
+@group(2) @binding(7)
+var<uniform> shadow: Shadow;
+
+// Cube maps of the distance to the nearest caster around each shadowed
+// point light, see `point_shadows.rs`.
+struct PointShadows {
+    // Cube of the main light, or -1 without its shadow.
+    light_layer: i32,
+    // Distance at which the main light's cube ends.
+    light_range: f32,
+    // Of the distance divided by the cube's range.
+    depth_bias: f32,
+    // In texels.
+    normal_bias: f32,
+    // The size of a texel at a distance of one.
+    texel_size: f32,
+}
+
+@group(2) @binding(8)
+var t_point_shadow: texture_depth_cube_array;
+@group(2) @binding(9)
+var<uniform> point_shadows: PointShadows;
+
+// The cluster containing the fragment at target pixel `pixel`. Fragments
+// beyond the last slice use it.
+fn cluster_at(pixel: vec2<f32>, world_position: vec3<f32>) -> u32 {
+    let grid = clusters.grid;
+    let uv = clamp((pixel - clusters.viewport.xy) / clusters.viewport.zw, vec2<f32>(0.0), vec2<f32>(0.9999));
+    let tile = vec2<u32>(uv * vec2<f32>(grid.xy));
+    let depth = max(-(clusters.view * vec4<f32>(world_position, 1.0)).z, clusters.near);
+    let slice = log(depth / clusters.near) / log(clusters.far / clusters.near) * f32(grid.z);
+    let z = min(u32(slice), grid.z - 1u);
+    return tile.x + grid.x * (tile.y + grid.y * z);
+}
+
+// How much of the light at `light_position` reaches `position`, from the
+// cube `layer` of distances up to `range`, or all of it for a negative
+// `layer`.
+fn point_visibility(
+    layer: i32,
+    light_position: vec3<f32>,
+    range: f32,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+) -> f32 {
+    if (layer < 0) {
+        return 1.0;
+    }
+    let light_distance = length(position - light_position);
+    let offset = position + normal * point_shadows.normal_bias * point_shadows.texel_size * light_distance;
+    let direction = offset - light_position;
+    let depth = length(direction) / range;
+    if (depth >= 1.0) {
+        return 1.0;
+    }
+    return textureSampleCompareLevel(
+        t_point_shadow,
+        s_shadow,
+        direction,
+        layer,
+        depth - point_shadows.depth_bias
+    );
+}
+
+// How much of the main light reaches `position`.
+fn light_visibility(position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    return point_visibility(
+        point_shadows.light_layer,
+        light.position,
+        point_shadows.light_range,
+        position,
+        normal
+    );
+}
+
+// Diffuse and specular light from the point lights of the fragment's
+// cluster, each fading to nothing at its radius.
+fn point_light(
+    pixel: vec2<f32>,
+    world_position: vec3<f32>,
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+) -> vec3<f32> {
+    let cluster = cluster_at(pixel, world_position);
+    let first = cluster * clusters.max_per_cluster;
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < cluster_counts[cluster]; i = i + 1u) {
+        let nearby = point_lights[cluster_indices[first + i]];
+        let to_light = nearby.position - world_position;
+        let distance_squared = max(dot(to_light, to_light), 1e-4);
+        let falloff = clamp(1.0 - pow(distance_squared / (nearby.radius * nearby.radius), 2.0), 0.0, 1.0);
+        let attenuation = falloff * falloff / (distance_squared + 1.0);
+
+        let light_dir = to_light * inverseSqrt(distance_squared);
+        let half_dir = normalize(view_dir + light_dir);
+        let diffuse_strength = max(dot(normal, light_dir), 0.0);
+        let specular_strength = pow(max(dot(normal, half_dir), 0.0), 32.0);
+        let visibility = point_visibility(
+            nearby.shadow,
+            nearby.position,
+            nearby.radius,
+            world_position,
+            normal
+        );
+        total += nearby.color * (diffuse_strength + specular_strength) * attenuation * visibility;
+    }
+    return total;
+}
+
+// How much of cascade `cascade` of the sun reaches `position`, filtered
+// over 3x3 texels.
+fn cascade_light(cascade: u32, position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let offset = position + normal * shadow.normal_bias * shadow.texel_sizes[cascade];
+    let clip = shadow.cascades[cascade] * vec4<f32>(offset, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    let texel = 1.0 / f32(textureDimensions(t_shadow).x);
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            lit += textureSampleCompareLevel(
+                t_shadow,
+                s_shadow,
+                uv + vec2<f32>(f32(x), f32(y)) * texel,
+                i32(cascade),
+                ndc.z - shadow.depth_bias
+            );
+        }
+    }
+    return lit / 9.0;
+}
+
+// How much of the sun reaches `position`, one beyond the last cascade.
+fn sun_visibility(position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let count = shadow.cascade_count;
+    if (count == 0u) {
+        return 1.0;
+    }
+    let depth = -(shadow.view * vec4<f32>(position, 1.0)).z;
+    if (depth >= shadow.splits[count - 1u]) {
+        return 1.0;
+    }
+    var cascade = 0u;
+    loop {
+        if (cascade + 1u >= count || depth < shadow.splits[cascade]) {
+            break;
+        }
+        cascade = cascade + 1u;
+    }
+    let lit = cascade_light(cascade, position, normal);
+
+    // Cascades fade into the next over the end of their range, and the
+    // last one into no shadow, so the seams do not show.
+    var start = 0.0;
+    if (cascade > 0u) {
+        start = shadow.splits[cascade - 1u];
+    }
+    let band = (shadow.splits[cascade] - start) * shadow.blend;
+    let fade = clamp((depth - (shadow.splits[cascade] - band)) / max(band, 1e-4), 0.0, 1.0);
+    if (fade <= 0.0) {
+        return lit;
+    }
+    var next = 1.0;
+    if (cascade + 1u < count) {
+        next = cascade_light(cascade + 1u, position, normal);
+    }
+    return mix(lit, next, fade);
+}
+
+// Diffuse and specular light from the sun, shadowed.
+fn sun_light(position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>) -> vec3<f32> {
+    if (all(shadow.color == vec3<f32>(0.0))) {
+        return vec3<f32>(0.0);
+    }
+    let light_dir = -shadow.direction;
+    let half_dir = normalize(view_dir + light_dir);
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), 32.0);
+    return shadow.color * (diffuse_strength + specular_strength) * sun_visibility(position, normal);
+}
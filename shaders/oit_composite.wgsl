@@ -0,0 +1,24 @@
+// Resolves weighted blended transparency over the current viewport. The
+// accumulated color is averaged and covers the target by one minus the
+// revealage, the product of the transparent fragments' remaining light.
+@group(0) @binding(0)
+var t_accum: texture_2d<f32>;
+@group(0) @binding(1)
+var t_revealage: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let revealage = textureLoad(t_revealage, pixel, 0).r;
+    if (revealage >= 1.0) {
+        discard;
+    }
+    let accum = textureLoad(t_accum, pixel, 0);
+    return vec4<f32>(accum.rgb / max(accum.a, 1e-5), revealage);
+}
@@ -0,0 +1,95 @@
+// Draws particles as soft round billboards facing the camera. Size and
+// color come from curves over each particle's life.
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+}
+
+struct SortEntry {
+    key: f32,
+    index: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var<storage, read> particles: array<Particle>;
+@group(1) @binding(1)
+var<storage, read> order: array<SortEntry>;
+// The color curve followed by the size curve in x, sampled evenly.
+@group(1) @binding(2)
+var<storage, read> curves: array<vec4<f32>>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+fn sample_curve(offset: u32, t: f32) -> vec4<f32> {
+    let samples = arrayLength(&curves) / 2u;
+    let x = clamp(t, 0.0, 1.0) * f32(samples - 1u);
+    let i = min(u32(x), samples - 2u);
+    return mix(curves[offset + i], curves[offset + i + 1u], x - f32(i));
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // Outside the clip volume, so dead particles draw nothing.
+    out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+
+    let index = order[instance_index].index;
+    if (index >= arrayLength(&particles)) {
+        return out;
+    }
+    let particle = particles[index];
+    if (particle.age >= particle.lifetime) {
+        return out;
+    }
+
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    let t = particle.age / particle.lifetime;
+    let samples = arrayLength(&curves) / 2u;
+    let size = sample_curve(samples, t).x;
+
+    let to_camera = normalize(camera.view_pos.xyz - particle.position);
+    var helper = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(to_camera.y) > 0.99) {
+        helper = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(helper, to_camera));
+    let up = cross(to_camera, right);
+    let position = particle.position + (right * corner.x + up * corner.y) * (size * 0.5);
+
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.corner = corner;
+    out.color = sample_curve(0u, t);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let falloff = 1.0 - smoothstep(0.5, 1.0, length(in.corner));
+    return vec4<f32>(in.color.rgb, in.color.a * falloff);
+}
@@ -0,0 +1,322 @@
+// Spawns and moves the particles of one emitter, then optionally sorts them
+// back to front for alpha blending.
+struct Frame {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    reverse_z: u32,
+    // Pixel rectangle of the main view in the depth texture.
+    viewport: vec4<f32>,
+    // How far behind the depth buffer a particle may be and still collide.
+    collision_thickness: f32,
+}
+
+struct Emitter {
+    position: vec3<f32>,
+    shape: u32,
+    direction: vec3<f32>,
+    // Sphere radius, or the cosine of the cone's half angle.
+    shape_param: f32,
+    gravity: vec3<f32>,
+    drag: f32,
+    lifetime: vec2<f32>,
+    speed: vec2<f32>,
+    noise_strength: f32,
+    noise_scale: f32,
+    // Restitution on collision; negative disables collisions.
+    bounce: f32,
+    spawn_count: u32,
+    time: f32,
+    dt: f32,
+    seed: u32,
+    triangle_count: u32,
+    capacity: u32,
+}
+
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+}
+
+struct SortEntry {
+    key: f32,
+    index: u32,
+}
+
+// Three corners per triangle; w of the first holds the running total of
+// the triangle areas divided by the mesh's area.
+struct MeshTriangle {
+    a: vec4<f32>,
+    b: vec4<f32>,
+    c: vec4<f32>,
+}
+
+struct SortStep {
+    j: u32,
+    k: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+@group(0) @binding(1)
+var t_depth: texture_depth_2d;
+
+@group(1) @binding(0)
+var<uniform> emitter: Emitter;
+
+@group(2) @binding(0)
+var<storage, read_write> particles: array<Particle>;
+@group(2) @binding(1)
+var<storage, read_write> order: array<SortEntry>;
+@group(2) @binding(2)
+var<storage, read_write> spawned: atomic<u32>;
+@group(2) @binding(3)
+var<storage, read> triangles: array<MeshTriangle>;
+
+@group(3) @binding(0)
+var<uniform> sorting: SortStep;
+
+let SHAPE_POINT: u32 = 0u;
+let SHAPE_SPHERE: u32 = 1u;
+let SHAPE_CONE: u32 = 2u;
+let SHAPE_MESH: u32 = 3u;
+let TAU: f32 = 6.283185307;
+
+fn hash(x: u32) -> u32 {
+    // PCG output permutation.
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(state: ptr<function, u32>) -> f32 {
+    *state = hash(*state);
+    return f32(*state) / 4294967295.0;
+}
+
+fn random_direction(state: ptr<function, u32>) -> vec3<f32> {
+    let z = random(state) * 2.0 - 1.0;
+    let angle = random(state) * TAU;
+    let r = sqrt(max(1.0 - z * z, 0.0));
+    return vec3<f32>(r * cos(angle), r * sin(angle), z);
+}
+
+// A direction at most acos(cos_angle) away from `axis`, uniform over the
+// spherical cap.
+fn random_in_cone(state: ptr<function, u32>, axis: vec3<f32>, cos_angle: f32) -> vec3<f32> {
+    let z = mix(1.0, cos_angle, random(state));
+    let angle = random(state) * TAU;
+    let r = sqrt(max(1.0 - z * z, 0.0));
+    var helper = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(axis.y) > 0.99) {
+        helper = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let side = normalize(cross(axis, helper));
+    let up = cross(side, axis);
+    return side * (r * cos(angle)) + up * (r * sin(angle)) + axis * z;
+}
+
+fn lattice(cell: vec3<i32>) -> f32 {
+    let h = hash(bitcast<u32>(cell.x) ^ hash(bitcast<u32>(cell.y) ^ hash(bitcast<u32>(cell.z))));
+    return f32(h) / 2147483647.5 - 1.0;
+}
+
+// Smoothly interpolated random values between -1 and 1 on the integer
+// lattice.
+fn value_noise(p: vec3<f32>) -> f32 {
+    let cell = vec3<i32>(floor(p));
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let x00 = mix(lattice(cell), lattice(cell + vec3<i32>(1, 0, 0)), u.x);
+    let x10 = mix(lattice(cell + vec3<i32>(0, 1, 0)), lattice(cell + vec3<i32>(1, 1, 0)), u.x);
+    let x01 = mix(lattice(cell + vec3<i32>(0, 0, 1)), lattice(cell + vec3<i32>(1, 0, 1)), u.x);
+    let x11 = mix(lattice(cell + vec3<i32>(0, 1, 1)), lattice(cell + vec3<i32>(1, 1, 1)), u.x);
+    return mix(mix(x00, x10, u.y), mix(x01, x11, u.y), u.z);
+}
+
+fn noise_force(p: vec3<f32>) -> vec3<f32> {
+    let q = p * emitter.noise_scale + vec3<f32>(0.0, emitter.time * 0.5, 0.0);
+    return vec3<f32>(
+        value_noise(q),
+        value_noise(q + vec3<f32>(17.1, 3.7, 9.2)),
+        value_noise(q + vec3<f32>(5.3, 31.7, 11.9)),
+    );
+}
+
+fn spawn(index: u32, slot: u32) -> Particle {
+    var state = hash(index ^ hash(slot ^ emitter.seed));
+    var position = emitter.position;
+    var direction = random_direction(&state);
+
+    if (emitter.shape == SHAPE_SPHERE) {
+        position += direction * emitter.shape_param;
+    } else if (emitter.shape == SHAPE_CONE) {
+        direction = random_in_cone(&state, emitter.direction, emitter.shape_param);
+    } else if (emitter.shape == SHAPE_MESH && emitter.triangle_count > 0u) {
+        // Pick a triangle with a probability proportional to its area.
+        let target_area = random(&state);
+        var low = 0u;
+        var high = emitter.triangle_count - 1u;
+        loop {
+            if (low >= high) {
+                break;
+            }
+            let middle = (low + high) / 2u;
+            if (triangles[middle].a.w < target_area) {
+                low = middle + 1u;
+            } else {
+                high = middle;
+            }
+        }
+        let picked = triangles[low];
+        var u = random(&state);
+        var v = random(&state);
+        if (u + v > 1.0) {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+        let ab = picked.b.xyz - picked.a.xyz;
+        let ac = picked.c.xyz - picked.a.xyz;
+        position += picked.a.xyz + ab * u + ac * v;
+        let normal = cross(ab, ac);
+        if (dot(normal, normal) > 0.0) {
+            direction = normalize(normal);
+        }
+    }
+
+    var particle: Particle;
+    particle.position = position;
+    particle.velocity = direction * mix(emitter.speed.x, emitter.speed.y, random(&state));
+    particle.age = 0.0;
+    particle.lifetime = mix(emitter.lifetime.x, emitter.lifetime.y, random(&state));
+    return particle;
+}
+
+fn world_at(pixel: vec2<i32>) -> vec3<f32> {
+    let depth = textureLoad(t_depth, pixel, 0);
+    let uv = (vec2<f32>(pixel) + 0.5 - frame.viewport.xy) / frame.viewport.zw;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = frame.inv_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+// Bounces `particle` off the depth buffer if its next position is just
+// behind the visible surface.
+fn collide(particle: ptr<function, Particle>, next: vec3<f32>) -> bool {
+    let clip = frame.view_proj * vec4<f32>(next, 1.0);
+    if (clip.w <= 0.0) {
+        return false;
+    }
+    let ndc = clip.xyz / clip.w;
+    if (any(abs(ndc.xy) >= vec2<f32>(1.0)) || ndc.z < 0.0 || ndc.z > 1.0) {
+        return false;
+    }
+
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    let size = vec2<i32>(textureDimensions(t_depth));
+    let pixel = clamp(vec2<i32>(frame.viewport.xy + uv * frame.viewport.zw), vec2<i32>(1), size - 2);
+    let scene = textureLoad(t_depth, pixel, 0);
+    var behind = ndc.z > scene;
+    if (frame.reverse_z != 0u) {
+        behind = ndc.z < scene;
+    }
+    if (!behind) {
+        return false;
+    }
+
+    let surface = world_at(pixel);
+    if (distance(surface, next) > frame.collision_thickness) {
+        // Hidden behind something rather than hitting it.
+        return false;
+    }
+    var normal = cross(world_at(pixel + vec2<i32>(0, 1)) - surface, world_at(pixel + vec2<i32>(1, 0)) - surface);
+    if (dot(normal, normal) == 0.0) {
+        return false;
+    }
+    normal = normalize(normal);
+    if (dot(normal, frame.eye - surface) < 0.0) {
+        normal = -normal;
+    }
+
+    let velocity = (*particle).velocity;
+    let into = dot(velocity, normal);
+    if (into < 0.0) {
+        (*particle).velocity = velocity - normal * into * (1.0 + emitter.bounce);
+    }
+    (*particle).position = surface + normal * 0.01;
+    return true;
+}
+
+@compute @workgroup_size(64)
+fn cs_simulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= emitter.capacity) {
+        return;
+    }
+
+    var particle = particles[index];
+    if (particle.age >= particle.lifetime) {
+        if (emitter.spawn_count > 0u) {
+            let slot = atomicAdd(&spawned, 1u);
+            if (slot < emitter.spawn_count) {
+                particles[index] = spawn(index, slot);
+            }
+        }
+        return;
+    }
+
+    let dt = emitter.dt;
+    var acceleration = emitter.gravity;
+    if (emitter.noise_strength > 0.0) {
+        acceleration += noise_force(particle.position) * emitter.noise_strength;
+    }
+    particle.velocity = (particle.velocity + acceleration * dt) / (1.0 + emitter.drag * dt);
+
+    let next = particle.position + particle.velocity * dt;
+    if (emitter.bounce < 0.0 || !collide(&particle, next)) {
+        particle.position = next;
+    }
+    particle.age += dt;
+    particles[index] = particle;
+}
+
+// Fills `order` with every particle's squared distance to the eye. Dead
+// particles and padding past the capacity sort last.
+@compute @workgroup_size(64)
+fn cs_sort_keys(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= arrayLength(&order)) {
+        return;
+    }
+
+    var key = -2.0;
+    if (index < emitter.capacity) {
+        let particle = particles[index];
+        key = -1.0;
+        if (particle.age < particle.lifetime) {
+            let offset = particle.position - frame.eye;
+            key = dot(offset, offset);
+        }
+    }
+    order[index] = SortEntry(key, index);
+}
+
+// One compare-and-swap step of a bitonic sort into descending order.
+@compute @workgroup_size(64)
+fn cs_sort_step(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    let partner = index ^ sorting.j;
+    if (partner <= index || partner >= arrayLength(&order)) {
+        return;
+    }
+
+    let a = order[index];
+    let b = order[partner];
+    let descending = (index & sorting.k) == 0u;
+    if ((a.key < b.key) == descending) {
+        order[index] = b;
+        order[partner] = a;
+    }
+}
@@ -0,0 +1,63 @@
+// Writes which instance and mesh covers each pixel, for mouse picking.
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Pick {
+    mesh: u32,
+}
+@group(1) @binding(0)
+var<uniform> pick: Pick;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+};
+
+struct PickOutput {
+    @location(0) id: u32,
+    @location(1) depth: f32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    // Zero means "nothing", so instances are stored one-based. The mesh index
+    // goes into the top byte.
+    out.id = (pick.mesh << 24u) | ((instance_index + 1u) & 0xffffffu);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> PickOutput {
+    var out: PickOutput;
+    out.id = in.id;
+    out.depth = in.clip_position.z;
+    return out;
+}
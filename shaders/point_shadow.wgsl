@@ -0,0 +1,56 @@
+// Draws the distance of shadow casters, the model's instances and the
+// terrain, from a point light into one face of its cube, see
+// `point_shadows.rs`. The distance is stored divided by the
+// light's range instead of the projected depth, so it can be compared
+// whichever face a direction falls on.
+struct Caster {
+    view_proj: mat4x4<f32>,
+    position: vec3<f32>,
+    range: f32,
+}
+@group(0) @binding(0)
+var<uniform> caster: Caster;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
+    var out: VertexOutput;
+    out.clip_position = caster.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    return out;
+}
+
+// Terrain vertices are already in world space.
+@vertex
+fn vs_terrain(@location(0) position: vec3<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = caster.view_proj * vec4<f32>(position, 1.0);
+    out.world_position = position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    return length(in.world_position - caster.position) / caster.range;
+}
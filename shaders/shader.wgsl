@@ -3,14 +3,6 @@ struct Camera {
     view_proj: mat4x4<f32>,
 }
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-}
-
-@group(2) @binding(0)
-var<uniform> light: Light;
-
 @group(1) @binding(0)
 var<uniform> camera: Camera;
 
@@ -38,6 +30,12 @@ struct VertexOutput {
     @location(1) tangent_position: vec3<f32>,
     @location(2) tangent_light_position: vec3<f32>,
     @location(3) tangent_view_position: vec3<f32>,
+    @location(4) world_position: vec3<f32>,
+    @location(5) world_tangent: vec3<f32>,
+    @location(6) world_bitangent: vec3<f32>,
+    @location(7) world_normal: vec3<f32>,
+    // Passed through so the camera stays a vertex-only binding.
+    @location(8) world_view_position: vec3<f32>,
 };
 
 @vertex
@@ -74,6 +72,11 @@ fn vs_main(
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tangent_matrix * light.position;
+    out.world_position = world_position.xyz;
+    out.world_tangent = world_tangent;
+    out.world_bitangent = world_bitangent;
+    out.world_normal = world_normal;
+    out.world_view_position = camera.view_pos.xyz;
     return out;
 }
 
@@ -87,14 +90,85 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+struct Material {
+    // Fragments with less alpha are discarded. Zero for opaque and blended
+    // materials.
+    alpha_cutoff: f32,
+    // Perceptual roughness of the specular reflection, from 0 to 1.
+    roughness: f32,
+    // 0 for dielectrics, 1 for metals, which tint their reflection with
+    // the albedo and have no diffuse light.
+    metallic: f32,
+    // Which decals land on it, see `decals.wgsl`.
+    decal_layer: u32,
+}
+@group(0) @binding(4)
+var<uniform> material: Material;
+
+// Image-based lighting, see `ibl.rs`, and ambient occlusion, see `ssao.rs`.
+struct Environment {
+    intensity: f32,
+    // The prefiltered map's last mip, which is for a roughness of one.
+    max_lod: f32,
+}
+
+@group(3) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(2)
+var t_brdf_lut: texture_2d<f32>;
+@group(3) @binding(3)
+var s_environment: sampler;
+@group(3) @binding(4)
+var<uniform> environment: Environment;
+// Per pixel of the view's target, or a single white texel without SSAO.
+@group(3) @binding(5)
+var t_occlusion: texture_2d<f32>;
+
+// Light from the environment, split into diffuse irradiance and a specular
+// reflection approximated with the split sum of the prefiltered map and the
+// BRDF lookup table.
+fn ambient_light(
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    albedo: vec3<f32>,
+    roughness: f32,
+    metallic: f32,
+) -> vec3<f32> {
+    let n_dot_v = max(dot(normal, view_dir), 0.0);
+    // Dielectrics reflect about 4% head on.
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let fresnel = f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+
+    let irradiance = textureSample(t_irradiance, s_environment, normal).rgb;
+    let diffuse = (1.0 - fresnel) * (1.0 - metallic) * irradiance * albedo;
+
+    let reflected = reflect(-view_dir, normal);
+    let prefiltered = textureSampleLevel(
+        t_prefiltered,
+        s_environment,
+        reflected,
+        roughness * environment.max_lod
+    ).rgb;
+    let brdf = textureSample(t_brdf_lut, s_environment, vec2<f32>(n_dot_v, roughness)).rg;
+    let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    return (diffuse + specular) * environment.intensity;
+}
+
+// The normal map's normal in world space.
+fn surface_normal(in: VertexOutput, tangent_normal: vec3<f32>) -> vec3<f32> {
+    return normalize(mat3x3<f32>(
+        in.world_tangent,
+        in.world_bitangent,
+        in.world_normal
+    ) * tangent_normal);
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
     let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
-    
-    // We don't need (or want) much ambient light, so 0.1 is fine
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
 
     // Create the lighting vectors
     let tangent_normal = object_normal.xyz * 2.0 - 1.0;
@@ -109,7 +183,71 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
     let specular_color = specular_strength * light.color;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    let world_normal = surface_normal(in, tangent_normal);
+    let world_view_dir = normalize(in.world_view_position - in.world_position);
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let occlusion_size = vec2<i32>(textureDimensions(t_occlusion));
+    let occlusion = textureLoad(t_occlusion, min(pixel, occlusion_size - 1), 0).r;
+    let ambient_color = ambient_light(
+        world_normal,
+        world_view_dir,
+        object_color.xyz,
+        material.roughness,
+        material.metallic
+    ) * occlusion;
+    let point_color = point_light(in.clip_position.xy, in.world_position, world_normal, world_view_dir);
+    let sun_color = sun_light(in.world_position, world_normal, world_view_dir);
+
+    let light_color = (diffuse_color + specular_color)
+        * light_visibility(in.world_position, world_normal);
+    let result = ambient_color
+        + (light_color + point_color + sun_color) * object_color.xyz;
 
     return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    if (color.a < material.alpha_cutoff) {
+        discard;
+    }
+    return color;
+}
+
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: f32,
+}
+
+// Weighted blended order-independent transparency (McGuire and Bavoil
+// 2013). Closer and more opaque fragments weigh more in the average.
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    let color = shade(in);
+    // The tangent basis is orthonormal, so this is the world distance.
+    let distance = length(in.tangent_view_position - in.tangent_position);
+    let weight = color.a * clamp(0.03 / (1e-5 + pow(distance / 200.0, 4.0)), 1e-2, 3e3);
+
+    var out: OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = color.a;
+    return out;
+}
+
+// The surface attributes `deferred.wgsl` lights, see `deferred.rs`.
+@fragment
+fn fs_gbuffer(in: VertexOutput) -> GBufferOutput {
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let object_normal = textureSample(t_normal, s_normal, in.tex_coords);
+    if (object_color.a < material.alpha_cutoff) {
+        discard;
+    }
+    let normal = surface_normal(in, object_normal.xyz * 2.0 - 1.0);
+
+    var out: GBufferOutput;
+    out.albedo = vec4<f32>(object_color.rgb, 1.0);
+    out.normal = vec4<f32>(encode_octahedral(normal), 0.0, 0.0);
+    out.material = vec4<f32>(material.roughness, material.metallic, f32(material.decal_layer) / 255.0, 1.0);
+    return out;
+}
@@ -0,0 +1,34 @@
+// Draws the depth of shadow casters, the model's instances and the
+// terrain, into one cascade of the sun's shadow map, see `shadows.rs`.
+struct Caster {
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> caster: Caster;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return caster.view_proj * model_matrix * vec4<f32>(position, 1.0);
+}
+
+// Terrain vertices are already in world space.
+@vertex
+fn vs_terrain(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return caster.view_proj * vec4<f32>(position, 1.0);
+}
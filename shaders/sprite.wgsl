@@ -0,0 +1,52 @@
+// Instanced sprites. The unit quad comes from the vertex buffer; each
+// instance places, rotates, scales and tints it.
+struct SpriteCamera {
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: SpriteCamera;
+
+@group(1) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(1) @binding(1)
+var s_atlas: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+struct SpriteInput {
+    @location(2) position: vec2<f32>,
+    @location(3) size: vec2<f32>,
+    @location(4) origin: vec2<f32>,
+    @location(5) rotation: f32,
+    @location(6) uv_min: vec2<f32>,
+    @location(7) uv_max: vec2<f32>,
+    @location(8) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(vertex: VertexInput, sprite: SpriteInput) -> VertexOutput {
+    let local = (vertex.position.xy - sprite.origin) * sprite.size;
+    let c = cos(sprite.rotation);
+    let s = sin(sprite.rotation);
+    let rotated = vec2<f32>(local.x * c - local.y * s, local.x * s + local.y * c);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(sprite.position + rotated, 0.0, 1.0);
+    out.uv = mix(sprite.uv_min, sprite.uv_max, vertex.tex_coords);
+    out.color = sprite.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color * textureSample(t_atlas, s_atlas, in.uv);
+}
@@ -0,0 +1,133 @@
+// Screen-space ambient occlusion: the fraction of a hemisphere of samples
+// around each pixel's surface that is not behind the depth buffer. One
+// where nothing occludes.
+struct Ssao {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    radius: f32,
+    // Pixel rectangle of the view in its target.
+    viewport: vec4<f32>,
+    bias: f32,
+    sample_count: u32,
+    // The depth of pixels nothing was drawn to.
+    far_depth: f32,
+    // Whether `t_normal` holds the normal prepass, rather than normals
+    // being reconstructed from depth.
+    use_normals: u32,
+    // `sample_count` samples in the hemisphere around +Z.
+    kernel: array<vec3<f32>, 64>,
+}
+
+@group(0) @binding(0)
+var<uniform> ssao: Ssao;
+@group(0) @binding(1)
+var t_depth: texture_depth_2d;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+// 4x4 rotations of the sample kernel around the normal, so neighbouring
+// pixels sample different directions and the blur can average them.
+@group(0) @binding(3)
+var t_noise: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn clamp_to_viewport(pixel: vec2<i32>) -> vec2<i32> {
+    let low = vec2<i32>(ssao.viewport.xy);
+    let high = vec2<i32>(ssao.viewport.xy + ssao.viewport.zw) - 1;
+    return clamp(pixel, low, high);
+}
+
+fn world_at(pixel: vec2<i32>) -> vec3<f32> {
+    let depth = textureLoad(t_depth, pixel, 0);
+    let uv = (vec2<f32>(pixel) + 0.5 - ssao.viewport.xy) / ssao.viewport.zw;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = ssao.inv_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+// The step from `position` to the neighbour along `offset` or against it,
+// whichever is shorter, so normals do not bend across depth edges.
+fn surface_step(pixel: vec2<i32>, offset: vec2<i32>, position: vec3<f32>) -> vec3<f32> {
+    let forward = world_at(clamp_to_viewport(pixel + offset)) - position;
+    let backward = position - world_at(clamp_to_viewport(pixel - offset));
+    let forward_length = dot(forward, forward);
+    let backward_length = dot(backward, backward);
+    // A zero step is the viewport's edge.
+    if (backward_length == 0.0 || (forward_length != 0.0 && forward_length < backward_length)) {
+        return forward;
+    }
+    return backward;
+}
+
+fn normal_at(pixel: vec2<i32>, position: vec3<f32>) -> vec3<f32> {
+    if (ssao.use_normals != 0u) {
+        return normalize(textureLoad(t_normal, pixel, 0).xyz);
+    }
+    let to_eye = normalize(ssao.eye - position);
+    let normal = cross(
+        surface_step(pixel, vec2<i32>(1, 0), position),
+        surface_step(pixel, vec2<i32>(0, 1), position)
+    );
+    let length_squared = dot(normal, normal);
+    // Also false for NaN, from neighbours at infinity.
+    if (!(length_squared > 0.0 && length_squared < 1e30)) {
+        return to_eye;
+    }
+    let unit = normal * inverseSqrt(length_squared);
+    if (dot(unit, to_eye) < 0.0) {
+        return -unit;
+    }
+    return unit;
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    if (textureLoad(t_depth, pixel, 0) == ssao.far_depth) {
+        return vec4<f32>(1.0);
+    }
+    let surface = world_at(pixel);
+    let normal = normal_at(pixel, surface);
+
+    let noise = textureLoad(t_noise, pixel % vec2<i32>(4), 0).xyz;
+    var tangent = noise - normal * dot(noise, normal);
+    if (dot(tangent, tangent) < 1e-4) {
+        tangent = cross(normal, vec3<f32>(0.0, 0.0, 1.0));
+        if (dot(tangent, tangent) < 1e-4) {
+            tangent = vec3<f32>(1.0, 0.0, 0.0);
+        }
+    }
+    tangent = normalize(tangent);
+    let bitangent = cross(normal, tangent);
+
+    let surface_distance = distance(ssao.eye, surface);
+    var occlusion = 0.0;
+    for (var i = 0u; i < ssao.sample_count; i = i + 1u) {
+        let offset = ssao.kernel[i];
+        let sample_position = surface
+            + (tangent * offset.x + bitangent * offset.y + normal * offset.z) * ssao.radius;
+        let clip = ssao.view_proj * vec4<f32>(sample_position, 1.0);
+        if (clip.w <= 0.0) {
+            continue;
+        }
+        let ndc = clip.xy / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        let sample_pixel = clamp_to_viewport(vec2<i32>(ssao.viewport.xy + uv * ssao.viewport.zw));
+        if (textureLoad(t_depth, sample_pixel, 0) == ssao.far_depth) {
+            continue;
+        }
+        let scene_distance = distance(ssao.eye, world_at(sample_pixel));
+        if (scene_distance <= distance(ssao.eye, sample_position) - ssao.bias) {
+            // Occluders much closer to the eye than the surface are in
+            // front of it rather than around it, and fade out.
+            let fade = ssao.radius / max(abs(surface_distance - scene_distance), 1e-4);
+            occlusion += smoothstep(0.0, 1.0, fade);
+        }
+    }
+    return vec4<f32>(1.0 - occlusion / f32(max(ssao.sample_count, 1u)));
+}
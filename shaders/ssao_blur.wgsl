@@ -0,0 +1,63 @@
+// Averages ambient occlusion over the 4x4 tile of its noise texture,
+// weighting neighbours by how close their depth is to the centre's so the
+// blur does not bleed across edges.
+struct Ssao {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    eye: vec3<f32>,
+    radius: f32,
+    viewport: vec4<f32>,
+    bias: f32,
+    sample_count: u32,
+    far_depth: f32,
+    use_normals: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> ssao: Ssao;
+@group(0) @binding(1)
+var t_depth: texture_depth_2d;
+@group(0) @binding(2)
+var t_occlusion: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn eye_distance(pixel: vec2<i32>, depth: f32) -> f32 {
+    let uv = (vec2<f32>(pixel) + 0.5 - ssao.viewport.xy) / ssao.viewport.zw;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = ssao.inv_view_proj * ndc;
+    return distance(ssao.eye, world.xyz / world.w);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let depth = textureLoad(t_depth, pixel, 0);
+    if (depth == ssao.far_depth) {
+        return vec4<f32>(1.0);
+    }
+    let center = eye_distance(pixel, depth);
+    let low = vec2<i32>(ssao.viewport.xy);
+    let high = vec2<i32>(ssao.viewport.xy + ssao.viewport.zw) - 1;
+
+    var sum = 0.0;
+    var weight = 0.0;
+    for (var y = -2; y < 2; y = y + 1) {
+        for (var x = -2; x < 2; x = x + 1) {
+            let neighbour = clamp(pixel + vec2<i32>(x, y), low, high);
+            let neighbour_depth = textureLoad(t_depth, neighbour, 0);
+            if (neighbour_depth == ssao.far_depth) {
+                continue;
+            }
+            let difference = abs(eye_distance(neighbour, neighbour_depth) - center);
+            let w = max(1.0 - difference / ssao.radius, 0.0);
+            sum += textureLoad(t_occlusion, neighbour, 0).r * w;
+            weight += w;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 1e-4));
+}
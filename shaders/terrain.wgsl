@@ -0,0 +1,94 @@
+// Heightmap terrain. Up to four tiling layers are blended by the weights in
+// the splat map, which is stretched once over the whole terrain.
+// Lit by the main light and the sun from lighting.wgsl.
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+
+struct TerrainParams {
+    // How often each layer repeats across the terrain.
+    layer_scales: vec4<f32>,
+    // Which decals land on it, see `decals.wgsl`.
+    decal_layer: u32,
+}
+
+@group(0) @binding(0)
+var t_splat: texture_2d<f32>;
+@group(0) @binding(1)
+var s_splat: sampler;
+@group(0) @binding(2)
+var t_layer0: texture_2d<f32>;
+@group(0) @binding(3)
+var t_layer1: texture_2d<f32>;
+@group(0) @binding(4)
+var t_layer2: texture_2d<f32>;
+@group(0) @binding(5)
+var t_layer3: texture_2d<f32>;
+@group(0) @binding(6)
+var s_layer: sampler;
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+@group(3) @binding(0)
+var<uniform> params: TerrainParams;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(vertex: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(vertex.position, 1.0);
+    out.world_position = vertex.position;
+    out.normal = vertex.normal;
+    out.tex_coords = vertex.tex_coords;
+    return out;
+}
+
+// The layers blended by the splat map at `uv`.
+fn splat_color(uv: vec2<f32>) -> vec3<f32> {
+    let weights = textureSample(t_splat, s_splat, uv);
+    var color = textureSample(t_layer0, s_layer, uv * params.layer_scales.x).rgb * weights.x;
+    color += textureSample(t_layer1, s_layer, uv * params.layer_scales.y).rgb * weights.y;
+    color += textureSample(t_layer2, s_layer, uv * params.layer_scales.z).rgb * weights.z;
+    color += textureSample(t_layer3, s_layer, uv * params.layer_scales.w).rgb * weights.w;
+    return color / max(dot(weights, vec4<f32>(1.0)), 1e-4);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = splat_color(in.tex_coords);
+
+    let ambient_color = light.color * 0.1;
+    let normal = normalize(in.normal);
+    let light_dir = normalize(light.position - in.world_position);
+    let diffuse_color = light.color * max(dot(normal, light_dir), 0.0)
+        * light_visibility(in.world_position, normal);
+    let sun_color = shadow.color * max(dot(normal, -shadow.direction), 0.0)
+        * sun_visibility(in.world_position, normal);
+
+    return vec4<f32>((ambient_color + diffuse_color + sun_color) * color, 1.0);
+}
+
+// The terrain's surface for `deferred.wgsl` to light, as a fully rough
+// dielectric.
+@fragment
+fn fs_gbuffer(in: VertexOutput) -> GBufferOutput {
+    var out: GBufferOutput;
+    out.albedo = vec4<f32>(splat_color(in.tex_coords), 1.0);
+    out.normal = vec4<f32>(encode_octahedral(normalize(in.normal)), 0.0, 0.0);
+    out.material = vec4<f32>(1.0, 0.0, f32(params.decal_layer) / 255.0, 1.0);
+    return out;
+}
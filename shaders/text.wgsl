@@ -0,0 +1,81 @@
+// Glyph quads sampled from the text atlas. Each instance is one glyph; the
+// quad corners come from the vertex index of a four-vertex triangle strip.
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// Size in pixels of the viewport the glyphs are laid out in.
+struct Viewport {
+    size: vec2<f32>,
+}
+@group(1) @binding(0)
+var<uniform> viewport: Viewport;
+
+@group(2) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(2) @binding(1)
+var s_atlas: sampler;
+
+struct GlyphInput {
+    @location(0) anchor: vec3<f32>,
+    @location(1) offset: vec2<f32>,
+    @location(2) size: vec2<f32>,
+    @location(3) uv_min: vec2<f32>,
+    @location(4) uv_max: vec2<f32>,
+    @location(5) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+fn corner(index: u32) -> vec2<f32> {
+    return vec2<f32>(f32(index & 1u), f32((index >> 1u) & 1u));
+}
+
+// `anchor.xy` is the top-left pixel of the text block.
+@vertex
+fn vs_screen(@builtin(vertex_index) index: u32, glyph: GlyphInput) -> VertexOutput {
+    let c = corner(index);
+    let pixel = glyph.anchor.xy + glyph.offset + c * glyph.size;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(
+        pixel.x / viewport.size.x * 2.0 - 1.0,
+        1.0 - pixel.y / viewport.size.y * 2.0,
+        0.0,
+        1.0,
+    );
+    out.uv = mix(glyph.uv_min, glyph.uv_max, c);
+    out.color = glyph.color;
+    return out;
+}
+
+// `anchor` is a world position; the text keeps a constant pixel size and
+// faces the camera.
+@vertex
+fn vs_world(@builtin(vertex_index) index: u32, glyph: GlyphInput) -> VertexOutput {
+    let c = corner(index);
+    let pixel = glyph.offset + c * glyph.size;
+
+    var clip = camera.view_proj * vec4<f32>(glyph.anchor, 1.0);
+    clip.x += pixel.x / viewport.size.x * 2.0 * clip.w;
+    clip.y -= pixel.y / viewport.size.y * 2.0 * clip.w;
+
+    var out: VertexOutput;
+    out.clip_position = clip;
+    out.uv = mix(glyph.uv_min, glyph.uv_max, c);
+    out.color = glyph.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(t_atlas, s_atlas, in.uv).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
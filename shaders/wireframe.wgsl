@@ -0,0 +1,82 @@
+// Wireframe overlay. `vs_main` is used with `PolygonMode::Line`; devices
+// without that feature draw filled triangles through `vs_barycentric`, which
+// pulls vertices from storage buffers so each corner gets its own barycentric
+// coordinate, and `fs_barycentric` keeps only the pixels near an edge.
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// `ModelVertex` as plain floats: position, tex_coords, normal, tangent and
+// bitangent.
+@group(1) @binding(0)
+var<storage, read> vertices: array<f32>;
+@group(1) @binding(1)
+var<storage, read> indices: array<u32>;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) barycentric: vec3<f32>,
+};
+
+fn transform(position: vec3<f32>, instance: InstanceInput) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return camera.view_proj * model_matrix * vec4<f32>(position, 1.0);
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = transform(model.position, instance);
+    out.barycentric = vec3<f32>(0.0);
+    return out;
+}
+
+@vertex
+fn vs_barycentric(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let base = indices[vertex_index] * 14u;
+    let position = vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+
+    var out: VertexOutput;
+    out.clip_position = transform(position, instance);
+    let corner = vertex_index % 3u;
+    out.barycentric = vec3<f32>(f32(corner == 0u), f32(corner == 1u), f32(corner == 2u));
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+}
+
+@fragment
+fn fs_barycentric(in: VertexOutput) -> @location(0) vec4<f32> {
+    let width = fwidth(in.barycentric);
+    let edge = smoothstep(vec3<f32>(0.0), width * 1.5, in.barycentric);
+    let alpha = 1.0 - min(edge.x, min(edge.y, edge.z));
+    if alpha < 0.01 {
+        discard;
+    }
+    return vec4<f32>(1.0, 1.0, 1.0, alpha);
+}
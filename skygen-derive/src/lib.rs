@@ -0,0 +1,42 @@
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput, Error};
+
+mod shader_type;
+mod vertex;
+
+/// Derives `skygen::vertex::Vertex` for a `#[repr(C)]` struct.
+///
+/// Every field becomes one vertex attribute (or one attribute per column for
+/// `[[f32; R]; C]` matrices). Locations start at 0 and increase by one per
+/// attribute unless a field is annotated with `#[location(n)]`, in which case
+/// numbering continues from `n`. The format is inferred from the field type
+/// and can be overridden with `#[format(Unorm8x4)]`.
+///
+/// Marking the struct with `#[vertex(instance)]` switches the step mode to
+/// `VertexStepMode::Instance`.
+///
+/// Offsets are taken from `core::mem::offset_of!` and the size of every
+/// format is checked against the size of its field at compile time.
+#[proc_macro_derive(Vertex, attributes(vertex, location, format))]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    vertex::expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `skygen::uniform::ShaderType` for a struct whose fields all
+/// implement it.
+///
+/// The std140 and std430 layouts are computed from the field types, so the
+/// Rust struct never needs `_padding` fields; the padding is inserted when the
+/// value is written into a buffer.
+#[proc_macro_derive(ShaderType)]
+pub fn derive_shader_type(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    shader_type::expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
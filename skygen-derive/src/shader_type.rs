@@ -0,0 +1,57 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, Result};
+
+pub(crate) fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) if !fields.named.is_empty() => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    name.span(),
+                    "`ShaderType` can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                name.span(),
+                "`ShaderType` can only be derived for structs",
+            ))
+        }
+    };
+
+    let idents = fields.iter().map(|field| &field.ident).collect::<Vec<_>>();
+    let types = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+
+    Ok(quote! {
+        impl #impl_generics ::skygen::uniform::ShaderType for #name #ty_generics #where_clause {
+            const STD140: ::skygen::uniform::TypeLayout = ::skygen::uniform::TypeLayout::structure(
+                &[#(<#types as ::skygen::uniform::ShaderType>::STD140),*],
+                ::skygen::uniform::MemoryLayout::Std140,
+            );
+            const STD430: ::skygen::uniform::TypeLayout = ::skygen::uniform::TypeLayout::structure(
+                &[#(<#types as ::skygen::uniform::ShaderType>::STD430),*],
+                ::skygen::uniform::MemoryLayout::Std430,
+            );
+
+            fn write(&self, layout: ::skygen::uniform::MemoryLayout, out: &mut [u8]) {
+                let mut offset = 0;
+                #(
+                    let field = layout.of::<#types>();
+                    offset = ::skygen::uniform::align_to(offset, field.align);
+                    ::skygen::uniform::ShaderType::write(
+                        &self.#idents,
+                        layout,
+                        &mut out[offset..offset + field.size],
+                    );
+                    offset += field.size;
+                )*
+                let _ = offset;
+            }
+        }
+    })
+}
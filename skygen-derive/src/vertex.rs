@@ -0,0 +1,261 @@
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::{spanned::Spanned, Data, DeriveInput, Error, Expr, Fields, Ident, Lit, Result, Type};
+
+struct Attribute {
+    field: Ident,
+    format: Ident,
+    location: u32,
+    column: u32,
+    column_ty: Option<TokenStream2>,
+    span: Span,
+}
+
+pub(crate) fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "`Vertex` cannot be derived for generic structs",
+        ));
+    }
+
+    if !has_repr_c(&input) {
+        return Err(Error::new(
+            name.span(),
+            "`Vertex` requires `#[repr(C)]` so that field offsets are stable",
+        ));
+    }
+
+    let step_mode = if is_instance(&input)? {
+        quote!(::skygen::wgpu::VertexStepMode::Instance)
+    } else {
+        quote!(::skygen::wgpu::VertexStepMode::Vertex)
+    };
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    name.span(),
+                    "`Vertex` can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                name.span(),
+                "`Vertex` can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut attributes = Vec::new();
+    let mut size_checks = Vec::new();
+    let mut next_location = 0;
+
+    for field in fields {
+        let ident = field.ident.clone().unwrap();
+        let location = match location_override(field)? {
+            Some(location) => location,
+            None => next_location,
+        };
+
+        let (format, columns, column_ty) = match format_override(field)? {
+            Some(format) => (format, 1, None),
+            None => infer_format(&field.ty)?,
+        };
+
+        let ty = &field.ty;
+        let message = format!(
+            "size of field `{}` does not match the size of `VertexFormat::{}`",
+            ident, format
+        );
+        size_checks.push(quote_spanned! {field.ty.span()=>
+            assert!(
+                ::core::mem::size_of::<#ty>() as u64
+                    == ::skygen::wgpu::VertexFormat::#format.size() * #columns as u64,
+                #message
+            );
+        });
+
+        for column in 0..columns {
+            attributes.push(Attribute {
+                field: ident.clone(),
+                format: format.clone(),
+                location: location + column,
+                column,
+                column_ty: column_ty.clone(),
+                span: field.span(),
+            });
+        }
+
+        next_location = location + columns;
+    }
+
+    for (i, a) in attributes.iter().enumerate() {
+        if attributes[..i].iter().any(|b| b.location == a.location) {
+            return Err(Error::new(
+                a.span,
+                format!("shader location {} is used more than once", a.location),
+            ));
+        }
+    }
+
+    let count = attributes.len();
+    let attribute_exprs = attributes.iter().map(|a| {
+        let Attribute {
+            field,
+            format,
+            location,
+            column,
+            ..
+        } = a;
+        let column_offset = match &a.column_ty {
+            Some(ty) => quote!(#column as u64 * ::core::mem::size_of::<#ty>() as u64),
+            None => quote!(0),
+        };
+
+        quote! {
+            ::skygen::wgpu::VertexAttribute {
+                format: ::skygen::wgpu::VertexFormat::#format,
+                offset: ::core::mem::offset_of!(#name, #field) as u64 + #column_offset,
+                shader_location: #location,
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #name {
+            pub const ATTRIBS: [::skygen::wgpu::VertexAttribute; #count] = [
+                #(#attribute_exprs),*
+            ];
+        }
+
+        const _: () = {
+            #(#size_checks)*
+        };
+
+        impl ::skygen::vertex::Vertex for #name {
+            fn desc<'a>() -> ::skygen::wgpu::VertexBufferLayout<'a> {
+                ::skygen::wgpu::VertexBufferLayout {
+                    array_stride: ::core::mem::size_of::<Self>() as ::skygen::wgpu::BufferAddress,
+                    step_mode: #step_mode,
+                    attributes: &Self::ATTRIBS,
+                }
+            }
+        }
+    })
+}
+
+fn has_repr_c(input: &DeriveInput) -> bool {
+    input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("repr"))
+        .any(|attr| {
+            attr.parse_args_with(
+                syn::punctuated::Punctuated::<Ident, syn::Token![,]>::parse_terminated,
+            )
+            .map(|idents| idents.iter().any(|ident| ident == "C"))
+            .unwrap_or(false)
+        })
+}
+
+fn is_instance(input: &DeriveInput) -> Result<bool> {
+    match input.attrs.iter().find(|attr| attr.path.is_ident("vertex")) {
+        Some(attr) => {
+            let ident: Ident = attr.parse_args()?;
+            if ident != "instance" {
+                return Err(Error::new(ident.span(), "expected `#[vertex(instance)]`"));
+            }
+
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+fn location_override(field: &syn::Field) -> Result<Option<u32>> {
+    match field
+        .attrs
+        .iter()
+        .find(|attr| attr.path.is_ident("location"))
+    {
+        Some(attr) => attr.parse_args::<syn::LitInt>()?.base10_parse().map(Some),
+        None => Ok(None),
+    }
+}
+
+fn format_override(field: &syn::Field) -> Result<Option<Ident>> {
+    match field.attrs.iter().find(|attr| attr.path.is_ident("format")) {
+        Some(attr) => attr.parse_args().map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Maps a field type onto a vertex format, the number of consecutive shader
+/// locations it occupies and, for matrices, the type of a single column.
+fn infer_format(ty: &Type) -> Result<(Ident, u32, Option<TokenStream2>)> {
+    let unsupported = || {
+        Error::new(
+            ty.span(),
+            "cannot infer a vertex format for this type, use `#[format(..)]`",
+        )
+    };
+
+    match ty {
+        Type::Path(_) => {
+            let scalar = scalar_prefix(ty).ok_or_else(unsupported)?;
+            Ok((Ident::new(scalar, Span::call_site()), 1, None))
+        }
+        Type::Array(array) => {
+            let len = array_len(&array.len).ok_or_else(unsupported)?;
+
+            match &*array.elem {
+                Type::Array(column) => {
+                    let rows = array_len(&column.len).ok_or_else(unsupported)?;
+                    if scalar_prefix(&column.elem) != Some("Float32") || !(2..=4).contains(&rows) {
+                        return Err(unsupported());
+                    }
+
+                    let format = Ident::new(&format!("Float32x{}", rows), Span::call_site());
+                    let column_ty = &array.elem;
+                    Ok((format, len, Some(quote!(#column_ty))))
+                }
+                elem => {
+                    let scalar = scalar_prefix(elem).ok_or_else(unsupported)?;
+                    if !(2..=4).contains(&len) {
+                        return Err(unsupported());
+                    }
+
+                    let format = Ident::new(&format!("{}x{}", scalar, len), Span::call_site());
+                    Ok((format, 1, None))
+                }
+            }
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+fn scalar_prefix(ty: &Type) -> Option<&'static str> {
+    match ty {
+        Type::Path(path) if path.path.is_ident("f32") => Some("Float32"),
+        Type::Path(path) if path.path.is_ident("u32") => Some("Uint32"),
+        Type::Path(path) if path.path.is_ident("i32") => Some("Sint32"),
+        Type::Path(path) if path.path.is_ident("f64") => Some("Float64"),
+        _ => None,
+    }
+}
+
+fn array_len(expr: &Expr) -> Option<u32> {
+    match expr {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Int(int) => int.base10_parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
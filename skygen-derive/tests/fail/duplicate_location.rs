@@ -0,0 +1,11 @@
+use skygen::vertex::Vertex;
+
+#[repr(C)]
+#[derive(Clone, Copy, Vertex)]
+struct Overlapping {
+    model: [[f32; 4]; 2],
+    #[location(1)]
+    color: [f32; 4],
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use skygen::vertex::Vertex;
+
+#[repr(C)]
+#[derive(Clone, Copy, Vertex)]
+struct Mismatched {
+    #[format(Float32x4)]
+    position: [f32; 3],
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use skygen::vertex::Vertex;
+
+#[derive(Clone, Copy, Vertex)]
+struct Unstable {
+    position: [f32; 3],
+}
+
+fn main() {}
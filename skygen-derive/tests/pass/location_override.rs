@@ -0,0 +1,28 @@
+use skygen::vertex::Vertex;
+use skygen::wgpu::{VertexFormat, VertexStepMode};
+
+#[repr(C)]
+#[derive(Clone, Copy, Vertex)]
+#[vertex(instance)]
+struct Instance {
+    #[location(5)]
+    model: [[f32; 4]; 4],
+    #[format(Unorm8x4)]
+    color: u32,
+    scale: f32,
+}
+
+fn main() {
+    let locations = Instance::ATTRIBS.map(|attribute| attribute.shader_location);
+    assert_eq!(locations, [5, 6, 7, 8, 9, 10]);
+
+    // One attribute per column, each 16 bytes past the previous one.
+    let offsets = Instance::ATTRIBS.map(|attribute| attribute.offset);
+    assert_eq!(offsets, [0, 16, 32, 48, 64, 68]);
+    assert_eq!(Instance::ATTRIBS[4].format, VertexFormat::Unorm8x4);
+    assert_eq!(Instance::ATTRIBS[5].format, VertexFormat::Float32);
+
+    let desc = Instance::desc();
+    assert_eq!(desc.step_mode, VertexStepMode::Instance);
+    assert_eq!(desc.array_stride, 72);
+}
@@ -0,0 +1,23 @@
+use skygen::model::ModelVertex;
+use skygen::vertex::Vertex;
+use skygen::wgpu::{VertexFormat, VertexStepMode};
+
+fn main() {
+    let normal = ModelVertex::ATTRIBS[2];
+    assert_eq!(normal.format, VertexFormat::Float32x3);
+    assert_eq!(normal.shader_location, 2);
+    assert_eq!(normal.offset, 20);
+
+    let formats = ModelVertex::ATTRIBS.map(|attribute| attribute.format);
+    assert_eq!(
+        formats,
+        [
+            VertexFormat::Float32x3,
+            VertexFormat::Float32x2,
+            VertexFormat::Float32x3,
+            VertexFormat::Float32x3,
+            VertexFormat::Float32x3,
+        ]
+    );
+    assert_eq!(ModelVertex::desc().step_mode, VertexStepMode::Vertex);
+}
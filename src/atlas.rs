@@ -0,0 +1,386 @@
+use anyhow::*;
+use image::{imageops::FilterType, DynamicImage, RgbaImage};
+use wgpu::{Device, Queue};
+
+use crate::texture::Texture;
+
+/// Packs rectangles into rows ("shelves") of increasing height. Simple, and
+/// good enough for glyphs, whose heights are similar at a given size.
+#[derive(Debug)]
+pub struct ShelfPacker {
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+}
+
+#[derive(Debug)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    used: u32,
+}
+
+impl ShelfPacker {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            shelves: Vec::new(),
+        }
+    }
+
+    /// Returns the top-left corner of a free `width` x `height` area.
+    pub fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if width > self.width {
+            return None;
+        }
+
+        // The lowest shelf the rectangle fits on wastes the least space.
+        let best = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= height && self.width - shelf.used >= width)
+            .min_by_key(|shelf| shelf.height);
+        if let Some(shelf) = best {
+            let x = shelf.used;
+            shelf.used += width;
+            return Some((x, shelf.y));
+        }
+
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        if y + height > self.height {
+            return None;
+        }
+
+        self.shelves.push(Shelf {
+            y,
+            height,
+            used: width,
+        });
+        Some((0, y))
+    }
+
+    pub fn clear(&mut self) {
+        self.shelves.clear();
+    }
+}
+
+/// Copies `image` into the middle of a larger image with a `gutter` pixel
+/// border repeating its edge pixels, so filtering at the edge of an atlas
+/// entry never reaches its neighbours.
+pub fn extrude(image: &RgbaImage, gutter: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    RgbaImage::from_fn(width + 2 * gutter, height + 2 * gutter, |x, y| {
+        let x = x.saturating_sub(gutter).min(width - 1);
+        let y = y.saturating_sub(gutter).min(height - 1);
+        *image.get_pixel(x, y)
+    })
+}
+
+/// Where an image ended up in an [`Atlas`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRect {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+}
+
+impl AtlasRect {
+    /// Maps a texture coordinate of the original image into the atlas page.
+    /// Coordinates outside `0..=1` are clamped; repeating textures cannot be
+    /// atlased.
+    pub fn remap(&self, uv: [f32; 2]) -> [f32; 2] {
+        let [u, v] = uv.map(|c| c.clamp(0.0, 1.0));
+        [
+            self.uv_min[0] + u * (self.uv_max[0] - self.uv_min[0]),
+            self.uv_min[1] + v * (self.uv_max[1] - self.uv_min[1]),
+        ]
+    }
+}
+
+/// Collects images and packs them into as few atlas pages as possible.
+///
+/// ```ignore
+/// let mut builder = AtlasBuilder::new(4096).with_mip_levels(4);
+/// let grass = builder.add(&grass_image)?;
+/// let atlas = builder.build()?;
+/// let textures = atlas.upload(&device, &queue, "terrain", false);
+/// let uv = atlas.rects[grass].remap(uv);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AtlasBuilder {
+    max_size: u32,
+    padding: u32,
+    mip_levels: u32,
+    images: Vec<RgbaImage>,
+}
+
+impl AtlasBuilder {
+    /// Pages are at most `max_size` pixels on each side.
+    pub fn new(max_size: u32) -> Self {
+        Self {
+            max_size,
+            padding: 1,
+            mip_levels: 1,
+            images: Vec::new(),
+        }
+    }
+
+    /// Width of the extruded border around each image.
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Number of mip levels the pages are uploaded with. Entries are aligned
+    /// and padded so that they stay apart down to the smallest level.
+    pub fn with_mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels.max(1);
+        self
+    }
+
+    /// Adds an image and returns its index into [`Atlas::rects`]. Empty
+    /// images have no edge to extrude and are rejected.
+    pub fn add(&mut self, image: &DynamicImage) -> Result<usize> {
+        if image.width() == 0 || image.height() == 0 {
+            bail!(
+                "cannot atlas an empty {}x{} image",
+                image.width(),
+                image.height()
+            );
+        }
+        self.images.push(image.to_rgba8());
+        Ok(self.images.len() - 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// One texel of the smallest mip level covers this many pixels of the
+    /// first along each axis.
+    fn block(&self) -> u32 {
+        1 << (self.mip_levels - 1)
+    }
+
+    pub fn build(self) -> Result<Atlas> {
+        let block = self.block();
+        // A texel of the smallest mip must not mix two entries, and its
+        // bilinear neighbours must still be gutter.
+        let gutter = self.padding.max(block);
+        let align = |size: u32| size.div_ceil(block) * block;
+
+        // Tall images first keeps shelves full.
+        let mut order = (0..self.images.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&index| {
+            let (width, height) = self.images[index].dimensions();
+            (std::cmp::Reverse(height), std::cmp::Reverse(width))
+        });
+
+        let mut packers: Vec<ShelfPacker> = Vec::new();
+        let mut extents: Vec<(u32, u32)> = Vec::new();
+        let mut placed = vec![(0, 0, 0); self.images.len()];
+        for index in order {
+            let (width, height) = self.images[index].dimensions();
+            let padded = (align(width + 2 * gutter), align(height + 2 * gutter));
+            if padded.0 > self.max_size || padded.1 > self.max_size {
+                bail!(
+                    "{}x{} image does not fit a {}x{} atlas page with {} pixels of gutter",
+                    width,
+                    height,
+                    self.max_size,
+                    self.max_size,
+                    gutter
+                );
+            }
+
+            let found = packers.iter_mut().enumerate().find_map(|(page, packer)| {
+                packer
+                    .allocate(padded.0, padded.1)
+                    .map(|position| (page, position))
+            });
+            let (page, (x, y)) = match found {
+                Some(found) => found,
+                None => {
+                    let mut packer = ShelfPacker::new(self.max_size, self.max_size);
+                    let position = packer
+                        .allocate(padded.0, padded.1)
+                        .context("image does not fit an empty atlas page")?;
+                    packers.push(packer);
+                    extents.push((0, 0));
+                    (packers.len() - 1, position)
+                }
+            };
+
+            let extent = &mut extents[page];
+            *extent = (extent.0.max(x + padded.0), extent.1.max(y + padded.1));
+            placed[index] = (page, x, y);
+        }
+
+        // Pages shrink to the smallest power of two holding their entries.
+        let sizes = extents
+            .iter()
+            .map(|&(width, height)| {
+                (
+                    width.next_power_of_two().clamp(block, self.max_size),
+                    height.next_power_of_two().clamp(block, self.max_size),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut pages = sizes
+            .iter()
+            .map(|&(width, height)| RgbaImage::new(width, height))
+            .collect::<Vec<_>>();
+
+        let mut rects = Vec::with_capacity(self.images.len());
+        for (image, &(page, x, y)) in self.images.iter().zip(&placed) {
+            image::imageops::replace(
+                &mut pages[page],
+                &extrude(image, gutter),
+                x as i64,
+                y as i64,
+            );
+
+            let (page_width, page_height) = (sizes[page].0 as f32, sizes[page].1 as f32);
+            let (x, y) = (x + gutter, y + gutter);
+            let (width, height) = image.dimensions();
+            rects.push(AtlasRect {
+                page,
+                x,
+                y,
+                width,
+                height,
+                uv_min: [x as f32 / page_width, y as f32 / page_height],
+                uv_max: [
+                    (x + width) as f32 / page_width,
+                    (y + height) as f32 / page_height,
+                ],
+            });
+        }
+
+        Ok(Atlas {
+            pages,
+            rects,
+            mip_levels: self.mip_levels,
+        })
+    }
+}
+
+/// Packed atlas pages in CPU memory, ready to be saved at build time or
+/// uploaded at runtime.
+#[derive(Debug, Clone)]
+pub struct Atlas {
+    pub pages: Vec<RgbaImage>,
+    /// One rectangle per image, in the order they were added.
+    pub rects: Vec<AtlasRect>,
+    pub mip_levels: u32,
+}
+
+impl Atlas {
+    /// The mip chain of a page, starting with the page itself. Levels are
+    /// capped where a page side would drop below one pixel.
+    pub fn mips(&self, page: usize) -> Vec<RgbaImage> {
+        let mut levels = vec![self.pages[page].clone()];
+        for _ in 1..self.mip_levels {
+            let previous = levels.last().unwrap();
+            let (width, height) = previous.dimensions();
+            if width == 1 && height == 1 {
+                break;
+            }
+            let next = image::imageops::resize(
+                previous,
+                (width / 2).max(1),
+                (height / 2).max(1),
+                FilterType::Triangle,
+            );
+            levels.push(next);
+        }
+        levels
+    }
+
+    /// Creates one texture per page with its full mip chain.
+    pub fn upload(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+        is_normal_map: bool,
+    ) -> Vec<Texture> {
+        (0..self.pages.len())
+            .map(|page| {
+                Texture::from_mips(
+                    device,
+                    queue,
+                    &self.mips(page),
+                    &format!("{} atlas {}", label, page),
+                    is_normal_map,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shelf_packer_reuses_rows_and_reports_full() {
+        let mut packer = ShelfPacker::new(32, 32);
+        assert_eq!(packer.allocate(20, 10), Some((0, 0)));
+        assert_eq!(packer.allocate(12, 8), Some((20, 0)));
+        assert_eq!(packer.allocate(10, 16), Some((0, 10)));
+        assert_eq!(packer.allocate(40, 1), None);
+        assert_eq!(packer.allocate(32, 7), None);
+    }
+
+    #[test]
+    fn entries_are_extruded_aligned_and_spread_over_pages() {
+        let mut builder = AtlasBuilder::new(64).with_mip_levels(3);
+        let red =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255])));
+        let blue =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 40, image::Rgba([0, 0, 255, 255])));
+        let indices = [&red, &blue, &blue].map(|image| builder.add(image).unwrap());
+        let atlas = builder.build().unwrap();
+
+        // Two 48x48 padded blue squares cannot share a 64x64 page, but the
+        // 12x12 padded red one fits next to the first.
+        assert_eq!(atlas.pages.len(), 2);
+        assert_eq!(atlas.rects[indices[0]].page, 0);
+        for (&index, color) in
+            indices
+                .iter()
+                .zip([[255, 0, 0, 255], [0, 0, 255, 255], [0, 0, 255, 255]])
+        {
+            let rect = atlas.rects[index];
+            let page = &atlas.pages[rect.page];
+            // The gutter repeats the edge, as wide as a texel of mip 2.
+            assert_eq!(page.get_pixel(rect.x - 4, rect.y - 4).0, color);
+            assert_eq!((rect.x - 4) % 4, 0);
+            assert_eq!(rect.remap([1.0, 1.0]), rect.uv_max);
+        }
+        assert_eq!(atlas.mips(0).len(), 3);
+    }
+
+    #[test]
+    fn empty_images_are_rejected() {
+        let mut builder = AtlasBuilder::new(64);
+        for (width, height) in [(0, 0), (0, 4), (4, 0)] {
+            let empty = DynamicImage::ImageRgba8(RgbaImage::new(width, height));
+            assert!(builder.add(&empty).is_err());
+        }
+        assert!(builder.is_empty());
+    }
+}
@@ -3,13 +3,47 @@ use std::time::Duration;
 use cgmath::{InnerSpace, Rad, Vector3};
 use winit::{
     dpi::PhysicalPosition,
-    event::{ElementState, MouseScrollDelta, VirtualKeyCode},
+    event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode},
 };
 
 use super::camera::{Camera, SAFE_FRAC_PI_2};
+use crate::instance::Instance;
 
+/// Turns window input into camera movement. `State` forwards input to the
+/// controller and calls [`update_camera`](Self::update_camera) once per frame.
+pub trait CameraController {
+    /// Returns whether the key was consumed.
+    fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool;
+
+    /// Returns whether the button was consumed.
+    fn process_mouse_button(&mut self, _button: MouseButton, _state: ElementState) -> bool {
+        false
+    }
+
+    /// Raw mouse movement, delivered whether or not a button is held.
+    fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64);
+
+    fn process_scroll(&mut self, delta: &MouseScrollDelta);
+
+    /// Called before [`update_camera`](Self::update_camera) with the scene's
+    /// instances, for controllers that track one of them.
+    fn observe_scene(&mut self, _instances: &[Instance]) {}
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration);
+}
+
+/// Converts a scroll event into "lines", assuming a line is about 100 pixels.
+pub(crate) fn scroll_lines(delta: &MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, scroll) => *scroll,
+        MouseScrollDelta::PixelDelta(PhysicalPosition { y: scroll, .. }) => *scroll as f32 / 100.0,
+    }
+}
+
+/// WASD fly-through controller that rotates with the mouse while the left
+/// button is held.
 #[derive(Debug)]
-pub struct CameraController {
+pub struct FpsController {
     amount_left: f32,
     amount_right: f32,
     amount_forward: f32,
@@ -21,9 +55,10 @@ pub struct CameraController {
     scroll: f32,
     speed: f32,
     sensitivity: f32,
+    rotating: bool,
 }
 
-impl CameraController {
+impl FpsController {
     pub fn new(speed: f32, sensitivity: f32) -> Self {
         Self {
             amount_left: 0.0,
@@ -37,10 +72,13 @@ impl CameraController {
             scroll: 0.0,
             speed,
             sensitivity,
+            rotating: false,
         }
     }
+}
 
-    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+impl CameraController for FpsController {
+    fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed {
             1.0
         } else {
@@ -75,20 +113,27 @@ impl CameraController {
         }
     }
 
-    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+    fn process_mouse_button(&mut self, button: MouseButton, state: ElementState) -> bool {
+        if button != MouseButton::Left {
+            return false;
+        }
+
+        self.rotating = state == ElementState::Pressed;
+        true
     }
 
-    pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
-        self.scroll = -match delta {
-            // I'm assuming a line is about 100 pixels
-            MouseScrollDelta::LineDelta(_, scroll) => scroll * 100.0,
-            MouseScrollDelta::PixelDelta(PhysicalPosition { y: scroll, .. }) => *scroll as f32,
-        };
+    fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        if self.rotating {
+            self.rotate_horizontal = mouse_dx as f32;
+            self.rotate_vertical = mouse_dy as f32;
+        }
+    }
+
+    fn process_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.scroll = -scroll_lines(delta) * 100.0;
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
         let dt = dt.as_secs_f32();
 
         // Move forward/backward and left/right
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use cgmath::{Point3, Quaternion, Rotation, Vector3};
+use winit::event::{ElementState, MouseScrollDelta, VirtualKeyCode};
+
+use super::{
+    camera::Camera,
+    controller::{scroll_lines, CameraController},
+};
+use crate::instance::Instance;
+
+/// Smoothly trails one of the scene's instances, keeping a fixed offset in
+/// the instance's local space and always looking at it.
+#[derive(Debug)]
+pub struct FollowController {
+    /// Index into the instances passed to `observe_scene`.
+    pub node: usize,
+    /// Camera position relative to the node, rotated with the node.
+    pub offset: Vector3<f32>,
+    /// How quickly the camera catches up, in 1/seconds. Higher is snappier.
+    pub stiffness: f32,
+    pub min_distance: f32,
+    target_position: Option<Point3<f32>>,
+    target_rotation: Quaternion<f32>,
+    zoom: f32,
+    scroll: f32,
+}
+
+impl FollowController {
+    pub fn new<V: Into<Vector3<f32>>>(node: usize, offset: V, stiffness: f32) -> Self {
+        Self {
+            node,
+            offset: offset.into(),
+            stiffness,
+            min_distance: 0.5,
+            target_position: None,
+            target_rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            zoom: 1.0,
+            scroll: 0.0,
+        }
+    }
+}
+
+impl CameraController for FollowController {
+    fn process_keyboard(&mut self, _key: VirtualKeyCode, _state: ElementState) -> bool {
+        false
+    }
+
+    fn process_mouse(&mut self, _mouse_dx: f64, _mouse_dy: f64) {}
+
+    fn process_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.scroll += scroll_lines(delta);
+    }
+
+    fn observe_scene(&mut self, instances: &[Instance]) {
+        if let Some(node) = instances.get(self.node) {
+            self.target_position = Some(Point3::new(
+                node.position.x,
+                node.position.y,
+                node.position.z,
+            ));
+            self.target_rotation = node.rotation;
+        }
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let target = match self.target_position {
+            Some(target) => target,
+            None => return,
+        };
+
+        self.zoom *= 0.9f32.powf(self.scroll);
+        self.scroll = 0.0;
+
+        let offset = self.target_rotation.rotate_vector(self.offset * self.zoom);
+        let desired = target + offset;
+
+        // Exponential smoothing is frame-rate independent, unlike a fixed lerp.
+        let t = 1.0 - (-self.stiffness * dt.as_secs_f32()).exp();
+        camera.position += (desired - camera.position) * t;
+
+        let to_camera = camera.position - target;
+        let distance = cgmath::InnerSpace::magnitude(to_camera);
+        if distance < self.min_distance && distance > 0.0 {
+            camera.position = target + to_camera * (self.min_distance / distance);
+        }
+
+        camera.look_at(target);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, InnerSpace, Rad, Rotation3};
+
+    use super::*;
+
+    fn node(position: Vector3<f32>, rotation: Quaternion<f32>) -> Instance {
+        Instance { position, rotation }
+    }
+
+    fn following(offset: Vector3<f32>, stiffness: f32) -> FollowController {
+        let mut controller = FollowController::new(0, offset, stiffness);
+        controller.observe_scene(&[node(
+            Vector3::new(10.0, 0.0, 0.0),
+            Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        )]);
+        controller
+    }
+
+    #[test]
+    fn stays_put_until_the_node_is_seen() {
+        let mut controller = FollowController::new(3, (0.0, 0.0, 5.0), 4.0);
+        controller.observe_scene(&[]);
+        let mut camera = Camera::new((1.0, 2.0, 3.0), Rad(0.0), Rad(0.0));
+        controller.update_camera(&mut camera, Duration::from_secs(1));
+        assert_eq!(camera.position, Point3::new(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn smoothing_closes_the_gap_exponentially() {
+        let stiffness = 2.0;
+        let mut controller = following(Vector3::new(0.0, 0.0, 5.0), stiffness);
+        let mut camera = Camera::new((0.0, 0.0, 5.0), Rad(0.0), Rad(0.0));
+
+        // After ln(2) / stiffness seconds, half of the way is covered.
+        let half_life = Duration::from_secs_f32(2f32.ln() / stiffness);
+        controller.update_camera(&mut camera, half_life);
+        assert!((camera.position - Point3::new(5.0, 0.0, 5.0)).magnitude() < 1e-4);
+
+        controller.update_camera(&mut camera, half_life);
+        assert!((camera.position - Point3::new(7.5, 0.0, 5.0)).magnitude() < 1e-4);
+        assert!((camera.forward() - Vector3::new(2.5, 0.0, -5.0).normalize()).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn smoothing_does_not_depend_on_the_frame_rate() {
+        let mut coarse = following(Vector3::new(0.0, 2.0, 5.0), 3.0);
+        let mut fine = following(Vector3::new(0.0, 2.0, 5.0), 3.0);
+        let mut coarse_camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+        let mut fine_camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+
+        coarse.update_camera(&mut coarse_camera, Duration::from_millis(100));
+        for _ in 0..10 {
+            fine.update_camera(&mut fine_camera, Duration::from_millis(10));
+        }
+        assert!((coarse_camera.position - fine_camera.position).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn offset_turns_with_the_node() {
+        let mut controller = FollowController::new(0, (0.0, 0.0, 5.0), 1e3);
+        controller.observe_scene(&[node(
+            Vector3::new(0.0, 1.0, 0.0),
+            Quaternion::from_angle_y(Deg(90.0)),
+        )]);
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+        controller.update_camera(&mut camera, Duration::from_secs(1));
+        assert!((camera.position - Point3::new(5.0, 1.0, 0.0)).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn keeps_the_minimum_distance_while_zoomed_in() {
+        let mut controller = following(Vector3::new(0.0, 0.0, 5.0), 1e3);
+        controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, 100.0));
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+        controller.update_camera(&mut camera, Duration::from_secs(1));
+
+        let distance = (camera.position - Point3::new(10.0, 0.0, 0.0)).magnitude();
+        assert!((distance - controller.min_distance).abs() < 1e-4);
+    }
+}
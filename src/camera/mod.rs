@@ -1,4 +1,7 @@
 pub mod camera;
 pub mod controller;
+pub mod follow;
+pub mod orbit;
 pub mod projection;
 pub mod uniform;
+pub mod view;
@@ -0,0 +1,263 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, MetricSpace, Point3, Rad, Vector3};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode};
+
+use super::{
+    camera::{Camera, SAFE_FRAC_PI_2},
+    controller::{scroll_lines, CameraController},
+};
+
+/// Arcball-style controller that orbits around a target point.
+///
+/// Dragging with the left button orbits, dragging with the right or middle
+/// button (or the left button while shift is held) pans the target in the
+/// view plane, and scrolling dollies towards or away from the target.
+#[derive(Debug)]
+pub struct OrbitController {
+    pub target: Point3<f32>,
+    pub distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    yaw: Rad<f32>,
+    pitch: Rad<f32>,
+    rotate_horizontal: f32,
+    rotate_vertical: f32,
+    pan_horizontal: f32,
+    pan_vertical: f32,
+    scroll: f32,
+    rotating: bool,
+    panning: bool,
+    shift: bool,
+    sensitivity: f32,
+    pan_speed: f32,
+    zoom_speed: f32,
+}
+
+impl OrbitController {
+    /// `sensitivity` is in radians per pixel of mouse movement, `pan_speed` is
+    /// the fraction of the orbit distance panned per pixel and `zoom_speed` is
+    /// the fraction of the distance dollied per scroll line.
+    pub fn new<P: Into<Point3<f32>>>(
+        target: P,
+        distance: f32,
+        sensitivity: f32,
+        pan_speed: f32,
+        zoom_speed: f32,
+    ) -> Self {
+        Self {
+            target: target.into(),
+            distance,
+            min_distance: 0.1,
+            max_distance: f32::MAX,
+            yaw: Rad(-std::f32::consts::FRAC_PI_2),
+            pitch: Rad(-0.35),
+            rotate_horizontal: 0.0,
+            rotate_vertical: 0.0,
+            pan_horizontal: 0.0,
+            pan_vertical: 0.0,
+            scroll: 0.0,
+            rotating: false,
+            panning: false,
+            shift: false,
+            sensitivity,
+            pan_speed,
+            zoom_speed,
+        }
+    }
+
+    /// Orbits around `target` from wherever `camera` currently is.
+    pub fn from_camera<P: Into<Point3<f32>>>(
+        camera: &Camera,
+        target: P,
+        sensitivity: f32,
+        pan_speed: f32,
+        zoom_speed: f32,
+    ) -> Self {
+        let target = target.into();
+        let mut controller = Self::new(
+            target,
+            camera.position.distance(target),
+            sensitivity,
+            pan_speed,
+            zoom_speed,
+        );
+
+        let mut view = Camera::new(camera.position, camera.yaw, camera.pitch);
+        view.look_at(target);
+        controller.yaw = view.yaw;
+        controller.pitch = view.pitch;
+        controller
+    }
+
+    /// Moves the orbit target, keeping the current angles and distance.
+    pub fn focus<P: Into<Point3<f32>>>(&mut self, target: P) {
+        self.target = target.into();
+    }
+}
+
+impl CameraController for OrbitController {
+    fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        match key {
+            VirtualKeyCode::LShift | VirtualKeyCode::RShift => {
+                self.shift = state == ElementState::Pressed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn process_mouse_button(&mut self, button: MouseButton, state: ElementState) -> bool {
+        let pressed = state == ElementState::Pressed;
+        match button {
+            MouseButton::Left if !pressed => {
+                self.rotating = false;
+                self.panning = false;
+            }
+            MouseButton::Left if self.shift => self.panning = true,
+            MouseButton::Left => self.rotating = true,
+            MouseButton::Right | MouseButton::Middle => self.panning = pressed,
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        if self.panning {
+            self.pan_horizontal += mouse_dx as f32;
+            self.pan_vertical += mouse_dy as f32;
+        } else if self.rotating {
+            self.rotate_horizontal += mouse_dx as f32;
+            self.rotate_vertical += mouse_dy as f32;
+        }
+    }
+
+    fn process_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.scroll += scroll_lines(delta);
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, _dt: Duration) {
+        // Mouse deltas are accumulated between frames, so they are applied
+        // as-is rather than scaled by the frame time.
+        self.yaw += Rad(self.rotate_horizontal * self.sensitivity);
+        self.pitch += Rad(-self.rotate_vertical * self.sensitivity);
+        self.pitch = Rad(self.pitch.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+
+        let forward = camera.forward();
+        let right = forward.cross(Vector3::unit_y()).normalize();
+        let up = right.cross(forward);
+
+        let pan = self.pan_speed * self.distance;
+        self.target += (-right * self.pan_horizontal + up * self.pan_vertical) * pan;
+        self.pan_horizontal = 0.0;
+        self.pan_vertical = 0.0;
+
+        self.distance *= (1.0 - self.zoom_speed).powf(self.scroll);
+        self.distance = self.distance.clamp(self.min_distance, self.max_distance);
+        self.scroll = 0.0;
+
+        camera.position = self.target - forward * self.distance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    fn drag(controller: &mut OrbitController, button: MouseButton, dx: f64, dy: f64) {
+        controller.process_mouse_button(button, ElementState::Pressed);
+        controller.process_mouse(dx, dy);
+        controller.process_mouse_button(button, ElementState::Released);
+    }
+
+    #[test]
+    fn camera_sits_on_the_orbit_looking_at_the_target() {
+        let mut controller = OrbitController::new((1.0, 2.0, 3.0), 5.0, 0.01, 0.001, 0.1);
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+        drag(&mut controller, MouseButton::Left, 40.0, -25.0);
+        controller.update_camera(&mut camera, FRAME);
+
+        let to_target = controller.target - camera.position;
+        assert!((to_target.magnitude() - 5.0).abs() < 1e-4);
+        assert!((to_target.normalize() - camera.forward()).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn pitch_stops_short_of_the_poles() {
+        let mut controller = OrbitController::new((0.0, 0.0, 0.0), 5.0, 0.01, 0.001, 0.1);
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+
+        drag(&mut controller, MouseButton::Left, 0.0, -1e4);
+        controller.update_camera(&mut camera, FRAME);
+        assert_eq!(camera.pitch, Rad(SAFE_FRAC_PI_2));
+
+        drag(&mut controller, MouseButton::Left, 0.0, 1e4);
+        controller.update_camera(&mut camera, FRAME);
+        assert_eq!(camera.pitch, Rad(-SAFE_FRAC_PI_2));
+    }
+
+    #[test]
+    fn yaw_follows_horizontal_drags() {
+        let mut controller = OrbitController::new((0.0, 0.0, 0.0), 5.0, 0.01, 0.001, 0.1);
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+        controller.update_camera(&mut camera, FRAME);
+        let yaw = camera.yaw;
+
+        drag(&mut controller, MouseButton::Left, 50.0, 0.0);
+        controller.update_camera(&mut camera, FRAME);
+        assert!((camera.yaw.0 - yaw.0 - 0.5).abs() < 1e-6);
+
+        // Deltas are consumed by the update.
+        controller.update_camera(&mut camera, FRAME);
+        assert!((camera.yaw.0 - yaw.0 - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn zoom_is_clamped_to_the_distance_limits() {
+        let mut controller = OrbitController::new((0.0, 0.0, 0.0), 5.0, 0.01, 0.001, 0.1);
+        controller.min_distance = 1.0;
+        controller.max_distance = 20.0;
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+
+        controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, 1.0));
+        controller.update_camera(&mut camera, FRAME);
+        assert!((controller.distance - 4.5).abs() < 1e-5);
+
+        controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, 100.0));
+        controller.update_camera(&mut camera, FRAME);
+        assert_eq!(controller.distance, 1.0);
+
+        controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, -100.0));
+        controller.update_camera(&mut camera, FRAME);
+        assert_eq!(controller.distance, 20.0);
+        assert!((camera.position.distance(controller.target) - 20.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn panning_moves_the_target_in_the_view_plane() {
+        let mut controller = OrbitController::new((0.0, 0.0, 0.0), 10.0, 0.01, 0.001, 0.1);
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+        controller.update_camera(&mut camera, FRAME);
+        let forward = camera.forward();
+
+        drag(&mut controller, MouseButton::Right, 100.0, 0.0);
+        controller.update_camera(&mut camera, FRAME);
+        let moved = controller.target - Point3::new(0.0, 0.0, 0.0);
+        assert!((moved.magnitude() - 1.0).abs() < 1e-4);
+        assert!(moved.dot(forward).abs() < 1e-4);
+
+        // Shift turns a left drag into a pan rather than an orbit.
+        controller.process_keyboard(VirtualKeyCode::LShift, ElementState::Pressed);
+        drag(&mut controller, MouseButton::Left, 0.0, 100.0);
+        controller.update_camera(&mut camera, FRAME);
+        assert_eq!(camera.forward(), forward);
+    }
+}
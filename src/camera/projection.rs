@@ -1,17 +1,40 @@
-use cgmath::{perspective, Matrix4, Rad};
+use cgmath::{ortho, perspective, Matrix4, Rad};
+use wgpu::CompareFunction;
 
 use super::camera::OPENGL_TO_WGPU_MATRIX;
 
-pub struct Projection {
-    aspect: f32,
-    fovy: Rad<f32>,
-    znear: f32,
-    zfar: f32,
+/// How the view volume is mapped to clip space. All variants keep their
+/// aspect ratio in sync through [`Projection::resize`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        aspect: f32,
+        fovy: Rad<f32>,
+        znear: f32,
+        zfar: f32,
+    },
+    /// `height` is the vertical extent of the view volume in world units; the
+    /// horizontal extent follows from the aspect ratio.
+    Orthographic {
+        aspect: f32,
+        height: f32,
+        znear: f32,
+        zfar: f32,
+    },
+    /// Perspective with the far plane at infinity and depth running from 1 at
+    /// the near plane to 0 at infinity, which spreads float precision evenly
+    /// over huge scenes. Pipelines must use [`Projection::depth_compare`] and
+    /// depth must be cleared to [`Projection::depth_clear_value`].
+    InfiniteReverseZ {
+        aspect: f32,
+        fovy: Rad<f32>,
+        znear: f32,
+    },
 }
 
 impl Projection {
     pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
-        Self {
+        Self::Perspective {
             aspect: width as f32 / height as f32,
             fovy: fovy.into(),
             znear,
@@ -19,11 +42,205 @@ impl Projection {
         }
     }
 
+    pub fn orthographic(width: u32, height: u32, extent: f32, znear: f32, zfar: f32) -> Self {
+        Self::Orthographic {
+            aspect: width as f32 / height as f32,
+            height: extent,
+            znear,
+            zfar,
+        }
+    }
+
+    pub fn infinite_reverse_z<F: Into<Rad<f32>>>(
+        width: u32,
+        height: u32,
+        fovy: F,
+        znear: f32,
+    ) -> Self {
+        Self::InfiniteReverseZ {
+            aspect: width as f32 / height as f32,
+            fovy: fovy.into(),
+            znear,
+        }
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
-        self.aspect = width as f32 / height as f32;
+        let new_aspect = width as f32 / height as f32;
+        match self {
+            Self::Perspective { aspect, .. }
+            | Self::Orthographic { aspect, .. }
+            | Self::InfiniteReverseZ { aspect, .. } => *aspect = new_aspect,
+        }
+    }
+
+    pub fn aspect(&self) -> f32 {
+        match *self {
+            Self::Perspective { aspect, .. }
+            | Self::Orthographic { aspect, .. }
+            | Self::InfiniteReverseZ { aspect, .. } => aspect,
+        }
+    }
+
+    pub fn znear(&self) -> f32 {
+        match *self {
+            Self::Perspective { znear, .. }
+            | Self::Orthographic { znear, .. }
+            | Self::InfiniteReverseZ { znear, .. } => znear,
+        }
+    }
+
+    /// Infinite for [`Projection::InfiniteReverseZ`].
+    pub fn zfar(&self) -> f32 {
+        match *self {
+            Self::Perspective { zfar, .. } | Self::Orthographic { zfar, .. } => zfar,
+            Self::InfiniteReverseZ { .. } => f32::INFINITY,
+        }
+    }
+
+    pub fn is_reverse_z(&self) -> bool {
+        matches!(self, Self::InfiniteReverseZ { .. })
+    }
+
+    /// The depth test that keeps the closest fragment for this projection.
+    pub fn depth_compare(&self) -> CompareFunction {
+        if self.is_reverse_z() {
+            CompareFunction::Greater
+        } else {
+            CompareFunction::Less
+        }
+    }
+
+    /// The depth value of "nothing drawn yet" for this projection.
+    pub fn depth_clear_value(&self) -> f32 {
+        if self.is_reverse_z() {
+            0.0
+        } else {
+            1.0
+        }
     }
 
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        match *self {
+            Self::Perspective {
+                aspect,
+                fovy,
+                znear,
+                zfar,
+            } => OPENGL_TO_WGPU_MATRIX * perspective(fovy, aspect, znear, zfar),
+            Self::Orthographic {
+                aspect,
+                height,
+                znear,
+                zfar,
+            } => {
+                let top = height / 2.0;
+                let right = top * aspect;
+                OPENGL_TO_WGPU_MATRIX * ortho(-right, right, -top, top, znear, zfar)
+            }
+            Self::InfiniteReverseZ {
+                aspect,
+                fovy,
+                znear,
+            } => {
+                // Already in wgpu's 0..1 depth range, so no OpenGL conversion.
+                let f = 1.0 / (fovy.0 / 2.0).tan();
+
+                #[rustfmt::skip]
+                let matrix = Matrix4::new(
+                    f / aspect, 0.0, 0.0,   0.0,
+                    0.0,        f,   0.0,   0.0,
+                    0.0,        0.0, 0.0,   -1.0,
+                    0.0,        0.0, znear, 0.0,
+                );
+                matrix
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Vector4};
+
+    use super::*;
+
+    /// Depth of a point `distance` in front of the camera, which looks down
+    /// -Z in view space.
+    fn depth(projection: &Projection, distance: f32) -> f32 {
+        let clip = projection.calc_matrix() * Vector4::new(0.0, 0.0, -distance, 1.0);
+        clip.z / clip.w
+    }
+
+    /// Whether `compare` lets a fragment at depth `new` over one at `old`.
+    fn passes(compare: CompareFunction, new: f32, old: f32) -> bool {
+        match compare {
+            CompareFunction::Less => new < old,
+            CompareFunction::Greater => new > old,
+            _ => unreachable!(),
+        }
+    }
+
+    fn projections() -> [Projection; 3] {
+        [
+            Projection::new(1600, 900, Deg(60.0), 0.5, 200.0),
+            Projection::orthographic(1600, 900, 20.0, 0.5, 200.0),
+            Projection::infinite_reverse_z(1600, 900, Deg(60.0), 0.5),
+        ]
+    }
+
+    #[test]
+    fn planes_map_to_the_depth_range() {
+        for projection in projections() {
+            let (near, far) = if projection.is_reverse_z() {
+                (1.0, 0.0)
+            } else {
+                (0.0, 1.0)
+            };
+            assert!(
+                (depth(&projection, 0.5) - near).abs() < 1e-5,
+                "{projection:?}"
+            );
+            let zfar = projection.zfar().min(1e7);
+            assert!(
+                (depth(&projection, zfar) - far).abs() < 1e-5,
+                "{projection:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn clear_value_is_the_far_plane() {
+        for projection in projections() {
+            let zfar = projection.zfar().min(1e7);
+            assert!(
+                (depth(&projection, zfar) - projection.depth_clear_value()).abs() < 1e-5,
+                "{projection:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn depth_compare_keeps_the_closest_fragment() {
+        for projection in projections() {
+            let compare = projection.depth_compare();
+            let clear = projection.depth_clear_value();
+            for (near, far) in [(0.5, 1.0), (1.0, 10.0), (10.0, 150.0)] {
+                let (near, far) = (depth(&projection, near), depth(&projection, far));
+                assert!(passes(compare, near, far), "{projection:?}");
+                assert!(!passes(compare, far, near), "{projection:?}");
+                assert!(passes(compare, far, clear), "{projection:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn reverse_z_keeps_precision_far_away() {
+        let projection = Projection::infinite_reverse_z(1600, 900, Deg(60.0), 0.5);
+        // Depth falls off as znear / distance, never reaching the clear
+        // value at any finite distance.
+        for distance in [1.0, 1e3, 1e6] {
+            let expected = 0.5 / distance;
+            assert!((depth(&projection, distance) - expected).abs() < expected * 1e-5);
+        }
     }
 }
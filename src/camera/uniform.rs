@@ -1,10 +1,9 @@
-use bytemuck::{Pod, Zeroable};
 use cgmath::SquareMatrix;
 
 use super::{camera::Camera, projection::Projection};
+use crate::uniform::ShaderType;
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[derive(Debug, Copy, Clone, ShaderType)]
 pub struct CameraUniform {
     pub view_position: [f32; 4],
     pub view_proj: [[f32; 4]; 4],
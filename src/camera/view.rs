@@ -0,0 +1,297 @@
+use wgpu::{BindGroupLayout, Color, Device, Queue, TextureFormat};
+
+use super::{camera::Camera, projection::Projection, uniform::CameraUniform};
+use crate::{
+    texture::{TargetId, Texture},
+    uniform::{ShaderType, UniformBuffer},
+};
+
+/// A rectangle in fractions of the render target, with the origin at the
+/// top-left corner. Fractions keep split-screen layouts valid across resizes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub const FULL: Self = Self::new(0.0, 0.0, 1.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Converts to whole pixels of a `target_width` x `target_height` target,
+    /// clamped to lie inside it and to cover at least one pixel.
+    pub fn to_pixels(&self, target_width: u32, target_height: u32) -> PixelRect {
+        let x0 = (self.x * target_width as f32).round() as u32;
+        let y0 = (self.y * target_height as f32).round() as u32;
+        let x1 = ((self.x + self.width) * target_width as f32).round() as u32;
+        let y1 = ((self.y + self.height) * target_height as f32).round() as u32;
+
+        let x = x0.min(target_width.saturating_sub(1));
+        let y = y0.min(target_height.saturating_sub(1));
+        PixelRect {
+            x,
+            y,
+            width: x1.min(target_width).saturating_sub(x).max(1),
+            height: y1.min(target_height).saturating_sub(y).max(1),
+        }
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelRect {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x as f64
+            && y >= self.y as f64
+            && x < (self.x + self.width) as f64
+            && y < (self.y + self.height) as f64
+    }
+}
+
+/// Where a [`CameraView`] renders to.
+pub enum ViewTarget {
+    /// The window's swapchain texture, shared by every surface view.
+    Surface,
+    /// An offscreen texture owned by the view, e.g. for a security monitor
+    /// whose `color` is then bound as a material texture.
+    Texture {
+        color: Texture,
+        depth: Texture,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+    },
+}
+
+impl ViewTarget {
+    pub fn texture(
+        device: &Device,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        label: &str,
+    ) -> Self {
+        Self::Texture {
+            color: Texture::create_render_target(device, width, height, format, label),
+            depth: Texture::create_depth_texture_sized(device, width, height, label),
+            width,
+            height,
+            format,
+        }
+    }
+
+    pub fn is_surface(&self) -> bool {
+        matches!(self, Self::Surface)
+    }
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+pub(crate) struct ClearUniform {
+    pub color: [f32; 4],
+    pub depth: f32,
+}
+
+/// One camera rendering the scene into a rectangle of a target.
+///
+/// Views are drawn in ascending `priority`, so a minimap with a higher
+/// priority than the main view ends up on top of it. A view without a clear
+/// color only resets depth and draws over whatever is already there.
+/// Create views with [`State::create_view`](crate::state::State::create_view).
+pub struct CameraView {
+    pub camera: Camera,
+    pub projection: Projection,
+    pub viewport: Viewport,
+    /// Limits drawing further than the viewport; defaults to the viewport.
+    pub scissor: Option<Viewport>,
+    target: ViewTarget,
+    pub clear_color: Option<Color>,
+    pub priority: i32,
+    pub(crate) camera_uniform: UniformBuffer<CameraUniform>,
+    pub(crate) clear_uniform: UniformBuffer<ClearUniform>,
+    /// Changes with `target`, see [`CameraView::set_target`].
+    pub(crate) target_id: TargetId,
+}
+
+impl CameraView {
+    pub(crate) const DEFAULT_CLEAR_COLOR: Color = Color {
+        r: 0.1,
+        g: 0.2,
+        b: 0.3,
+        a: 1.0,
+    };
+
+    pub(crate) fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        clear_layout: &BindGroupLayout,
+        camera: Camera,
+        projection: Projection,
+    ) -> Self {
+        Self {
+            camera,
+            projection,
+            viewport: Viewport::FULL,
+            scissor: None,
+            target: ViewTarget::Surface,
+            clear_color: Some(Self::DEFAULT_CLEAR_COLOR),
+            priority: 0,
+            camera_uniform: UniformBuffer::new(
+                device,
+                camera_layout,
+                CameraUniform::new(),
+                "camera_bind_group",
+            ),
+            clear_uniform: UniformBuffer::new(
+                device,
+                clear_layout,
+                ClearUniform {
+                    color: [0.0; 4],
+                    depth: 1.0,
+                },
+                "clear_bind_group",
+            ),
+            target_id: TargetId::next(),
+        }
+    }
+
+    pub fn with_viewport(mut self, viewport: Viewport) -> Self {
+        self.viewport = viewport;
+        self
+    }
+
+    pub fn with_scissor(mut self, scissor: Viewport) -> Self {
+        self.scissor = Some(scissor);
+        self
+    }
+
+    pub fn with_target(mut self, target: ViewTarget) -> Self {
+        self.set_target(target);
+        self
+    }
+
+    pub fn with_clear_color(mut self, clear_color: Option<Color>) -> Self {
+        self.clear_color = clear_color;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn target(&self) -> &ViewTarget {
+        &self.target
+    }
+
+    /// Renders into `target` from now on. Passes reading the previous
+    /// target's depth rebind to the new one on their next `prepare`.
+    pub fn set_target(&mut self, target: ViewTarget) {
+        self.target = target;
+        self.target_id = TargetId::next();
+    }
+
+    /// The size of the texture this view renders into, given the current
+    /// surface size.
+    pub fn target_size(&self, surface_width: u32, surface_height: u32) -> (u32, u32) {
+        match self.target {
+            ViewTarget::Surface => (surface_width, surface_height),
+            ViewTarget::Texture { width, height, .. } => (width, height),
+        }
+    }
+
+    pub fn pixel_viewport(&self, surface_width: u32, surface_height: u32) -> PixelRect {
+        let (width, height) = self.target_size(surface_width, surface_height);
+        self.viewport.to_pixels(width, height)
+    }
+
+    pub fn pixel_scissor(&self, surface_width: u32, surface_height: u32) -> PixelRect {
+        let (width, height) = self.target_size(surface_width, surface_height);
+        self.scissor
+            .unwrap_or(self.viewport)
+            .to_pixels(width, height)
+    }
+
+    /// Keeps the projection's aspect ratio in sync with the viewport.
+    pub fn fit_projection(&mut self, surface_width: u32, surface_height: u32) {
+        let rect = self.pixel_viewport(surface_width, surface_height);
+        self.projection.resize(rect.width, rect.height);
+    }
+
+    pub fn camera_uniform(&self) -> &UniformBuffer<CameraUniform> {
+        &self.camera_uniform
+    }
+
+    pub(crate) fn update(&mut self, queue: &Queue) {
+        self.camera_uniform
+            .get_mut()
+            .update_view_proj(&self.camera, &self.projection);
+        self.camera_uniform.update(queue);
+
+        let color = self.clear_color.unwrap_or(Color::TRANSPARENT);
+        self.clear_uniform.set(ClearUniform {
+            color: [
+                color.r as f32,
+                color.g as f32,
+                color.b as f32,
+                color.a as f32,
+            ],
+            depth: self.projection.depth_clear_value(),
+        });
+        self.clear_uniform.update(queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflection::ShaderReflection;
+
+    #[test]
+    fn split_screen_viewports_tile_the_target() {
+        let left = Viewport::new(0.0, 0.0, 0.5, 1.0).to_pixels(1281, 720);
+        let right = Viewport::new(0.5, 0.0, 0.5, 1.0).to_pixels(1281, 720);
+
+        assert_eq!(left.x + left.width, right.x);
+        assert_eq!(right.x + right.width, 1281);
+        assert_eq!(left.height, 720);
+    }
+
+    #[test]
+    fn clear_uniform_matches_shader() {
+        let reflection =
+            ShaderReflection::from_wgsl(include_str!("../../shaders/clear.wgsl")).unwrap();
+        let entries = reflection.bind_group_layout_entries().unwrap();
+
+        match entries[0][0].ty {
+            wgpu::BindingType::Buffer {
+                min_binding_size, ..
+            } => assert_eq!(
+                min_binding_size.unwrap().get() as usize,
+                ClearUniform::STD140.size
+            ),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! Clustered forward shading of [`PointLight`]s.
+//!
+//! Each view's viewport is split into screen tiles and its depth into
+//! slices growing exponentially from the near plane, giving a grid of
+//! clusters. A compute pass lists the lights whose spheres reach into each
+//! cluster, and `shader.wgsl` only loops over the list of the cluster its
+//! fragment is in. The lists live in storage buffers next to the
+//! [`LightUniform`](crate::light::LightUniform) in the scene's light bind
+//! group, which is therefore one per view. It also holds the view's
+//! [`Shadows`] and the [`PointShadows`], and is rebuilt when their
+//! targets change.
+
+use anyhow::*;
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, BufferUsages, CommandEncoder, ComputePipeline, Device,
+    Queue,
+};
+
+use crate::{
+    camera::{projection::Projection, view::CameraView},
+    light::{PointLight, PointLightRaw},
+    point_shadows::PointShadows,
+    reflection::{ReflectedLayout, ShaderReflection},
+    shadows::Shadows,
+    texture::TargetId,
+    uniform::{self, MemoryLayout, ShaderType},
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct ClusterUniform {
+    view: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    viewport: [f32; 4],
+    grid: [u32; 3],
+    light_count: u32,
+    near: f32,
+    far: f32,
+    max_per_cluster: u32,
+}
+
+/// The cluster grid of one view.
+struct ViewClusters {
+    params: Buffer,
+    counts: Buffer,
+    indices: Buffer,
+    assign_bind_group: BindGroup,
+}
+
+pub(crate) struct LightClusters {
+    layout: ReflectedLayout,
+    pipeline: ComputePipeline,
+    lights: Buffer,
+    /// Lights `lights` has room for.
+    capacity: usize,
+    /// Indexed like `State::views`.
+    views: Vec<ViewClusters>,
+    /// The scene's light bind group of each view.
+    bind_groups: Vec<BindGroup>,
+    /// The `Shadows::target_id` and `PointShadows::target_id` that
+    /// `bind_groups` were built with.
+    shadow_target_ids: Option<(TargetId, TargetId)>,
+}
+
+impl LightClusters {
+    /// Screen tiles across and down each view.
+    pub const TILES: (u32, u32) = (16, 9);
+    pub const SLICES: u32 = 24;
+    /// Lights past this many in one cluster are left out of it.
+    pub const MAX_LIGHTS_PER_CLUSTER: u32 = 64;
+    /// Where the last slice ends for infinite or very deep projections.
+    /// Fragments beyond it use the last slice.
+    pub const MAX_DEPTH: f32 = 1000.0;
+
+    const CLUSTER_COUNT: u32 = Self::TILES.0 * Self::TILES.1 * Self::SLICES;
+
+    pub(crate) fn new(device: &Device) -> Result<Self> {
+        let layout = ShaderReflection::from_wgsl(include_str!("../shaders/clusters.wgsl"))?
+            .create_layout(device, "Light Cluster Layout")?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Cluster Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/clusters.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Cluster Pipeline"),
+            layout: Some(&layout.pipeline_layout),
+            module: &module,
+            entry_point: "cs_assign",
+        });
+
+        let capacity = 1;
+        Ok(Self {
+            lights: light_buffer(device, capacity),
+            layout,
+            pipeline,
+            capacity,
+            views: Vec::new(),
+            bind_groups: Vec::new(),
+            shadow_target_ids: None,
+        })
+    }
+
+    /// Uploads `point_lights` and the camera of every one of `views`,
+    /// creating their cluster lists as needed, and binds them with `light`,
+    /// `shadows` and `point_shadows` for `scene_layout` unless they are
+    /// bound already. `light` and `scene_layout` must not change.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        scene_layout: &BindGroupLayout,
+        light: &Buffer,
+        shadows: &Shadows,
+        point_shadows: &PointShadows,
+        point_lights: &[PointLight],
+        views: &[CameraView],
+        surface_size: (u32, u32),
+    ) {
+        if point_lights.len() > self.capacity {
+            self.capacity = point_lights.len().next_power_of_two();
+            self.lights = light_buffer(device, self.capacity);
+            // Every bind group refers to the old buffer.
+            self.views.clear();
+            self.bind_groups.clear();
+        }
+        if !point_lights.is_empty() {
+            let stride = PointLightRaw::STD430.size;
+            let mut bytes = vec![0; stride * point_lights.len()];
+            for (index, (point_light, out)) in point_lights
+                .iter()
+                .zip(bytes.chunks_exact_mut(stride))
+                .enumerate()
+            {
+                point_light
+                    .to_raw(point_shadows.layer(index))
+                    .write(MemoryLayout::Std430, out);
+            }
+            queue.write_buffer(&self.lights, 0, &bytes);
+        }
+
+        self.views.truncate(views.len());
+        while self.views.len() < views.len() {
+            let clusters = self.create_view_clusters(device);
+            self.views.push(clusters);
+        }
+        let shadow_target_ids = (shadows.target_id(), point_shadows.target_id());
+        if self.bind_groups.len() != self.views.len()
+            || self.shadow_target_ids != Some(shadow_target_ids)
+        {
+            self.bind_groups = self
+                .views
+                .iter()
+                .enumerate()
+                .map(|(index, clusters)| {
+                    let buffers = [
+                        light,
+                        &clusters.params,
+                        &self.lights,
+                        &clusters.counts,
+                        &clusters.indices,
+                    ];
+                    let mut entries = buffers
+                        .iter()
+                        .zip(0..)
+                        .map(|(buffer, binding)| wgpu::BindGroupEntry {
+                            binding,
+                            resource: buffer.as_entire_binding(),
+                        })
+                        .collect::<Vec<_>>();
+                    entries.extend(shadows.bind_group_entries(index));
+                    entries.extend(point_shadows.bind_group_entries());
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("light_bind_group"),
+                        layout: scene_layout,
+                        entries: &entries,
+                    })
+                })
+                .collect();
+            self.shadow_target_ids = Some(shadow_target_ids);
+        }
+
+        for (view, clusters) in views.iter().zip(&self.views) {
+            let viewport = view.pixel_viewport(surface_size.0, surface_size.1);
+            let (near, far) = slice_range(&view.projection);
+            let params = ClusterUniform {
+                view: view.camera.calc_matrix().into(),
+                inv_proj: view
+                    .projection
+                    .calc_matrix()
+                    .invert()
+                    .unwrap_or_else(Matrix4::identity)
+                    .into(),
+                viewport: [
+                    viewport.x as f32,
+                    viewport.y as f32,
+                    viewport.width as f32,
+                    viewport.height as f32,
+                ],
+                grid: [Self::TILES.0, Self::TILES.1, Self::SLICES],
+                light_count: point_lights.len() as u32,
+                near,
+                far,
+                max_per_cluster: Self::MAX_LIGHTS_PER_CLUSTER,
+            };
+            queue.write_buffer(
+                &clusters.params,
+                0,
+                &uniform::to_bytes(&params, MemoryLayout::Std140),
+            );
+        }
+    }
+
+    fn create_view_clusters(&self, device: &Device) -> ViewClusters {
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("light_clusters"),
+            size: ClusterUniform::STD140.size as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let storage = |label, size: u32| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as u64 * 4,
+                usage: BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let counts = storage("light_cluster_counts", Self::CLUSTER_COUNT);
+        let indices = storage(
+            "light_cluster_indices",
+            Self::CLUSTER_COUNT * Self::MAX_LIGHTS_PER_CLUSTER,
+        );
+
+        let buffers = [&params, &self.lights, &counts, &indices];
+        let entries = buffers
+            .iter()
+            .zip(0..)
+            .map(|(buffer, binding)| wgpu::BindGroupEntry {
+                binding,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>();
+        let assign_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("light_cluster_assign_bind_group"),
+            layout: &self.layout.bind_group_layouts[0],
+            entries: &entries,
+        });
+
+        ViewClusters {
+            params,
+            counts,
+            indices,
+            assign_bind_group,
+        }
+    }
+
+    /// Records the light assignment of every view [`prepare`](Self::prepare)
+    /// has set up.
+    pub(crate) fn assign(&self, encoder: &mut CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light Cluster Pass"),
+        });
+        pass.set_pipeline(&self.pipeline);
+        for clusters in &self.views {
+            pass.set_bind_group(0, &clusters.assign_bind_group, &[]);
+            pass.dispatch_workgroups(Self::CLUSTER_COUNT.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+    }
+
+    /// The light bind group of view `index`, for group 2 of the scene.
+    pub(crate) fn bind_group(&self, index: usize) -> &BindGroup {
+        &self.bind_groups[index]
+    }
+}
+
+/// The view depths the slices span, with infinite projections cut off at
+/// [`LightClusters::MAX_DEPTH`].
+fn slice_range(projection: &Projection) -> (f32, f32) {
+    let near = projection.znear().max(0.01);
+    let far = projection
+        .zfar()
+        .min(LightClusters::MAX_DEPTH)
+        .max(near * 2.0);
+    (near, far)
+}
+
+/// Storage for `capacity` lights, which must not be zero as storage
+/// buffers cannot be empty.
+fn light_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("point_lights"),
+        size: (capacity * PointLightRaw::STD430.size) as u64,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Deg;
+
+    use super::*;
+
+    #[test]
+    fn slices_span_the_projection_up_to_the_max_depth() {
+        let perspective = Projection::new(1600, 900, Deg(60.0), 0.1, 100.0);
+        assert_eq!(slice_range(&perspective), (0.1, 100.0));
+
+        let orthographic = Projection::orthographic(1600, 900, 20.0, 1.0, 5000.0);
+        assert_eq!(slice_range(&orthographic), (1.0, LightClusters::MAX_DEPTH));
+
+        let infinite = Projection::infinite_reverse_z(1600, 900, Deg(60.0), 0.5);
+        assert_eq!(slice_range(&infinite), (0.5, LightClusters::MAX_DEPTH));
+    }
+
+    #[test]
+    fn slices_never_collapse() {
+        // The shader divides by the log of far over near.
+        let touching = Projection::new(1600, 900, Deg(60.0), 0.0, 0.0);
+        assert_eq!(slice_range(&touching), (0.01, 0.02));
+
+        let thin = Projection::new(1600, 900, Deg(60.0), 5.0, 6.0);
+        assert_eq!(slice_range(&thin), (5.0, 10.0));
+
+        let beyond = Projection::new(1600, 900, Deg(60.0), 2000.0, 3000.0);
+        assert_eq!(slice_range(&beyond), (2000.0, 4000.0));
+    }
+
+    fn buffer_size(entry: &wgpu::BindGroupLayoutEntry) -> usize {
+        match entry.ty {
+            wgpu::BindingType::Buffer {
+                min_binding_size, ..
+            } => min_binding_size.unwrap().get() as usize,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn scene_reads_what_the_clusters_write() {
+        let scene = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER)
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        let assign = ShaderReflection::from_wgsl(include_str!("../shaders/clusters.wgsl"))
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        assert_eq!(scene[2].len(), 10);
+        assert_eq!(assign.len(), 1);
+        for (entry, scene) in assign[0].iter().zip(&scene[2][1..5]) {
+            assert_eq!(entry.binding + 1, scene.binding);
+            assert_eq!(buffer_size(entry), buffer_size(scene));
+            assert!(scene.visibility.contains(wgpu::ShaderStages::FRAGMENT));
+        }
+        assert_eq!(buffer_size(&assign[0][0]), ClusterUniform::STD140.size);
+        // A runtime-sized array's minimum is one element.
+        assert_eq!(buffer_size(&assign[0][1]), PointLightRaw::STD430.size);
+    }
+
+    #[test]
+    fn cluster_lists_fit_the_default_limits() {
+        let limits = wgpu::Limits::default();
+        let indices = LightClusters::CLUSTER_COUNT * LightClusters::MAX_LIGHTS_PER_CLUSTER * 4;
+        assert!(indices <= limits.max_storage_buffer_binding_size);
+        assert!(
+            LightClusters::CLUSTER_COUNT.div_ceil(WORKGROUP_SIZE)
+                <= limits.max_compute_workgroups_per_dimension
+        );
+    }
+}
@@ -0,0 +1,684 @@
+use std::{collections::HashMap, f32::consts::TAU, ops::Range, time::Duration};
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3, Vector4,
+};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, BufferDescriptor, BufferUsages,
+    CompareFunction, Device, PipelineLayout, Queue, RenderPass, RenderPipeline, TextureFormat,
+};
+
+use crate::{
+    camera::{camera::Camera, projection::Projection},
+    reflection::ShaderReflection,
+    texture::Texture,
+    vertex::Vertex,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Vertex)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+/// How a debug shape is drawn. Build one from a color and adjust it:
+///
+/// ```ignore
+/// state.debug.aabb(min, max, DebugStyle::new(RED).for_duration(Duration::from_secs(2)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugStyle {
+    pub color: [f32; 4],
+    /// How long the shape stays visible. `None` draws it for a single frame.
+    pub duration: Option<Duration>,
+    /// Whether scene geometry hides the shape.
+    pub depth_test: bool,
+}
+
+impl DebugStyle {
+    pub const fn new(color: [f32; 4]) -> Self {
+        Self {
+            color,
+            duration: None,
+            depth_test: true,
+        }
+    }
+
+    pub const fn for_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Draws the shape on top of the scene.
+    pub const fn on_top(mut self) -> Self {
+        self.depth_test = false;
+        self
+    }
+}
+
+impl From<[f32; 4]> for DebugStyle {
+    fn from(color: [f32; 4]) -> Self {
+        Self::new(color)
+    }
+}
+
+impl From<[f32; 3]> for DebugStyle {
+    fn from([r, g, b]: [f32; 3]) -> Self {
+        Self::new([r, g, b, 1.0])
+    }
+}
+
+struct Shape {
+    /// Start of the shape's vertices in `Shapes::vertices`.
+    start: usize,
+    end: usize,
+    depth_test: bool,
+    remaining: Option<Duration>,
+    drawn: bool,
+}
+
+struct DebugPipelines {
+    depth_tested: RenderPipeline,
+    on_top: RenderPipeline,
+}
+
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// The queued shapes and their line vertices.
+#[derive(Default)]
+struct Shapes {
+    vertices: Vec<DebugVertex>,
+    shapes: Vec<Shape>,
+}
+
+impl Shapes {
+    fn line(&mut self, a: Point3<f32>, b: Point3<f32>, style: DebugStyle) {
+        self.push(style, |lines| lines.push((a, b, style.color)));
+    }
+
+    fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, style: DebugStyle) {
+        let corner = |i: usize| {
+            Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        self.box_edges(std::array::from_fn(corner), style);
+    }
+
+    fn sphere(&mut self, center: Point3<f32>, radius: f32, style: DebugStyle) {
+        self.push(style, |lines| {
+            for (u, v) in [
+                (Vector3::unit_x(), Vector3::unit_y()),
+                (Vector3::unit_x(), Vector3::unit_z()),
+                (Vector3::unit_y(), Vector3::unit_z()),
+            ] {
+                circle(lines, center, u * radius, v * radius, style.color);
+            }
+        });
+    }
+
+    fn frustum(&mut self, camera: &Camera, projection: &Projection, style: DebugStyle) {
+        if let Some(corners) = frustum_corners(camera, projection) {
+            self.box_edges(corners, style);
+        }
+    }
+
+    fn axes(&mut self, transform: Matrix4<f32>, length: f32, style: DebugStyle) {
+        let origin = transform.transform_point(Point3::origin());
+        self.push(style, |lines| {
+            for (axis, color) in [
+                (Vector3::unit_x(), [1.0, 0.0, 0.0, 1.0]),
+                (Vector3::unit_y(), [0.0, 1.0, 0.0, 1.0]),
+                (Vector3::unit_z(), [0.0, 0.0, 1.0, 1.0]),
+            ] {
+                let end = transform.transform_point(Point3::from_vec(axis * length));
+                lines.push((origin, end, color));
+            }
+        });
+    }
+
+    fn grid(&mut self, center: Point3<f32>, cell_size: f32, cells: u32, style: DebugStyle) {
+        let half = cell_size * cells as f32 / 2.0;
+        self.push(style, |lines| {
+            for i in 0..=cells {
+                let offset = i as f32 * cell_size - half;
+                lines.push((
+                    center + Vector3::new(offset, 0.0, -half),
+                    center + Vector3::new(offset, 0.0, half),
+                    style.color,
+                ));
+                lines.push((
+                    center + Vector3::new(-half, 0.0, offset),
+                    center + Vector3::new(half, 0.0, offset),
+                    style.color,
+                ));
+            }
+        });
+    }
+
+    fn arrow(&mut self, from: Point3<f32>, to: Point3<f32>, style: DebugStyle) {
+        let direction = to - from;
+        let length = direction.magnitude();
+        if length == 0.0 {
+            return;
+        }
+
+        let direction = direction / length;
+        let helper = if direction.y.abs() < 0.99 {
+            Vector3::unit_y()
+        } else {
+            Vector3::unit_x()
+        };
+        let side = direction.cross(helper).normalize();
+        let up = side.cross(direction);
+        let head = length * 0.2;
+
+        self.push(style, |lines| {
+            lines.push((from, to, style.color));
+            for prong in [side, -side, up, -up] {
+                let back = to - direction * head + prong * head * 0.5;
+                lines.push((to, back, style.color));
+            }
+        });
+    }
+
+    fn clear(&mut self) {
+        self.vertices.clear();
+        self.shapes.clear();
+    }
+
+    fn update(&mut self, dt: Duration) {
+        for shape in &mut self.shapes {
+            if let Some(remaining) = &mut shape.remaining {
+                *remaining = remaining.saturating_sub(dt);
+            }
+        }
+
+        let expired = |shape: &Shape| {
+            shape.drawn
+                && shape
+                    .remaining
+                    .map_or(true, |remaining| remaining.is_zero())
+        };
+        if !self.shapes.iter().any(expired) {
+            return;
+        }
+
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        self.shapes.retain_mut(|shape| {
+            if expired(shape) {
+                return false;
+            }
+            let start = vertices.len();
+            vertices.extend_from_slice(&self.vertices[shape.start..shape.end]);
+            shape.start = start;
+            shape.end = vertices.len();
+            true
+        });
+        self.vertices = vertices;
+    }
+
+    /// Queues the lines `build` adds as one shape.
+    fn push(
+        &mut self,
+        style: DebugStyle,
+        build: impl FnOnce(&mut Vec<(Point3<f32>, Point3<f32>, [f32; 4])>),
+    ) {
+        let mut lines = Vec::new();
+        build(&mut lines);
+
+        let start = self.vertices.len();
+        for (a, b, color) in lines {
+            for position in [a, b] {
+                self.vertices.push(DebugVertex {
+                    position: position.into(),
+                    color,
+                });
+            }
+        }
+
+        self.shapes.push(Shape {
+            start,
+            end: self.vertices.len(),
+            depth_test: style.depth_test,
+            remaining: style.duration,
+            drawn: false,
+        });
+    }
+
+    /// The twelve edges of a box whose corner `i` has bit 0, 1 and 2 set for
+    /// the maximum x, y and z side respectively.
+    fn box_edges(&mut self, corners: [Point3<f32>; 8], style: DebugStyle) {
+        self.push(style, |lines| {
+            for i in 0..8 {
+                for bit in [1, 2, 4] {
+                    if i & bit == 0 {
+                        lines.push((corners[i], corners[i | bit], style.color));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Marks every shape drawn and returns their vertices, the depth-tested
+    /// ones first, with the range of each kind.
+    fn sort(&mut self) -> (Vec<DebugVertex>, Range<u32>, Range<u32>) {
+        let mut sorted = Vec::with_capacity(self.vertices.len());
+        let mut ranges = [0..0, 0..0];
+        for (depth_test, range) in [true, false].into_iter().zip(&mut ranges) {
+            let start = sorted.len() as u32;
+            for shape in self
+                .shapes
+                .iter_mut()
+                .filter(|s| s.depth_test == depth_test)
+            {
+                sorted.extend_from_slice(&self.vertices[shape.start..shape.end]);
+                shape.drawn = true;
+            }
+            *range = start..sorted.len() as u32;
+        }
+        let [depth_tested, on_top] = ranges;
+        (sorted, depth_tested, on_top)
+    }
+}
+
+/// Immediate-mode line drawing for debugging.
+///
+/// Shapes are queued from anywhere during a frame and drawn by every view
+/// with a `LineList` pipeline. Shapes without a duration disappear after
+/// they have been drawn once; the others stay until their time runs out.
+pub struct DebugDraw {
+    shapes: Shapes,
+    /// Vertex ranges in `buffer` for depth-tested and on-top lines, as
+    /// uploaded by the last [`prepare`](Self::prepare).
+    depth_tested: Range<u32>,
+    on_top: Range<u32>,
+    buffer: Buffer,
+    capacity: usize,
+    layout: PipelineLayout,
+    pipelines: HashMap<(TextureFormat, CompareFunction), DebugPipelines>,
+}
+
+impl DebugDraw {
+    pub fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        camera_entries: &[BindGroupLayoutEntry],
+    ) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(include_str!("../shaders/debug.wgsl"))?;
+        reflection
+            .validate_bind_group_layouts(&[camera_entries])
+            .context("debug.wgsl")?;
+        reflection.validate_vertex_layouts("vs_main", &[DebugVertex::desc()])?;
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+
+        let capacity = 1024;
+        Ok(Self {
+            shapes: Shapes::default(),
+            depth_tested: 0..0,
+            on_top: 0..0,
+            buffer: create_vertex_buffer(device, capacity),
+            capacity,
+            layout,
+            pipelines: HashMap::new(),
+        })
+    }
+
+    pub fn line<S: Into<DebugStyle>>(&mut self, a: Point3<f32>, b: Point3<f32>, style: S) {
+        self.shapes.line(a, b, style.into());
+    }
+
+    /// An axis-aligned box given by its minimum and maximum corners.
+    pub fn aabb<S: Into<DebugStyle>>(&mut self, min: Point3<f32>, max: Point3<f32>, style: S) {
+        self.shapes.aabb(min, max, style.into());
+    }
+
+    /// Three great circles approximating a sphere.
+    pub fn sphere<S: Into<DebugStyle>>(&mut self, center: Point3<f32>, radius: f32, style: S) {
+        self.shapes.sphere(center, radius, style.into());
+    }
+
+    /// The view volume of `camera` seen through `projection`. Infinite
+    /// projections are cut off at a thousand times the near plane distance.
+    pub fn frustum<S: Into<DebugStyle>>(
+        &mut self,
+        camera: &Camera,
+        projection: &Projection,
+        style: S,
+    ) {
+        self.shapes.frustum(camera, projection, style.into());
+    }
+
+    /// Red, green and blue lines along the X, Y and Z axes of `transform`.
+    /// Only the duration and depth test of `style` are used.
+    pub fn axes<S: Into<DebugStyle>>(&mut self, transform: Matrix4<f32>, length: f32, style: S) {
+        self.shapes.axes(transform, length, style.into());
+    }
+
+    /// A square grid on the XZ plane with `cells` cells of `cell_size` along
+    /// each side, centered on `center`.
+    pub fn grid<S: Into<DebugStyle>>(
+        &mut self,
+        center: Point3<f32>,
+        cell_size: f32,
+        cells: u32,
+        style: S,
+    ) {
+        self.shapes.grid(center, cell_size, cells, style.into());
+    }
+
+    /// A line from `from` to `to` with a four-pronged head at `to`.
+    pub fn arrow<S: Into<DebugStyle>>(&mut self, from: Point3<f32>, to: Point3<f32>, style: S) {
+        self.shapes.arrow(from, to, style.into());
+    }
+
+    /// Forgets every queued shape, including those with time left.
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+    }
+
+    /// Advances durations by `dt` and drops shapes that have been drawn and
+    /// whose time is up.
+    pub fn update(&mut self, dt: Duration) {
+        self.shapes.update(dt);
+    }
+
+    /// Uploads the queued lines, sorted into depth-tested and on-top ranges,
+    /// and makes sure pipelines exist for every target in `keys`.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        keys: impl IntoIterator<Item = (TextureFormat, CompareFunction)>,
+    ) {
+        for key in keys {
+            if !self.pipelines.contains_key(&key) {
+                let pipelines = DebugPipelines {
+                    depth_tested: create_debug_pipeline(device, &self.layout, key.0, key.1, true),
+                    on_top: create_debug_pipeline(device, &self.layout, key.0, key.1, false),
+                };
+                self.pipelines.insert(key, pipelines);
+            }
+        }
+
+        let (sorted, depth_tested, on_top) = self.shapes.sort();
+        self.depth_tested = depth_tested;
+        self.on_top = on_top;
+
+        if sorted.len() > self.capacity {
+            self.capacity = sorted.len().next_power_of_two();
+            self.buffer = create_vertex_buffer(device, self.capacity);
+        }
+        if !sorted.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&sorted));
+        }
+    }
+
+    /// Draws the lines uploaded by the last [`prepare`](Self::prepare) into
+    /// a pass whose target matches `key`.
+    pub(crate) fn draw<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        key: (TextureFormat, CompareFunction),
+        camera_bind_group: &'a BindGroup,
+    ) {
+        let Some(pipelines) = self.pipelines.get(&key) else {
+            return;
+        };
+
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.buffer.slice(..));
+        for (pipeline, range) in [
+            (&pipelines.depth_tested, &self.depth_tested),
+            (&pipelines.on_top, &self.on_top),
+        ] {
+            if !range.is_empty() {
+                pass.set_pipeline(pipeline);
+                pass.draw(range.clone(), 0..1);
+            }
+        }
+    }
+}
+
+/// The corners of the view volume of `camera` seen through `projection`,
+/// ordered as in `Shapes::box_edges` with the near plane first. Infinite
+/// projections are cut off at a thousand times the near plane distance.
+fn frustum_corners(camera: &Camera, projection: &Projection) -> Option<[Point3<f32>; 8]> {
+    let inverse = (projection.calc_matrix() * camera.calc_matrix()).invert()?;
+    let near = 1.0 - projection.depth_clear_value();
+    let far = if projection.is_reverse_z() {
+        1e-3
+    } else {
+        projection.depth_clear_value()
+    };
+    Some(std::array::from_fn(|i| {
+        let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+        let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+        let z = if i & 4 == 0 { near } else { far };
+        Point3::from_homogeneous(inverse * Vector4::new(x, y, z, 1.0))
+    }))
+}
+
+fn circle(
+    lines: &mut Vec<(Point3<f32>, Point3<f32>, [f32; 4])>,
+    center: Point3<f32>,
+    u: Vector3<f32>,
+    v: Vector3<f32>,
+    color: [f32; 4],
+) {
+    let point = |i: usize| {
+        let (sin, cos) = (i as f32 / CIRCLE_SEGMENTS as f32 * TAU).sin_cos();
+        center + u * cos + v * sin
+    };
+    for i in 0..CIRCLE_SEGMENTS {
+        lines.push((point(i), point(i + 1), color));
+    }
+}
+
+fn create_vertex_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Debug Vertex Buffer"),
+        size: (capacity * std::mem::size_of::<DebugVertex>()) as u64,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_debug_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    color_format: TextureFormat,
+    depth_compare: CompareFunction,
+    depth_test: bool,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Debug Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/debug.wgsl").into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Debug Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[DebugVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: if depth_test {
+                depth_compare
+            } else {
+                CompareFunction::Always
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Deg;
+
+    use super::*;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+
+    fn vertex_count(add: impl FnOnce(&mut Shapes)) -> usize {
+        let mut shapes = Shapes::default();
+        add(&mut shapes);
+        shapes.vertices.len()
+    }
+
+    #[test]
+    fn shapes_have_two_vertices_per_line() {
+        let style = DebugStyle::new(RED);
+        let (a, b) = (Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 3.0));
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(0.0));
+        let projection = Projection::new(800, 600, Deg(60.0), 0.1, 100.0);
+
+        assert_eq!(vertex_count(|s| s.line(a, b, style)), 2);
+        assert_eq!(vertex_count(|s| s.aabb(a, b, style)), 12 * 2);
+        assert_eq!(
+            vertex_count(|s| s.sphere(a, 1.0, style)),
+            3 * CIRCLE_SEGMENTS * 2
+        );
+        assert_eq!(
+            vertex_count(|s| s.frustum(&camera, &projection, style)),
+            12 * 2
+        );
+        assert_eq!(
+            vertex_count(|s| s.axes(Matrix4::identity(), 1.0, style)),
+            3 * 2
+        );
+        assert_eq!(vertex_count(|s| s.grid(a, 1.0, 4, style)), 5 * 2 * 2);
+        assert_eq!(vertex_count(|s| s.arrow(a, b, style)), 5 * 2);
+        assert_eq!(vertex_count(|s| s.arrow(a, a, style)), 0);
+    }
+
+    #[test]
+    fn untimed_shapes_last_until_drawn() {
+        let mut shapes = Shapes::default();
+        shapes.line(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            RED.into(),
+        );
+
+        // Not drawn yet, so a frame without a prepare keeps it.
+        shapes.update(Duration::from_millis(16));
+        assert_eq!(shapes.shapes.len(), 1);
+
+        shapes.sort();
+        shapes.update(Duration::ZERO);
+        assert!(shapes.shapes.is_empty());
+        assert!(shapes.vertices.is_empty());
+    }
+
+    #[test]
+    fn timed_shapes_expire_after_their_duration() {
+        let mut shapes = Shapes::default();
+        let (a, b) = (Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0));
+        shapes.line(a, b, RED.into());
+        shapes.line(
+            a,
+            b,
+            DebugStyle::new(GREEN).for_duration(Duration::from_secs(1)),
+        );
+
+        shapes.sort();
+        shapes.update(Duration::from_millis(600));
+        // The untimed line is gone and the timed one moved to the front.
+        assert_eq!(shapes.shapes.len(), 1);
+        assert_eq!(shapes.shapes[0].start..shapes.shapes[0].end, 0..2);
+        assert!(shapes.vertices.iter().all(|vertex| vertex.color == GREEN));
+
+        shapes.sort();
+        shapes.update(Duration::from_millis(600));
+        assert!(shapes.shapes.is_empty());
+    }
+
+    #[test]
+    fn sort_puts_depth_tested_lines_first() {
+        let mut shapes = Shapes::default();
+        let (a, b) = (Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        shapes.line(a, b, DebugStyle::new(GREEN).on_top());
+        shapes.aabb(a, b, RED.into());
+
+        let (sorted, depth_tested, on_top) = shapes.sort();
+        assert_eq!((depth_tested, on_top), (0..24, 24..26));
+        assert!(sorted[..24].iter().all(|vertex| vertex.color == RED));
+        assert!(sorted[24..].iter().all(|vertex| vertex.color == GREEN));
+    }
+
+    #[test]
+    fn frustum_corners_lie_on_the_near_and_far_planes() {
+        // Looking down -Z from (1, 2, 3).
+        let camera = Camera::new((1.0, 2.0, 3.0), Deg(-90.0), Deg(0.0));
+        let forward = camera.forward();
+        for (projection, far) in [
+            (Projection::new(800, 800, Deg(90.0), 0.5, 100.0), 100.0),
+            (Projection::orthographic(800, 800, 4.0, 0.5, 100.0), 100.0),
+            // Cut off at a thousand times the near plane.
+            (
+                Projection::infinite_reverse_z(800, 800, Deg(90.0), 0.5),
+                500.0,
+            ),
+        ] {
+            let corners = frustum_corners(&camera, &projection).unwrap();
+            for (i, corner) in corners.into_iter().enumerate() {
+                let offset = corner - camera.position;
+                let distance = offset.dot(forward);
+                let expected = if i & 4 == 0 { 0.5 } else { far };
+                assert!(
+                    (distance - expected).abs() < expected * 1e-3,
+                    "{projection:?} corner {i}: {distance}"
+                );
+
+                // Bits 0 and 1 pick the right and top sides.
+                let half = match projection {
+                    Projection::Orthographic { height, .. } => height / 2.0,
+                    _ => distance,
+                };
+                let x = if i & 1 == 0 { -half } else { half };
+                let y = if i & 2 == 0 { -half } else { half };
+                assert!(
+                    (offset.x - x).abs() < half * 1e-3,
+                    "{projection:?} corner {i}"
+                );
+                assert!(
+                    (offset.y - y).abs() < half * 1e-3,
+                    "{projection:?} corner {i}"
+                );
+            }
+        }
+    }
+}
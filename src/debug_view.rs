@@ -0,0 +1,538 @@
+use std::{collections::HashMap, ops::Range};
+
+use anyhow::*;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, CompareFunction, Device, Features, PipelineLayout,
+    PolygonMode, Queue, RenderPass, RenderPipeline, TextureFormat,
+};
+
+use crate::{
+    instance::InstanceRaw,
+    model::{Model, ModelVertex},
+    reflection::{ReflectedLayout, ShaderReflection},
+    texture::Texture,
+    uniform::{ShaderType, UniformBuffer},
+    vertex::Vertex,
+};
+
+// wireframe.wgsl reads `ModelVertex` from storage as 14 floats.
+const _: () = assert!(std::mem::size_of::<ModelVertex>() == 14 * 4);
+
+/// What the main pass shows instead of the lit scene. The discriminants are
+/// matched by `debug_view.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u32)]
+pub enum DebugViewMode {
+    #[default]
+    Lit = 0,
+    Normals = 1,
+    Tangents = 2,
+    Bitangents = 3,
+    Uvs = 4,
+    /// The diffuse texture without any lighting.
+    Albedo = 5,
+    /// Lighting on a white surface with the interpolated vertex normal.
+    Lighting = 6,
+    /// Diffuse texture mip level, from blue (0) to red (5 and above).
+    MipLevel = 7,
+    /// Additive heat map of how often each pixel is shaded.
+    Overdraw = 8,
+    /// Distance from the camera, white up close and black at `depth_range`.
+    Depth = 9,
+}
+
+impl DebugViewMode {
+    pub const ALL: [Self; 10] = [
+        Self::Lit,
+        Self::Normals,
+        Self::Tangents,
+        Self::Bitangents,
+        Self::Uvs,
+        Self::Albedo,
+        Self::Lighting,
+        Self::MipLevel,
+        Self::Overdraw,
+        Self::Depth,
+    ];
+
+    /// The following mode, wrapping around to `Lit`.
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct DebugViewUniform {
+    mode: u32,
+    depth_range: f32,
+}
+
+/// How the wireframe overlay is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WireframeMethod {
+    /// `PolygonMode::Line` over the regular vertex and index buffers.
+    Lines,
+    /// Filled triangles with vertices pulled from storage buffers, keeping
+    /// only the pixels near an edge.
+    Barycentric,
+}
+
+impl WireframeMethod {
+    fn for_features(features: Features) -> Self {
+        if features.contains(Features::POLYGON_MODE_LINE) {
+            Self::Lines
+        } else {
+            Self::Barycentric
+        }
+    }
+
+    fn polygon_mode(self) -> PolygonMode {
+        match self {
+            Self::Lines => PolygonMode::Line,
+            Self::Barycentric => PolygonMode::Fill,
+        }
+    }
+
+    /// The vertex and fragment entry points in `wireframe.wgsl`.
+    fn entry_points(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Lines => ("vs_main", "fs_main"),
+            Self::Barycentric => ("vs_barycentric", "fs_barycentric"),
+        }
+    }
+
+    fn vertex_buffers(self) -> Vec<wgpu::VertexBufferLayout<'static>> {
+        match self {
+            Self::Lines => vec![ModelVertex::desc(), InstanceRaw::desc()],
+            Self::Barycentric => vec![InstanceRaw::desc()],
+        }
+    }
+
+    fn blend(self) -> Option<wgpu::BlendState> {
+        match self {
+            Self::Lines => None,
+            Self::Barycentric => Some(wgpu::BlendState::ALPHA_BLENDING),
+        }
+    }
+}
+
+struct DebugViewPipelines {
+    debug: RenderPipeline,
+    overdraw: RenderPipeline,
+    wireframe: RenderPipeline,
+}
+
+/// Runtime-switchable debug views of the main pass and a wireframe overlay.
+///
+/// The overlay uses `PolygonMode::Line` when the device has
+/// `POLYGON_MODE_LINE`. Otherwise it redraws the triangles with vertices
+/// pulled from storage buffers and keeps only the pixels near an edge.
+pub struct DebugViews {
+    pub mode: DebugViewMode,
+    pub wireframe: bool,
+    /// Distance at which the `Depth` view fades to black.
+    pub depth_range: f32,
+    wireframe_method: WireframeMethod,
+    uniform: UniformBuffer<DebugViewUniform>,
+    layout: PipelineLayout,
+    wireframe_layout: PipelineLayout,
+    mesh_layout: BindGroupLayout,
+    mesh_bind_groups: Vec<BindGroup>,
+    pipelines: HashMap<(TextureFormat, CompareFunction), DebugViewPipelines>,
+}
+
+impl DebugViews {
+    /// `scene` is the layout reflected from `shader.wgsl`, whose texture,
+    /// camera and light groups the debug shaders share.
+    pub fn new(device: &Device, scene: &ReflectedLayout) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(include_str!("../shaders/debug_view.wgsl"))?;
+        let mut reflected = reflection.create_layout(device, "Debug View Pipeline Layout")?;
+        reflection
+            .validate_bind_group_layouts(&[
+                &scene.entries[0],
+                &scene.entries[1],
+                &scene.entries[2],
+                &reflected.entries[3],
+            ])
+            .context("debug_view.wgsl")?;
+        let debug_layout = reflected.bind_group_layouts.remove(3);
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug View Pipeline Layout"),
+            bind_group_layouts: &[
+                &scene.bind_group_layouts[0],
+                &scene.bind_group_layouts[1],
+                &scene.bind_group_layouts[2],
+                &debug_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let reflection = ShaderReflection::from_wgsl(include_str!("../shaders/wireframe.wgsl"))?;
+        let mut reflected = reflection.create_layout(device, "Wireframe Pipeline Layout")?;
+        reflection
+            .validate_bind_group_layouts(&[&scene.entries[1], &reflected.entries[1]])
+            .context("wireframe.wgsl")?;
+        let mesh_layout = reflected.bind_group_layouts.remove(1);
+
+        let wireframe_method = WireframeMethod::for_features(device.features());
+        let camera_layout = &scene.bind_group_layouts[1];
+        let wireframe_groups = match wireframe_method {
+            WireframeMethod::Lines => vec![camera_layout],
+            WireframeMethod::Barycentric => vec![camera_layout, &mesh_layout],
+        };
+        let wireframe_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Wireframe Pipeline Layout"),
+            bind_group_layouts: &wireframe_groups,
+            push_constant_ranges: &[],
+        });
+
+        let depth_range = 50.0;
+        Ok(Self {
+            mode: DebugViewMode::Lit,
+            wireframe: false,
+            depth_range,
+            wireframe_method,
+            uniform: UniformBuffer::new(
+                device,
+                &debug_layout,
+                DebugViewUniform {
+                    mode: DebugViewMode::Lit as u32,
+                    depth_range,
+                },
+                "debug_view_bind_group",
+            ),
+            layout,
+            wireframe_layout,
+            mesh_layout,
+            mesh_bind_groups: Vec::new(),
+            pipelines: HashMap::new(),
+        })
+    }
+
+    /// Whether the overlay is drawn with `PolygonMode::Line` rather than the
+    /// barycentric fallback.
+    pub fn has_line_mode(&self) -> bool {
+        self.wireframe_method == WireframeMethod::Lines
+    }
+
+    /// Uploads the current settings and creates whatever the enabled views
+    /// need for the targets in `keys`.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        model: &Model,
+        keys: impl IntoIterator<Item = (TextureFormat, CompareFunction)>,
+    ) {
+        self.uniform.set(DebugViewUniform {
+            mode: self.mode as u32,
+            depth_range: self.depth_range,
+        });
+        self.uniform.update(queue);
+
+        if self.mode == DebugViewMode::Lit && !self.wireframe {
+            return;
+        }
+
+        for key in keys {
+            if !self.pipelines.contains_key(&key) {
+                let pipelines = self.create_pipelines(device, key);
+                self.pipelines.insert(key, pipelines);
+            }
+        }
+
+        if self.wireframe && self.wireframe_method == WireframeMethod::Barycentric {
+            while self.mesh_bind_groups.len() < model.meshes.len() {
+                let mesh = &model.meshes[self.mesh_bind_groups.len()];
+                self.mesh_bind_groups
+                    .push(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("wireframe_mesh_bind_group"),
+                        layout: &self.mesh_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: mesh.vertex_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: mesh.index_buffer.as_entire_binding(),
+                            },
+                        ],
+                    }));
+            }
+        }
+    }
+
+    /// Draws `model` with the current debug view. Returns `false` without
+    /// drawing anything in `Lit` mode, where the regular pipeline applies.
+    pub(crate) fn draw_scene<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        key: (TextureFormat, CompareFunction),
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a BindGroup,
+        light_bind_group: &'a BindGroup,
+    ) -> bool {
+        if self.mode == DebugViewMode::Lit {
+            return false;
+        }
+        let Some(pipelines) = self.pipelines.get(&key) else {
+            return false;
+        };
+
+        use crate::model::DrawModel;
+        pass.set_pipeline(if self.mode == DebugViewMode::Overdraw {
+            &pipelines.overdraw
+        } else {
+            &pipelines.debug
+        });
+        pass.set_bind_group(3, self.uniform.bind_group(), &[]);
+        pass.draw_model_instanced(model, instances, camera_bind_group, light_bind_group);
+        true
+    }
+
+    /// Draws the wireframe overlay if it is enabled.
+    pub(crate) fn draw_wireframe<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        key: (TextureFormat, CompareFunction),
+        model: &'a Model,
+        instances: Range<u32>,
+        instance_buffer: &'a Buffer,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        if !self.wireframe {
+            return;
+        }
+        let Some(pipelines) = self.pipelines.get(&key) else {
+            return;
+        };
+
+        pass.set_pipeline(&pipelines.wireframe);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        if self.wireframe_method == WireframeMethod::Lines {
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for mesh in &model.meshes {
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+            }
+        } else {
+            pass.set_vertex_buffer(0, instance_buffer.slice(..));
+            for (mesh, bind_group) in model.meshes.iter().zip(&self.mesh_bind_groups) {
+                pass.set_bind_group(1, bind_group, &[]);
+                pass.draw(0..mesh.num_elements, instances.clone());
+            }
+        }
+    }
+
+    fn create_pipelines(
+        &self,
+        device: &Device,
+        (color_format, depth_compare): (TextureFormat, CompareFunction),
+    ) -> DebugViewPipelines {
+        let debug_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug View Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/debug_view.wgsl").into()),
+        });
+        let wireframe_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Wireframe Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/wireframe.wgsl").into()),
+        });
+
+        let scene_buffers = [ModelVertex::desc(), InstanceRaw::desc()];
+
+        let debug = create_pipeline(
+            device,
+            &self.layout,
+            PipelineShader {
+                module: &debug_shader,
+                vertex: "vs_main",
+                fragment: "fs_main",
+                buffers: &scene_buffers,
+            },
+            color_format,
+            None,
+            PolygonMode::Fill,
+            Some((depth_compare, true)),
+        );
+
+        let overdraw = create_pipeline(
+            device,
+            &self.layout,
+            PipelineShader {
+                module: &debug_shader,
+                vertex: "vs_main",
+                fragment: "fs_overdraw",
+                buffers: &scene_buffers,
+            },
+            color_format,
+            Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::REPLACE,
+            }),
+            PolygonMode::Fill,
+            None,
+        );
+
+        // The overlay lies exactly on the surfaces it outlines, so equal
+        // depth has to pass.
+        let overlay_compare = match depth_compare {
+            CompareFunction::Less => CompareFunction::LessEqual,
+            CompareFunction::Greater => CompareFunction::GreaterEqual,
+            other => other,
+        };
+        let method = self.wireframe_method;
+        let (vertex, fragment) = method.entry_points();
+        let wireframe = create_pipeline(
+            device,
+            &self.wireframe_layout,
+            PipelineShader {
+                module: &wireframe_shader,
+                vertex,
+                fragment,
+                buffers: &method.vertex_buffers(),
+            },
+            color_format,
+            method.blend(),
+            method.polygon_mode(),
+            Some((overlay_compare, false)),
+        );
+
+        DebugViewPipelines {
+            debug,
+            overdraw,
+            wireframe,
+        }
+    }
+}
+
+struct PipelineShader<'a> {
+    module: &'a wgpu::ShaderModule,
+    vertex: &'a str,
+    fragment: &'a str,
+    buffers: &'a [wgpu::VertexBufferLayout<'a>],
+}
+
+/// `depth` is the compare function and whether depth is written, or `None`
+/// to draw everything without touching depth.
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: PipelineShader,
+    color_format: TextureFormat,
+    blend: Option<wgpu::BlendState>,
+    polygon_mode: PolygonMode,
+    depth: Option<(CompareFunction, bool)>,
+) -> RenderPipeline {
+    let (depth_compare, depth_write_enabled) = depth.unwrap_or((CompareFunction::Always, false));
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Debug View Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader.module,
+            entry_point: shader.vertex,
+            buffers: shader.buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader.module,
+            entry_point: shader.fragment,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(blend.unwrap_or(wgpu::BlendState::REPLACE)),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            // Overdraw counts back faces too.
+            cull_mode: depth.map(|_| wgpu::Face::Back),
+            polygon_mode,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_shaders_validate() {
+        let scene = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER).unwrap();
+        let entries = scene.bind_group_layout_entries().unwrap();
+
+        let debug =
+            ShaderReflection::from_wgsl(include_str!("../shaders/debug_view.wgsl")).unwrap();
+        let own = debug.bind_group_layout_entries().unwrap();
+        debug
+            .validate_bind_group_layouts(&[&entries[0], &entries[1], &entries[2], &own[3]])
+            .unwrap();
+        debug
+            .validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])
+            .unwrap();
+
+        let wireframe =
+            ShaderReflection::from_wgsl(include_str!("../shaders/wireframe.wgsl")).unwrap();
+        let own = wireframe.bind_group_layout_entries().unwrap();
+        wireframe
+            .validate_bind_group_layouts(&[&entries[1], &own[1]])
+            .unwrap();
+        for method in [WireframeMethod::Lines, WireframeMethod::Barycentric] {
+            let (vertex, _) = method.entry_points();
+            wireframe
+                .validate_vertex_layouts(vertex, &method.vertex_buffers())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn next_cycles_through_every_mode() {
+        let mut mode = DebugViewMode::Lit;
+        for expected in DebugViewMode::ALL.iter().skip(1) {
+            mode = mode.next();
+            assert_eq!(mode, *expected);
+        }
+        assert_eq!(mode.next(), DebugViewMode::Lit);
+
+        for (i, mode) in DebugViewMode::ALL.iter().enumerate() {
+            assert_eq!(*mode as usize, i);
+        }
+    }
+
+    #[test]
+    fn wireframe_falls_back_without_line_mode() {
+        let lines = WireframeMethod::for_features(
+            Features::POLYGON_MODE_LINE | Features::DEPTH_CLIP_CONTROL,
+        );
+        assert_eq!(lines, WireframeMethod::Lines);
+        assert_eq!(lines.polygon_mode(), PolygonMode::Line);
+
+        for features in [Features::empty(), Features::POLYGON_MODE_POINT] {
+            let fallback = WireframeMethod::for_features(features);
+            assert_eq!(fallback, WireframeMethod::Barycentric);
+            assert_eq!(fallback.polygon_mode(), PolygonMode::Fill);
+            assert_eq!(
+                fallback.entry_points(),
+                ("vs_barycentric", "fs_barycentric")
+            );
+        }
+    }
+}
@@ -0,0 +1,570 @@
+//! Box-projected decals: bullet holes, signs and road markings placed on
+//! whatever meshes or terrain are below them, without touching their
+//! texture coordinates.
+//!
+//! Decals are drawn into the G-buffer between its geometry pass and the
+//! lighting, so they need [`ShadingPath::Deferred`]. Each decal's box is
+//! drawn with its inside faces, and every pixel covered by the box finds
+//! its surface's position from the G-buffer's depth. Surfaces inside the
+//! box take the decal's albedo, blended by the albedo's alpha, the decal's
+//! opacity and how squarely the surface faces the side of the box the
+//! decal is projected from. Decals with a normal map also blend their
+//! normal by that much into a copy of the G-buffer's normals taken before
+//! the pass, so where two of them overlap the last one drawn shows. Only
+//! surfaces whose [material layer](crate::model::Material::decal_layer),
+//! or [the terrain's](crate::terrain::TerrainBuilder::with_decal_layer), is
+//! in the decal's mask receive it. Blended materials are drawn forward
+//! afterwards and get none.
+
+use std::ops::Range;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Angle, Deg, Matrix4, Quaternion, SquareMatrix, Vector3, Zero};
+use wgpu::{
+    BindGroup, Buffer, CommandEncoder, Device, Operations, PipelineLayout, Queue,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+};
+
+use crate::{
+    camera::view::{CameraView, PixelRect},
+    deferred::{Deferred, GBufferViews, ShadingPath},
+    reflection::{ReflectedLayout, ShaderReflection},
+    texture::{TargetId, Texture},
+    uniform::{self, MemoryLayout, ShaderType},
+    vertex::Vertex,
+};
+
+/// `decals.wgsl` behind `gbuffer.wgsl`, for the normal encoding.
+const DECAL_SHADER: &str = concat!(
+    include_str!("../shaders/gbuffer.wgsl"),
+    include_str!("../shaders/decals.wgsl")
+);
+
+/// A decal in [`State::decals`](crate::state::State::decals).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecalId(usize);
+
+/// Textures added with [`Decals::add_textures`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecalTexturesId(usize);
+
+/// A texture projected along the local -y axis of a box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decal {
+    /// The centre of the box.
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    /// The box's width along x, depth of projection along y and height
+    /// along z.
+    pub size: Vector3<f32>,
+    pub textures: DecalTexturesId,
+    pub opacity: f32,
+    /// The angle between a surface and the box's y axis up to which the
+    /// decal is fully shown.
+    pub fade_start: Deg<f32>,
+    /// The angle at which the decal has faded out.
+    pub fade_end: Deg<f32>,
+    /// The material layers it lands on, one bit each.
+    pub layers: u32,
+}
+
+impl Decal {
+    /// A one unit decal at `position`, landing on every layer.
+    pub fn new(position: Vector3<f32>, textures: DecalTexturesId) -> Self {
+        Self {
+            position,
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            size: Vector3::new(1.0, 1.0, 1.0),
+            textures,
+            opacity: 1.0,
+            fade_start: Deg(60.0),
+            fade_end: Deg(80.0),
+            layers: u32::MAX,
+        }
+    }
+
+    fn to_raw(self) -> DecalRaw {
+        let model = Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.size.x, self.size.y, self.size.z);
+        DecalRaw {
+            model: model.into(),
+            inv_model: model.invert().unwrap_or_else(Matrix4::zero).into(),
+            fade: [
+                self.opacity,
+                self.fade_start.cos(),
+                self.fade_end.cos(),
+                0.0,
+            ],
+            layers: self.layers,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable, Vertex)]
+#[vertex(instance)]
+struct DecalRaw {
+    #[location(0)]
+    model: [[f32; 4]; 4],
+    inv_model: [[f32; 4]; 4],
+    fade: [f32; 4],
+    layers: u32,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct FrameUniform {
+    view_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+    far_depth: f32,
+    viewport: [f32; 4],
+}
+
+/// A decal's albedo and normal map, kept alive with the bind group that
+/// uses them.
+struct DecalTextures {
+    _albedo: Texture,
+    /// `None` where the shared flat normal map stands in, and the decals
+    /// leave the G-buffer's normals alone.
+    normal: Option<Texture>,
+    bind_group: BindGroup,
+}
+
+/// The camera of one view, bound with its G-buffer's depth and material
+/// and the copy of its normals.
+struct DecalView {
+    params: Buffer,
+    /// Where the G-buffer's normals are copied before the decals draw onto
+    /// them.
+    surface_normal: wgpu::Texture,
+    bind_group: BindGroup,
+}
+
+/// The decals of [`State::decals`](crate::state::State::decals).
+pub struct Decals {
+    layout: ReflectedLayout,
+    /// Draws the decals without a normal map, onto the albedo only.
+    pipeline: RenderPipeline,
+    normal_pipeline: RenderPipeline,
+    /// Stands in for decals without a normal map.
+    flat_normal: Texture,
+    textures: Vec<DecalTextures>,
+    decals: Vec<Option<Decal>>,
+    /// The decals' `DecalRaw`s, ordered by their textures.
+    instances: Buffer,
+    capacity: usize,
+    /// Instance ranges sharing textures.
+    batches: Vec<(DecalTexturesId, Range<u32>)>,
+    /// Indexed like `State::views`.
+    views: Vec<DecalView>,
+    /// The `Deferred::target_id` whose G-buffers `views` are bound with.
+    gbuffer_target_id: Option<TargetId>,
+}
+
+impl Decals {
+    pub(crate) fn new(device: &Device, queue: &Queue) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(DECAL_SHADER)?;
+        reflection
+            .validate_vertex_layouts("vs_main", &[DecalRaw::desc()])
+            .context("decals.wgsl")?;
+        let layout = reflection.create_layout(device, "Decal Pipeline Layout")?;
+        let pipeline = create_decal_pipeline(device, &layout.pipeline_layout, false);
+        let normal_pipeline = create_decal_pipeline(device, &layout.pipeline_layout, true);
+
+        let flat = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba([128, 128, 255, 255]),
+        ));
+        let flat_normal = Texture::from_image(device, queue, &flat, Some("flat_normal"), true)?;
+
+        let capacity = 16;
+        Ok(Self {
+            layout,
+            pipeline,
+            normal_pipeline,
+            flat_normal,
+            textures: Vec::new(),
+            decals: Vec::new(),
+            instances: instance_buffer(device, capacity),
+            capacity,
+            batches: Vec::new(),
+            views: Vec::new(),
+            gbuffer_target_id: None,
+        })
+    }
+
+    /// Adds the textures of one or more decals. Without a `normal` map,
+    /// decals keep the shape of the surface below.
+    pub fn add_textures(
+        &mut self,
+        device: &Device,
+        albedo: Texture,
+        normal: Option<Texture>,
+    ) -> DecalTexturesId {
+        let normal_texture = normal.as_ref().unwrap_or(&self.flat_normal);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("decal_bind_group"),
+            layout: &self.layout.bind_group_layouts[1],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&albedo.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&albedo.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+                },
+            ],
+        });
+        self.textures.push(DecalTextures {
+            _albedo: albedo,
+            normal,
+            bind_group,
+        });
+        DecalTexturesId(self.textures.len() - 1)
+    }
+
+    /// Panics if `decal.textures` were not added to these decals.
+    pub fn add(&mut self, decal: Decal) -> DecalId {
+        assert!(
+            decal.textures.0 < self.textures.len(),
+            "unknown decal textures"
+        );
+        self.decals.push(Some(decal));
+        DecalId(self.decals.len() - 1)
+    }
+
+    pub fn remove(&mut self, id: DecalId) -> Option<Decal> {
+        self.decals.get_mut(id.0)?.take()
+    }
+
+    pub fn get(&self, id: DecalId) -> Option<&Decal> {
+        self.decals.get(id.0)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: DecalId) -> Option<&mut Decal> {
+        self.decals.get_mut(id.0)?.as_mut()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.iter().all(Option::is_none)
+    }
+
+    /// Uploads the decals, grouped by their textures, and the camera of
+    /// every one of `views`, binding it with the view's G-buffer in
+    /// `deferred` when those have changed.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        views: &[CameraView],
+        deferred: &Deferred,
+        surface_size: (u32, u32),
+    ) {
+        // Decals without a normal map first, so the pipeline changes once.
+        let mut decals: Vec<&Decal> = self.decals.iter().flatten().collect();
+        decals.sort_by_key(|decal| {
+            let normal_mapped = self.textures[decal.textures.0].normal.is_some();
+            (normal_mapped, decal.textures.0)
+        });
+        self.batches.clear();
+        for (index, decal) in decals.iter().enumerate() {
+            let index = index as u32;
+            match self.batches.last_mut() {
+                Some((textures, range)) if *textures == decal.textures => range.end = index + 1,
+                _ => self.batches.push((decal.textures, index..index + 1)),
+            }
+        }
+        if decals.is_empty() {
+            return;
+        }
+
+        if decals.len() > self.capacity {
+            self.capacity = decals.len().next_power_of_two();
+            self.instances = instance_buffer(device, self.capacity);
+        }
+        let raw: Vec<DecalRaw> = decals.iter().map(|decal| decal.to_raw()).collect();
+        queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&raw));
+
+        let target_id = deferred.target_id();
+        if self.views.len() != views.len() || self.gbuffer_target_id != Some(target_id) {
+            self.views = (0..views.len())
+                .map(|index| self.create_view(device, deferred.views(index)))
+                .collect();
+            self.gbuffer_target_id = Some(target_id);
+        }
+        for (view, decal_view) in views.iter().zip(&self.views) {
+            let view_proj = view.projection.calc_matrix() * view.camera.calc_matrix();
+            let viewport = view.pixel_viewport(surface_size.0, surface_size.1);
+            let frame = FrameUniform {
+                view_proj: view_proj.into(),
+                inv_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity).into(),
+                far_depth: view.projection.depth_clear_value(),
+                viewport: [
+                    viewport.x as f32,
+                    viewport.y as f32,
+                    viewport.width as f32,
+                    viewport.height as f32,
+                ],
+            };
+            queue.write_buffer(
+                &decal_view.params,
+                0,
+                &uniform::to_bytes(&frame, MemoryLayout::Std140),
+            );
+        }
+    }
+
+    fn create_view(&self, device: &Device, gbuffer: GBufferViews) -> DecalView {
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("decal_frame_uniform"),
+            size: FrameUniform::STD140.size as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (width, height) = gbuffer.size;
+        let surface_normal = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("decal_surface_normal"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ShadingPath::NORMAL_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let surface_normal_view = surface_normal.create_view(&Default::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("decal_frame_bind_group"),
+            layout: &self.layout.bind_group_layouts[0],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(gbuffer.depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(gbuffer.material),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&surface_normal_view),
+                },
+            ],
+        });
+        DecalView {
+            params,
+            surface_normal,
+            bind_group,
+        }
+    }
+
+    /// Records the decals of view `index` onto its G-buffer, in a pass of
+    /// their own as they read its depth and material layers, after copying
+    /// its normals.
+    pub(crate) fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        index: usize,
+        gbuffer: GBufferViews,
+        viewport: PixelRect,
+        scissor: PixelRect,
+    ) {
+        if self.batches.is_empty() {
+            return;
+        }
+        let decal_view = &self.views[index];
+        encoder.copy_texture_to_texture(
+            gbuffer.normal_texture.as_image_copy(),
+            decal_view.surface_normal.as_image_copy(),
+            wgpu::Extent3d {
+                width: gbuffer.size.0,
+                height: gbuffer.size.1,
+                depth_or_array_layers: 1,
+            },
+        );
+        let targets = [gbuffer.albedo, gbuffer.normal].map(|view| {
+            Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })
+        });
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Decal Pass"),
+            color_attachments: &targets,
+            depth_stencil_attachment: None,
+        });
+        pass.set_viewport(
+            viewport.x as f32,
+            viewport.y as f32,
+            viewport.width as f32,
+            viewport.height as f32,
+            0.0,
+            1.0,
+        );
+        pass.set_scissor_rect(scissor.x, scissor.y, scissor.width, scissor.height);
+        pass.set_bind_group(0, &decal_view.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.instances.slice(..));
+        let mut normal_mapped = None;
+        for (textures, instances) in &self.batches {
+            let textures = &self.textures[textures.0];
+            if normal_mapped != Some(textures.normal.is_some()) {
+                normal_mapped = Some(textures.normal.is_some());
+                pass.set_pipeline(if textures.normal.is_some() {
+                    &self.normal_pipeline
+                } else {
+                    &self.pipeline
+                });
+            }
+            pass.set_bind_group(1, &textures.bind_group, &[]);
+            pass.draw(0..BOX_STRIP_VERTICES, instances.clone());
+        }
+    }
+}
+
+/// The corners of the triangle strip `decals.wgsl` covers a box with.
+const BOX_STRIP_VERTICES: u32 = 14;
+
+fn instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("decal_instances"),
+        size: (capacity * std::mem::size_of::<DecalRaw>()) as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Blends into the G-buffer's albedo by the decal's alpha, leaving its
+/// alpha as the geometry pass wrote it. With `normal_map`, also writes the
+/// normal `decals.wgsl` blended itself, and otherwise leaves it be. The
+/// boxes' inside faces are drawn, as their outside ones disappear with the
+/// camera inside.
+fn create_decal_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    normal_map: bool,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Decal Shader"),
+        source: wgpu::ShaderSource::Wgsl(DECAL_SHADER.into()),
+    });
+    let albedo = Some(wgpu::ColorTargetState {
+        format: ShadingPath::ALBEDO_FORMAT,
+        blend: Some(wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        }),
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+    let normal = Some(wgpu::ColorTargetState {
+        format: ShadingPath::NORMAL_FORMAT,
+        blend: None,
+        write_mask: if normal_map {
+            wgpu::ColorWrites::ALL
+        } else {
+            wgpu::ColorWrites::empty()
+        },
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Decal Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[DecalRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[albedo, normal],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            cull_mode: Some(wgpu::Face::Front),
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Rotation3, Vector4};
+
+    use super::*;
+
+    #[test]
+    fn shader_matches_instances_and_frame() {
+        let reflection = ShaderReflection::from_wgsl(DECAL_SHADER).unwrap();
+        reflection
+            .validate_vertex_layouts("vs_main", &[DecalRaw::desc()])
+            .unwrap();
+        let entries = reflection.bind_group_layout_entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].len(), 4);
+        match entries[0][0].ty {
+            wgpu::BindingType::Buffer {
+                min_binding_size, ..
+            } => assert_eq!(
+                min_binding_size.unwrap().get() as usize,
+                FrameUniform::STD140.size
+            ),
+            _ => panic!("expected the frame uniform at binding 0"),
+        }
+        assert_eq!(entries[1].len(), 4);
+    }
+
+    #[test]
+    fn inverse_model_maps_the_box_to_the_unit_cube() {
+        let mut decal = Decal::new(Vector3::new(3.0, -2.0, 5.0), DecalTexturesId(0));
+        decal.rotation = Quaternion::from_angle_y(Deg(30.0));
+        decal.size = Vector3::new(4.0, 0.5, 2.0);
+        let raw = decal.to_raw();
+        let model = Matrix4::from(raw.model);
+        let inv_model = Matrix4::from(raw.inv_model);
+        for corner in [
+            Vector4::new(0.5, 0.5, 0.5, 1.0),
+            Vector4::new(-0.5, 0.5, -0.5, 1.0),
+            Vector4::new(0.5, -0.5, -0.5, 1.0),
+        ] {
+            let local = inv_model * (model * corner);
+            assert!((local - corner).magnitude() < 1e-4);
+        }
+        // The box spans its size around its position.
+        let corner = model * Vector4::new(0.5, 0.5, 0.5, 1.0);
+        let offset = corner.truncate() - decal.position;
+        assert!((offset.magnitude() - (decal.size / 2.0).magnitude()).abs() < 1e-4);
+        assert_eq!(raw.fade[1], Deg(60.0).cos());
+    }
+}
@@ -0,0 +1,469 @@
+//! Deferred shading of the model's opaque meshes and the terrain.
+//!
+//! Before each view's pass, the meshes are drawn with the scene's own
+//! pipeline layout and materials into a G-buffer: albedo, an
+//! octahedral-encoded world normal, roughness and metalness, and depth. The
+//! terrain follows with its own pipeline. The view pass then lights every
+//! covered pixel once with a full-screen triangle, which also writes the
+//! G-buffer's depth so that the light, blended materials and overlays
+//! drawn after it are still hidden behind the scene. Everything else stays
+//! forward.
+
+use anyhow::*;
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::{
+    BindGroup, Buffer, CommandEncoder, CompareFunction, Device, Operations, PipelineLayout, Queue,
+    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+    RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::{
+    camera::view::CameraView,
+    instance::InstanceRaw,
+    light::SCENE_SHADER,
+    model::{DrawModel, Model, ModelVertex},
+    reflection::{ReflectedLayout, ShaderReflection},
+    terrain::Terrain,
+    texture::{TargetId, Texture},
+    uniform::{self, MemoryLayout, ShaderType},
+    vertex::Vertex,
+};
+
+/// How [`State`](crate::state::State) shades the model's opaque meshes.
+/// Either way draws the same [`Model`] and
+/// [`Material`](crate::model::Material)s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingPath {
+    /// Each mesh is lit as it is drawn.
+    #[default]
+    Forward,
+    /// Meshes and the terrain are drawn into a G-buffer first and lit once
+    /// per pixel, which keeps the cost of lighting independent of overdraw.
+    /// Only this path receives [decals](crate::decals).
+    Deferred,
+}
+
+impl ShadingPath {
+    pub const ALBEDO_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+    /// Two channels are enough for an octahedral normal.
+    pub const NORMAL_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+    /// Roughness in red, metalness in green and the
+    /// [decal layer](crate::model::Material::decal_layer) in blue.
+    pub const MATERIAL_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+}
+
+const DEFERRED_SHADER: &str = concat!(
+    include_str!("../shaders/gbuffer.wgsl"),
+    include_str!("../shaders/lighting.wgsl"),
+    include_str!("../shaders/deferred.wgsl")
+);
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct FrameUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    eye: [f32; 3],
+    far_depth: f32,
+    viewport: [f32; 4],
+}
+
+/// The targets of one view's G-buffer that passes between its geometry
+/// and lighting, like the decals, draw onto or read.
+pub(crate) struct GBufferViews<'a> {
+    pub size: (u32, u32),
+    pub albedo: &'a TextureView,
+    pub normal: &'a TextureView,
+    /// The texture of `normal`, for passes that copy it before drawing
+    /// onto it.
+    pub normal_texture: &'a wgpu::Texture,
+    pub material: &'a TextureView,
+    pub depth: &'a TextureView,
+}
+
+/// The G-buffer of one view.
+struct GBuffer {
+    size: (u32, u32),
+    albedo: Texture,
+    normal: Texture,
+    material: Texture,
+    depth: Texture,
+    params: Buffer,
+    bind_group: BindGroup,
+}
+
+pub(crate) struct Deferred {
+    /// The G-buffer bind group of `deferred.wgsl`.
+    layout: ReflectedLayout,
+    /// That bind group followed by the scene's environment and light bind
+    /// groups, the latter in group 2 as in `lighting.wgsl`.
+    pub(crate) lighting_layout: PipelineLayout,
+    /// Indexed like `State::views`.
+    views: Vec<GBuffer>,
+    /// Replaced whenever `views` changes.
+    target_id: TargetId,
+}
+
+impl Deferred {
+    /// `scene` is the layout of `shader.wgsl`, whose light and environment
+    /// bind groups the lighting pass shares.
+    pub(crate) fn new(device: &Device, scene: &ReflectedLayout) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(DEFERRED_SHADER)?;
+        let layout = reflection.create_layout(device, "Deferred Lighting Pipeline Layout")?;
+        reflection
+            .validate_bind_group_layouts(&[
+                &layout.entries[0],
+                &scene.entries[3],
+                &scene.entries[2],
+            ])
+            .context("deferred.wgsl")?;
+        let lighting_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred Lighting Pipeline Layout"),
+            bind_group_layouts: &[
+                &layout.bind_group_layouts[0],
+                &scene.bind_group_layouts[3],
+                &scene.bind_group_layouts[2],
+            ],
+            push_constant_ranges: &[],
+        });
+
+        Ok(Self {
+            layout,
+            lighting_layout,
+            views: Vec::new(),
+            target_id: TargetId::next(),
+        })
+    }
+
+    /// Makes sure every one of `views` has a G-buffer of its size and
+    /// uploads its camera.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        views: &[CameraView],
+        surface_size: (u32, u32),
+    ) {
+        if self.views.len() > views.len() {
+            self.views.truncate(views.len());
+            self.target_id = TargetId::next();
+        }
+        for (index, view) in views.iter().enumerate() {
+            let size = view.target_size(surface_size.0, surface_size.1);
+            if self
+                .views
+                .get(index)
+                .map_or(true, |gbuffer| gbuffer.size != size)
+            {
+                let gbuffer = self.create_gbuffer(device, size);
+                if index < self.views.len() {
+                    self.views[index] = gbuffer;
+                } else {
+                    self.views.push(gbuffer);
+                }
+                self.target_id = TargetId::next();
+            }
+
+            let view_proj = view.projection.calc_matrix() * view.camera.calc_matrix();
+            let viewport = view.pixel_viewport(surface_size.0, surface_size.1);
+            let params = FrameUniform {
+                inv_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity).into(),
+                eye: view.camera.position.into(),
+                far_depth: view.projection.depth_clear_value(),
+                viewport: [
+                    viewport.x as f32,
+                    viewport.y as f32,
+                    viewport.width as f32,
+                    viewport.height as f32,
+                ],
+            };
+            queue.write_buffer(
+                &self.views[index].params,
+                0,
+                &uniform::to_bytes(&params, MemoryLayout::Std140),
+            );
+        }
+    }
+
+    fn create_gbuffer(&self, device: &Device, (width, height): (u32, u32)) -> GBuffer {
+        let target =
+            |format, label| Texture::create_render_target(device, width, height, format, label);
+        let albedo = target(ShadingPath::ALBEDO_FORMAT, "gbuffer_albedo");
+        let normal = target(ShadingPath::NORMAL_FORMAT, "gbuffer_normal");
+        let material = target(ShadingPath::MATERIAL_FORMAT, "gbuffer_material");
+        let depth = Texture::create_depth_texture_sized(device, width, height, "gbuffer_depth");
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Deferred Frame"),
+            size: FrameUniform::STD140.size as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("gbuffer_bind_group"),
+            layout: &self.layout.bind_group_layouts[0],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&albedo.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&material.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&depth.view),
+                },
+            ],
+        });
+
+        GBuffer {
+            size: (width, height),
+            albedo,
+            normal,
+            material,
+            depth,
+            params,
+            bind_group,
+        }
+    }
+
+    /// Records the geometry pass of view `index`, which
+    /// [`prepare`](Self::prepare) has set up, with the `terrain` after the
+    /// meshes. `environment` only fills the scene layout's last group,
+    /// which the G-buffer does not read.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        index: usize,
+        view: &CameraView,
+        surface_size: (u32, u32),
+        pipeline: &RenderPipeline,
+        model: &Model,
+        instance_buffer: &Buffer,
+        instance_count: u32,
+        terrain: Option<&Terrain>,
+        light: &BindGroup,
+        environment: &BindGroup,
+    ) {
+        let gbuffer = &self.views[index];
+        let targets = [&gbuffer.albedo, &gbuffer.normal, &gbuffer.material].map(|texture| {
+            Some(RenderPassColorAttachment {
+                view: &texture.view,
+                resolve_target: None,
+                ops: Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })
+        });
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("G-Buffer Pass"),
+            color_attachments: &targets,
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &gbuffer.depth.view,
+                depth_ops: Some(Operations {
+                    load: wgpu::LoadOp::Clear(view.projection.depth_clear_value()),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        let (width, height) = surface_size;
+        let viewport = view.pixel_viewport(width, height);
+        let scissor = view.pixel_scissor(width, height);
+        pass.set_viewport(
+            viewport.x as f32,
+            viewport.y as f32,
+            viewport.width as f32,
+            viewport.height as f32,
+            0.0,
+            1.0,
+        );
+        pass.set_scissor_rect(scissor.x, scissor.y, scissor.width, scissor.height);
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(3, environment, &[]);
+        pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        for (mesh, material) in model.meshes_by_transparency(false) {
+            pass.draw_mesh_instanced(
+                mesh,
+                material,
+                0..instance_count,
+                view.camera_uniform().bind_group(),
+                light,
+            );
+        }
+        if let Some(terrain) = terrain {
+            terrain.draw_gbuffer(
+                &mut pass,
+                view.projection.depth_compare(),
+                view.camera.position,
+                view.camera_uniform().bind_group(),
+                light,
+            );
+        }
+    }
+
+    /// The G-buffer targets of view `index`.
+    pub(crate) fn views(&self, index: usize) -> GBufferViews<'_> {
+        let gbuffer = &self.views[index];
+        GBufferViews {
+            size: gbuffer.size,
+            albedo: &gbuffer.albedo.view,
+            normal: &gbuffer.normal.view,
+            normal_texture: &gbuffer.normal.texture,
+            material: &gbuffer.material.view,
+            depth: &gbuffer.depth.view,
+        }
+    }
+
+    /// Changes whenever the textures of [`Deferred::views`] do.
+    pub(crate) fn target_id(&self) -> TargetId {
+        self.target_id
+    }
+
+    /// The G-buffer of view `index`, for group 0 of the lighting pipeline.
+    pub(crate) fn bind_group(&self, index: usize) -> &BindGroup {
+        &self.views[index].bind_group
+    }
+}
+
+/// The pipelines filling the G-buffer and lighting it into a view with
+/// `color_format` and `depth_compare`.
+pub(crate) fn create_deferred_pipelines(
+    device: &Device,
+    scene_layout: &PipelineLayout,
+    lighting_layout: &PipelineLayout,
+    color_format: TextureFormat,
+    depth_compare: CompareFunction,
+) -> (RenderPipeline, RenderPipeline) {
+    let depth_stencil = Some(wgpu::DepthStencilState {
+        format: Texture::DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    });
+    let target = |format| {
+        Some(wgpu::ColorTargetState {
+            format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        })
+    };
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("G-Buffer Shader"),
+        source: wgpu::ShaderSource::Wgsl(SCENE_SHADER.into()),
+    });
+    let gbuffer = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("G-Buffer Pipeline"),
+        layout: Some(scene_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_gbuffer",
+            targets: &[
+                target(ShadingPath::ALBEDO_FORMAT),
+                target(ShadingPath::NORMAL_FORMAT),
+                target(ShadingPath::MATERIAL_FORMAT),
+            ],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: depth_stencil.clone(),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Deferred Lighting Shader"),
+        source: wgpu::ShaderSource::Wgsl(DEFERRED_SHADER.into()),
+    });
+    let lighting = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Deferred Lighting Pipeline"),
+        layout: Some(lighting_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[target(color_format)],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    (gbuffer, lighting)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lighting_shares_the_scene_groups() {
+        let scene = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER)
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        let entries = ShaderReflection::from_wgsl(DEFERRED_SHADER)
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].len(), 5);
+        assert!(matches!(
+            entries[0][0].ty,
+            wgpu::BindingType::Buffer {
+                min_binding_size: Some(size),
+                ..
+            } if size.get() as usize == FrameUniform::STD140.size
+        ));
+        assert!(matches!(
+            entries[0][4].ty,
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                ..
+            }
+        ));
+        ShaderReflection::from_wgsl(DEFERRED_SHADER)
+            .unwrap()
+            .validate_bind_group_layouts(&[&entries[0], &scene[3], &scene[2]])
+            .unwrap();
+    }
+
+    #[test]
+    fn gbuffer_entry_point_matches_the_scene_vertex_layout() {
+        let reflection = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER).unwrap();
+        reflection
+            .validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])
+            .unwrap();
+        assert!(reflection
+            .module()
+            .entry_points
+            .iter()
+            .any(|entry| entry.name == "fs_gbuffer" && entry.stage == naga::ShaderStage::Fragment));
+    }
+}
@@ -0,0 +1,464 @@
+//! Distance and height fog, and light scattered in the atmosphere.
+//!
+//! After each view's opaque scene and terrain, a full-screen pass reads the
+//! view's depth to find how far away every pixel is and blends the fog
+//! over it. With an [`Atmosphere`] and [`State::sun`](crate::state::State::sun)
+//! set, sunlight scattered by air (Rayleigh) and aerosols (Mie) between the
+//! eye and the surface is added first, which tints distant geometry
+//! towards the sky, and pixels nothing was drawn to get the sky itself.
+//! Blended materials, particles and overlays are drawn over the fog.
+
+use anyhow::*;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3};
+use wgpu::{
+    BindGroup, Buffer, CommandEncoder, Device, Operations, PipelineLayout, Queue,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::{
+    camera::view::{CameraView, PixelRect, ViewTarget},
+    light::DirectionalLight,
+    reflection::{ReflectedLayout, ShaderReflection},
+    texture::TargetId,
+    uniform::{self, MemoryLayout, ShaderType},
+};
+
+/// How the fog thickens with the distance from the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FogFalloff {
+    /// None at `start`, everything from `end` on.
+    Linear {
+        start: f32,
+        end: f32,
+    },
+    Exponential {
+        density: f32,
+    },
+    /// Clearer near the camera than [`Exponential`](Self::Exponential)
+    /// and thicker further away.
+    ExponentialSquared {
+        density: f32,
+    },
+}
+
+impl FogFalloff {
+    /// How much of something `distance` away shows through the fog, from 1
+    /// to 0, as the fog shader computes it. Anything close to 0 can be
+    /// culled.
+    pub fn transmittance(self, distance: f32) -> f32 {
+        match self {
+            Self::Linear { start, end } => {
+                ((end - distance) / (end - start).max(1e-4)).clamp(0.0, 1.0)
+            }
+            Self::Exponential { density } => (-density * distance).exp(),
+            Self::ExponentialSquared { density } => (-(density * distance).powi(2)).exp(),
+        }
+    }
+}
+
+/// Fog settling in low places, thinning out exponentially with height.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeightFog {
+    /// The world height at which the fog has `density`.
+    pub base: f32,
+    pub density: f32,
+    /// How quickly the density falls off above `base`, per world unit.
+    pub falloff: f32,
+}
+
+impl Default for HeightFog {
+    fn default() -> Self {
+        Self {
+            base: 0.0,
+            density: 0.05,
+            falloff: 0.2,
+        }
+    }
+}
+
+/// A planet's atmosphere, in meters, defaulting to Earth's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atmosphere {
+    pub planet_radius: f32,
+    /// Height of the top of the atmosphere above the ground.
+    pub thickness: f32,
+    /// Rayleigh scattering coefficients at sea level, per meter, for red,
+    /// green and blue.
+    pub rayleigh: [f32; 3],
+    /// Height over which the density of air falls by a factor of e.
+    pub rayleigh_height: f32,
+    /// Mie scattering coefficient at sea level, per meter.
+    pub mie: f32,
+    pub mie_height: f32,
+    /// How much aerosols scatter forward, from 0 to just below 1.
+    pub mie_g: f32,
+    /// Multiplies the sun's color.
+    pub sun_intensity: f32,
+    /// Meters per world unit. Raise it to see aerial perspective in small
+    /// scenes.
+    pub world_scale: f32,
+    /// The world height of the ground.
+    pub ground_height: f32,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            planet_radius: 6_360_000.0,
+            thickness: 100_000.0,
+            rayleigh: [5.8e-6, 13.5e-6, 33.1e-6],
+            rayleigh_height: 8_000.0,
+            mie: 21e-6,
+            mie_height: 1_200.0,
+            mie_g: 0.76,
+            sun_intensity: 20.0,
+            world_scale: 1.0,
+            ground_height: 0.0,
+        }
+    }
+}
+
+/// Settings for [`State::fog`](crate::state::State::fog). Everything is
+/// off by default.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FogSettings {
+    pub falloff: Option<FogFalloff>,
+    pub height: Option<HeightFog>,
+    /// The color of both kinds of fog.
+    pub color: [f32; 3],
+    /// Scatters the light of [`State::sun`](crate::state::State::sun)
+    /// into the sky and the air in front of geometry. Has no effect
+    /// without a sun.
+    pub atmosphere: Option<Atmosphere>,
+}
+
+impl FogSettings {
+    /// Whether there is anything to draw with `sun`.
+    pub fn is_active(&self, sun: Option<&DirectionalLight>) -> bool {
+        self.falloff.is_some()
+            || self.height.is_some()
+            || (self.atmosphere.is_some() && sun.is_some())
+    }
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct FogUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    eye: [f32; 3],
+    far_depth: f32,
+    viewport: [f32; 4],
+    color: [f32; 3],
+    falloff: u32,
+    start: f32,
+    end: f32,
+    density: f32,
+    background: u32,
+    height_base: f32,
+    height_density: f32,
+    height_falloff: f32,
+    scattering: u32,
+    sun_direction: [f32; 3],
+    planet_radius: f32,
+    sun_radiance: [f32; 3],
+    atmosphere_radius: f32,
+    rayleigh: [f32; 3],
+    rayleigh_height: f32,
+    mie: f32,
+    mie_height: f32,
+    mie_g: f32,
+    world_scale: f32,
+    ground_height: f32,
+}
+
+/// The uniform of one view, bound with the depth of its target.
+struct FogView {
+    params: Buffer,
+    bind_group: BindGroup,
+    /// The `CameraView::target_id` whose depth `bind_group` reads.
+    target_id: TargetId,
+}
+
+pub(crate) struct Fog {
+    pub(crate) layout: ReflectedLayout,
+    /// Indexed like `State::views`.
+    views: Vec<FogView>,
+}
+
+impl Fog {
+    pub(crate) fn new(device: &Device) -> Result<Self> {
+        let layout = ShaderReflection::from_wgsl(include_str!("../shaders/fog.wgsl"))?
+            .create_layout(device, "Fog Pipeline Layout")?;
+        Ok(Self {
+            layout,
+            views: Vec::new(),
+        })
+    }
+
+    /// Uploads `settings` with the camera of every one of `views`, binding
+    /// it with the depth of views whose target changed. Surface views read
+    /// `surface_depth`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        settings: &FogSettings,
+        sun: Option<&DirectionalLight>,
+        views: &[CameraView],
+        surface_depth: &TextureView,
+        surface_size: (u32, u32),
+    ) {
+        self.views.truncate(views.len());
+        for (index, view) in views.iter().enumerate() {
+            if self
+                .views
+                .get(index)
+                .is_some_and(|fog_view| fog_view.target_id == view.target_id)
+            {
+                continue;
+            }
+            let depth = match view.target() {
+                ViewTarget::Surface => surface_depth,
+                ViewTarget::Texture { depth, .. } => &depth.view,
+            };
+            let fog_view = self.create_view(device, depth, view.target_id);
+            if index < self.views.len() {
+                self.views[index] = fog_view;
+            } else {
+                self.views.push(fog_view);
+            }
+        }
+
+        let (falloff, start, end, density) = match settings.falloff {
+            None => (0, 0.0, 0.0, 0.0),
+            Some(FogFalloff::Linear { start, end }) => (1, start, end, 0.0),
+            Some(FogFalloff::Exponential { density }) => (2, 0.0, 0.0, density),
+            Some(FogFalloff::ExponentialSquared { density }) => (3, 0.0, 0.0, density),
+        };
+        let height = settings.height.unwrap_or(HeightFog {
+            density: 0.0,
+            ..Default::default()
+        });
+        let scattering = sun
+            .filter(|sun| sun.direction.magnitude2() > 0.0)
+            .zip(settings.atmosphere);
+        let atmosphere = settings.atmosphere.unwrap_or_default();
+        let (sun_direction, sun_radiance) = match scattering {
+            Some((sun, atmosphere)) => (
+                sun.direction.normalize(),
+                sun.color.map(|channel| channel * atmosphere.sun_intensity),
+            ),
+            None => (-Vector3::unit_y(), [0.0; 3]),
+        };
+
+        for (view, fog_view) in views.iter().zip(&self.views) {
+            let view_proj = view.projection.calc_matrix() * view.camera.calc_matrix();
+            let viewport = view.pixel_viewport(surface_size.0, surface_size.1);
+            let params = FogUniform {
+                inv_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity).into(),
+                eye: view.camera.position.into(),
+                far_depth: view.projection.depth_clear_value(),
+                viewport: [
+                    viewport.x as f32,
+                    viewport.y as f32,
+                    viewport.width as f32,
+                    viewport.height as f32,
+                ],
+                color: settings.color,
+                falloff,
+                start,
+                end,
+                density,
+                // Views without a clear color show the ones below where
+                // they draw nothing.
+                background: view.clear_color.is_some() as u32,
+                height_base: height.base,
+                height_density: height.density,
+                height_falloff: height.falloff,
+                scattering: scattering.is_some() as u32,
+                sun_direction: sun_direction.into(),
+                planet_radius: atmosphere.planet_radius,
+                sun_radiance,
+                atmosphere_radius: atmosphere.planet_radius + atmosphere.thickness,
+                rayleigh: atmosphere.rayleigh,
+                rayleigh_height: atmosphere.rayleigh_height,
+                mie: atmosphere.mie,
+                mie_height: atmosphere.mie_height,
+                mie_g: atmosphere.mie_g,
+                world_scale: atmosphere.world_scale,
+                ground_height: atmosphere.ground_height,
+            };
+            queue.write_buffer(
+                &fog_view.params,
+                0,
+                &uniform::to_bytes(&params, MemoryLayout::Std140),
+            );
+        }
+    }
+
+    /// Drops the bind groups of surface views, whose depth is recreated
+    /// with the surface.
+    pub(crate) fn resize(&mut self) {
+        self.views.clear();
+    }
+
+    fn create_view(&self, device: &Device, depth: &TextureView, target_id: TargetId) -> FogView {
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("fog_uniform"),
+            size: FogUniform::STD140.size as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("fog_bind_group"),
+            layout: &self.layout.bind_group_layouts[0],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+            ],
+        });
+        FogView {
+            params,
+            bind_group,
+            target_id,
+        }
+    }
+
+    /// Records the fog of view `index` over `color`, in a pass of its own
+    /// as the depth cannot be read while it is attached.
+    pub(crate) fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        index: usize,
+        pipeline: &RenderPipeline,
+        color: &TextureView,
+        viewport: PixelRect,
+        scissor: PixelRect,
+    ) {
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Fog Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: color,
+                resolve_target: None,
+                ops: Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        pass.set_viewport(
+            viewport.x as f32,
+            viewport.y as f32,
+            viewport.width as f32,
+            viewport.height as f32,
+            0.0,
+            1.0,
+        );
+        pass.set_scissor_rect(scissor.x, scissor.y, scissor.width, scissor.height);
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.views[index].bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// The fog pass into targets of `color_format`. The fog's color is added
+/// to what is there, scaled by the alpha the shader leaves showing.
+pub(crate) fn create_fog_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    color_format: TextureFormat,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Fog Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/fog.wgsl").into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Fog Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::SrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falloffs_fade_with_distance() {
+        let linear = FogFalloff::Linear {
+            start: 10.0,
+            end: 20.0,
+        };
+        assert_eq!(linear.transmittance(5.0), 1.0);
+        assert_eq!(linear.transmittance(15.0), 0.5);
+        assert_eq!(linear.transmittance(25.0), 0.0);
+
+        let density = 0.1;
+        let exponential = FogFalloff::Exponential { density };
+        let squared = FogFalloff::ExponentialSquared { density };
+        assert!((exponential.transmittance(10.0) - (-1.0f32).exp()).abs() < 1e-6);
+        // Squared fog starts out clearer and ends up thicker.
+        assert!(squared.transmittance(5.0) > exponential.transmittance(5.0));
+        assert!(squared.transmittance(20.0) < exponential.transmittance(20.0));
+    }
+
+    #[test]
+    fn shader_reads_the_uniform() {
+        let entries = ShaderReflection::from_wgsl(include_str!("../shaders/fog.wgsl"))
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        match entries[0][0].ty {
+            wgpu::BindingType::Buffer {
+                min_binding_size, ..
+            } => assert_eq!(
+                min_binding_size.unwrap().get() as usize,
+                FogUniform::STD140.size
+            ),
+            _ => panic!("expected the fog uniform at binding 0"),
+        }
+        assert!(matches!(
+            entries[0][1].ty,
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,259 @@
+use std::time::Instant;
+
+use egui::{Event, Key, Modifiers, PointerButton, Pos2, RawInput, Rect, Vec2};
+use winit::{
+    dpi::PhysicalSize,
+    event::{
+        ElementState, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+    },
+};
+
+/// Collects winit window events as egui input for the next frame.
+pub struct GuiInput {
+    raw: RawInput,
+    start: Instant,
+    pixels_per_point: f32,
+    size: PhysicalSize<u32>,
+    pointer: Option<Pos2>,
+    modifiers: Modifiers,
+}
+
+impl GuiInput {
+    /// Points of scrolling per line reported by a mouse wheel.
+    const POINTS_PER_LINE: f32 = 50.0;
+
+    pub fn new(size: PhysicalSize<u32>, scale_factor: f64) -> Self {
+        Self {
+            raw: RawInput::default(),
+            start: Instant::now(),
+            pixels_per_point: scale_factor as f32,
+            size,
+            pointer: None,
+            modifiers: Modifiers::default(),
+        }
+    }
+
+    pub fn pixels_per_point(&self) -> f32 {
+        self.pixels_per_point
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.size = size;
+    }
+
+    /// Translates `event`. Returns which kind of input it was, or `None` for
+    /// events egui does not care about.
+    pub fn on_event(&mut self, event: &WindowEvent) -> Option<InputKind> {
+        match event {
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
+                self.pixels_per_point = *scale_factor as f32;
+                self.size = **new_inner_size;
+                None
+            }
+            WindowEvent::Focused(focused) => {
+                self.raw.has_focus = *focused;
+                None
+            }
+            WindowEvent::ModifiersChanged(state) => {
+                self.modifiers = modifiers(*state);
+                None
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let pos = Pos2::new(
+                    position.x as f32 / self.pixels_per_point,
+                    position.y as f32 / self.pixels_per_point,
+                );
+                self.pointer = Some(pos);
+                self.raw.events.push(Event::PointerMoved(pos));
+                Some(InputKind::Pointer)
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.pointer = None;
+                self.raw.events.push(Event::PointerGone);
+                Some(InputKind::Pointer)
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let (Some(pos), Some(button)) = (self.pointer, pointer_button(*button)) else {
+                    return None;
+                };
+                self.raw.events.push(Event::PointerButton {
+                    pos,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers: self.modifiers,
+                });
+                Some(InputKind::Pointer)
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match *delta {
+                    MouseScrollDelta::LineDelta(x, y) => Vec2::new(x, y) * Self::POINTS_PER_LINE,
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        Vec2::new(delta.x as f32, delta.y as f32) / self.pixels_per_point
+                    }
+                };
+                let event = if self.modifiers.ctrl || self.modifiers.command {
+                    // Pinch-to-zoom style zooming, as egui-winit does it.
+                    Event::Zoom((delta.y / 200.0).exp())
+                } else {
+                    Event::Scroll(delta)
+                };
+                self.raw.events.push(event);
+                Some(InputKind::Pointer)
+            }
+            WindowEvent::ReceivedCharacter(c) => {
+                // Control characters arrive as key events instead.
+                if !c.is_control() && !self.modifiers.ctrl && !self.modifiers.mac_cmd {
+                    self.raw.events.push(Event::Text(c.to_string()));
+                }
+                Some(InputKind::Keyboard)
+            }
+            WindowEvent::KeyboardInput { input, .. } => {
+                let pressed = input.state == ElementState::Pressed;
+                let keycode = input.virtual_keycode?;
+
+                if pressed && self.modifiers.command {
+                    match keycode {
+                        VirtualKeyCode::C => self.raw.events.push(Event::Copy),
+                        VirtualKeyCode::X => self.raw.events.push(Event::Cut),
+                        _ => {}
+                    }
+                }
+                if let Some(key) = key(keycode) {
+                    self.raw.events.push(Event::Key {
+                        key,
+                        pressed,
+                        modifiers: self.modifiers,
+                    });
+                }
+                Some(InputKind::Keyboard)
+            }
+            _ => None,
+        }
+    }
+
+    /// The input gathered since the last call.
+    pub fn take(&mut self) -> RawInput {
+        let size = Vec2::new(self.size.width as f32, self.size.height as f32);
+        self.raw.screen_rect = Some(Rect::from_min_size(
+            Pos2::ZERO,
+            size / self.pixels_per_point,
+        ));
+        self.raw.pixels_per_point = Some(self.pixels_per_point);
+        self.raw.time = Some(self.start.elapsed().as_secs_f64());
+        self.raw.modifiers = self.modifiers;
+        self.raw.take()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Pointer,
+    Keyboard,
+}
+
+fn modifiers(state: ModifiersState) -> Modifiers {
+    Modifiers {
+        alt: state.alt(),
+        ctrl: state.ctrl(),
+        shift: state.shift(),
+        mac_cmd: cfg!(target_os = "macos") && state.logo(),
+        command: if cfg!(target_os = "macos") {
+            state.logo()
+        } else {
+            state.ctrl()
+        },
+    }
+}
+
+fn pointer_button(button: MouseButton) -> Option<PointerButton> {
+    match button {
+        MouseButton::Left => Some(PointerButton::Primary),
+        MouseButton::Right => Some(PointerButton::Secondary),
+        MouseButton::Middle => Some(PointerButton::Middle),
+        MouseButton::Other(1) => Some(PointerButton::Extra1),
+        MouseButton::Other(2) => Some(PointerButton::Extra2),
+        MouseButton::Other(_) => None,
+    }
+}
+
+fn key(keycode: VirtualKeyCode) -> Option<Key> {
+    use VirtualKeyCode as V;
+
+    Some(match keycode {
+        V::Down => Key::ArrowDown,
+        V::Left => Key::ArrowLeft,
+        V::Right => Key::ArrowRight,
+        V::Up => Key::ArrowUp,
+        V::Escape => Key::Escape,
+        V::Tab => Key::Tab,
+        V::Back => Key::Backspace,
+        V::Return | V::NumpadEnter => Key::Enter,
+        V::Space => Key::Space,
+        V::Insert => Key::Insert,
+        V::Delete => Key::Delete,
+        V::Home => Key::Home,
+        V::End => Key::End,
+        V::PageUp => Key::PageUp,
+        V::PageDown => Key::PageDown,
+        V::Key0 | V::Numpad0 => Key::Num0,
+        V::Key1 | V::Numpad1 => Key::Num1,
+        V::Key2 | V::Numpad2 => Key::Num2,
+        V::Key3 | V::Numpad3 => Key::Num3,
+        V::Key4 | V::Numpad4 => Key::Num4,
+        V::Key5 | V::Numpad5 => Key::Num5,
+        V::Key6 | V::Numpad6 => Key::Num6,
+        V::Key7 | V::Numpad7 => Key::Num7,
+        V::Key8 | V::Numpad8 => Key::Num8,
+        V::Key9 | V::Numpad9 => Key::Num9,
+        V::A => Key::A,
+        V::B => Key::B,
+        V::C => Key::C,
+        V::D => Key::D,
+        V::E => Key::E,
+        V::F => Key::F,
+        V::G => Key::G,
+        V::H => Key::H,
+        V::I => Key::I,
+        V::J => Key::J,
+        V::K => Key::K,
+        V::L => Key::L,
+        V::M => Key::M,
+        V::N => Key::N,
+        V::O => Key::O,
+        V::P => Key::P,
+        V::Q => Key::Q,
+        V::R => Key::R,
+        V::S => Key::S,
+        V::T => Key::T,
+        V::U => Key::U,
+        V::V => Key::V,
+        V::W => Key::W,
+        V::X => Key::X,
+        V::Y => Key::Y,
+        V::Z => Key::Z,
+        V::F1 => Key::F1,
+        V::F2 => Key::F2,
+        V::F3 => Key::F3,
+        V::F4 => Key::F4,
+        V::F5 => Key::F5,
+        V::F6 => Key::F6,
+        V::F7 => Key::F7,
+        V::F8 => Key::F8,
+        V::F9 => Key::F9,
+        V::F10 => Key::F10,
+        V::F11 => Key::F11,
+        V::F12 => Key::F12,
+        V::F13 => Key::F13,
+        V::F14 => Key::F14,
+        V::F15 => Key::F15,
+        V::F16 => Key::F16,
+        V::F17 => Key::F17,
+        V::F18 => Key::F18,
+        V::F19 => Key::F19,
+        V::F20 => Key::F20,
+        _ => return None,
+    })
+}
@@ -0,0 +1,210 @@
+//! Optional [egui](https://docs.rs/egui) integration, enabled with the `egui`
+//! feature.
+//!
+//! Window events reach egui before the camera controller, which only sees the
+//! ones egui does not want. The UI itself is built by a hook installed with
+//! [`State::set_ui`](crate::state::State::set_ui) and drawn over everything
+//! else each frame.
+//!
+//! Of egui's platform output only the cursor icon is applied. Copied text
+//! and opened links are dropped, as there is no clipboard or browser access.
+
+pub mod input;
+pub mod renderer;
+
+use anyhow::*;
+use wgpu::{CommandEncoder, Device, Queue, TextureFormat, TextureView};
+use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
+
+use self::{
+    input::{GuiInput, InputKind},
+    renderer::GuiRenderer,
+};
+
+pub type UiHook = Box<dyn FnMut(&mut egui::Context)>;
+
+pub struct Gui {
+    pub context: egui::Context,
+    input: GuiInput,
+    renderer: GuiRenderer,
+    ui: Option<UiHook>,
+    /// The cursor the last frame's UI asked for, until it is applied.
+    cursor_icon: Option<egui::CursorIcon>,
+}
+
+impl Gui {
+    pub fn new(
+        device: &Device,
+        color_format: TextureFormat,
+        size: PhysicalSize<u32>,
+        scale_factor: f64,
+    ) -> Result<Self> {
+        Ok(Self {
+            context: egui::Context::default(),
+            input: GuiInput::new(size, scale_factor),
+            renderer: GuiRenderer::new(device, color_format)?,
+            ui: None,
+            cursor_icon: None,
+        })
+    }
+
+    pub fn set_ui(&mut self, ui: impl FnMut(&mut egui::Context) + 'static) {
+        self.ui = Some(Box::new(ui));
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.input.resize(size);
+    }
+
+    /// Feeds `event` to egui and returns whether egui wants it for itself.
+    ///
+    /// Button and key releases are never claimed, so a drag that started in
+    /// the scene still ends there when the cursor is released over a window,
+    /// and a key held down for the camera stops it even once a text field
+    /// has focus.
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        match self.input.on_event(event) {
+            Some(InputKind::Pointer) => {
+                let released = matches!(
+                    event,
+                    WindowEvent::MouseInput {
+                        state: winit::event::ElementState::Released,
+                        ..
+                    }
+                );
+                let moved = matches!(event, WindowEvent::CursorMoved { .. });
+                !released && !moved && self.context.wants_pointer_input()
+            }
+            Some(InputKind::Keyboard) => {
+                let released = matches!(
+                    event,
+                    WindowEvent::KeyboardInput {
+                        input: winit::event::KeyboardInput {
+                            state: winit::event::ElementState::Released,
+                            ..
+                        },
+                        ..
+                    }
+                );
+                !released && self.context.wants_keyboard_input()
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the pointer is over egui or dragging something in it, in
+    /// which case raw mouse motion should not turn the camera.
+    pub fn wants_pointer_input(&self) -> bool {
+        self.context.wants_pointer_input()
+    }
+
+    /// Shows the cursor the UI last asked for over `window`.
+    pub fn update_window(&mut self, window: &Window) {
+        let Some(icon) = self.cursor_icon.take() else {
+            return;
+        };
+        match cursor_icon(icon) {
+            Some(icon) => {
+                window.set_cursor_visible(true);
+                window.set_cursor_icon(icon);
+            }
+            None => window.set_cursor_visible(false),
+        }
+    }
+
+    /// Runs the UI hook and records a pass drawing its output over `target`.
+    pub(crate) fn render(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        target: &TextureView,
+        surface_size: (u32, u32),
+    ) {
+        // Taken even without a UI, so events do not pile up.
+        let input = self.input.take();
+        let Some(ui) = &mut self.ui else {
+            return;
+        };
+
+        let mut context = self.context.clone();
+        context.begin_frame(input);
+        ui(&mut context);
+        let output = context.end_frame();
+        self.cursor_icon = Some(output.platform_output.cursor_icon);
+        let primitives = context.tessellate(output.shapes);
+
+        self.renderer
+            .set_textures(device, queue, &output.textures_delta);
+        self.renderer.prepare(
+            device,
+            queue,
+            &primitives,
+            surface_size,
+            self.input.pixels_per_point(),
+        );
+
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Egui Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.renderer.draw(&mut pass);
+        }
+
+        // Freed textures may still be used by the commands recorded above,
+        // which wgpu keeps alive until they are done.
+        self.renderer.free_textures(&output.textures_delta);
+    }
+}
+
+/// The winit cursor for `icon`, or `None` to hide it.
+fn cursor_icon(icon: egui::CursorIcon) -> Option<winit::window::CursorIcon> {
+    use egui::CursorIcon as Egui;
+    use winit::window::CursorIcon as Winit;
+    Some(match icon {
+        Egui::None => return None,
+        Egui::Default => Winit::Default,
+        Egui::ContextMenu => Winit::ContextMenu,
+        Egui::Help => Winit::Help,
+        Egui::PointingHand => Winit::Hand,
+        Egui::Progress => Winit::Progress,
+        Egui::Wait => Winit::Wait,
+        Egui::Cell => Winit::Cell,
+        Egui::Crosshair => Winit::Crosshair,
+        Egui::Text => Winit::Text,
+        Egui::VerticalText => Winit::VerticalText,
+        Egui::Alias => Winit::Alias,
+        Egui::Copy => Winit::Copy,
+        Egui::Move => Winit::Move,
+        Egui::NoDrop => Winit::NoDrop,
+        Egui::NotAllowed => Winit::NotAllowed,
+        Egui::Grab => Winit::Grab,
+        Egui::Grabbing => Winit::Grabbing,
+        Egui::AllScroll => Winit::AllScroll,
+        Egui::ResizeHorizontal => Winit::EwResize,
+        Egui::ResizeNeSw => Winit::NeswResize,
+        Egui::ResizeNwSe => Winit::NwseResize,
+        Egui::ResizeVertical => Winit::NsResize,
+        Egui::ResizeEast => Winit::EResize,
+        Egui::ResizeSouthEast => Winit::SeResize,
+        Egui::ResizeSouth => Winit::SResize,
+        Egui::ResizeSouthWest => Winit::SwResize,
+        Egui::ResizeWest => Winit::WResize,
+        Egui::ResizeNorthWest => Winit::NwResize,
+        Egui::ResizeNorth => Winit::NResize,
+        Egui::ResizeNorthEast => Winit::NeResize,
+        Egui::ResizeColumn => Winit::ColResize,
+        Egui::ResizeRow => Winit::RowResize,
+        Egui::ZoomIn => Winit::ZoomIn,
+        Egui::ZoomOut => Winit::ZoomOut,
+    })
+}
@@ -0,0 +1,382 @@
+use std::{collections::HashMap, num::NonZeroU32, ops::Range};
+
+use anyhow::*;
+use egui::{
+    epaint::{ImageDelta, Primitive, Vertex as EguiVertex},
+    ClippedPrimitive, ImageData, TextureFilter, TextureId, TexturesDelta,
+};
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, BufferDescriptor, BufferUsages, Device, Queue, RenderPass,
+    RenderPipeline, TextureFormat, VertexAttribute, VertexBufferLayout,
+};
+
+use crate::{
+    reflection::ShaderReflection,
+    texture::Texture,
+    uniform::{ShaderType, UniformBuffer},
+};
+
+const VERTEX_ATTRIBUTES: [VertexAttribute; 3] =
+    wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Unorm8x4];
+
+/// `egui::epaint::Vertex` is defined outside the crate, so it cannot derive
+/// [`Vertex`](crate::vertex::Vertex).
+const VERTEX_LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: std::mem::size_of::<EguiVertex>() as wgpu::BufferAddress,
+    step_mode: wgpu::VertexStepMode::Vertex,
+    attributes: &VERTEX_ATTRIBUTES,
+};
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct ScreenUniform {
+    size_in_points: [f32; 2],
+}
+
+struct GuiTexture {
+    texture: Texture,
+    size: [u32; 2],
+    bind_group: BindGroup,
+}
+
+/// One clipped mesh of the frame, as ranges in the shared buffers.
+struct DrawCall {
+    texture: TextureId,
+    /// Scissor rectangle in physical pixels.
+    clip: [u32; 4],
+    indices: Range<u32>,
+    base_vertex: i32,
+}
+
+/// Draws tessellated egui output with its own textures, including the font
+/// atlas egui manages.
+pub struct GuiRenderer {
+    pipeline: RenderPipeline,
+    texture_layout: BindGroupLayout,
+    screen: UniformBuffer<ScreenUniform>,
+    textures: HashMap<TextureId, GuiTexture>,
+    vertex_buffer: Buffer,
+    vertex_capacity: usize,
+    index_buffer: Buffer,
+    index_capacity: usize,
+    draws: Vec<DrawCall>,
+}
+
+impl GuiRenderer {
+    pub fn new(device: &Device, color_format: TextureFormat) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(include_str!("../../shaders/egui.wgsl"))?;
+        reflection.validate_vertex_layouts("vs_main", &[VERTEX_LAYOUT])?;
+
+        let mut layout = reflection.create_layout(device, "Egui Pipeline Layout")?;
+        let pipeline = create_gui_pipeline(device, &layout.pipeline_layout, color_format);
+        let texture_layout = layout.bind_group_layouts.remove(1);
+        let screen = UniformBuffer::new(
+            device,
+            &layout.bind_group_layouts[0],
+            ScreenUniform {
+                size_in_points: [1.0, 1.0],
+            },
+            "egui_screen",
+        );
+
+        let vertex_capacity = 1024;
+        let index_capacity = 4096;
+        Ok(Self {
+            pipeline,
+            texture_layout,
+            screen,
+            textures: HashMap::new(),
+            vertex_buffer: create_buffer(
+                device,
+                "Egui Vertex Buffer",
+                vertex_capacity * std::mem::size_of::<EguiVertex>(),
+                BufferUsages::VERTEX,
+            ),
+            vertex_capacity,
+            index_buffer: create_buffer(
+                device,
+                "Egui Index Buffer",
+                index_capacity * std::mem::size_of::<u32>(),
+                BufferUsages::INDEX,
+            ),
+            index_capacity,
+            draws: Vec::new(),
+        })
+    }
+
+    /// Applies the texture changes that must happen before painting.
+    pub fn set_textures(&mut self, device: &Device, queue: &Queue, delta: &TexturesDelta) {
+        for (id, image) in &delta.set {
+            self.set_texture(device, queue, *id, image);
+        }
+    }
+
+    /// Drops the textures egui no longer uses. Call after painting.
+    pub fn free_textures(&mut self, delta: &TexturesDelta) {
+        for id in &delta.free {
+            self.textures.remove(id);
+        }
+    }
+
+    fn set_texture(&mut self, device: &Device, queue: &Queue, id: TextureId, delta: &ImageDelta) {
+        let (size, pixels) = match &delta.image {
+            ImageData::Color(image) => (image.size, image.pixels.clone()),
+            ImageData::Font(image) => (image.size, image.srgba_pixels(1.0).collect::<Vec<_>>()),
+        };
+        let size = [size[0] as u32, size[1] as u32];
+
+        let origin = match delta.pos {
+            Some([x, y]) => [x as u32, y as u32],
+            None => {
+                let texture = create_gui_texture(device, size, delta.filter);
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("egui_texture_bind_group"),
+                    layout: &self.texture_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                        },
+                    ],
+                });
+                self.textures.insert(
+                    id,
+                    GuiTexture {
+                        texture,
+                        size,
+                        bind_group,
+                    },
+                );
+                [0, 0]
+            }
+        };
+
+        let Some(target) = self.textures.get(&id) else {
+            log::warn!("egui updated texture {:?} before creating it", id);
+            return;
+        };
+        if origin[0] + size[0] > target.size[0] || origin[1] + size[1] > target.size[1] {
+            log::warn!("egui texture {:?} update is out of bounds", id);
+            return;
+        }
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &target.texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: origin[0],
+                    y: origin[1],
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&pixels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * size[0]),
+                rows_per_image: NonZeroU32::new(size[1]),
+            },
+            wgpu::Extent3d {
+                width: size[0],
+                height: size[1],
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Uploads the meshes of a frame for a surface of `surface_size` pixels.
+    /// Paint callbacks are not supported and skipped.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        primitives: &[ClippedPrimitive],
+        surface_size: (u32, u32),
+        pixels_per_point: f32,
+    ) {
+        self.screen.set(ScreenUniform {
+            size_in_points: [
+                surface_size.0 as f32 / pixels_per_point,
+                surface_size.1 as f32 / pixels_per_point,
+            ],
+        });
+        self.screen.update(queue);
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        self.draws.clear();
+        for primitive in primitives {
+            let Primitive::Mesh(mesh) = &primitive.primitive else {
+                continue;
+            };
+
+            // Clip rectangles are in points and may reach past the surface.
+            let rect = primitive.clip_rect;
+            let x0 = (rect.min.x * pixels_per_point).round().max(0.0) as u32;
+            let y0 = (rect.min.y * pixels_per_point).round().max(0.0) as u32;
+            let x1 = ((rect.max.x * pixels_per_point).round().max(0.0) as u32).min(surface_size.0);
+            let y1 = ((rect.max.y * pixels_per_point).round().max(0.0) as u32).min(surface_size.1);
+            if x1 <= x0 || y1 <= y0 || mesh.indices.is_empty() {
+                continue;
+            }
+
+            let start = indices.len() as u32;
+            self.draws.push(DrawCall {
+                texture: mesh.texture_id,
+                clip: [x0, y0, x1 - x0, y1 - y0],
+                indices: start..start + mesh.indices.len() as u32,
+                base_vertex: vertices.len() as i32,
+            });
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        if vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = vertices.len().next_power_of_two();
+            self.vertex_buffer = create_buffer(
+                device,
+                "Egui Vertex Buffer",
+                self.vertex_capacity * std::mem::size_of::<EguiVertex>(),
+                BufferUsages::VERTEX,
+            );
+        }
+        if indices.len() > self.index_capacity {
+            self.index_capacity = indices.len().next_power_of_two();
+            self.index_buffer = create_buffer(
+                device,
+                "Egui Index Buffer",
+                self.index_capacity * std::mem::size_of::<u32>(),
+                BufferUsages::INDEX,
+            );
+        }
+        if !vertices.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+            queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&indices));
+        }
+    }
+
+    /// Draws the meshes uploaded by the last [`prepare`](Self::prepare) into
+    /// a pass over the whole surface.
+    pub fn draw<'a>(&'a self, pass: &mut RenderPass<'a>) {
+        if self.draws.is_empty() {
+            return;
+        }
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, self.screen.bind_group(), &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for draw in &self.draws {
+            let Some(texture) = self.textures.get(&draw.texture) else {
+                continue;
+            };
+            let [x, y, width, height] = draw.clip;
+            pass.set_scissor_rect(x, y, width, height);
+            pass.set_bind_group(1, &texture.bind_group, &[]);
+            pass.draw_indexed(draw.indices.clone(), draw.base_vertex, 0..1);
+        }
+    }
+}
+
+fn create_buffer(device: &Device, label: &str, size: usize, usage: BufferUsages) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some(label),
+        size: size as u64,
+        usage: usage | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_gui_texture(device: &Device, size: [u32; 2], filter: TextureFilter) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("egui_texture"),
+        size: wgpu::Extent3d {
+            width: size[0],
+            height: size[1],
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let filter = match filter {
+        TextureFilter::Nearest => wgpu::FilterMode::Nearest,
+        TextureFilter::Linear => wgpu::FilterMode::Linear,
+    };
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        mag_filter: filter,
+        min_filter: filter,
+        ..Default::default()
+    });
+
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+fn create_gui_pipeline(
+    device: &Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: TextureFormat,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Egui Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/egui.wgsl").into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Egui Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[VERTEX_LAYOUT],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            // Shading happens in linear space; targets that do not encode
+            // sRGB on write get gamma-encoded colors from the shader.
+            entry_point: if color_format.describe().srgb {
+                "fs_main"
+            } else {
+                "fs_gamma"
+            },
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn egui_shader_matches_vertex_layout() {
+        let reflection =
+            ShaderReflection::from_wgsl(include_str!("../../shaders/egui.wgsl")).unwrap();
+        reflection
+            .validate_vertex_layouts("vs_main", &[VERTEX_LAYOUT])
+            .unwrap();
+    }
+}
@@ -0,0 +1,753 @@
+//! Image-based lighting: ambient light taken from an HDR environment map.
+//!
+//! The map is reduced to a diffuse irradiance cubemap, a specular cubemap
+//! prefiltered for increasing roughness along its mips, and a lookup table
+//! of the BRDF's scale and bias to the Fresnel term, which `shader.wgsl`
+//! combines with the split-sum approximation. Computing them takes a
+//! moment, so they can be cached on disk.
+
+use std::{f32::consts::PI, fs, num::NonZeroU32, path::Path, sync::mpsc};
+
+use anyhow::*;
+use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Device, Queue, TextureFormat};
+
+use crate::{
+    reflection::ShaderReflection,
+    ssao::Ssao,
+    texture::TargetId,
+    uniform::{self, MemoryLayout, ShaderType, UniformBuffer},
+};
+
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTERED_SIZE: u32 = 128;
+/// Mips of the prefiltered map, from a roughness of 0 to 1.
+pub const PREFILTERED_MIPS: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 128;
+
+const FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+const TEXEL_BYTES: u32 = 8;
+const SAMPLE_COUNT: u32 = 512;
+const CACHE_MAGIC: &[u8; 8] = b"SKYIBL01";
+/// Bump when the cached maps change in a way the key does not see, like
+/// their encoding.
+const CACHE_FORMAT_VERSION: u32 = 1;
+
+/// Size, mips and layers of the irradiance, prefiltered and BRDF maps, in
+/// the order they are cached.
+const MAP_LAYOUTS: [(u32, u32, u32); 3] = [
+    (IRRADIANCE_SIZE, 1, 6),
+    (PREFILTERED_SIZE, PREFILTERED_MIPS, 6),
+    (BRDF_LUT_SIZE, 1, 1),
+];
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct BakeParams {
+    roughness: f32,
+    sample_count: u32,
+    texel_solid_angle: f32,
+    max_lod: f32,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct EnvironmentUniform {
+    intensity: f32,
+    max_lod: f32,
+}
+
+/// The computed maps, ordered as in [`MAP_LAYOUTS`].
+struct IblMaps([wgpu::Texture; 3]);
+
+/// Ambient light from an environment, bound as group 3 of `shader.wgsl`
+/// together with the view's ambient occlusion.
+pub struct Environment {
+    maps: IblMaps,
+    views: [wgpu::TextureView; 3],
+    sampler: wgpu::Sampler,
+    params: wgpu::Buffer,
+    intensity: f32,
+    /// Bound without occlusion, built with `occluded`.
+    unoccluded: Option<BindGroup>,
+    /// Bound with the occlusion of each view, for the SSAO targets of
+    /// `occlusion_target_id`.
+    occluded: Vec<BindGroup>,
+    occlusion_target_id: Option<TargetId>,
+}
+
+impl Environment {
+    /// Computes the lighting of an equirectangular HDR (or any image format
+    /// `image` decodes) environment map.
+    pub fn from_hdr(device: &Device, queue: &Queue, bytes: &[u8]) -> Result<Self> {
+        let image = image::load_from_memory(bytes)?.into_rgba32f();
+        let maps = bake(device, queue, &image)?;
+        Ok(Self::from_maps(device, maps))
+    }
+
+    /// Like [`from_hdr`](Self::from_hdr), but reads the maps from `cache`
+    /// if it was written for the same `bytes`, and otherwise writes them
+    /// there. Failing to write the cache only logs a warning.
+    pub fn from_hdr_cached(
+        device: &Device,
+        queue: &Queue,
+        bytes: &[u8],
+        cache: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let cache = cache.as_ref();
+        let key = cache_key(bytes);
+        if let Some(payload) = fs::read(cache)
+            .ok()
+            .as_deref()
+            .and_then(|data| cache_payload(data, key))
+        {
+            let maps = upload(device, queue, payload);
+            return Ok(Self::from_maps(device, maps));
+        }
+
+        let image = image::load_from_memory(bytes)?.into_rgba32f();
+        let maps = bake(device, queue, &image)?;
+        let mut data = CACHE_MAGIC.to_vec();
+        data.extend_from_slice(&key.to_le_bytes());
+        data.extend(read_back(device, queue, &maps)?);
+        if let Err(error) = fs::write(cache, data) {
+            log::warn!("could not write the IBL cache {:?}: {}", cache, error);
+        }
+        Ok(Self::from_maps(device, maps))
+    }
+
+    /// An environment of one color in every direction, which lights like a
+    /// constant ambient term.
+    pub fn uniform(device: &Device, queue: &Queue, color: [f32; 3]) -> Result<Self> {
+        let [r, g, b] = color;
+        let image = image::Rgba32FImage::from_pixel(1, 1, image::Rgba([r, g, b, 1.0]));
+        let maps = bake(device, queue, &image)?;
+        Ok(Self::from_maps(device, maps))
+    }
+
+    fn from_maps(device: &Device, maps: IblMaps) -> Self {
+        let intensity = 1.0;
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Params"),
+            contents: &uniform::to_bytes(&environment_uniform(intensity), MemoryLayout::Std140),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let cube = |texture: &wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            })
+        };
+        let [irradiance, prefiltered, brdf_lut] = &maps.0;
+        let irradiance = cube(irradiance);
+        let prefiltered = cube(prefiltered);
+        let brdf_lut = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("environment_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            maps,
+            views: [irradiance, prefiltered, brdf_lut],
+            sampler,
+            params,
+            intensity,
+            unoccluded: None,
+            occluded: Vec::new(),
+            occlusion_target_id: None,
+        }
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    /// Scales the ambient light. Defaults to 1.
+    pub fn set_intensity(&mut self, queue: &Queue, intensity: f32) {
+        self.intensity = intensity;
+        queue.write_buffer(
+            &self.params,
+            0,
+            &uniform::to_bytes(&environment_uniform(intensity), MemoryLayout::Std140),
+        );
+    }
+
+    /// The irradiance cubemap, the prefiltered cubemap and the BRDF lookup
+    /// table.
+    pub fn textures(&self) -> [&wgpu::Texture; 3] {
+        let [irradiance, prefiltered, brdf_lut] = &self.maps.0;
+        [irradiance, prefiltered, brdf_lut]
+    }
+
+    /// Binds the environment for `layout` without occlusion and with that
+    /// of every view of `ambient_occlusion`, unless its targets are the
+    /// ones already bound.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        ambient_occlusion: &Ssao,
+    ) {
+        let target_id = ambient_occlusion.target_id();
+        if self.occlusion_target_id == Some(target_id) {
+            return;
+        }
+        let unoccluded = self.create_bind_group(device, layout, ambient_occlusion.unoccluded());
+        self.occluded = ambient_occlusion
+            .occlusions()
+            .map(|occlusion| self.create_bind_group(device, layout, occlusion))
+            .collect();
+        self.unoccluded = Some(unoccluded);
+        self.occlusion_target_id = Some(target_id);
+    }
+
+    /// Group 3 of `shader.wgsl` without ambient occlusion.
+    pub(crate) fn unoccluded_bind_group(&self) -> &BindGroup {
+        self.unoccluded
+            .as_ref()
+            .expect("Environment::prepare binds the environment")
+    }
+
+    /// Group 3 of `shader.wgsl` with the ambient occlusion of view `index`.
+    pub(crate) fn bind_group(&self, index: usize) -> &BindGroup {
+        &self.occluded[index]
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        occlusion: &wgpu::TextureView,
+    ) -> BindGroup {
+        let [irradiance, prefiltered, brdf_lut] = &self.views;
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("environment_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(irradiance),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(prefiltered),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(brdf_lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(occlusion),
+                },
+            ],
+        })
+    }
+}
+
+fn environment_uniform(intensity: f32) -> EnvironmentUniform {
+    EnvironmentUniform {
+        intensity,
+        max_lod: (PREFILTERED_MIPS - 1) as f32,
+    }
+}
+
+/// The roughness mip `mip` of the prefiltered map is baked for. The scene
+/// samples roughness times `max_lod`, so this must be its inverse.
+fn mip_roughness(mip: u32) -> f32 {
+    mip as f32 / (PREFILTERED_MIPS - 1) as f32
+}
+
+fn mip_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).leading_zeros()
+}
+
+fn create_map(
+    device: &Device,
+    label: &str,
+    (size, mips, layers): (u32, u32, u32),
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count: mips,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::COPY_DST,
+    })
+}
+
+fn create_maps(device: &Device) -> IblMaps {
+    let [irradiance, prefiltered, brdf_lut] = MAP_LAYOUTS;
+    IblMaps([
+        create_map(device, "irradiance_map", irradiance),
+        create_map(device, "prefiltered_map", prefiltered),
+        create_map(device, "brdf_lut", brdf_lut),
+    ])
+}
+
+/// A view of one mip of `texture` that `ibl.wgsl` can write to.
+fn storage_view(texture: &wgpu::Texture, mip: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        base_mip_level: mip,
+        mip_level_count: NonZeroU32::new(1),
+        ..Default::default()
+    })
+}
+
+/// Uploads `image` with a mip chain and computes the maps from it.
+fn bake(device: &Device, queue: &Queue, image: &image::Rgba32FImage) -> Result<IblMaps> {
+    let reflected = ShaderReflection::from_wgsl(include_str!("../shaders/ibl.wgsl"))?
+        .create_layout(device, "IBL Pipeline Layout")?;
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("IBL Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/ibl.wgsl").into()),
+    });
+    let pipeline = |entry_point: &str| {
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&reflected.pipeline_layout),
+            module: &shader,
+            entry_point,
+        })
+    };
+    let downsample = pipeline("cs_downsample");
+    let irradiance = pipeline("cs_irradiance");
+    let prefilter = pipeline("cs_prefilter");
+    let brdf_lut = pipeline("cs_brdf_lut");
+
+    let (width, height) = image.dimensions();
+    let source_mips = mip_count(width, height);
+    let source = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("environment_source"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: source_mips,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::COPY_DST,
+    });
+    let texels = image
+        .as_raw()
+        .iter()
+        .map(|&value| f16_bits(value))
+        .collect::<Vec<_>>();
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &source,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        bytemuck::cast_slice(&texels),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(width * TEXEL_BYTES),
+            rows_per_image: NonZeroU32::new(height),
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+
+    // Wraps around horizontally, the seam of the equirectangular map.
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("environment_source_sampler"),
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    let source_group = |view: &wgpu::TextureView| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ibl_source_bind_group"),
+            layout: &reflected.bind_group_layouts[0],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        })
+    };
+    let destination_group = |texture: &wgpu::Texture, mip: u32| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ibl_destination_bind_group"),
+            layout: &reflected.bind_group_layouts[1],
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&storage_view(texture, mip)),
+            }],
+        })
+    };
+    let params = |roughness: f32| {
+        UniformBuffer::new(
+            device,
+            &reflected.bind_group_layouts[2],
+            BakeParams {
+                roughness,
+                sample_count: SAMPLE_COUNT,
+                texel_solid_angle: 4.0 * PI / (width * height) as f32,
+                max_lod: (source_mips - 1) as f32,
+            },
+            "ibl_params",
+        )
+    };
+    let workgroups = |size: u32, layers: u32| (size.div_ceil(8), size.div_ceil(8), layers);
+
+    // Every dispatch with its pipeline, bind groups and workgroup count,
+    // made up front as the pass borrows them.
+    let mut dispatches = Vec::new();
+    for mip in 1..source_mips {
+        let above = source.create_view(&wgpu::TextureViewDescriptor {
+            base_mip_level: mip - 1,
+            mip_level_count: NonZeroU32::new(1),
+            ..Default::default()
+        });
+        let size = (width >> mip).max(height >> mip).max(1);
+        dispatches.push((
+            &downsample,
+            source_group(&above),
+            destination_group(&source, mip),
+            params(0.0),
+            workgroups(size, 1),
+        ));
+    }
+
+    let maps = create_maps(device);
+    let source_view = source.create_view(&wgpu::TextureViewDescriptor::default());
+    dispatches.push((
+        &irradiance,
+        source_group(&source_view),
+        destination_group(&maps.0[0], 0),
+        params(0.0),
+        workgroups(IRRADIANCE_SIZE, 6),
+    ));
+    for mip in 0..PREFILTERED_MIPS {
+        dispatches.push((
+            &prefilter,
+            source_group(&source_view),
+            destination_group(&maps.0[1], mip),
+            params(mip_roughness(mip)),
+            workgroups((PREFILTERED_SIZE >> mip).max(1), 6),
+        ));
+    }
+    dispatches.push((
+        &brdf_lut,
+        source_group(&source_view),
+        destination_group(&maps.0[2], 0),
+        params(0.0),
+        workgroups(BRDF_LUT_SIZE, 1),
+    ));
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("IBL Encoder"),
+    });
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("IBL Pass"),
+        });
+        for (pipeline, source, destination, params, (x, y, z)) in &dispatches {
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, source, &[]);
+            pass.set_bind_group(1, destination, &[]);
+            pass.set_bind_group(2, params.bind_group(), &[]);
+            pass.dispatch_workgroups(*x, *y, *z);
+        }
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+    Ok(maps)
+}
+
+/// Byte length of one mip of a map, with tightly packed rows.
+fn mip_bytes(size: u32, mip: u32, layers: u32) -> usize {
+    let size = (size >> mip).max(1);
+    (size * size * layers * TEXEL_BYTES) as usize
+}
+
+fn payload_len() -> usize {
+    MAP_LAYOUTS
+        .iter()
+        .flat_map(|&(size, mips, layers)| (0..mips).map(move |mip| mip_bytes(size, mip, layers)))
+        .sum()
+}
+
+/// Copies every mip of the maps back, in the order they are cached.
+fn read_back(device: &Device, queue: &Queue, maps: &IblMaps) -> Result<Vec<u8>> {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("IBL Readback Encoder"),
+    });
+    let mut copies = Vec::new();
+    for (texture, &(size, mips, layers)) in maps.0.iter().zip(&MAP_LAYOUTS) {
+        for mip in 0..mips {
+            let width = (size >> mip).max(1);
+            let row = width * TEXEL_BYTES;
+            let padded_row = row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+                * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("ibl_readback_buffer"),
+                size: (padded_row * width * layers) as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: mip,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: NonZeroU32::new(padded_row),
+                        rows_per_image: NonZeroU32::new(width),
+                    },
+                },
+                wgpu::Extent3d {
+                    width,
+                    height: width,
+                    depth_or_array_layers: layers,
+                },
+            );
+            copies.push((buffer, row as usize, padded_row as usize));
+        }
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let (sender, receiver) = mpsc::channel();
+    for (buffer, _, _) in &copies {
+        let sender = sender.clone();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result.is_ok());
+            });
+    }
+    device.poll(wgpu::Maintain::Wait);
+
+    let mut data = Vec::with_capacity(payload_len());
+    for (buffer, row, padded_row) in &copies {
+        ensure!(receiver.recv()?, "could not map the IBL readback buffer");
+        data.extend(
+            buffer
+                .slice(..)
+                .get_mapped_range()
+                .chunks(*padded_row)
+                .flat_map(|padded| &padded[..*row]),
+        );
+        buffer.unmap();
+    }
+    Ok(data)
+}
+
+/// Creates the maps from a cache payload of [`payload_len`] bytes.
+fn upload(device: &Device, queue: &Queue, payload: &[u8]) -> IblMaps {
+    let maps = create_maps(device);
+    let mut offset = 0;
+    for (texture, &(size, mips, layers)) in maps.0.iter().zip(&MAP_LAYOUTS) {
+        for mip in 0..mips {
+            let width = (size >> mip).max(1);
+            let len = mip_bytes(size, mip, layers);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: mip,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &payload[offset..offset + len],
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(width * TEXEL_BYTES),
+                    rows_per_image: NonZeroU32::new(width),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height: width,
+                    depth_or_array_layers: layers,
+                },
+            );
+            offset += len;
+        }
+    }
+    maps
+}
+
+/// FNV-1a of the source and of everything that shapes the maps, including
+/// the shader baking them, so a cache goes stale when either changes.
+fn cache_key(bytes: &[u8]) -> u64 {
+    let settings = [
+        CACHE_FORMAT_VERSION,
+        IRRADIANCE_SIZE,
+        PREFILTERED_SIZE,
+        PREFILTERED_MIPS,
+        BRDF_LUT_SIZE,
+        SAMPLE_COUNT,
+    ];
+    settings
+        .iter()
+        .flat_map(|setting| setting.to_le_bytes())
+        .chain(include_str!("../shaders/ibl.wgsl").bytes())
+        .chain(bytes.iter().copied())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// The maps in a cache file, if it was written for `key`.
+fn cache_payload(data: &[u8], key: u64) -> Option<&[u8]> {
+    let data = data.strip_prefix(CACHE_MAGIC)?;
+    let (stored_key, payload) = data.split_at(8.min(data.len()));
+    (stored_key == key.to_le_bytes() && payload.len() == payload_len()).then_some(payload)
+}
+
+/// The nearest half-precision float, saturated to the largest finite one.
+fn f16_bits(value: f32) -> u16 {
+    let value = if value.is_nan() {
+        0.0
+    } else {
+        value.clamp(-65504.0, 65504.0)
+    };
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        // Subnormal, with the implicit leading bit shifted into the mantissa.
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = (mantissa >> shift) + ((mantissa >> (shift - 1)) & 1);
+        return sign | half as u16;
+    }
+    // Rounding may carry into the exponent, which is still the right value.
+    let half = ((exponent as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1);
+    sign | half as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f16_bits_round_and_saturate() {
+        assert_eq!(f16_bits(0.0), 0x0000);
+        assert_eq!(f16_bits(1.0), 0x3c00);
+        assert_eq!(f16_bits(-2.0), 0xc000);
+        assert_eq!(f16_bits(0.1), 0x2e66);
+        assert_eq!(f16_bits(65504.0), 0x7bff);
+        assert_eq!(f16_bits(1.0e9), 0x7bff);
+        assert_eq!(f16_bits(f32::INFINITY), 0x7bff);
+        assert_eq!(f16_bits(2.0f32.powi(-24)), 0x0001);
+        assert_eq!(f16_bits(2.0f32.powi(-30)), 0x0000);
+    }
+
+    #[test]
+    fn scene_samples_the_mip_baked_for_its_roughness() {
+        for shader in [
+            include_str!("../shaders/shader.wgsl"),
+            include_str!("../shaders/deferred.wgsl"),
+        ] {
+            assert!(shader.contains("roughness * environment.max_lod"));
+        }
+        let max_lod = environment_uniform(1.0).max_lod;
+        for mip in 0..PREFILTERED_MIPS {
+            assert_eq!(mip_roughness(mip) * max_lod, mip as f32);
+        }
+        assert_eq!(mip_roughness(0), 0.0);
+        assert_eq!(mip_roughness(PREFILTERED_MIPS - 1), 1.0);
+        assert!(PREFILTERED_MIPS <= mip_count(PREFILTERED_SIZE, PREFILTERED_SIZE));
+    }
+
+    #[test]
+    fn mips_go_down_to_one_texel() {
+        assert_eq!(mip_count(1, 1), 1);
+        assert_eq!(mip_count(128, 64), 8);
+        assert_eq!(mip_count(1000, 3), 10);
+    }
+
+    #[test]
+    fn cache_is_keyed_by_the_source() {
+        let key = cache_key(b"environment");
+        assert_ne!(key, cache_key(b"other environment"));
+
+        let mut data = CACHE_MAGIC.to_vec();
+        data.extend_from_slice(&key.to_le_bytes());
+        data.resize(data.len() + payload_len(), 7);
+        assert_eq!(
+            cache_payload(&data, key).map(<[u8]>::len),
+            Some(payload_len())
+        );
+        assert!(cache_payload(&data, cache_key(b"other environment")).is_none());
+        assert!(cache_payload(&data[..data.len() - 1], key).is_none());
+        assert!(cache_payload(&data[..4], key).is_none());
+    }
+
+    #[test]
+    fn shaders_agree_on_the_environment() {
+        let bake = ShaderReflection::from_wgsl(include_str!("../shaders/ibl.wgsl"))
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        assert_eq!(bake.len(), 3);
+
+        let scene = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER)
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        let environment = &scene[3];
+        assert_eq!(environment.len(), 6);
+        assert!(matches!(
+            environment[0].ty,
+            wgpu::BindingType::Texture {
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                ..
+            }
+        ));
+        assert!(matches!(
+            environment[2].ty,
+            wgpu::BindingType::Texture {
+                view_dimension: wgpu::TextureViewDimension::D2,
+                ..
+            }
+        ));
+    }
+}
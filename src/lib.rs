@@ -1,5 +1,6 @@
 use std::time::Instant;
 
+use camera::controller::CameraController;
 use state::State;
 use winit::{
     event::{DeviceEvent, ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
@@ -8,19 +9,56 @@ use winit::{
     window::WindowBuilder,
 };
 
+extern crate self as skygen;
+
+#[cfg(feature = "egui")]
+pub use egui;
+pub use wgpu;
+
+pub mod atlas;
 pub mod camera;
+pub mod clusters;
+pub mod debug_draw;
+pub mod debug_view;
+pub mod decals;
+pub mod deferred;
+pub mod fog;
+#[cfg(feature = "egui")]
+pub mod gui;
+pub mod ibl;
 pub mod instance;
 // pub mod mesh;
 pub mod light;
 pub mod mesh;
 pub mod model;
+pub mod particles;
+pub mod picking;
+pub mod point_shadows;
+pub mod primitives;
+pub mod reflection;
 pub mod renderer;
 pub mod resources;
+pub mod shadows;
+pub mod sprite;
+pub mod ssao;
 pub mod state;
+pub mod terrain;
+pub mod text;
 pub mod texture;
+pub mod transparency;
+pub mod uniform;
 pub mod vertex;
 
-pub async fn run() {
+pub async fn run<C: CameraController + 'static>(camera_controller: C) {
+    run_with(camera_controller, |_| {}).await
+}
+
+/// Like [`run`], but lets `setup` configure the state before the first
+/// frame, e.g. to add views or install a UI with `State::set_ui`.
+pub async fn run_with<C: CameraController + 'static>(
+    camera_controller: C,
+    setup: impl FnOnce(&mut State),
+) {
     env_logger::init();
 
     let event_loop = EventLoopBuilder::new().with_any_thread(true).build();
@@ -29,7 +67,8 @@ pub async fn run() {
         .build(&event_loop)
         .unwrap();
 
-    let mut state = State::new(&window).await;
+    let mut state = State::new(&window, camera_controller).await;
+    setup(&mut state);
     let mut last_render_time = Instant::now();
 
     event_loop.run(move |event, _, control_flow| match event {
@@ -45,6 +84,8 @@ pub async fn run() {
                 Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                 Err(e) => eprintln!("{:?}", e),
             }
+            #[cfg(feature = "egui")]
+            state.gui.update_window(&window);
         }
         Event::MainEventsCleared => {
             window.request_redraw();
@@ -52,9 +93,7 @@ pub async fn run() {
         Event::DeviceEvent {
             event: DeviceEvent::MouseMotion{ delta, },
             .. // We're not using device_id currently
-        } => if state.mouse_pressed {
-            state.camera_controller.process_mouse(delta.0, delta.1)
-        }
+        } if !state.ui_wants_pointer() => state.camera_controller.process_mouse(delta.0, delta.1),
         Event::WindowEvent {
             ref event,
             window_id,
@@ -87,9 +126,10 @@ pub async fn run() {
 
 #[cfg(test)]
 mod tests {
+    use crate::camera::controller::FpsController;
 
     #[test]
     fn run() {
-        pollster::block_on(super::run());
+        pollster::block_on(super::run(FpsController::new(7.48341, 1.4)));
     }
 }
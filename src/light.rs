@@ -1,10 +1,60 @@
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+use cgmath::Vector3;
+
+use crate::uniform::ShaderType;
+
+/// `shader.wgsl` behind `gbuffer.wgsl` and `lighting.wgsl`, which declare
+/// the G-buffer, the lights and the functions that shade with them for the
+/// other lit shaders as well.
+pub(crate) const SCENE_SHADER: &str = concat!(
+    include_str!("../shaders/gbuffer.wgsl"),
+    include_str!("../shaders/lighting.wgsl"),
+    include_str!("../shaders/shader.wgsl")
+);
+
+#[derive(Debug, Copy, Clone, ShaderType)]
 pub struct LightUniform {
     pub position: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding: u32,
     pub color: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding2: u32,
+}
+
+/// A light shining in all directions from `position`, fading out smoothly
+/// to nothing at `radius`. Add them to
+/// [`State::point_lights`](crate::state::State::point_lights).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub color: [f32; 3],
+    /// Whether it gets one of the cubes of
+    /// [`State::point_shadows`](crate::state::State::point_shadows).
+    pub casts_shadow: bool,
+}
+
+impl PointLight {
+    /// `shadow` is its cube in the point shadow maps, or -1.
+    pub(crate) fn to_raw(self, shadow: i32) -> PointLightRaw {
+        PointLightRaw {
+            position: self.position,
+            radius: self.radius,
+            color: self.color,
+            shadow,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+pub(crate) struct PointLightRaw {
+    position: [f32; 3],
+    radius: f32,
+    color: [f32; 3],
+    shadow: i32,
+}
+
+/// Light from infinitely far away, shining along `direction` everywhere
+/// like the sun. Set it as [`State::sun`](crate::state::State::sun), where
+/// it casts cascaded shadows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
 }
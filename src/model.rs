@@ -1,20 +1,68 @@
 use std::ops::Range;
 
 use bytemuck::{Pod, Zeroable};
-use wgpu::{BindGroup, RenderPass};
+use wgpu::{util::DeviceExt, BindGroup, RenderPass};
 
-use crate::{texture::Texture, vertex::Vertex};
+use crate::{
+    texture::Texture,
+    uniform::{self, MemoryLayout, ShaderType},
+    vertex::Vertex,
+};
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
 }
 
+impl Model {
+    /// The meshes drawn in the transparent pass if `transparent`, or with
+    /// the opaque ones otherwise, with their materials.
+    pub fn meshes_by_transparency(
+        &self,
+        transparent: bool,
+    ) -> impl Iterator<Item = (&Mesh, &Material)> {
+        self.meshes
+            .iter()
+            .map(|mesh| (mesh, &self.materials[mesh.material]))
+            .filter(move |(_, material)| (material.alpha_mode == AlphaMode::Blend) == transparent)
+    }
+
+    pub fn has_transparency(&self) -> bool {
+        self.meshes_by_transparency(true).next().is_some()
+    }
+}
+
+/// How a material's diffuse alpha is used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    /// Alpha is ignored.
+    Opaque,
+    /// Fragments with less alpha than the cutoff are discarded, the rest are
+    /// drawn opaque.
+    AlphaTest(f32),
+    /// Blended over what is behind, after everything opaque has been
+    /// drawn. See [`TransparencyMode`](crate::transparency::TransparencyMode).
+    Blend,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct MaterialUniform {
+    alpha_cutoff: f32,
+    roughness: f32,
+    metallic: f32,
+    decal_layer: u32,
+}
+
 pub struct Material {
     pub name: String,
     pub diffuse_texture: Texture,
     pub normal_texture: Texture,
     pub bind_group: wgpu::BindGroup,
+    alpha_mode: AlphaMode,
+    roughness: f32,
+    metallic: f32,
+    decal_layer: u32,
+    params: wgpu::Buffer,
 }
 
 impl Material {
@@ -25,6 +73,19 @@ impl Material {
         normal_texture: Texture,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        let alpha_mode = AlphaMode::Opaque;
+        let roughness = 0.5;
+        let metallic = 0.0;
+        let decal_layer = 0;
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Material Params", name)),
+            contents: &uniform::to_bytes(
+                &material_uniform(alpha_mode, roughness, metallic, decal_layer),
+                MemoryLayout::Std140,
+            ),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
@@ -44,6 +105,10 @@ impl Material {
                     binding: 3,
                     resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: params.as_entire_binding(),
+                },
             ],
             label: Some(name),
         });
@@ -53,8 +118,87 @@ impl Material {
             diffuse_texture,
             normal_texture, // NEW!
             bind_group,
+            alpha_mode,
+            roughness,
+            metallic,
+            decal_layer,
+            params,
         }
     }
+
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    pub fn set_alpha_mode(&mut self, queue: &wgpu::Queue, alpha_mode: AlphaMode) {
+        self.alpha_mode = alpha_mode;
+        self.write_params(queue);
+    }
+
+    /// How blurry the environment's reflection is, from 0 for a mirror to 1.
+    /// Defaults to 0.5.
+    pub fn roughness(&self) -> f32 {
+        self.roughness
+    }
+
+    pub fn set_roughness(&mut self, queue: &wgpu::Queue, roughness: f32) {
+        self.roughness = roughness.clamp(0.0, 1.0);
+        self.write_params(queue);
+    }
+
+    /// 0 for dielectrics, 1 for metals. Defaults to 0.
+    pub fn metallic(&self) -> f32 {
+        self.metallic
+    }
+
+    pub fn set_metallic(&mut self, queue: &wgpu::Queue, metallic: f32) {
+        self.metallic = metallic.clamp(0.0, 1.0);
+        self.write_params(queue);
+    }
+
+    /// Which of the 32 layers of [decals](crate::decals) the material is
+    /// on. Decals only land on the layers in their mask. Defaults to 0.
+    pub fn decal_layer(&self) -> u32 {
+        self.decal_layer
+    }
+
+    pub fn set_decal_layer(&mut self, queue: &wgpu::Queue, decal_layer: u32) {
+        self.decal_layer = decal_layer.min(31);
+        self.write_params(queue);
+    }
+
+    fn write_params(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.params,
+            0,
+            &uniform::to_bytes(
+                &material_uniform(
+                    self.alpha_mode,
+                    self.roughness,
+                    self.metallic,
+                    self.decal_layer,
+                ),
+                MemoryLayout::Std140,
+            ),
+        );
+    }
+}
+
+fn material_uniform(
+    alpha_mode: AlphaMode,
+    roughness: f32,
+    metallic: f32,
+    decal_layer: u32,
+) -> MaterialUniform {
+    MaterialUniform {
+        roughness,
+        metallic,
+        decal_layer,
+        alpha_cutoff: match alpha_mode {
+            AlphaMode::AlphaTest(cutoff) => cutoff,
+            AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+        },
+    }
 }
 
 pub struct Mesh {
@@ -65,8 +209,38 @@ pub struct Mesh {
     pub material: usize,
 }
 
+impl Mesh {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material: usize,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            // Storage lets the wireframe fallback pull vertices itself.
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::STORAGE,
+        });
+
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material,
+        }
+    }
+}
+
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Vertex)]
 pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
@@ -75,24 +249,73 @@ pub struct ModelVertex {
     pub bitangent: [f32; 3],
 }
 
-impl Vertex for ModelVertex {
-    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &Self::ATTRIBS,
+/// Fills in the tangents and bitangents of `vertices` from the positions and
+/// texture coordinates of the triangles in `indices`, averaged per vertex.
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut triangles_included = vec![0; vertices.len()];
+
+    // Calculate tangents and bitangets. We're going to
+    // use the triangles, so we need to loop through the
+    // indices in chunks of 3
+    for c in indices.chunks_exact(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: cgmath::Vector3<_> = v0.position.into();
+        let pos1: cgmath::Vector3<_> = v1.position.into();
+        let pos2: cgmath::Vector3<_> = v2.position.into();
+
+        let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
+        let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
+        let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
+
+        // Calculate the edges of the triangle
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+
+        // This will give us a direction to calculate the
+        // tangent and bitangent
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving the following system of equations will
+        // give us the tangent and bitangent.
+        //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        // Luckily, the place I found this equation provided
+        // the solution!
+        let det = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        if det.abs() <= f32::EPSILON {
+            // Degenerate texture mapping, the triangle says nothing about
+            // the tangent directions.
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // We flip the bitangent to enable right-handed normal
+        // maps with wgpu texture coordinate system
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        // We'll use the same tangent/bitangent for each vertex in the triangle
+        for &i in c {
+            let v = &mut vertices[i as usize];
+            v.tangent = (tangent + cgmath::Vector3::from(v.tangent)).into();
+            v.bitangent = (bitangent + cgmath::Vector3::from(v.bitangent)).into();
+            // Used to average the tangents/bitangents
+            triangles_included[i as usize] += 1;
         }
     }
-}
 
-impl ModelVertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
-        0 => Float32x3,
-        1 => Float32x2,
-        2 => Float32x2,
-        3 => Float32x3,
-        4 => Float32x3,
-    ];
+    // Average the tangents/bitangents
+    for (v, n) in vertices.iter_mut().zip(triangles_included) {
+        if n == 0 {
+            continue;
+        }
+        let denom = 1.0 / n as f32;
+        v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
+        v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+    }
 }
 
 pub trait DrawModel<'a> {
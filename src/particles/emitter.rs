@@ -0,0 +1,237 @@
+use std::sync::Arc;
+
+use cgmath::{Point3, Rad, Vector3, Zero};
+
+use crate::primitives::MeshData;
+
+/// Values a [`Curve`] can blend between.
+pub trait Lerp: Copy {
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for [f32; 4] {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        [0, 1, 2, 3].map(|i| self[i].lerp(other[i], t))
+    }
+}
+
+/// A value over a particle's life, from 0 at birth to 1 at death, linear
+/// between its keys and flat before the first and after the last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve<T> {
+    keys: Vec<(f32, T)>,
+}
+
+impl<T: Lerp> Curve<T> {
+    pub fn constant(value: T) -> Self {
+        Self {
+            keys: vec![(0.0, value)],
+        }
+    }
+
+    /// From `start` at birth to `end` at death.
+    pub fn linear(start: T, end: T) -> Self {
+        Self::constant(start).with_key(1.0, end)
+    }
+
+    /// Adds a key at `t`, replacing any key already there.
+    pub fn with_key(mut self, t: f32, value: T) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        match self.keys.binary_search_by(|(key, _)| key.total_cmp(&t)) {
+            Ok(index) => self.keys[index].1 = value,
+            Err(index) => self.keys.insert(index, (t, value)),
+        }
+        self
+    }
+
+    pub fn keys(&self) -> &[(f32, T)] {
+        &self.keys
+    }
+
+    pub fn sample(&self, t: f32) -> T {
+        let next = self.keys.partition_point(|&(key, _)| key <= t);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+        let (start, from) = self.keys[next - 1];
+        let (end, to) = self.keys[next];
+        from.lerp(to, (t - start) / (end - start))
+    }
+
+    /// `count` evenly spaced samples from birth to death.
+    pub(crate) fn bake(&self, count: usize) -> impl Iterator<Item = T> + '_ {
+        (0..count).map(move |i| self.sample(i as f32 / (count - 1) as f32))
+    }
+}
+
+/// Where new particles appear and which way they start moving.
+#[derive(Debug, Clone)]
+pub enum EmitterShape {
+    /// At the emitter's position, in every direction.
+    Point,
+    /// On the surface of a sphere around the emitter, moving outwards.
+    Sphere { radius: f32 },
+    /// At the emitter's position, within `angle` of its direction.
+    Cone { angle: Rad<f32> },
+    /// On the triangles of a mesh placed at the emitter's position, evenly
+    /// by area, moving along the face normals.
+    Mesh(Arc<MeshData>),
+}
+
+/// How particles combine with what is behind them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParticleBlend {
+    /// Adds light, for fire and sparks. Order does not matter, so these
+    /// particles are never sorted.
+    Additive,
+    /// Covers by the particle's alpha, for smoke and dust. Sorted back to
+    /// front every frame.
+    Alpha,
+}
+
+/// Describes one stream of particles. Add it with
+/// [`ParticleSystem::add_emitter`](super::ParticleSystem::add_emitter) and
+/// change it later through
+/// [`ParticleSystem::emitter_mut`](super::ParticleSystem::emitter_mut).
+///
+/// ```ignore
+/// let sparks = ParticleEmitter::new(4096)
+///     .with_shape(EmitterShape::Cone { angle: Deg(20.0).into() })
+///     .with_rate(500.0)
+///     .with_speed(4.0, 8.0)
+///     .with_gravity(Vector3::new(0.0, -9.81, 0.0))
+///     .with_color(Curve::linear([1.0, 0.6, 0.2, 1.0], [1.0, 0.1, 0.0, 0.0]))
+///     .with_bounce(0.4)
+///     .with_blend(ParticleBlend::Additive);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParticleEmitter {
+    pub position: Point3<f32>,
+    /// Axis of [`EmitterShape::Cone`].
+    pub direction: Vector3<f32>,
+    pub shape: EmitterShape,
+    /// New particles per second.
+    pub rate: f32,
+    /// Range of seconds each particle lives.
+    pub lifetime: [f32; 2],
+    /// Range of starting speeds.
+    pub speed: [f32; 2],
+    /// Width of a particle in world units.
+    pub size: Curve<f32>,
+    pub color: Curve<[f32; 4]>,
+    pub gravity: Vector3<f32>,
+    /// Fraction of the velocity lost per second.
+    pub drag: f32,
+    /// Strength of a smooth random force that changes over space and time.
+    pub noise_strength: f32,
+    /// How quickly the noise changes over space, in cycles per unit.
+    pub noise_scale: f32,
+    /// Speed kept when bouncing off the depth buffer, or `None` to fly
+    /// through geometry.
+    pub bounce: Option<f32>,
+    pub blend: ParticleBlend,
+    capacity: u32,
+}
+
+impl ParticleEmitter {
+    /// A point emitting ten white particles per second upwards and outwards,
+    /// with room for `capacity` particles alive at once.
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            position: Point3::new(0.0, 0.0, 0.0),
+            direction: Vector3::unit_y(),
+            shape: EmitterShape::Point,
+            rate: 10.0,
+            lifetime: [1.0, 2.0],
+            speed: [1.0, 2.0],
+            size: Curve::constant(0.2),
+            color: Curve::constant([1.0; 4]),
+            gravity: Vector3::zero(),
+            drag: 0.0,
+            noise_strength: 0.0,
+            noise_scale: 1.0,
+            bounce: None,
+            blend: ParticleBlend::Alpha,
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn with_position(mut self, position: Point3<f32>) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn with_direction(mut self, direction: Vector3<f32>) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn with_shape(mut self, shape: EmitterShape) -> Self {
+        self.shape = shape;
+        self
+    }
+
+    pub fn with_rate(mut self, rate: f32) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    pub fn with_lifetime(mut self, min: f32, max: f32) -> Self {
+        self.lifetime = [min, max];
+        self
+    }
+
+    pub fn with_speed(mut self, min: f32, max: f32) -> Self {
+        self.speed = [min, max];
+        self
+    }
+
+    pub fn with_size(mut self, size: Curve<f32>) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_color(mut self, color: Curve<[f32; 4]>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_gravity(mut self, gravity: Vector3<f32>) -> Self {
+        self.gravity = gravity;
+        self
+    }
+
+    pub fn with_drag(mut self, drag: f32) -> Self {
+        self.drag = drag;
+        self
+    }
+
+    pub fn with_noise(mut self, strength: f32, scale: f32) -> Self {
+        self.noise_strength = strength;
+        self.noise_scale = scale;
+        self
+    }
+
+    pub fn with_bounce(mut self, bounce: f32) -> Self {
+        self.bounce = Some(bounce);
+        self
+    }
+
+    pub fn with_blend(mut self, blend: ParticleBlend) -> Self {
+        self.blend = blend;
+        self
+    }
+}
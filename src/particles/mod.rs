@@ -0,0 +1,861 @@
+//! Particles simulated and sorted in compute shaders and drawn as
+//! billboards facing the camera.
+//!
+//! ```ignore
+//! let smoke = state.add_particle_emitter(
+//!     ParticleEmitter::new(2048)
+//!         .with_shape(EmitterShape::Sphere { radius: 0.5 })
+//!         .with_size(Curve::linear(0.2, 1.5))
+//!         .with_color(Curve::constant([0.5, 0.5, 0.5, 0.6]).with_key(1.0, [0.5, 0.5, 0.5, 0.0]))
+//!         .with_noise(2.0, 0.5),
+//! );
+//! state.particles.burst(smoke, 200);
+//! ```
+
+pub mod emitter;
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3};
+use wgpu::{
+    util::DeviceExt, BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, BufferUsages,
+    CommandEncoder, CompareFunction, ComputePipeline, Device, PipelineLayout, Queue, RenderPass,
+    RenderPipeline, TextureFormat, TextureView,
+};
+
+use crate::{
+    camera::view::CameraView,
+    primitives::MeshData,
+    reflection::{ReflectedLayout, ShaderReflection},
+    texture::Texture,
+    uniform::{self, MemoryLayout, ShaderType, UniformBuffer},
+};
+
+pub use emitter::{Curve, EmitterShape, Lerp, ParticleBlend, ParticleEmitter};
+
+/// Samples per curve in the buffer the render shader reads.
+const CURVE_SAMPLES: usize = 32;
+const WORKGROUP_SIZE: u32 = 64;
+
+/// One particle as stored on the GPU. It is alive while `age < lifetime`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct Particle {
+    position: [f32; 3],
+    age: f32,
+    velocity: [f32; 3],
+    lifetime: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct SortEntry {
+    key: f32,
+    index: u32,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct FrameUniform {
+    view_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+    eye: [f32; 3],
+    reverse_z: u32,
+    viewport: [f32; 4],
+    collision_thickness: f32,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct EmitterUniform {
+    position: [f32; 3],
+    shape: u32,
+    direction: [f32; 3],
+    shape_param: f32,
+    gravity: [f32; 3],
+    drag: f32,
+    lifetime: [f32; 2],
+    speed: [f32; 2],
+    noise_strength: f32,
+    noise_scale: f32,
+    bounce: f32,
+    spawn_count: u32,
+    time: f32,
+    dt: f32,
+    seed: u32,
+    triangle_count: u32,
+    capacity: u32,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct SortStep {
+    j: u32,
+    k: u32,
+}
+
+/// Names an emitter added to a [`ParticleSystem`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EmitterId(usize);
+
+/// An emitter with the buffers its particles live in.
+struct GpuEmitter {
+    emitter: ParticleEmitter,
+    uniform: UniformBuffer<EmitterUniform>,
+    particles: Buffer,
+    /// Particle indices in drawing order, padded to a power of two for
+    /// sorting.
+    order: Buffer,
+    order_len: u32,
+    /// Counts the particles spawned this step, so no more than asked for
+    /// are.
+    counter: Buffer,
+    /// The mesh `triangles` was built from, if the shape is a mesh.
+    mesh: Option<Arc<MeshData>>,
+    triangles: Buffer,
+    triangle_count: u32,
+    curves: Buffer,
+    baked_curves: Vec<[f32; 4]>,
+    simulate_bind_group: BindGroup,
+    draw_bind_group: BindGroup,
+    /// Fraction of a particle left over from the spawn rate.
+    spawn_remainder: f32,
+    bursts: u32,
+    /// Seconds to simulate in the next frame, if any.
+    pending_step: Option<f32>,
+}
+
+/// All particle emitters of the scene.
+///
+/// Particles bounce off whatever the main view drew into its depth buffer
+/// in the previous frame, so they only collide with geometry in sight.
+/// Alpha-blended emitters are sorted from the main view's eye, which other
+/// views share.
+pub struct ParticleSystem {
+    emitters: Vec<Option<GpuEmitter>>,
+    /// How far behind the depth buffer a particle may be and still bounce
+    /// off it, rather than being hidden behind it.
+    pub collision_thickness: f32,
+    time: f32,
+    frames: u32,
+    frame: Buffer,
+    compute: ReflectedLayout,
+    simulate: ComputePipeline,
+    sort_keys: ComputePipeline,
+    sort_step: ComputePipeline,
+    /// One uniform per bitonic sort step, by number of entries sorted.
+    sort_steps: HashMap<u32, Vec<UniformBuffer<SortStep>>>,
+    draw_layout: BindGroupLayout,
+    layout: PipelineLayout,
+    pipelines: HashMap<(TextureFormat, CompareFunction, ParticleBlend), RenderPipeline>,
+}
+
+impl ParticleSystem {
+    pub fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        camera_entries: &[BindGroupLayoutEntry],
+    ) -> Result<Self> {
+        let reflection =
+            ShaderReflection::from_wgsl(include_str!("../../shaders/particle_sim.wgsl"))?;
+        let compute = reflection.create_layout(device, "Particle Simulation Layout")?;
+        let simulate_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Simulation Layout"),
+            bind_group_layouts: &compute.bind_group_layouts[..3].iter().collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Simulation Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("../../shaders/particle_sim.wgsl").into(),
+            ),
+        });
+        let compute_pipeline = |label, layout, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                module: &module,
+                entry_point,
+            })
+        };
+        let simulate = compute_pipeline("Particle Simulation", &simulate_layout, "cs_simulate");
+        let sort_keys = compute_pipeline("Particle Sort Keys", &simulate_layout, "cs_sort_keys");
+        let sort_step = compute_pipeline(
+            "Particle Sort Step",
+            &compute.pipeline_layout,
+            "cs_sort_step",
+        );
+
+        let reflection = ShaderReflection::from_wgsl(include_str!("../../shaders/particle.wgsl"))?;
+        let mut reflected = reflection.create_layout(device, "Particle Pipeline Layout")?;
+        reflection
+            .validate_bind_group_layouts(&[camera_entries, &reflected.entries[1]])
+            .context("particle.wgsl")?;
+        let draw_layout = reflected.bind_group_layouts.remove(1);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &draw_layout],
+            push_constant_ranges: &[],
+        });
+
+        Ok(Self {
+            emitters: Vec::new(),
+            collision_thickness: 0.5,
+            time: 0.0,
+            frames: 0,
+            frame: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("particle_frame"),
+                size: FrameUniform::STD140.size as u64,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            compute,
+            simulate,
+            sort_keys,
+            sort_step,
+            sort_steps: HashMap::new(),
+            draw_layout,
+            layout,
+            pipelines: HashMap::new(),
+        })
+    }
+
+    /// Creates the buffers for `emitter`'s particles. It starts emitting in
+    /// the next frame.
+    pub fn add_emitter(&mut self, device: &Device, emitter: ParticleEmitter) -> EmitterId {
+        let capacity = emitter.capacity();
+        let order_len = capacity.next_power_of_two();
+        let storage = |label, size: usize, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as u64,
+                usage: BufferUsages::STORAGE | usage,
+                mapped_at_creation: false,
+            })
+        };
+
+        // Unsorted emitters draw in index order.
+        let identity = (0..order_len)
+            .map(|index| SortEntry { key: 0.0, index })
+            .collect::<Vec<_>>();
+        let order = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("particle_order"),
+            contents: bytemuck::cast_slice(&identity),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let particles = storage(
+            "particles",
+            capacity as usize * std::mem::size_of::<Particle>(),
+            BufferUsages::empty(),
+        );
+        let counter = storage("particle_counter", 4, BufferUsages::COPY_DST);
+        let curves = storage(
+            "particle_curves",
+            2 * CURVE_SAMPLES * std::mem::size_of::<[f32; 4]>(),
+            BufferUsages::COPY_DST,
+        );
+        let (mesh, triangles, triangle_count) = triangle_buffer(device, &emitter.shape);
+        let simulate_bind_group =
+            self.simulate_bind_group(device, &particles, &order, &counter, &triangles);
+        let draw_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("particle_draw_bind_group"),
+            layout: &self.draw_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: particles.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: order.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: curves.as_entire_binding(),
+                },
+            ],
+        });
+
+        let gpu = GpuEmitter {
+            uniform: UniformBuffer::new(
+                device,
+                &self.compute.bind_group_layouts[1],
+                emitter_uniform(&emitter, 0, 0.0, 0.0, 0, triangle_count),
+                "particle_emitter",
+            ),
+            emitter,
+            // New buffers are zeroed, so every particle starts out dead.
+            particles,
+            order,
+            order_len,
+            counter,
+            mesh,
+            triangles,
+            triangle_count,
+            curves,
+            baked_curves: Vec::new(),
+            simulate_bind_group,
+            draw_bind_group,
+            spawn_remainder: 0.0,
+            bursts: 0,
+            pending_step: None,
+        };
+        self.emitters.push(Some(gpu));
+        EmitterId(self.emitters.len() - 1)
+    }
+
+    /// Removes the emitter and every particle it has alive.
+    pub fn remove_emitter(&mut self, id: EmitterId) -> Option<ParticleEmitter> {
+        self.emitters.get_mut(id.0)?.take().map(|gpu| gpu.emitter)
+    }
+
+    pub fn emitter(&self, id: EmitterId) -> Option<&ParticleEmitter> {
+        Some(&self.emitters.get(id.0)?.as_ref()?.emitter)
+    }
+
+    pub fn emitter_mut(&mut self, id: EmitterId) -> Option<&mut ParticleEmitter> {
+        Some(&mut self.emitters.get_mut(id.0)?.as_mut()?.emitter)
+    }
+
+    /// Spawns `count` particles in the next frame on top of the emitter's
+    /// rate, as far as its capacity allows.
+    pub fn burst(&mut self, id: EmitterId, count: u32) {
+        if let Some(Some(gpu)) = self.emitters.get_mut(id.0) {
+            gpu.bursts = gpu.bursts.saturating_add(count);
+        }
+    }
+
+    /// Decides how many particles each emitter spawns in the next frame and
+    /// how far the simulation advances.
+    pub(crate) fn update(&mut self, dt: f32) {
+        self.time += dt;
+        for gpu in self.emitters.iter_mut().flatten() {
+            // Steps the frame missed are folded into this one.
+            let dt = dt + gpu.pending_step.unwrap_or(0.0);
+            gpu.spawn_remainder += gpu.emitter.rate.max(0.0) * dt;
+            gpu.pending_step = Some(dt);
+        }
+    }
+
+    /// Uploads changed emitters and makes sure pipelines exist for every
+    /// target in `keys`.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        keys: impl IntoIterator<Item = (TextureFormat, CompareFunction)>,
+    ) {
+        let mut emitters = std::mem::take(&mut self.emitters);
+        for gpu in emitters.iter_mut().flatten() {
+            let mesh = match &gpu.emitter.shape {
+                EmitterShape::Mesh(mesh) => Some(mesh),
+                _ => None,
+            };
+            let changed = match (mesh, &gpu.mesh) {
+                (Some(mesh), Some(built)) => !Arc::ptr_eq(mesh, built),
+                (None, None) => false,
+                _ => true,
+            };
+            if changed {
+                let (mesh, triangles, triangle_count) = triangle_buffer(device, &gpu.emitter.shape);
+                gpu.simulate_bind_group = self.simulate_bind_group(
+                    device,
+                    &gpu.particles,
+                    &gpu.order,
+                    &gpu.counter,
+                    &triangles,
+                );
+                gpu.mesh = mesh;
+                gpu.triangles = triangles;
+                gpu.triangle_count = triangle_count;
+            }
+
+            let baked = gpu
+                .emitter
+                .color
+                .bake(CURVE_SAMPLES)
+                .chain(gpu.emitter.size.bake(CURVE_SAMPLES).map(|size| [size; 4]))
+                .collect::<Vec<_>>();
+            if baked != gpu.baked_curves {
+                queue.write_buffer(&gpu.curves, 0, bytemuck::cast_slice(&baked));
+                gpu.baked_curves = baked;
+            }
+        }
+        self.emitters = emitters;
+
+        let blends = self
+            .emitters
+            .iter()
+            .flatten()
+            .map(|gpu| gpu.emitter.blend)
+            .collect::<Vec<_>>();
+        for (format, compare) in keys {
+            for &blend in &blends {
+                self.pipelines
+                    .entry((format, compare, blend))
+                    .or_insert_with(|| {
+                        create_particle_pipeline(device, &self.layout, format, compare, blend)
+                    });
+            }
+        }
+    }
+
+    /// Records the simulation of every emitter that was updated since the
+    /// last frame, colliding with `depth`, the depth buffer of `view` from
+    /// the previous frame. Sorts alpha-blended emitters from `view`'s eye.
+    pub(crate) fn simulate(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        view: &CameraView,
+        depth: &TextureView,
+        surface_size: (u32, u32),
+    ) {
+        if !self
+            .emitters
+            .iter()
+            .flatten()
+            .any(|gpu| gpu.pending_step.is_some())
+        {
+            return;
+        }
+
+        let view_proj = view.projection.calc_matrix() * view.camera.calc_matrix();
+        let viewport = view.pixel_viewport(surface_size.0, surface_size.1);
+        let frame = FrameUniform {
+            view_proj: view_proj.into(),
+            inv_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity).into(),
+            eye: view.camera.position.into(),
+            reverse_z: view.projection.is_reverse_z() as u32,
+            viewport: [
+                viewport.x as f32,
+                viewport.y as f32,
+                viewport.width as f32,
+                viewport.height as f32,
+            ],
+            collision_thickness: self.collision_thickness,
+        };
+        queue.write_buffer(
+            &self.frame,
+            0,
+            &uniform::to_bytes(&frame, MemoryLayout::Std140),
+        );
+        let frame_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("particle_frame_bind_group"),
+            layout: &self.compute.bind_group_layouts[0],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.frame.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+            ],
+        });
+
+        self.frames = self.frames.wrapping_add(1);
+        let mut simulated = Vec::new();
+        for (index, gpu) in self.emitters.iter_mut().enumerate() {
+            let gpu = match gpu {
+                Some(gpu) if gpu.pending_step.is_some() => gpu,
+                _ => continue,
+            };
+            simulated.push(index);
+            let dt = gpu.pending_step.take().unwrap_or(0.0);
+            let spawn_count = gpu.spawn_remainder.floor();
+            gpu.spawn_remainder -= spawn_count;
+            let spawn_count = (spawn_count as u32).saturating_add(std::mem::take(&mut gpu.bursts));
+            let seed = self.frames.wrapping_mul(0x9e37_79b9) ^ index as u32;
+            gpu.uniform.set(emitter_uniform(
+                &gpu.emitter,
+                spawn_count,
+                self.time,
+                dt,
+                seed,
+                gpu.triangle_count,
+            ));
+            gpu.uniform.update(queue);
+            queue.write_buffer(&gpu.counter, 0, bytemuck::bytes_of(&0u32));
+
+            if gpu.emitter.blend == ParticleBlend::Alpha && gpu.order_len > 1 {
+                self.sort_steps
+                    .entry(gpu.order_len)
+                    .or_insert_with(|| sort_steps(device, &self.compute, gpu.order_len));
+            }
+        }
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particle Simulation Pass"),
+        });
+        pass.set_bind_group(0, &frame_bind_group, &[]);
+        for gpu in simulated
+            .into_iter()
+            .flat_map(|index| &self.emitters[index])
+        {
+            pass.set_bind_group(1, gpu.uniform.bind_group(), &[]);
+            pass.set_bind_group(2, &gpu.simulate_bind_group, &[]);
+            pass.set_pipeline(&self.simulate);
+            pass.dispatch_workgroups(gpu.emitter.capacity().div_ceil(WORKGROUP_SIZE), 1, 1);
+
+            let steps = match self.sort_steps.get(&gpu.order_len) {
+                Some(steps) if gpu.emitter.blend == ParticleBlend::Alpha => steps,
+                _ => continue,
+            };
+            let workgroups = gpu.order_len.div_ceil(WORKGROUP_SIZE);
+            pass.set_pipeline(&self.sort_keys);
+            pass.dispatch_workgroups(workgroups, 1, 1);
+            pass.set_pipeline(&self.sort_step);
+            for step in steps {
+                pass.set_bind_group(3, step.bind_group(), &[]);
+                pass.dispatch_workgroups(workgroups, 1, 1);
+            }
+        }
+    }
+
+    /// Draws every emitter's particles as seen through the camera.
+    pub(crate) fn draw<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        key: (TextureFormat, CompareFunction),
+        camera_bind_group: &'a BindGroup,
+    ) {
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        for gpu in self.emitters.iter().flatten() {
+            let pipeline = match self.pipelines.get(&(key.0, key.1, gpu.emitter.blend)) {
+                Some(pipeline) => pipeline,
+                None => continue,
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(1, &gpu.draw_bind_group, &[]);
+            pass.draw(0..6, 0..gpu.emitter.capacity());
+        }
+    }
+
+    fn simulate_bind_group(
+        &self,
+        device: &Device,
+        particles: &Buffer,
+        order: &Buffer,
+        counter: &Buffer,
+        triangles: &Buffer,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("particle_simulate_bind_group"),
+            layout: &self.compute.bind_group_layouts[2],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: particles.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: order.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: counter.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: triangles.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
+
+/// Uploads the triangles of a mesh shape, returning the mesh they came
+/// from and their number. Other shapes get a placeholder triangle, as
+/// storage buffers cannot be empty.
+fn triangle_buffer(device: &Device, shape: &EmitterShape) -> (Option<Arc<MeshData>>, Buffer, u32) {
+    let mesh = match shape {
+        EmitterShape::Mesh(mesh) => Some(mesh.clone()),
+        _ => None,
+    };
+    let mut triangles = mesh.as_deref().map(mesh_triangles).unwrap_or_default();
+    let count = triangles.len() as u32;
+    if triangles.is_empty() {
+        triangles.push([[0.0; 4]; 3]);
+    }
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("particle_triangles"),
+        contents: bytemuck::cast_slice(&triangles),
+        usage: BufferUsages::STORAGE,
+    });
+    (mesh, buffer, count)
+}
+
+fn emitter_uniform(
+    emitter: &ParticleEmitter,
+    spawn_count: u32,
+    time: f32,
+    dt: f32,
+    seed: u32,
+    triangle_count: u32,
+) -> EmitterUniform {
+    let (shape, shape_param) = match emitter.shape {
+        EmitterShape::Point => (0, 0.0),
+        EmitterShape::Sphere { radius } => (1, radius),
+        EmitterShape::Cone { angle } => (2, angle.0.cos()),
+        EmitterShape::Mesh(_) => (3, 0.0),
+    };
+    let direction = if emitter.direction.magnitude2() > 0.0 {
+        emitter.direction.normalize()
+    } else {
+        Vector3::unit_y()
+    };
+    EmitterUniform {
+        position: emitter.position.into(),
+        shape,
+        direction: direction.into(),
+        shape_param,
+        gravity: emitter.gravity.into(),
+        drag: emitter.drag.max(0.0),
+        lifetime: emitter.lifetime,
+        speed: emitter.speed,
+        noise_strength: emitter.noise_strength,
+        noise_scale: emitter.noise_scale,
+        bounce: emitter.bounce.map_or(-1.0, |bounce| bounce.max(0.0)),
+        spawn_count,
+        time,
+        dt,
+        seed,
+        triangle_count,
+        capacity: emitter.capacity(),
+    }
+}
+
+/// The corners of every triangle with any area, the first carrying the
+/// running total of the areas up to and including it, divided by the
+/// total. Sampling a number below 1 against those totals picks triangles
+/// evenly by area.
+fn mesh_triangles(mesh: &MeshData) -> Vec<[[f32; 4]; 3]> {
+    let mut total = 0.0;
+    let mut triangles: Vec<[[f32; 4]; 3]> = Vec::new();
+    for triangle in mesh.indices.chunks_exact(3) {
+        let [a, b, c] =
+            [0, 1, 2].map(|i| Vector3::from(mesh.vertices[triangle[i] as usize].position));
+        let area = (b - a).cross(c - a).magnitude() / 2.0;
+        if area > 0.0 {
+            total += area;
+            triangles.push([
+                a.extend(total).into(),
+                b.extend(0.0).into(),
+                c.extend(0.0).into(),
+            ]);
+        }
+    }
+    for triangle in &mut triangles {
+        triangle[0][3] /= total;
+    }
+    triangles
+}
+
+/// Uniforms for each step of a bitonic sort of `len` entries, in order.
+fn sort_steps(
+    device: &Device,
+    compute: &ReflectedLayout,
+    len: u32,
+) -> Vec<UniformBuffer<SortStep>> {
+    bitonic_steps(len)
+        .into_iter()
+        .map(|step| {
+            UniformBuffer::new(
+                device,
+                &compute.bind_group_layouts[3],
+                step,
+                "particle_sort_step",
+            )
+        })
+        .collect()
+}
+
+/// The steps of a bitonic sort of `len` entries, a power of two.
+fn bitonic_steps(len: u32) -> Vec<SortStep> {
+    let mut steps = Vec::new();
+    let mut k = 2;
+    while k <= len {
+        let mut j = k / 2;
+        while j > 0 {
+            steps.push(SortStep { j, k });
+            j /= 2;
+        }
+        k *= 2;
+    }
+    steps
+}
+
+fn create_particle_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    color_format: TextureFormat,
+    depth_compare: CompareFunction,
+    blend: ParticleBlend,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Particle Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/particle.wgsl").into()),
+    });
+
+    let blend = match blend {
+        ParticleBlend::Additive => wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        },
+        ParticleBlend::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Particle Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        // Particles are hidden by the scene but do not hide each other.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_hold_their_ends_and_blend_between_keys() {
+        let curve = Curve::constant(1.0).with_key(0.5, 3.0).with_key(1.0, 0.0);
+        assert_eq!(curve.sample(-1.0), 1.0);
+        assert_eq!(curve.sample(0.25), 2.0);
+        assert_eq!(curve.sample(0.75), 1.5);
+        assert_eq!(curve.sample(2.0), 0.0);
+
+        let replaced = curve.with_key(0.5, 1.0);
+        assert_eq!(replaced.keys().len(), 3);
+        assert_eq!(replaced.bake(3).collect::<Vec<_>>(), [1.0, 1.0, 0.0]);
+
+        let fade = Curve::linear([1.0; 4], [0.0; 4]);
+        assert_eq!(fade.sample(0.5), [0.5; 4]);
+    }
+
+    #[test]
+    fn bitonic_steps_sort_descending() {
+        let mut seed = 7u32;
+        for len in [1, 2, 8, 64, 1024] {
+            // Padding keys as `cs_sort_keys` writes them, then distances.
+            let mut order = (0..len)
+                .map(|index| {
+                    seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    let key = match index % 5 {
+                        0 => -2.0,
+                        1 => -1.0,
+                        _ => (seed >> 8) as f32 / 1000.0,
+                    };
+                    (key, index)
+                })
+                .collect::<Vec<_>>();
+
+            // `cs_sort_step` in `particle_sim.wgsl`, one invocation per
+            // entry.
+            for SortStep { j, k } in bitonic_steps(len) {
+                for index in 0..len {
+                    let partner = index ^ j;
+                    if partner <= index || partner >= len {
+                        continue;
+                    }
+                    let (a, b) = (order[index as usize], order[partner as usize]);
+                    let descending = index & k == 0;
+                    if (a.0 < b.0) == descending {
+                        order.swap(index as usize, partner as usize);
+                    }
+                }
+            }
+
+            assert!(order.windows(2).all(|pair| pair[0].0 >= pair[1].0), "{len}");
+            let mut indices = order.iter().map(|&(_, index)| index).collect::<Vec<_>>();
+            indices.sort_unstable();
+            assert!(indices.into_iter().eq(0..len));
+        }
+    }
+
+    #[test]
+    fn mesh_triangles_are_picked_by_area() {
+        let mut mesh = MeshData::cube(2.0);
+        // A degenerate triangle, which can never be picked.
+        mesh.indices.extend([0, 0, 1]);
+        let triangles = mesh_triangles(&mesh);
+        assert_eq!(triangles.len(), 12);
+        for (i, triangle) in triangles.iter().enumerate() {
+            assert!((triangle[0][3] - (i + 1) as f32 / 12.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn particle_shaders_match_their_buffers() {
+        let size =
+            |entries: &[Vec<BindGroupLayoutEntry>], group: usize, binding: usize| match entries
+                [group][binding]
+                .ty
+            {
+                wgpu::BindingType::Buffer {
+                    min_binding_size, ..
+                } => min_binding_size.unwrap().get() as usize,
+                _ => unreachable!(),
+            };
+
+        let simulation =
+            ShaderReflection::from_wgsl(include_str!("../../shaders/particle_sim.wgsl"))
+                .unwrap()
+                .bind_group_layout_entries()
+                .unwrap();
+        assert_eq!(FrameUniform::STD140.size, size(&simulation, 0, 0));
+        assert_eq!(EmitterUniform::STD140.size, size(&simulation, 1, 0));
+        assert!(SortStep::STD140.size >= size(&simulation, 3, 0));
+        assert_eq!(std::mem::size_of::<Particle>(), size(&simulation, 2, 0));
+        assert_eq!(std::mem::size_of::<SortEntry>(), size(&simulation, 2, 1));
+
+        let scene = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER)
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        let reflection =
+            ShaderReflection::from_wgsl(include_str!("../../shaders/particle.wgsl")).unwrap();
+        let own = reflection.bind_group_layout_entries().unwrap();
+        reflection
+            .validate_bind_group_layouts(&[&scene[1], &own[1]])
+            .unwrap();
+        reflection.validate_vertex_layouts("vs_main", &[]).unwrap();
+    }
+}
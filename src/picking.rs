@@ -0,0 +1,485 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    task::Poll,
+};
+
+use anyhow::*;
+use cgmath::{Matrix4, Point3, SquareMatrix, Vector4};
+use wgpu::{
+    BindGroupLayout, BindGroupLayoutEntry, Buffer, BufferDescriptor, BufferUsages, CommandEncoder,
+    CompareFunction, Device, Extent3d, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout,
+    Operations, Origin3d, PipelineLayout, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, TextureAspect,
+    TextureFormat,
+};
+
+use crate::{
+    camera::view::{CameraView, PixelRect},
+    instance::InstanceRaw,
+    model::{Model, ModelVertex},
+    reflection::ShaderReflection,
+    texture::Texture,
+    uniform::{ShaderType, UniformBuffer},
+    vertex::Vertex,
+};
+
+/// The object found under a pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PickResult {
+    /// Index into `State::instances`.
+    pub instance_index: usize,
+    /// Index into the model's `meshes`.
+    pub mesh: usize,
+    pub world_position: Point3<f32>,
+    /// Depth buffer value at the pixel, in the view's projection.
+    pub depth: f32,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct PickUniform {
+    mesh: u32,
+}
+
+/// The layout of the pick shader's own group, the mesh each draw writes.
+fn pick_group_entries() -> [BindGroupLayoutEntry; 1] {
+    [BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(PickUniform::STD140.size as u64),
+        },
+        count: None,
+    }]
+}
+
+/// Copies of a single texel still need a 256 byte aligned row pitch.
+const ROW_PITCH: u64 = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64;
+
+const MAP_PENDING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+struct Request {
+    view: usize,
+    x: u32,
+    y: u32,
+}
+
+struct InFlight {
+    x: u32,
+    y: u32,
+    viewport: PixelRect,
+    inverse_view_proj: Matrix4<f32>,
+    mapping: bool,
+    state: Arc<AtomicU8>,
+}
+
+/// GPU picking through an ID buffer.
+///
+/// A pick renders the scene once more, clipped to the requested pixel, into
+/// an `R32Uint` target holding `mesh << 24 | (instance + 1)` and an `R32Float`
+/// target holding depth. Both texels are copied into a mappable buffer whose
+/// result arrives a frame or two later, so picking never stalls rendering.
+pub struct Picker {
+    id_target: Texture,
+    depth_target: Texture,
+    depth_texture: Texture,
+    layout: PipelineLayout,
+    pick_layout: BindGroupLayout,
+    pipelines: HashMap<CompareFunction, RenderPipeline>,
+    mesh_uniforms: Vec<UniformBuffer<PickUniform>>,
+    readback: Buffer,
+    requested: Option<Request>,
+    in_flight: Option<InFlight>,
+}
+
+impl Picker {
+    pub const ID_FORMAT: TextureFormat = TextureFormat::R32Uint;
+    pub const DEPTH_FORMAT: TextureFormat = TextureFormat::R32Float;
+
+    /// `camera_layout` and `camera_entries` describe the camera bind group
+    /// shared with the main shader.
+    pub fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        camera_entries: &[BindGroupLayoutEntry],
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(include_str!("../shaders/pick.wgsl"))?;
+        reflection
+            .validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])?;
+
+        let pick_entries = pick_group_entries();
+        reflection
+            .validate_bind_group_layouts(&[camera_entries, &pick_entries])
+            .context("pick.wgsl")?;
+        let pick_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Pick Bind Group Layout"),
+            entries: &pick_entries,
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pick Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &pick_layout],
+            push_constant_ranges: &[],
+        });
+
+        let (id_target, depth_target, depth_texture) = create_targets(device, width, height);
+
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("Pick Readback"),
+            size: 2 * ROW_PITCH,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            id_target,
+            depth_target,
+            depth_texture,
+            layout,
+            pick_layout,
+            pipelines: HashMap::new(),
+            mesh_uniforms: Vec::new(),
+            readback,
+            requested: None,
+            in_flight: None,
+        })
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        (self.id_target, self.depth_target, self.depth_texture) =
+            create_targets(device, width, height);
+        // The pixel of a pending request may no longer exist.
+        self.requested = None;
+    }
+
+    /// Asks for the object under surface pixel (`x`, `y`) as seen through
+    /// `view`. A newer request replaces one that has not been rendered yet.
+    pub fn request(&mut self, view: usize, x: u32, y: u32) {
+        self.requested = Some(Request { view, x, y });
+    }
+
+    /// Whether a pick was requested or is waiting for its readback.
+    pub fn is_busy(&self) -> bool {
+        self.requested.is_some() || self.in_flight.is_some()
+    }
+
+    /// Records the pick pass for the pending request, if any. The readback
+    /// buffer is in use until the result has been taken, so requests made in
+    /// the meantime wait for the next frame.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn encode(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        views: &[CameraView],
+        surface_size: (u32, u32),
+        model: &Model,
+        instance_buffer: &Buffer,
+        instance_count: u32,
+    ) {
+        if self.in_flight.is_some() {
+            return;
+        }
+        let Some(request) = self.requested.take() else {
+            return;
+        };
+        let Some(view) = views.get(request.view) else {
+            return;
+        };
+
+        let compare = view.projection.depth_compare();
+        if !self.pipelines.contains_key(&compare) {
+            let pipeline = create_pick_pipeline(device, &self.layout, compare);
+            self.pipelines.insert(compare, pipeline);
+        }
+
+        while self.mesh_uniforms.len() < model.meshes.len() {
+            let mesh = self.mesh_uniforms.len() as u32;
+            self.mesh_uniforms.push(UniformBuffer::new(
+                device,
+                &self.pick_layout,
+                PickUniform { mesh },
+                "pick_bind_group",
+            ));
+        }
+
+        let (width, height) = surface_size;
+        let viewport = view.pixel_viewport(width, height);
+        {
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Pick Pass"),
+                color_attachments: &[
+                    Some(RenderPassColorAttachment {
+                        view: &self.id_target.view,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }),
+                    Some(RenderPassColorAttachment {
+                        view: &self.depth_target.view,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: view.projection.depth_clear_value() as f64,
+                                g: 0.0,
+                                b: 0.0,
+                                a: 0.0,
+                            }),
+                            store: true,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(Operations {
+                        load: wgpu::LoadOp::Clear(view.projection.depth_clear_value()),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            pass.set_viewport(
+                viewport.x as f32,
+                viewport.y as f32,
+                viewport.width as f32,
+                viewport.height as f32,
+                0.0,
+                1.0,
+            );
+            pass.set_scissor_rect(request.x, request.y, 1, 1);
+            pass.set_pipeline(&self.pipelines[&compare]);
+            pass.set_bind_group(0, view.camera_uniform().bind_group(), &[]);
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+
+            for (mesh, uniform) in model.meshes.iter().zip(&self.mesh_uniforms) {
+                pass.set_bind_group(1, uniform.bind_group(), &[]);
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, 0..instance_count);
+            }
+        }
+
+        for (index, texture) in [&self.id_target, &self.depth_target].iter().enumerate() {
+            encoder.copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture: &texture.texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: request.x,
+                        y: request.y,
+                        z: 0,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyBuffer {
+                    buffer: &self.readback,
+                    layout: ImageDataLayout {
+                        offset: index as u64 * ROW_PITCH,
+                        bytes_per_row: NonZeroU32::new(ROW_PITCH as u32),
+                        rows_per_image: None,
+                    },
+                },
+                Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view_proj = view.projection.calc_matrix() * view.camera.calc_matrix();
+        self.in_flight = Some(InFlight {
+            x: request.x,
+            y: request.y,
+            viewport,
+            inverse_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity),
+            mapping: false,
+            state: Arc::new(AtomicU8::new(MAP_PENDING)),
+        });
+    }
+
+    /// Starts mapping the readback buffer. Must be called after the encoder
+    /// passed to [`encode`](Self::encode) has been submitted.
+    pub(crate) fn after_submit(&mut self) {
+        let Some(in_flight) = &mut self.in_flight else {
+            return;
+        };
+        if std::mem::replace(&mut in_flight.mapping, true) {
+            return;
+        }
+
+        let state = in_flight.state.clone();
+        self.readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let value = if result.is_ok() { MAP_DONE } else { MAP_FAILED };
+                state.store(value, Ordering::Release);
+            });
+    }
+
+    /// Returns the finished pick, with `Ready(None)` meaning the pixel showed
+    /// only background. The device must be polled for this to make progress.
+    pub fn poll(&mut self) -> Poll<Option<PickResult>> {
+        let Some(in_flight) = &self.in_flight else {
+            return Poll::Pending;
+        };
+
+        match in_flight.state.load(Ordering::Acquire) {
+            MAP_DONE => {}
+            MAP_FAILED => {
+                self.in_flight = None;
+                return Poll::Ready(None);
+            }
+            _ => return Poll::Pending,
+        }
+
+        let (id, depth) = {
+            let bytes = self.readback.slice(..).get_mapped_range();
+            let at = |offset: usize| -> [u8; 4] { bytes[offset..offset + 4].try_into().unwrap() };
+            (
+                u32::from_ne_bytes(at(0)),
+                f32::from_ne_bytes(at(ROW_PITCH as usize)),
+            )
+        };
+        self.readback.unmap();
+
+        let in_flight = self.in_flight.take().unwrap();
+        Poll::Ready(decode(id, depth, &in_flight))
+    }
+}
+
+fn decode(id: u32, depth: f32, in_flight: &InFlight) -> Option<PickResult> {
+    let instance = id & 0xff_ffff;
+    if instance == 0 {
+        return None;
+    }
+
+    // Back from the pixel center to normalized device coordinates, then
+    // through the inverse view-projection into the world.
+    let viewport = in_flight.viewport;
+    let ndc_x = (in_flight.x as f32 + 0.5 - viewport.x as f32) / viewport.width as f32 * 2.0 - 1.0;
+    let ndc_y = 1.0 - (in_flight.y as f32 + 0.5 - viewport.y as f32) / viewport.height as f32 * 2.0;
+    let world = in_flight.inverse_view_proj * Vector4::new(ndc_x, ndc_y, depth, 1.0);
+
+    Some(PickResult {
+        instance_index: instance as usize - 1,
+        mesh: (id >> 24) as usize,
+        world_position: Point3::from_homogeneous(world),
+        depth,
+    })
+}
+
+fn create_targets(device: &Device, width: u32, height: u32) -> (Texture, Texture, Texture) {
+    (
+        Texture::create_render_target(device, width, height, Picker::ID_FORMAT, "pick_ids"),
+        Texture::create_render_target(device, width, height, Picker::DEPTH_FORMAT, "pick_depth"),
+        Texture::create_depth_texture_sized(device, width, height, "pick_depth_texture"),
+    )
+}
+
+fn create_pick_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    depth_compare: CompareFunction,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Pick Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/pick.wgsl").into()),
+    });
+
+    let target = |format| {
+        Some(wgpu::ColorTargetState {
+            format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        })
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Pick Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[target(Picker::ID_FORMAT), target(Picker::DEPTH_FORMAT)],
+        }),
+        primitive: wgpu::PrimitiveState {
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, EuclideanSpace, InnerSpace};
+
+    use crate::camera::{camera::Camera, projection::Projection};
+
+    #[test]
+    fn pick_shader_accepts_main_camera_group() {
+        let scene = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER).unwrap();
+        let camera = &scene.bind_group_layout_entries().unwrap()[1];
+
+        let pick = ShaderReflection::from_wgsl(include_str!("../shaders/pick.wgsl")).unwrap();
+        pick.validate_bind_group_layouts(&[camera, &pick_group_entries()])
+            .unwrap();
+    }
+
+    #[test]
+    fn decode_recovers_world_position() {
+        let camera = Camera::new((0.0, 0.0, 10.0), Deg(-90.0), Deg(0.0));
+        let projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+
+        // The origin projects onto the center pixel.
+        let clip = view_proj * Point3::origin().to_homogeneous();
+        let in_flight = InFlight {
+            x: 400,
+            y: 300,
+            viewport: PixelRect {
+                x: 0,
+                y: 0,
+                width: 801,
+                height: 601,
+            },
+            inverse_view_proj: view_proj.invert().unwrap(),
+            mapping: true,
+            state: Arc::new(AtomicU8::new(MAP_DONE)),
+        };
+
+        let result = decode(3 << 24 | 42, clip.z / clip.w, &in_flight).unwrap();
+        assert_eq!(result.instance_index, 41);
+        assert_eq!(result.mesh, 3);
+        assert!(result.world_position.to_vec().magnitude() < 1e-3);
+
+        assert_eq!(decode(0, 1.0, &in_flight), None);
+    }
+}
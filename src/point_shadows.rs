@@ -0,0 +1,486 @@
+//! Omnidirectional shadows of the main light and of
+//! [`PointLight`]s that [cast one](PointLight::casts_shadow).
+//!
+//! Each shadowed light gets a cube of a depth cube array, its six faces
+//! drawn with 90° projections looking out from the light. The faces store
+//! the distance to the nearest caster divided by the light's range rather
+//! than the projected depth, so the fragment shader looks it up with the
+//! direction from the light and compares it with its own distance,
+//! whichever face the direction falls on. The model's opaque meshes and the
+//! terrain are drawn into the faces. The cubes do not depend on the camera
+//! and are drawn once per frame for every view, with the terrain at the
+//! levels it gets from the light.
+//!
+//! Cube arrays need [`wgpu::DownlevelFlags::CUBE_ARRAY_TEXTURES`], which
+//! every native backend has.
+
+use anyhow::*;
+use cgmath::{Deg, EuclideanSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Vector3};
+use wgpu::{
+    BindGroupLayout, Buffer, CommandEncoder, Device, PipelineLayout, Queue,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, TextureView,
+};
+
+use crate::{
+    camera::camera::OPENGL_TO_WGPU_MATRIX,
+    instance::InstanceRaw,
+    light::{LightUniform, PointLight},
+    model::{Model, ModelVertex},
+    reflection::ShaderReflection,
+    terrain::{Terrain, TerrainVertex},
+    texture::{TargetId, Texture},
+    uniform::{self, MemoryLayout, ShaderType, UniformBuffer},
+    vertex::Vertex,
+};
+
+/// Tuning for [`State::point_shadows`](crate::state::State::point_shadows).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointShadowSettings {
+    pub enabled: bool,
+    /// Whether the main light casts a shadow, taking the first cube.
+    pub light_shadow: bool,
+    /// How far from the main light its shadow reaches, as it has no
+    /// radius of its own. Point lights use theirs.
+    pub light_range: f32,
+    /// How many lights cast shadows, up to [`PointShadows::MAX_LIGHTS`].
+    /// Point lights past it are drawn without one.
+    pub max_lights: u32,
+    /// Width and height of each cube face.
+    pub resolution: u32,
+    /// Subtracted from the distance compared against the cube, as a
+    /// fraction of the light's range.
+    pub depth_bias: f32,
+    /// How many texels surfaces are pushed out along their normal before
+    /// looking them up.
+    pub normal_bias: f32,
+}
+
+impl Default for PointShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            light_shadow: true,
+            light_range: 50.0,
+            max_lights: 4,
+            resolution: 512,
+            depth_bias: 0.002,
+            normal_bias: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct PointShadowUniform {
+    light_layer: i32,
+    light_range: f32,
+    depth_bias: f32,
+    normal_bias: f32,
+    texel_size: f32,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct CasterUniform {
+    view_proj: [[f32; 4]; 4],
+    position: [f32; 3],
+    range: f32,
+}
+
+/// The world directions of a face's right, up and forward, in wgpu's
+/// +X, -X, +Y, -Y, +Z, -Z order. They match `cube_direction` in ibl.wgsl,
+/// which is mirrored against a right-handed view.
+const FACES: [[[f32; 3]; 3]; 6] = [
+    [[0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+    [[0.0, 0.0, 1.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]],
+    [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]],
+    [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]],
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    [[-1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]],
+];
+
+pub(crate) struct PointShadows {
+    caster_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    terrain_pipeline: RenderPipeline,
+    resolution: u32,
+    /// Cubes the map has room for.
+    capacity: u32,
+    map: wgpu::Texture,
+    map_view: TextureView,
+    /// One view per face of every cube, to draw into.
+    face_views: Vec<TextureView>,
+    uniform: Buffer,
+    /// Six per shadowed light.
+    casters: Vec<UniformBuffer<CasterUniform>>,
+    /// The position of each shadowed light this frame.
+    positions: Vec<Point3<f32>>,
+    /// The cube of each point light, or -1.
+    layers: Vec<i32>,
+    /// Replaced whenever `map` changes.
+    target_id: TargetId,
+}
+
+impl PointShadows {
+    /// Within the default limit of 256 texture array layers.
+    pub const MAX_LIGHTS: u32 = 16;
+
+    pub(crate) fn new(device: &Device) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(include_str!("../shaders/point_shadow.wgsl"))?;
+        reflection
+            .validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])?;
+        reflection.validate_vertex_layouts("vs_terrain", &[TerrainVertex::desc()])?;
+        let mut layout = reflection.create_layout(device, "Point Shadow Pipeline Layout")?;
+        let pipeline = create_caster_pipeline(
+            device,
+            &layout.pipeline_layout,
+            "vs_main",
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+        );
+        let terrain_pipeline = create_caster_pipeline(
+            device,
+            &layout.pipeline_layout,
+            "vs_terrain",
+            &[TerrainVertex::desc()],
+        );
+        let caster_layout = layout.bind_group_layouts.remove(0);
+
+        let (resolution, capacity) = (1, 1);
+        let (map, map_view, face_views) = create_map(device, resolution, capacity);
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("point_shadow_uniform"),
+            size: PointShadowUniform::STD140.size as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            caster_layout,
+            pipeline,
+            terrain_pipeline,
+            resolution,
+            capacity,
+            map,
+            map_view,
+            face_views,
+            uniform,
+            casters: Vec::new(),
+            positions: Vec::new(),
+            layers: Vec::new(),
+            target_id: TargetId::next(),
+        })
+    }
+
+    /// Picks the lights that cast shadows this frame and places their
+    /// cubes around them.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        settings: &PointShadowSettings,
+        light: &LightUniform,
+        point_lights: &[PointLight],
+    ) {
+        let (light_layer, layers) = assign_cubes(settings, point_lights);
+        let mut lights = Vec::new();
+        if light_layer >= 0 {
+            lights.push((light.position, settings.light_range));
+        }
+        lights.extend(
+            point_lights
+                .iter()
+                .zip(&layers)
+                .filter(|(_, &layer)| layer >= 0)
+                .map(|(point_light, _)| (point_light.position, point_light.radius)),
+        );
+        self.layers = layers;
+        self.positions = lights
+            .iter()
+            .map(|&(position, _)| position.into())
+            .collect();
+
+        // A single texel until a light needs more.
+        let resolution = if lights.is_empty() {
+            1
+        } else {
+            settings.resolution.max(1)
+        };
+        let capacity = (lights.len() as u32).max(1);
+        if resolution != self.resolution || capacity != self.capacity {
+            (self.map, self.map_view, self.face_views) = create_map(device, resolution, capacity);
+            self.resolution = resolution;
+            self.capacity = capacity;
+            self.target_id = TargetId::next();
+        }
+
+        while self.casters.len() < lights.len() * 6 {
+            self.casters.push(UniformBuffer::new(
+                device,
+                &self.caster_layout,
+                CasterUniform {
+                    view_proj: Matrix4::identity().into(),
+                    position: [0.0; 3],
+                    range: 1.0,
+                },
+                "point_shadow_caster",
+            ));
+        }
+        for ((position, range), casters) in lights.iter().zip(self.casters.chunks_exact_mut(6)) {
+            for (face, caster) in casters.iter_mut().enumerate() {
+                caster.set(CasterUniform {
+                    view_proj: face_view_proj(face, (*position).into(), *range).into(),
+                    position: *position,
+                    range: *range,
+                });
+                caster.update(queue);
+            }
+        }
+
+        let params = PointShadowUniform {
+            light_layer,
+            light_range: settings.light_range,
+            depth_bias: settings.depth_bias,
+            normal_bias: settings.normal_bias,
+            texel_size: 2.0 / resolution as f32,
+        };
+        queue.write_buffer(
+            &self.uniform,
+            0,
+            &uniform::to_bytes(&params, MemoryLayout::Std140),
+        );
+    }
+
+    /// The cube of point light `index`, or -1 if it casts no shadow.
+    pub(crate) fn layer(&self, index: usize) -> i32 {
+        self.layers.get(index).copied().unwrap_or(-1)
+    }
+
+    /// Records the faces of every cube [`prepare`](Self::prepare) placed.
+    pub(crate) fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        model: &Model,
+        instance_buffer: &Buffer,
+        instance_count: u32,
+        terrain: Option<&Terrain>,
+    ) {
+        let faces = self.face_views.iter().zip(&self.casters);
+        for (face, (face_view, caster)) in faces.take(self.positions.len() * 6).enumerate() {
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Point Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: face_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, caster.bind_group(), &[]);
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for (mesh, _) in model.meshes_by_transparency(false) {
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, 0..instance_count);
+            }
+            if let Some(terrain) = terrain {
+                pass.set_pipeline(&self.terrain_pipeline);
+                terrain.draw_chunks(&mut pass, self.positions[face / 6]);
+            }
+        }
+    }
+
+    /// Changes whenever the resources of
+    /// [`PointShadows::bind_group_entries`] do.
+    pub(crate) fn target_id(&self) -> TargetId {
+        self.target_id
+    }
+
+    /// Bindings 8 and 9 of the scene's light group.
+    pub(crate) fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 2] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::TextureView(&self.map_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: self.uniform.as_entire_binding(),
+            },
+        ]
+    }
+}
+
+/// The main light's cube, and that of each of `point_lights`, with -1 for
+/// those without.
+fn assign_cubes(settings: &PointShadowSettings, point_lights: &[PointLight]) -> (i32, Vec<i32>) {
+    let max_lights = if settings.enabled {
+        settings.max_lights.min(PointShadows::MAX_LIGHTS) as i32
+    } else {
+        0
+    };
+    let mut next = 0;
+    let mut assign = |shadowed: bool| {
+        if shadowed && next < max_lights {
+            next += 1;
+            next - 1
+        } else {
+            -1
+        }
+    };
+    let light_layer = assign(settings.light_shadow);
+    let layers = point_lights
+        .iter()
+        .map(|point_light| assign(point_light.casts_shadow))
+        .collect();
+    (light_layer, layers)
+}
+
+fn create_map(
+    device: &Device,
+    resolution: u32,
+    cubes: u32,
+) -> (wgpu::Texture, TextureView, Vec<TextureView>) {
+    let map = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("point_shadow_map"),
+        size: wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: cubes * 6,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: Texture::DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = map.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::CubeArray),
+        ..Default::default()
+    });
+    let faces = (0..cubes * 6)
+        .map(|layer| {
+            map.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            })
+        })
+        .collect();
+    (map, view, faces)
+}
+
+/// Projects face `face` of the cube around `position`, out to `range`.
+fn face_view_proj(face: usize, position: Point3<f32>, range: f32) -> Matrix4<f32> {
+    let [right, up, forward] = FACES[face].map(Vector3::from);
+    let rotation = Matrix3::from_cols(right, up, -forward).transpose();
+    let view = Matrix4::from(rotation) * Matrix4::from_translation(-position.to_vec());
+    let projection = cgmath::perspective(Deg(90.0), 1.0, range * 1e-3, range);
+    OPENGL_TO_WGPU_MATRIX * projection * view
+}
+
+/// The pipeline drawing casters' distance from the light.
+fn create_caster_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    entry_point: &str,
+    buffers: &[wgpu::VertexBufferLayout],
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Point Shadow Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/point_shadow.wgsl").into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Point Shadow Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point,
+            buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Transform;
+
+    use super::*;
+
+    #[test]
+    fn faces_match_the_cube_lookup() {
+        let position = Point3::new(1.0, 2.0, 3.0);
+        for (face, [right, up, forward]) in FACES.iter().enumerate() {
+            let view_proj = face_view_proj(face, position, 10.0);
+            for (x, y) in [(0.0, 0.0), (0.5, -0.25), (-0.9, 0.9)] {
+                // The direction ibl.wgsl gives the pixel at NDC (x, y).
+                let direction =
+                    Vector3::from(*right) * x + Vector3::from(*up) * y + Vector3::from(*forward);
+                let ndc = view_proj.transform_point(position + direction * 2.0);
+                assert!((ndc.x - x).abs() < 1e-4, "face {face}: {ndc:?}");
+                assert!((ndc.y - y).abs() < 1e-4, "face {face}: {ndc:?}");
+                assert!(ndc.z > 0.0 && ndc.z < 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn cubes_go_to_the_first_shadowed_lights() {
+        let point_light = |casts_shadow| PointLight {
+            position: [0.0; 3],
+            radius: 1.0,
+            color: [1.0; 3],
+            casts_shadow,
+        };
+        let lights = [true, false, true, true].map(point_light);
+        let settings = PointShadowSettings {
+            max_lights: 3,
+            ..Default::default()
+        };
+        assert_eq!(assign_cubes(&settings, &lights), (0, vec![1, -1, 2, -1]));
+
+        let settings = PointShadowSettings {
+            light_shadow: false,
+            ..settings
+        };
+        assert_eq!(assign_cubes(&settings, &lights), (-1, vec![0, -1, 1, 2]));
+        let settings = PointShadowSettings {
+            enabled: false,
+            ..settings
+        };
+        assert_eq!(assign_cubes(&settings, &lights), (-1, vec![-1; 4]));
+    }
+
+    #[test]
+    fn casters_match_model_and_terrain_vertices() {
+        let reflection =
+            ShaderReflection::from_wgsl(include_str!("../shaders/point_shadow.wgsl")).unwrap();
+        reflection
+            .validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])
+            .unwrap();
+        reflection
+            .validate_vertex_layouts("vs_terrain", &[TerrainVertex::desc()])
+            .unwrap();
+    }
+}
@@ -0,0 +1,525 @@
+//! Procedurally generated meshes with normals, texture coordinates and
+//! tangents, centred on the origin with +Y up.
+//!
+//! ```ignore
+//! let sphere = MeshData::uv_sphere(0.5, 32, 16).into_model(&device, "sphere", material);
+//! ```
+
+use std::{
+    collections::HashMap,
+    f32::consts::{FRAC_PI_2, PI, TAU},
+};
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::model::{compute_tangents, Material, Mesh, Model, ModelVertex};
+
+/// Vertices and triangle indices of a mesh on the CPU. Triangles wind
+/// counter-clockwise when seen from the side their normals point to.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// A `size` x `size` square in the XZ plane facing +Y.
+    pub fn plane(size: f32) -> Self {
+        Self::grid(size, size, 1, 1)
+    }
+
+    /// A `width` x `depth` rectangle in the XZ plane facing +Y, split into
+    /// `columns` x `rows` quads. Texture coordinates span the whole grid.
+    pub fn grid(width: f32, depth: f32, columns: u32, rows: u32) -> Self {
+        let mut data = Self::default();
+        data.push_surface(columns.max(1), rows.max(1), |u, v| {
+            (
+                Vector3::new((u - 0.5) * width, 0.0, (v - 0.5) * depth),
+                Vector3::unit_y(),
+            )
+        });
+        data.finish()
+    }
+
+    /// An axis aligned cube with `size` long edges. Every face has its own
+    /// vertices and the whole texture, upright on the side faces.
+    pub fn cube(size: f32) -> Self {
+        // Normal, then the directions u and v grow in as seen from outside.
+        let faces = [
+            (Vector3::unit_x(), -Vector3::unit_z(), -Vector3::unit_y()),
+            (-Vector3::unit_x(), Vector3::unit_z(), -Vector3::unit_y()),
+            (Vector3::unit_z(), Vector3::unit_x(), -Vector3::unit_y()),
+            (-Vector3::unit_z(), -Vector3::unit_x(), -Vector3::unit_y()),
+            (Vector3::unit_y(), Vector3::unit_x(), Vector3::unit_z()),
+            (-Vector3::unit_y(), Vector3::unit_x(), -Vector3::unit_z()),
+        ];
+
+        let mut data = Self::default();
+        for (normal, right, down) in faces {
+            data.push_surface(1, 1, |u, v| {
+                let position = (normal * 0.5 + right * (u - 0.5) + down * (v - 0.5)) * size;
+                (position, normal)
+            });
+        }
+        data.finish()
+    }
+
+    /// A sphere made of `sectors` slices around Y and `stacks` rings from
+    /// pole to pole, textured with an equirectangular map.
+    pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Self {
+        let stacks = stacks.max(2);
+        let profile = (0..=stacks)
+            .map(|stack| {
+                let angle = stack as f32 / stacks as f32 * PI;
+                let (sin, cos) = angle.sin_cos();
+                ProfilePoint {
+                    radius: radius * sin,
+                    y: radius * cos,
+                    normal: [sin, cos],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut data = Self::default();
+        data.push_revolution(sectors, &profile);
+        data.finish()
+    }
+
+    /// A sphere made by splitting each triangle of an icosahedron into four
+    /// `subdivisions` times, so triangles are close to equal in size. Texture
+    /// coordinates use the same equirectangular mapping as [`Self::uv_sphere`].
+    pub fn icosphere(radius: f32, subdivisions: u32) -> Self {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut points = [
+            [-1.0, t, 0.0],
+            [1.0, t, 0.0],
+            [-1.0, -t, 0.0],
+            [1.0, -t, 0.0],
+            [0.0, -1.0, t],
+            [0.0, 1.0, t],
+            [0.0, -1.0, -t],
+            [0.0, 1.0, -t],
+            [t, 0.0, -1.0],
+            [t, 0.0, 1.0],
+            [-t, 0.0, -1.0],
+            [-t, 0.0, 1.0],
+        ]
+        .map(|p| Vector3::from(p).normalize())
+        .to_vec();
+        let mut triangles = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: usize, b: usize| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    points.push(((points[a] + points[b]) / 2.0).normalize());
+                    points.len() - 1
+                })
+            };
+            triangles = triangles
+                .into_iter()
+                .flat_map(|[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        let mut data = Self::default();
+        // Points on the texture seam or at a pole get one vertex per distinct
+        // texture coordinate.
+        let mut vertices = HashMap::new();
+        for triangle in triangles {
+            let mut uvs = triangle.map(|index| equirectangular(points[index]));
+            // Triangles straddling the seam take the u > 1 side of it.
+            let us = uvs.map(|[u, _]| u);
+            if us.iter().cloned().fold(f32::MIN, f32::max)
+                - us.iter().cloned().fold(f32::MAX, f32::min)
+                > 0.5
+            {
+                for uv in &mut uvs {
+                    if uv[0] < 0.5 {
+                        uv[0] += 1.0;
+                    }
+                }
+            }
+            // The longitude of a pole is arbitrary, so take the one of the
+            // opposite edge.
+            for corner in 0..3 {
+                if points[triangle[corner]].y.abs() > 1.0 - 1e-6 {
+                    uvs[corner][0] = (uvs[(corner + 1) % 3][0] + uvs[(corner + 2) % 3][0]) / 2.0;
+                }
+            }
+
+            let [a, b, c] = [0, 1, 2].map(|corner| {
+                let point = points[triangle[corner]];
+                let uv = uvs[corner];
+                *vertices
+                    .entry((triangle[corner], uv[0].to_bits()))
+                    .or_insert_with(|| data.push_vertex(point * radius, point, uv))
+            });
+            data.push_triangle(a, b, c);
+        }
+        data.finish()
+    }
+
+    /// A cylinder around Y with flat caps. The side wraps the texture once
+    /// around; each cap maps a disc inscribed in it.
+    pub fn cylinder(radius: f32, height: f32, segments: u32) -> Self {
+        let half = height / 2.0;
+        let mut data = Self::default();
+        data.push_revolution(
+            segments,
+            &[
+                ProfilePoint {
+                    radius,
+                    y: half,
+                    normal: [1.0, 0.0],
+                },
+                ProfilePoint {
+                    radius,
+                    y: -half,
+                    normal: [1.0, 0.0],
+                },
+            ],
+        );
+        data.push_disc(radius, half, segments);
+        data.push_disc(radius, -half, segments);
+        data.finish()
+    }
+
+    /// A cone around Y with its apex at `height / 2` and a flat base.
+    pub fn cone(radius: f32, height: f32, segments: u32) -> Self {
+        let half = height / 2.0;
+        let slope = Vector3::new(height, radius, 0.0).normalize();
+        let normal = [slope.x, slope.y];
+        let mut data = Self::default();
+        data.push_revolution(
+            segments,
+            &[
+                ProfilePoint {
+                    radius: 0.0,
+                    y: half,
+                    normal,
+                },
+                ProfilePoint {
+                    radius,
+                    y: -half,
+                    normal,
+                },
+            ],
+        );
+        data.push_disc(radius, -half, segments);
+        data.finish()
+    }
+
+    /// A cylinder around Y capped with hemispheres, `height` tall overall.
+    /// `rings` is the number of rings in each hemisphere.
+    pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Self {
+        let rings = rings.max(1);
+        let half = (height / 2.0 - radius).max(0.0);
+        let hemisphere = |center: f32, from: f32| {
+            (0..=rings).map(move |ring| {
+                let angle = from + ring as f32 / rings as f32 * FRAC_PI_2;
+                let (sin, cos) = angle.sin_cos();
+                ProfilePoint {
+                    radius: radius * sin,
+                    y: center + radius * cos,
+                    normal: [sin, cos],
+                }
+            })
+        };
+        let profile = hemisphere(half, 0.0)
+            .chain(hemisphere(-half, FRAC_PI_2))
+            .collect::<Vec<_>>();
+
+        let mut data = Self::default();
+        data.push_revolution(segments, &profile);
+        data.finish()
+    }
+
+    /// A ring around Y whose tube of `minor_radius` follows a circle of
+    /// `major_radius`.
+    pub fn torus(
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u32,
+        minor_segments: u32,
+    ) -> Self {
+        let minor_segments = minor_segments.max(3);
+        let profile = (0..=minor_segments)
+            .map(|segment| {
+                let angle = segment as f32 / minor_segments as f32 * TAU;
+                let (sin, cos) = angle.sin_cos();
+                ProfilePoint {
+                    radius: major_radius + minor_radius * cos,
+                    y: minor_radius * sin,
+                    normal: [cos, sin],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut data = Self::default();
+        data.push_revolution(major_segments, &profile);
+        data.finish()
+    }
+
+    /// Uploads the data as a mesh drawn with `material`, an index into the
+    /// materials of the model it will belong to.
+    pub fn to_mesh(&self, device: &wgpu::Device, name: &str, material: usize) -> Mesh {
+        Mesh::new(device, name, &self.vertices, &self.indices, material)
+    }
+
+    /// Uploads the data as a single mesh model drawn with `material`.
+    pub fn into_model(self, device: &wgpu::Device, name: &str, material: Material) -> Model {
+        Model {
+            meshes: vec![self.to_mesh(device, name, 0)],
+            materials: vec![material],
+        }
+    }
+
+    fn push_vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, uv: [f32; 2]) -> u32 {
+        self.vertices.push(ModelVertex {
+            position: position.into(),
+            tex_coords: uv,
+            normal: normal.into(),
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        });
+        self.vertices.len() as u32 - 1
+    }
+
+    /// Adds a triangle, wound to face the way its vertex normals point.
+    /// Triangles without area, like those at the tip of a cone, are dropped.
+    fn push_triangle(&mut self, a: u32, b: u32, c: u32) {
+        let [pa, pb, pc] = [a, b, c].map(|i| Vector3::from(self.vertices[i as usize].position));
+        let face = (pb - pa).cross(pc - pa);
+        let longest = (pb - pa)
+            .magnitude2()
+            .max((pc - pb).magnitude2())
+            .max((pa - pc).magnitude2());
+        if face.magnitude2() <= longest * longest * 1e-10 {
+            return;
+        }
+
+        let normal = [a, b, c]
+            .iter()
+            .map(|&i| Vector3::from(self.vertices[i as usize].normal))
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, n| sum + n);
+        if face.dot(normal) >= 0.0 {
+            self.indices.extend([a, b, c]);
+        } else {
+            self.indices.extend([a, c, b]);
+        }
+    }
+
+    /// Adds a `columns` x `rows` patch of quads whose corners `f` places from
+    /// their texture coordinates, returning a position and a normal.
+    fn push_surface(
+        &mut self,
+        columns: u32,
+        rows: u32,
+        f: impl Fn(f32, f32) -> (Vector3<f32>, Vector3<f32>),
+    ) {
+        self.push_surface_with(columns, rows, |u, row| {
+            let v = row as f32 / rows as f32;
+            let (position, normal) = f(u, v);
+            (position, normal, v)
+        })
+    }
+
+    /// Like [`Self::push_surface`], but `f` gets the row index and returns the
+    /// v texture coordinate too, so rows need not be evenly spaced.
+    fn push_surface_with(
+        &mut self,
+        columns: u32,
+        rows: u32,
+        f: impl Fn(f32, u32) -> (Vector3<f32>, Vector3<f32>, f32),
+    ) {
+        let first = self.vertices.len() as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let u = column as f32 / columns as f32;
+                let (position, normal, v) = f(u, row);
+                self.push_vertex(position, normal, [u, v]);
+            }
+        }
+
+        let stride = columns + 1;
+        for row in 0..rows {
+            for column in 0..columns {
+                let a = first + row * stride + column;
+                let (b, c, d) = (a + stride, a + 1, a + stride + 1);
+                self.push_triangle(a, b, c);
+                self.push_triangle(c, b, d);
+            }
+        }
+    }
+
+    /// Sweeps `profile` around Y in `segments` steps. The v texture
+    /// coordinate follows the length of the profile.
+    fn push_revolution(&mut self, segments: u32, profile: &[ProfilePoint]) {
+        let segments = segments.max(3);
+        let mut lengths = vec![0.0; profile.len()];
+        for i in 1..profile.len() {
+            let (a, b) = (&profile[i - 1], &profile[i]);
+            lengths[i] = lengths[i - 1] + (b.radius - a.radius).hypot(b.y - a.y);
+        }
+        let total = lengths.last().cloned().unwrap_or(0.0).max(f32::EPSILON);
+
+        let rows = profile.len() as u32 - 1;
+        self.push_surface_with(segments, rows, |u, row| {
+            let row = row as usize;
+            let point = &profile[row];
+            // Counter-clockwise seen from +Y, so textures read left to right
+            // from outside.
+            let (sin, cos) = (u * TAU).sin_cos();
+            let position = Vector3::new(point.radius * cos, point.y, -point.radius * sin);
+            let [radial, up] = point.normal;
+            let normal = Vector3::new(radial * cos, up, -radial * sin).normalize();
+            (position, normal, lengths[row] / total)
+        });
+    }
+
+    /// Adds a flat disc at height `y` facing away from the origin.
+    fn push_disc(&mut self, radius: f32, y: f32, segments: u32) {
+        let segments = segments.max(3);
+        let normal = Vector3::unit_y() * y.signum();
+        let center = self.push_vertex(Vector3::new(0.0, y, 0.0), normal, [0.5, 0.5]);
+        for segment in 0..segments {
+            let (sin, cos) = (segment as f32 / segments as f32 * TAU).sin_cos();
+            let position = Vector3::new(radius * cos, y, -radius * sin);
+            self.push_vertex(
+                position,
+                normal,
+                [0.5 + 0.5 * cos, 0.5 - 0.5 * sin * y.signum()],
+            );
+        }
+        for segment in 0..segments {
+            let next = (segment + 1) % segments;
+            self.push_triangle(center, center + 1 + segment, center + 1 + next);
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        compute_tangents(&mut self.vertices, &self.indices);
+        // Averaging over the flat triangles of a curved surface tilts the
+        // tangents slightly, so make them perpendicular to the exact normals.
+        for v in &mut self.vertices {
+            let normal = Vector3::from(v.normal);
+            let tangent = Vector3::from(v.tangent);
+            let bitangent = Vector3::from(v.bitangent);
+            if tangent.magnitude2() == 0.0 {
+                continue;
+            }
+            let tangent = (tangent - normal * normal.dot(tangent)).normalize();
+            let bitangent = bitangent - normal * normal.dot(bitangent);
+            let bitangent = (bitangent - tangent * tangent.dot(bitangent)).normalize();
+            v.tangent = tangent.into();
+            v.bitangent = bitangent.into();
+        }
+        self
+    }
+}
+
+/// A point of the outline swept by [`MeshData::push_revolution`], with its
+/// outward normal in the same (radius, y) plane.
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: [f32; 2],
+}
+
+/// Texture coordinates of a point on the unit sphere, matching the sweep of
+/// [`MeshData::push_revolution`].
+fn equirectangular(point: Vector3<f32>) -> [f32; 2] {
+    let u = (-point.z).atan2(point.x) / TAU;
+    [u.rem_euclid(1.0), point.y.clamp(-1.0, 1.0).acos() / PI]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all() -> Vec<(&'static str, MeshData)> {
+        vec![
+            ("plane", MeshData::plane(2.0)),
+            ("grid", MeshData::grid(3.0, 2.0, 4, 3)),
+            ("cube", MeshData::cube(2.0)),
+            ("uv_sphere", MeshData::uv_sphere(1.0, 16, 8)),
+            ("icosphere", MeshData::icosphere(1.0, 2)),
+            ("cylinder", MeshData::cylinder(1.0, 2.0, 12)),
+            ("cone", MeshData::cone(1.0, 2.0, 12)),
+            ("capsule", MeshData::capsule(0.5, 2.0, 12, 4)),
+            ("torus", MeshData::torus(1.0, 0.25, 16, 8)),
+        ]
+    }
+
+    #[test]
+    fn normals_tangents_and_winding_agree() {
+        for (name, data) in all() {
+            assert_eq!(data.indices.len() % 3, 0, "{}", name);
+            for triangle in data.indices.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| data.vertices[triangle[i] as usize]);
+                let face = (Vector3::from(b.position) - Vector3::from(a.position))
+                    .cross(Vector3::from(c.position) - Vector3::from(a.position));
+                for v in [a, b, c] {
+                    let normal = Vector3::from(v.normal);
+                    let tangent = Vector3::from(v.tangent);
+                    assert!((normal.magnitude() - 1.0).abs() < 1e-4, "{}", name);
+                    assert!(face.dot(normal) > 0.0, "{} winds inwards", name);
+                    assert!((tangent.magnitude() - 1.0).abs() < 1e-4, "{}", name);
+                    assert!(
+                        tangent.dot(normal).abs() < 1e-4,
+                        "{} tangent is not in the surface",
+                        name
+                    );
+                    assert!(v.tex_coords.iter().all(|c| c.is_finite()), "{}", name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn closed_shapes_enclose_their_volume() {
+        // Sum of the signed volumes of the tetrahedra spanned with the origin.
+        let volume = |data: &MeshData| {
+            data.indices
+                .chunks(3)
+                .map(|t| {
+                    let [a, b, c] =
+                        [0, 1, 2].map(|i| Vector3::from(data.vertices[t[i] as usize].position));
+                    a.dot(b.cross(c)) / 6.0
+                })
+                .sum::<f32>()
+        };
+        assert!((volume(&MeshData::cube(2.0)) - 8.0).abs() < 1e-4);
+        let sphere = 4.0 / 3.0 * PI;
+        assert!((volume(&MeshData::uv_sphere(1.0, 64, 32)) - sphere).abs() < 0.05);
+        assert!((volume(&MeshData::icosphere(1.0, 4)) - sphere).abs() < 0.05);
+        let cylinder = PI * 2.0;
+        assert!((volume(&MeshData::cylinder(1.0, 2.0, 128)) - cylinder).abs() < 0.01);
+        assert!((volume(&MeshData::cone(1.0, 2.0, 128)) - cylinder / 3.0).abs() < 0.01);
+    }
+}
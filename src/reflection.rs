@@ -0,0 +1,605 @@
+use std::num::{NonZeroU32, NonZeroU64};
+
+use anyhow::{anyhow, bail, Context, Result};
+use naga::{
+    valid::{Capabilities, ModuleInfo, ValidationFlags, Validator},
+    AddressSpace, Binding, ImageClass, ImageDimension, Module, ScalarKind, ShaderStage,
+    StorageAccess, StorageFormat, TypeInner, VectorSize,
+};
+use wgpu::{
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    BufferBindingType, Device, PipelineLayout, PipelineLayoutDescriptor, SamplerBindingType,
+    ShaderStages, StorageTextureAccess, TextureFormat, TextureSampleType, TextureViewDimension,
+    VertexBufferLayout, VertexFormat,
+};
+
+/// A single `@location(n)` input of a vertex entry point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexInput {
+    pub name: Option<String>,
+    pub location: u32,
+    pub kind: ScalarKind,
+    pub components: u32,
+}
+
+/// Parses a WGSL source into a naga module, rendering parse errors with the
+/// offending source span.
+pub fn parse_wgsl(source: &str) -> Result<Module> {
+    naga::front::wgsl::parse_str(source).map_err(|e| anyhow!(e.emit_to_string(source)))
+}
+
+/// Collects the `@location` inputs of the vertex entry point `entry_point`,
+/// flattening struct arguments like `VertexInput` and `InstanceInput`.
+pub fn vertex_inputs(module: &Module, entry_point: &str) -> Result<Vec<VertexInput>> {
+    let entry = module
+        .entry_points
+        .iter()
+        .find(|ep| ep.stage == ShaderStage::Vertex && ep.name == entry_point)
+        .ok_or_else(|| anyhow!("shader has no vertex entry point named `{}`", entry_point))?;
+
+    let mut inputs = Vec::new();
+    for argument in &entry.function.arguments {
+        match &module.types[argument.ty].inner {
+            TypeInner::Struct { members, .. } => {
+                for member in members {
+                    push_input(
+                        module,
+                        &mut inputs,
+                        &member.name,
+                        member.ty,
+                        &member.binding,
+                    )?;
+                }
+            }
+            _ => push_input(
+                module,
+                &mut inputs,
+                &argument.name,
+                argument.ty,
+                &argument.binding,
+            )?,
+        }
+    }
+
+    inputs.sort_by_key(|input| input.location);
+    Ok(inputs)
+}
+
+fn push_input(
+    module: &Module,
+    inputs: &mut Vec<VertexInput>,
+    name: &Option<String>,
+    ty: naga::Handle<naga::Type>,
+    binding: &Option<Binding>,
+) -> Result<()> {
+    let location = match binding {
+        Some(Binding::Location { location, .. }) => *location,
+        _ => return Ok(()),
+    };
+
+    let (kind, components) = match module.types[ty].inner {
+        TypeInner::Scalar { kind, .. } => (kind, 1),
+        TypeInner::Vector { kind, size, .. } => (kind, vector_size(size)),
+        ref other => bail!(
+            "vertex input {:?} at location {} has unsupported type {:?}",
+            name,
+            location,
+            other
+        ),
+    };
+
+    inputs.push(VertexInput {
+        name: name.clone(),
+        location,
+        kind,
+        components,
+    });
+
+    Ok(())
+}
+
+/// Checks that every `@location` input of `entry_point` is provided by one of
+/// `layouts` with a format of the same scalar kind and component count.
+pub fn validate_vertex_layouts(
+    module: &Module,
+    entry_point: &str,
+    layouts: &[VertexBufferLayout],
+) -> Result<()> {
+    for input in vertex_inputs(module, entry_point)? {
+        let attribute = layouts
+            .iter()
+            .flat_map(|layout| layout.attributes.iter())
+            .find(|attribute| attribute.shader_location == input.location)
+            .ok_or_else(|| {
+                anyhow!(
+                    "vertex input {:?} at location {} is not provided by any vertex buffer",
+                    input.name,
+                    input.location
+                )
+            })?;
+
+        let (kind, components) = format_shape(attribute.format);
+        if kind != input.kind || components != input.components {
+            bail!(
+                "vertex input {:?} at location {} expects {} {:?} component(s), but the vertex buffer provides {:?}",
+                input.name,
+                input.location,
+                input.components,
+                input.kind,
+                attribute.format
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// A validated shader module together with the resources its entry points
+/// bind, used to derive bind group and pipeline layouts instead of writing
+/// them out by hand.
+pub struct ShaderReflection {
+    module: Module,
+    info: ModuleInfo,
+}
+
+/// Layouts derived from a [`ShaderReflection`]. `entries[n]` describes
+/// `@group(n)` and is kept around so other pipelines sharing the same bind
+/// groups can be validated against it.
+pub struct ReflectedLayout {
+    pub entries: Vec<Vec<BindGroupLayoutEntry>>,
+    pub bind_group_layouts: Vec<BindGroupLayout>,
+    pub pipeline_layout: PipelineLayout,
+}
+
+impl ShaderReflection {
+    pub fn from_wgsl(source: &str) -> Result<Self> {
+        let module = parse_wgsl(source)?;
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .map_err(|e| anyhow!(e.emit_to_string(source)))?;
+
+        Ok(Self { module, info })
+    }
+
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    pub fn vertex_inputs(&self, entry_point: &str) -> Result<Vec<VertexInput>> {
+        vertex_inputs(&self.module, entry_point)
+    }
+
+    pub fn validate_vertex_layouts(
+        &self,
+        entry_point: &str,
+        layouts: &[VertexBufferLayout],
+    ) -> Result<()> {
+        validate_vertex_layouts(&self.module, entry_point, layouts)
+    }
+
+    /// Derives the bind group layout entries for every `@group` the shader
+    /// declares. Visibility is the set of stages whose entry points actually
+    /// use a binding. Float textures are assumed to be filterable, as the
+    /// shader alone cannot tell.
+    pub fn bind_group_layout_entries(&self) -> Result<Vec<Vec<BindGroupLayoutEntry>>> {
+        let mut groups: Vec<Vec<BindGroupLayoutEntry>> = Vec::new();
+
+        for (handle, global) in self.module.global_variables.iter() {
+            let binding = match &global.binding {
+                Some(binding) => binding,
+                None => continue,
+            };
+
+            let mut visibility = ShaderStages::NONE;
+            for (i, entry_point) in self.module.entry_points.iter().enumerate() {
+                if !self.info.get_entry_point(i)[handle].is_empty() {
+                    visibility |= stage_flags(entry_point.stage);
+                }
+            }
+
+            let (ty, count) = match self.module.types[global.ty].inner {
+                TypeInner::BindingArray { base, size } => {
+                    let count = match size {
+                        naga::ArraySize::Constant(c) => match self.module.constants[c].inner {
+                            naga::ConstantInner::Scalar {
+                                value: naga::ScalarValue::Uint(n),
+                                ..
+                            } => NonZeroU32::new(n as u32),
+                            naga::ConstantInner::Scalar {
+                                value: naga::ScalarValue::Sint(n),
+                                ..
+                            } => NonZeroU32::new(n as u32),
+                            _ => None,
+                        },
+                        naga::ArraySize::Dynamic => None,
+                    };
+                    (base, count)
+                }
+                _ => (global.ty, None),
+            };
+
+            let entry = BindGroupLayoutEntry {
+                binding: binding.binding,
+                visibility,
+                ty: self
+                    .binding_type(global.space, ty)
+                    .with_context(|| format!("{:?} in @group({})", global.name, binding.group))?,
+                count,
+            };
+
+            let group = binding.group as usize;
+            if groups.len() <= group {
+                groups.resize_with(group + 1, Vec::new);
+            }
+            groups[group].push(entry);
+        }
+
+        for entries in &mut groups {
+            entries.sort_by_key(|entry| entry.binding);
+        }
+
+        Ok(groups)
+    }
+
+    /// Creates one bind group layout per `@group` and a pipeline layout that
+    /// uses them in order.
+    pub fn create_layout(&self, device: &Device, label: &str) -> Result<ReflectedLayout> {
+        let entries = self.bind_group_layout_entries()?;
+
+        let bind_group_layouts = entries
+            .iter()
+            .enumerate()
+            .map(|(group, entries)| {
+                device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some(&format!("{} group {}", label, group)),
+                    entries,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+
+        Ok(ReflectedLayout {
+            entries,
+            bind_group_layouts,
+            pipeline_layout,
+        })
+    }
+
+    /// Checks that Rust-side bind group layouts, given in `@group` order, can
+    /// serve every binding the shader declares.
+    pub fn validate_bind_group_layouts(&self, groups: &[&[BindGroupLayoutEntry]]) -> Result<()> {
+        for (group, expected) in self.bind_group_layout_entries()?.iter().enumerate() {
+            if expected.is_empty() {
+                continue;
+            }
+
+            let provided = groups.get(group).ok_or_else(|| {
+                anyhow!(
+                    "shader uses @group({}), but only {} bind group layout(s) were provided",
+                    group,
+                    groups.len()
+                )
+            })?;
+
+            for expected in expected {
+                let entry = provided
+                    .iter()
+                    .find(|entry| entry.binding == expected.binding)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "shader expects @group({}) @binding({}) as {:?}, but the bind group layout has no such binding",
+                            group,
+                            expected.binding,
+                            expected.ty
+                        )
+                    })?;
+
+                if !binding_types_compatible(&entry.ty, &expected.ty) {
+                    bail!(
+                        "@group({}) @binding({}) is {:?} in the bind group layout, but the shader expects {:?}",
+                        group,
+                        expected.binding,
+                        entry.ty,
+                        expected.ty
+                    );
+                }
+
+                if !entry.visibility.contains(expected.visibility) {
+                    bail!(
+                        "@group({}) @binding({}) is visible to {:?}, but the shader uses it from {:?}",
+                        group,
+                        expected.binding,
+                        entry.visibility,
+                        expected.visibility
+                    );
+                }
+
+                if entry.count != expected.count {
+                    bail!(
+                        "@group({}) @binding({}) has count {:?}, but the shader declares {:?}",
+                        group,
+                        expected.binding,
+                        entry.count,
+                        expected.count
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn binding_type(
+        &self,
+        space: AddressSpace,
+        ty: naga::Handle<naga::Type>,
+    ) -> Result<BindingType> {
+        let inner = &self.module.types[ty].inner;
+
+        Ok(match (space, inner) {
+            (AddressSpace::Uniform, _) => BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: NonZeroU64::new(inner.size(&self.module.constants) as u64),
+            },
+            (AddressSpace::Storage { access }, _) => BindingType::Buffer {
+                ty: BufferBindingType::Storage {
+                    read_only: !access.contains(StorageAccess::STORE),
+                },
+                has_dynamic_offset: false,
+                min_binding_size: NonZeroU64::new(inner.size(&self.module.constants) as u64),
+            },
+            (AddressSpace::Handle, TypeInner::Sampler { comparison }) => {
+                BindingType::Sampler(if *comparison {
+                    SamplerBindingType::Comparison
+                } else {
+                    SamplerBindingType::Filtering
+                })
+            }
+            (
+                AddressSpace::Handle,
+                TypeInner::Image {
+                    dim,
+                    arrayed,
+                    class,
+                },
+            ) => {
+                let view_dimension = view_dimension(*dim, *arrayed);
+                match *class {
+                    ImageClass::Sampled { kind, multi } => BindingType::Texture {
+                        sample_type: match kind {
+                            ScalarKind::Float => TextureSampleType::Float { filterable: !multi },
+                            ScalarKind::Sint => TextureSampleType::Sint,
+                            ScalarKind::Uint => TextureSampleType::Uint,
+                            ScalarKind::Bool => bail!("boolean textures are not supported"),
+                        },
+                        view_dimension,
+                        multisampled: multi,
+                    },
+                    ImageClass::Depth { multi } => BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension,
+                        multisampled: multi,
+                    },
+                    ImageClass::Storage { format, access } => BindingType::StorageTexture {
+                        access: if access.contains(StorageAccess::LOAD | StorageAccess::STORE) {
+                            StorageTextureAccess::ReadWrite
+                        } else if access.contains(StorageAccess::STORE) {
+                            StorageTextureAccess::WriteOnly
+                        } else {
+                            StorageTextureAccess::ReadOnly
+                        },
+                        format: storage_format(format),
+                        view_dimension,
+                    },
+                }
+            }
+            (space, other) => bail!("unsupported binding {:?} in {:?} space", other, space),
+        })
+    }
+}
+
+/// Whether a binding declared on the Rust side can be used where the shader
+/// expects `expected`. Buffers only need to be at least as large as the
+/// shader's struct, and any float texture serves a float binding.
+fn binding_types_compatible(provided: &BindingType, expected: &BindingType) -> bool {
+    match (provided, expected) {
+        (
+            BindingType::Buffer {
+                ty,
+                min_binding_size,
+                ..
+            },
+            BindingType::Buffer {
+                ty: expected_ty,
+                min_binding_size: expected_size,
+                ..
+            },
+        ) => {
+            ty == expected_ty
+                && match (min_binding_size, expected_size) {
+                    (Some(size), Some(expected)) => size >= expected,
+                    _ => true,
+                }
+        }
+        (BindingType::Sampler(_), BindingType::Sampler(SamplerBindingType::Comparison)) => {
+            *provided == BindingType::Sampler(SamplerBindingType::Comparison)
+        }
+        (BindingType::Sampler(provided), BindingType::Sampler(_)) => {
+            *provided != SamplerBindingType::Comparison
+        }
+        (
+            BindingType::Texture {
+                sample_type,
+                view_dimension,
+                multisampled,
+            },
+            BindingType::Texture {
+                sample_type: expected_type,
+                view_dimension: expected_dimension,
+                multisampled: expected_multisampled,
+            },
+        ) => {
+            let same_sample_type = matches!(
+                (sample_type, expected_type),
+                (
+                    TextureSampleType::Float { .. },
+                    TextureSampleType::Float { .. }
+                )
+            ) || sample_type == expected_type;
+
+            same_sample_type
+                && view_dimension == expected_dimension
+                && multisampled == expected_multisampled
+        }
+        (provided, expected) => provided == expected,
+    }
+}
+
+fn stage_flags(stage: ShaderStage) -> ShaderStages {
+    match stage {
+        ShaderStage::Vertex => ShaderStages::VERTEX,
+        ShaderStage::Fragment => ShaderStages::FRAGMENT,
+        ShaderStage::Compute => ShaderStages::COMPUTE,
+    }
+}
+
+fn view_dimension(dim: ImageDimension, arrayed: bool) -> TextureViewDimension {
+    match (dim, arrayed) {
+        (ImageDimension::D1, _) => TextureViewDimension::D1,
+        (ImageDimension::D2, false) => TextureViewDimension::D2,
+        (ImageDimension::D2, true) => TextureViewDimension::D2Array,
+        (ImageDimension::D3, _) => TextureViewDimension::D3,
+        (ImageDimension::Cube, false) => TextureViewDimension::Cube,
+        (ImageDimension::Cube, true) => TextureViewDimension::CubeArray,
+    }
+}
+
+fn storage_format(format: StorageFormat) -> TextureFormat {
+    match format {
+        StorageFormat::R8Unorm => TextureFormat::R8Unorm,
+        StorageFormat::R8Snorm => TextureFormat::R8Snorm,
+        StorageFormat::R8Uint => TextureFormat::R8Uint,
+        StorageFormat::R8Sint => TextureFormat::R8Sint,
+        StorageFormat::R16Uint => TextureFormat::R16Uint,
+        StorageFormat::R16Sint => TextureFormat::R16Sint,
+        StorageFormat::R16Float => TextureFormat::R16Float,
+        StorageFormat::Rg8Unorm => TextureFormat::Rg8Unorm,
+        StorageFormat::Rg8Snorm => TextureFormat::Rg8Snorm,
+        StorageFormat::Rg8Uint => TextureFormat::Rg8Uint,
+        StorageFormat::Rg8Sint => TextureFormat::Rg8Sint,
+        StorageFormat::R32Uint => TextureFormat::R32Uint,
+        StorageFormat::R32Sint => TextureFormat::R32Sint,
+        StorageFormat::R32Float => TextureFormat::R32Float,
+        StorageFormat::Rg16Uint => TextureFormat::Rg16Uint,
+        StorageFormat::Rg16Sint => TextureFormat::Rg16Sint,
+        StorageFormat::Rg16Float => TextureFormat::Rg16Float,
+        StorageFormat::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+        StorageFormat::Rgba8Snorm => TextureFormat::Rgba8Snorm,
+        StorageFormat::Rgba8Uint => TextureFormat::Rgba8Uint,
+        StorageFormat::Rgba8Sint => TextureFormat::Rgba8Sint,
+        StorageFormat::Rgb10a2Unorm => TextureFormat::Rgb10a2Unorm,
+        StorageFormat::Rg11b10Float => TextureFormat::Rg11b10Float,
+        StorageFormat::Rg32Uint => TextureFormat::Rg32Uint,
+        StorageFormat::Rg32Sint => TextureFormat::Rg32Sint,
+        StorageFormat::Rg32Float => TextureFormat::Rg32Float,
+        StorageFormat::Rgba16Uint => TextureFormat::Rgba16Uint,
+        StorageFormat::Rgba16Sint => TextureFormat::Rgba16Sint,
+        StorageFormat::Rgba16Float => TextureFormat::Rgba16Float,
+        StorageFormat::Rgba32Uint => TextureFormat::Rgba32Uint,
+        StorageFormat::Rgba32Sint => TextureFormat::Rgba32Sint,
+        StorageFormat::Rgba32Float => TextureFormat::Rgba32Float,
+    }
+}
+
+fn vector_size(size: VectorSize) -> u32 {
+    match size {
+        VectorSize::Bi => 2,
+        VectorSize::Tri => 3,
+        VectorSize::Quad => 4,
+    }
+}
+
+/// The scalar kind and component count a vertex format is read as in a shader.
+fn format_shape(format: VertexFormat) -> (ScalarKind, u32) {
+    use VertexFormat::*;
+
+    match format {
+        Float32 | Float64 => (ScalarKind::Float, 1),
+        Uint32 => (ScalarKind::Uint, 1),
+        Sint32 => (ScalarKind::Sint, 1),
+        Uint8x2 | Uint16x2 | Uint32x2 => (ScalarKind::Uint, 2),
+        Uint32x3 => (ScalarKind::Uint, 3),
+        Uint8x4 | Uint16x4 | Uint32x4 => (ScalarKind::Uint, 4),
+        Sint8x2 | Sint16x2 | Sint32x2 => (ScalarKind::Sint, 2),
+        Sint32x3 => (ScalarKind::Sint, 3),
+        Sint8x4 | Sint16x4 | Sint32x4 => (ScalarKind::Sint, 4),
+        Unorm8x2 | Snorm8x2 | Unorm16x2 | Snorm16x2 | Float16x2 | Float32x2 | Float64x2 => {
+            (ScalarKind::Float, 2)
+        }
+        Float32x3 | Float64x3 => (ScalarKind::Float, 3),
+        Unorm8x4 | Snorm8x4 | Unorm16x4 | Snorm16x4 | Float16x4 | Float32x4 | Float64x4 => {
+            (ScalarKind::Float, 4)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instance::InstanceRaw, model::ModelVertex, vertex::Vertex};
+
+    #[test]
+    fn model_shader_matches_vertex_layouts() {
+        let module = parse_wgsl(crate::light::SCENE_SHADER).unwrap();
+        validate_vertex_layouts(
+            &module,
+            "vs_main",
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn mismatched_format_is_rejected() {
+        let module = parse_wgsl(crate::light::SCENE_SHADER).unwrap();
+        let attributes = wgpu::vertex_attr_array![
+            0 => Float32x3,
+            1 => Float32x2,
+            2 => Float32x2,
+            3 => Float32x3,
+            4 => Float32x3,
+        ];
+        let layout = wgpu::VertexBufferLayout {
+            array_stride: 0,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &attributes,
+        };
+
+        assert!(
+            validate_vertex_layouts(&module, "vs_main", &[layout, InstanceRaw::desc()]).is_err()
+        );
+    }
+
+    #[test]
+    fn light_shader_accepts_main_shader_bind_groups() {
+        let main = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER).unwrap();
+        let light = ShaderReflection::from_wgsl(include_str!("../shaders/light.wgsl")).unwrap();
+
+        let entries = main.bind_group_layout_entries().unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].len(), 5);
+
+        light
+            .validate_bind_group_layouts(&[&entries[1], &entries[2]])
+            .unwrap();
+        assert!(light
+            .validate_bind_group_layouts(&[&entries[0], &entries[2]])
+            .is_err());
+    }
+}
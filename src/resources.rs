@@ -1,9 +1,11 @@
 use std::io::{BufReader, Cursor};
 
-use wgpu::util::DeviceExt;
+use anyhow::Context;
+use image::GenericImageView;
 
 use crate::{
-    model::{Material, Mesh, Model, ModelVertex},
+    atlas::AtlasBuilder,
+    model::{compute_tangents, Material, Mesh, Model, ModelVertex},
     texture::Texture,
 };
 
@@ -35,12 +37,7 @@ pub async fn load_texture(
     Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
 }
 
-pub async fn load_model(
-    file_name: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    layout: &wgpu::BindGroupLayout,
-) -> anyhow::Result<Model> {
+async fn load_obj(file_name: &str) -> anyhow::Result<(Vec<tobj::Model>, Vec<tobj::Material>)> {
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
@@ -59,9 +56,20 @@ pub async fn load_model(
     )
     .await?;
 
+    Ok((models, obj_materials?))
+}
+
+pub async fn load_model(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<Model> {
+    let (models, obj_materials) = load_obj(file_name).await?;
+
     let mut materials = Vec::new();
 
-    for material in obj_materials? {
+    for material in obj_materials {
         let diffuse_texture = load_texture(&material.diffuse_texture, false, device, queue).await?;
         let normal_texture = load_texture(&material.normal_texture, true, device, queue).await?;
 
@@ -77,113 +85,125 @@ pub async fn load_model(
     let meshes = models
         .into_iter()
         .map(|m| {
-            let mut vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                    // We'll calculate these later
-                    tangent: [0.0; 3],
-                    bitangent: [0.0; 3],
-                })
-                .collect::<Vec<_>>();
-
-            let indices = &m.mesh.indices;
-            let mut triangles_included = vec![0; vertices.len()];
-
-            // Calculate tangents and bitangets. We're going to
-            // use the triangles, so we need to loop through the
-            // indices in chunks of 3
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: cgmath::Vector3<_> = v0.position.into();
-                let pos1: cgmath::Vector3<_> = v1.position.into();
-                let pos2: cgmath::Vector3<_> = v2.position.into();
-
-                let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-                let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-                let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
-
-                // Calculate the edges of the triangle
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                // This will give us a direction to calculate the
-                // tangent and bitangent
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                // Solving the following system of equations will
-                // give us the tangent and bitangent.
-                //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-                //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-                // Luckily, the place I found this equation provided
-                // the solution!
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                // We flip the bitangent to enable right-handed normal
-                // maps with wgpu texture coordinate system
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-                // We'll use the same tangent/bitangent for each vertex in the triangle
-                vertices[c[0] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-                vertices[c[1] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-                vertices[c[2] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-                vertices[c[0] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-                vertices[c[1] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-                vertices[c[2] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-
-                // Used to average the tangents/bitangents
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
-            }
-
-            // Average the tangents/bitangents
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let mut v = &mut vertices[i];
-                v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-                v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
-            }
-
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
-            }
+            let material = m.mesh.material_id.unwrap_or(0);
+            create_mesh(file_name, device, m, material, |uv| uv)
         })
         .collect::<Vec<_>>();
 
     Ok(Model { meshes, materials })
 }
+
+/// Like [`load_model`], but packs the diffuse and normal maps of all
+/// materials into atlases built with `builder` and remaps the texture
+/// coordinates to match. Meshes on the same atlas page share one material,
+/// so drawing them needs no bind group switches.
+///
+/// Normal maps are resized to their diffuse map's size if they differ.
+/// Texture coordinates outside `0..=1` are clamped. Every mesh needs a
+/// material.
+pub async fn load_model_atlased(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    builder: AtlasBuilder,
+) -> anyhow::Result<Model> {
+    let (models, obj_materials) = load_obj(file_name).await?;
+
+    let mut diffuse_builder = builder.clone();
+    let mut normal_builder = builder;
+    for material in &obj_materials {
+        let diffuse = image::load_from_memory(&load_binary(&material.diffuse_texture).await?)?;
+        let mut normal = image::load_from_memory(&load_binary(&material.normal_texture).await?)?;
+        if normal.dimensions() != diffuse.dimensions() {
+            normal = normal.resize_exact(
+                diffuse.width(),
+                diffuse.height(),
+                image::imageops::FilterType::Triangle,
+            );
+        }
+        diffuse_builder
+            .add(&diffuse)
+            .with_context(|| format!("{}: {}", file_name, material.diffuse_texture))?;
+        normal_builder
+            .add(&normal)
+            .with_context(|| format!("{}: {}", file_name, material.normal_texture))?;
+    }
+
+    // Both builders see the same sizes in the same order, so they pack
+    // identically and one set of rectangles serves both.
+    let diffuse_atlas = diffuse_builder.build()?;
+    let normal_atlas = normal_builder.build()?;
+    anyhow::ensure!(
+        diffuse_atlas.rects == normal_atlas.rects,
+        "{}: diffuse and normal maps packed differently",
+        file_name
+    );
+
+    let diffuse_textures = diffuse_atlas.upload(device, queue, file_name, false);
+    let normal_textures =
+        normal_atlas.upload(device, queue, &format!("{} normal", file_name), true);
+    let materials = diffuse_textures
+        .into_iter()
+        .zip(normal_textures)
+        .enumerate()
+        .map(|(page, (diffuse, normal))| {
+            let name = format!("{} atlas {}", file_name, page);
+            Material::new(device, &name, diffuse, normal, layout)
+        })
+        .collect::<Vec<_>>();
+
+    // Without a material there is no atlas entry to remap into, and raw
+    // coordinates would sample whatever sits at the page origin.
+    let meshes = models
+        .into_iter()
+        .map(|m| {
+            let rect = m
+                .mesh
+                .material_id
+                .and_then(|id| diffuse_atlas.rects.get(id))
+                .with_context(|| format!("{}: mesh {} has no material", file_name, m.name))?;
+            Ok(create_mesh(file_name, device, m, rect.page, |uv| {
+                rect.remap(uv)
+            }))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Model { meshes, materials })
+}
+
+/// Builds a mesh with tangents, passing its texture coordinates through
+/// `remap_uv` once the tangents have been computed from the originals.
+fn create_mesh(
+    file_name: &str,
+    device: &wgpu::Device,
+    m: tobj::Model,
+    material: usize,
+    remap_uv: impl Fn([f32; 2]) -> [f32; 2],
+) -> Mesh {
+    let mut vertices = (0..m.mesh.positions.len() / 3)
+        .map(|i| ModelVertex {
+            position: [
+                m.mesh.positions[i * 3],
+                m.mesh.positions[i * 3 + 1],
+                m.mesh.positions[i * 3 + 2],
+            ],
+            tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
+            normal: [
+                m.mesh.normals[i * 3],
+                m.mesh.normals[i * 3 + 1],
+                m.mesh.normals[i * 3 + 2],
+            ],
+            // We'll calculate these later
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        })
+        .collect::<Vec<_>>();
+
+    compute_tangents(&mut vertices, &m.mesh.indices);
+    for v in &mut vertices {
+        v.tex_coords = remap_uv(v.tex_coords);
+    }
+
+    Mesh::new(device, file_name, &vertices, &m.mesh.indices, material)
+}
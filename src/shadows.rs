@@ -0,0 +1,605 @@
+//! Cascaded shadow maps for [`State::sun`](crate::state::State::sun).
+//!
+//! The depth range of each view, up to [`ShadowSettings::max_distance`],
+//! is split into cascades that grow with the distance from the camera. Each
+//! cascade gets a layer of a depth texture array, fitted around the
+//! bounding sphere of its slice of the view frustum. The sphere's size does
+//! not change as the camera turns, and its centre is snapped to whole
+//! texels of the shadow map, so edges do not shimmer as the camera moves.
+//! The model's opaque meshes and the terrain are drawn into the layers
+//! right before the view's pass, and the scene's fragments pick the cascade for their depth,
+//! fading into the next one at its end.
+
+use anyhow::*;
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix4, MetricSpace, Point3, SquareMatrix, Transform, Vector3,
+    Vector4,
+};
+use wgpu::{
+    BindGroupLayout, Buffer, CommandEncoder, Device, PipelineLayout, Queue,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, Sampler, TextureView,
+};
+
+use crate::{
+    camera::{camera::OPENGL_TO_WGPU_MATRIX, view::CameraView},
+    instance::InstanceRaw,
+    light::DirectionalLight,
+    model::{Model, ModelVertex},
+    reflection::ShaderReflection,
+    terrain::{Terrain, TerrainVertex},
+    texture::{TargetId, Texture},
+    uniform::{self, Array, MemoryLayout, ShaderType, UniformBuffer},
+    vertex::Vertex,
+};
+
+/// How the shadowed depth range is divided between cascades.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CascadeSplit {
+    /// Every cascade reaches the same factor further than the last, which
+    /// keeps shadow texels the same size on screen.
+    Logarithmic,
+    /// Blends the logarithmic splits with evenly spaced ones by `lambda`,
+    /// from 0 for even to 1 for logarithmic, as the logarithmic scheme
+    /// spends a lot of its resolution very close to the camera.
+    Practical { lambda: f32 },
+}
+
+impl CascadeSplit {
+    /// The distance at which each of `count` cascades between `near` and
+    /// `far` ends.
+    pub fn distances(self, near: f32, far: f32, count: u32) -> Vec<f32> {
+        (1..=count)
+            .map(|i| {
+                let t = i as f32 / count as f32;
+                let logarithmic = near * (far / near).powf(t);
+                match self {
+                    Self::Logarithmic => logarithmic,
+                    Self::Practical { lambda } => {
+                        lambda * logarithmic + (1.0 - lambda) * (near + (far - near) * t)
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Tuning for [`State::shadows`](crate::state::State::shadows).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Shadows are only drawn while [`State::sun`](crate::state::State::sun)
+    /// is set.
+    pub enabled: bool,
+    /// Up to [`Shadows::MAX_CASCADES`].
+    pub cascades: u32,
+    pub split: CascadeSplit,
+    /// How far from the camera shadows reach, if the projection's far
+    /// plane is further.
+    pub max_distance: f32,
+    /// Width and height of each cascade's layer.
+    pub resolution: u32,
+    /// Fraction of each cascade over which it fades into the next.
+    pub blend: f32,
+    /// How far beyond a cascade towards the sun casters are still drawn.
+    pub caster_distance: f32,
+    /// Subtracted from the depth compared against the shadow map.
+    pub depth_bias: f32,
+    /// How many texels surfaces are pushed out along their normal before
+    /// looking them up, which keeps them from shadowing themselves.
+    pub normal_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            cascades: 4,
+            split: CascadeSplit::Practical { lambda: 0.75 },
+            max_distance: 500.0,
+            resolution: 2048,
+            blend: 0.1,
+            caster_distance: 500.0,
+            depth_bias: 0.0001,
+            normal_bias: 1.5,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct ShadowUniform {
+    cascades: Array<[[f32; 4]; 4], { Shadows::MAX_CASCADES }>,
+    splits: [f32; 4],
+    texel_sizes: [f32; 4],
+    view: [[f32; 4]; 4],
+    direction: [f32; 3],
+    cascade_count: u32,
+    color: [f32; 3],
+    blend: f32,
+    depth_bias: f32,
+    normal_bias: f32,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct CasterUniform {
+    view_proj: [[f32; 4]; 4],
+}
+
+/// The cascades of one view.
+struct ViewShadows {
+    uniform: Buffer,
+    casters: Vec<UniformBuffer<CasterUniform>>,
+    cascade_count: u32,
+}
+
+pub(crate) struct Shadows {
+    caster_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    terrain_pipeline: RenderPipeline,
+    resolution: u32,
+    map: wgpu::Texture,
+    map_view: TextureView,
+    /// One view per cascade, to draw into.
+    layer_views: Vec<TextureView>,
+    sampler: Sampler,
+    /// Indexed like `State::views`.
+    views: Vec<ViewShadows>,
+    /// Replaced whenever `map` or `views` changes.
+    target_id: TargetId,
+}
+
+impl Shadows {
+    /// As many as the shader's uniform has room for.
+    pub const MAX_CASCADES: usize = 4;
+
+    pub(crate) fn new(device: &Device) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(include_str!("../shaders/shadow.wgsl"))?;
+        reflection
+            .validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])?;
+        reflection.validate_vertex_layouts("vs_terrain", &[TerrainVertex::desc()])?;
+        let mut layout = reflection.create_layout(device, "Shadow Pipeline Layout")?;
+        let pipeline = create_caster_pipeline(
+            device,
+            &layout.pipeline_layout,
+            "vs_main",
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+        );
+        let terrain_pipeline = create_caster_pipeline(
+            device,
+            &layout.pipeline_layout,
+            "vs_terrain",
+            &[TerrainVertex::desc()],
+        );
+        let caster_layout = layout.bind_group_layouts.remove(0);
+
+        // A single texel per layer until a sun needs more.
+        let resolution = 1;
+        let (map, map_view, layer_views) = create_map(device, resolution);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        Ok(Self {
+            caster_layout,
+            pipeline,
+            terrain_pipeline,
+            resolution,
+            map,
+            map_view,
+            layer_views,
+            sampler,
+            views: Vec::new(),
+            target_id: TargetId::next(),
+        })
+    }
+
+    /// Fits the cascades of every one of `views` and uploads them along
+    /// with `sun`.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        settings: &ShadowSettings,
+        sun: Option<&DirectionalLight>,
+        views: &[CameraView],
+    ) {
+        let direction = sun
+            .map(|sun| sun.direction)
+            .filter(|direction| direction.magnitude2() > 0.0)
+            .map(InnerSpace::normalize);
+        let shadowed = settings.enabled && direction.is_some();
+        let resolution = if shadowed {
+            settings.resolution.max(1)
+        } else {
+            1
+        };
+        if resolution != self.resolution {
+            (self.map, self.map_view, self.layer_views) = create_map(device, resolution);
+            self.resolution = resolution;
+            self.target_id = TargetId::next();
+        }
+
+        if self.views.len() != views.len() {
+            self.target_id = TargetId::next();
+        }
+        self.views.truncate(views.len());
+        while self.views.len() < views.len() {
+            let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("shadow_uniform"),
+                size: ShadowUniform::STD140.size as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let casters = (0..Self::MAX_CASCADES)
+                .map(|_| {
+                    UniformBuffer::new(
+                        device,
+                        &self.caster_layout,
+                        CasterUniform {
+                            view_proj: Matrix4::identity().into(),
+                        },
+                        "shadow_caster",
+                    )
+                })
+                .collect();
+            self.views.push(ViewShadows {
+                uniform,
+                casters,
+                cascade_count: 0,
+            });
+        }
+
+        let direction = direction.unwrap_or_else(|| -Vector3::unit_y());
+        let color = match sun {
+            Some(sun) if sun.direction.magnitude2() > 0.0 => sun.color,
+            _ => [0.0; 3],
+        };
+        for (view, shadows) in views.iter().zip(&mut self.views) {
+            let near = view.projection.znear().max(0.01);
+            let far = view
+                .projection
+                .zfar()
+                .min(settings.max_distance)
+                .max(near * 2.0);
+            let count = if shadowed {
+                settings.cascades.clamp(1, Self::MAX_CASCADES as u32)
+            } else {
+                0
+            };
+            let splits = settings.split.distances(near, far, count);
+
+            let mut params = ShadowUniform {
+                cascades: Array([Matrix4::identity().into(); Self::MAX_CASCADES]),
+                splits: [f32::MAX; 4],
+                texel_sizes: [0.0; 4],
+                view: view.camera.calc_matrix().into(),
+                direction: direction.into(),
+                cascade_count: count,
+                color,
+                blend: settings.blend.clamp(0.0, 1.0),
+                depth_bias: settings.depth_bias,
+                normal_bias: settings.normal_bias,
+            };
+            let mut start = near;
+            for (cascade, &end) in splits.iter().enumerate() {
+                let corners = slice_corners(view, start, end);
+                let (view_proj, texel_size) =
+                    fit_cascade(&corners, direction, resolution, settings.caster_distance);
+                params.cascades.0[cascade] = view_proj.into();
+                params.splits[cascade] = end;
+                params.texel_sizes[cascade] = texel_size;
+                shadows.casters[cascade].set(CasterUniform {
+                    view_proj: view_proj.into(),
+                });
+                shadows.casters[cascade].update(queue);
+                start = end;
+            }
+            shadows.cascade_count = count;
+            queue.write_buffer(
+                &shadows.uniform,
+                0,
+                &uniform::to_bytes(&params, MemoryLayout::Std140),
+            );
+        }
+    }
+
+    /// Records the cascades of view `index`, which
+    /// [`prepare`](Self::prepare) has fitted, if it has any. The terrain
+    /// casts at the levels it is drawn at from `eye`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        index: usize,
+        model: &Model,
+        instance_buffer: &Buffer,
+        instance_count: u32,
+        terrain: Option<&Terrain>,
+        eye: Point3<f32>,
+    ) {
+        let shadows = &self.views[index];
+        for cascade in 0..shadows.cascade_count as usize {
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &self.layer_views[cascade],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, shadows.casters[cascade].bind_group(), &[]);
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for (mesh, _) in model.meshes_by_transparency(false) {
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, 0..instance_count);
+            }
+            if let Some(terrain) = terrain {
+                pass.set_pipeline(&self.terrain_pipeline);
+                terrain.draw_chunks(&mut pass, eye);
+            }
+        }
+    }
+
+    /// Changes whenever the resources of [`Shadows::bind_group_entries`]
+    /// do.
+    pub(crate) fn target_id(&self) -> TargetId {
+        self.target_id
+    }
+
+    /// Bindings 5 to 7 of the scene's light group for view `index`.
+    pub(crate) fn bind_group_entries(&self, index: usize) -> [wgpu::BindGroupEntry<'_>; 3] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&self.map_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: self.views[index].uniform.as_entire_binding(),
+            },
+        ]
+    }
+}
+
+fn create_map(device: &Device, resolution: u32) -> (wgpu::Texture, TextureView, Vec<TextureView>) {
+    let map = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("shadow_map"),
+        size: wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: Shadows::MAX_CASCADES as u32,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: Texture::DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = map.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+    let layers = (0..Shadows::MAX_CASCADES as u32)
+        .map(|layer| {
+            map.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            })
+        })
+        .collect();
+    (map, view, layers)
+}
+
+/// The world-space corners of the part of `view`'s frustum between the
+/// view depths `near` and `far`.
+fn slice_corners(view: &CameraView, near: f32, far: f32) -> [Point3<f32>; 8] {
+    let inv_proj = view
+        .projection
+        .calc_matrix()
+        .invert()
+        .unwrap_or_else(Matrix4::identity);
+    let inv_view = view
+        .camera
+        .calc_matrix()
+        .invert()
+        .unwrap_or_else(Matrix4::identity);
+    let unproject =
+        |x: f32, y: f32, z: f32| Point3::from_homogeneous(inv_proj * Vector4::new(x, y, z, 1.0));
+
+    let mut corners = [Point3::origin(); 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+        let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+        let depth = if i & 4 == 0 { near } else { far };
+        // Any depth lies on the line through two points of the same
+        // pixel, which works for every kind of projection.
+        let a = unproject(x, y, 0.25);
+        let b = unproject(x, y, 0.75);
+        let t = (-depth - a.z) / (b.z - a.z);
+        *corner = inv_view.transform_point(a + (b - a) * t);
+    }
+    corners
+}
+
+/// The light space of a cascade around `corners` with the sun shining
+/// along `direction`, and the world size of one of its texels.
+fn fit_cascade(
+    corners: &[Point3<f32>; 8],
+    direction: Vector3<f32>,
+    resolution: u32,
+    caster_distance: f32,
+) -> (Matrix4<f32>, f32) {
+    let center = Point3::centroid(corners);
+    let radius = corners
+        .iter()
+        .map(|corner| corner.distance(center))
+        .fold(0.0, f32::max);
+    // Rounded so that float noise does not change the texel size.
+    let radius = (radius * 16.0).ceil() / 16.0;
+    let texel_size = 2.0 * radius / resolution as f32;
+
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let rotation = Matrix4::look_to_rh(Point3::origin(), direction, up);
+    let light_center = rotation.transform_point(center);
+    let snapped = Vector3::new(
+        (light_center.x / texel_size).floor() * texel_size,
+        (light_center.y / texel_size).floor() * texel_size,
+        light_center.z,
+    );
+    let light_view = Matrix4::from_translation(-snapped) * rotation;
+    let projection = cgmath::ortho(
+        -radius,
+        radius,
+        -radius,
+        radius,
+        -(radius + caster_distance),
+        radius,
+    );
+    (OPENGL_TO_WGPU_MATRIX * projection * light_view, texel_size)
+}
+
+/// The pipeline drawing casters' depth, with a slope-scaled bias against
+/// acne on surfaces at grazing angles to the sun.
+fn create_caster_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    entry_point: &str,
+    buffers: &[wgpu::VertexBufferLayout],
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shadow Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shadow.wgsl").into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Shadow Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point,
+            buffers,
+        },
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_end_at_the_far_plane() {
+        let logarithmic = CascadeSplit::Logarithmic.distances(0.1, 1000.0, 4);
+        for (split, expected) in logarithmic.iter().zip([1.0, 10.0, 100.0, 1000.0]) {
+            assert!((split - expected).abs() < expected * 1e-4);
+        }
+        let even = CascadeSplit::Practical { lambda: 0.0 }.distances(1.0, 101.0, 4);
+        assert_eq!(even, vec![26.0, 51.0, 76.0, 101.0]);
+    }
+
+    #[test]
+    fn cascades_move_in_whole_texels() {
+        let corners = |offset: f32| {
+            let mut corners = [Point3::origin(); 8];
+            for (i, corner) in corners.iter_mut().enumerate() {
+                *corner = Point3::new(
+                    (i & 1) as f32 * 10.0 + offset,
+                    (i & 2) as f32 * 5.0,
+                    (i & 4) as f32 * 2.5 - offset * 0.5,
+                );
+            }
+            corners
+        };
+        let direction = Vector3::new(-1.0, -2.0, -0.5).normalize();
+        let (a, texel_size) = fit_cascade(&corners(0.0), direction, 1024, 100.0);
+        let (b, moved_texel_size) = fit_cascade(&corners(0.123), direction, 1024, 100.0);
+        assert_eq!(texel_size, moved_texel_size);
+
+        // A fixed point lands on the same spot within its texel.
+        let point = Point3::new(3.0, 1.0, -2.0);
+        let texels = |matrix: Matrix4<f32>| {
+            let ndc = matrix.transform_point(point);
+            (ndc.x * 512.0, ndc.y * 512.0)
+        };
+        let (a, b) = (texels(a), texels(b));
+        for shift in [a.0 - b.0, a.1 - b.1] {
+            assert!((shift - shift.round()).abs() < 1e-2, "{shift}");
+        }
+    }
+
+    #[test]
+    fn scene_group_holds_the_shadow() {
+        let entries = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER)
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        let shadow = entries[2]
+            .iter()
+            .filter(|entry| (5..8).contains(&entry.binding))
+            .collect::<Vec<_>>();
+        assert_eq!(shadow.len(), 3);
+        assert!(matches!(
+            shadow[0].ty,
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                ..
+            }
+        ));
+        assert!(matches!(
+            shadow[2].ty,
+            wgpu::BindingType::Buffer {
+                min_binding_size: Some(size),
+                ..
+            } if size.get() as usize == ShadowUniform::STD140.size
+        ));
+    }
+
+    #[test]
+    fn casters_match_model_and_terrain_vertices() {
+        let reflection =
+            ShaderReflection::from_wgsl(include_str!("../shaders/shadow.wgsl")).unwrap();
+        reflection
+            .validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])
+            .unwrap();
+        reflection
+            .validate_vertex_layouts("vs_terrain", &[TerrainVertex::desc()])
+            .unwrap();
+    }
+}
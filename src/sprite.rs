@@ -0,0 +1,511 @@
+use std::ops::Range;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{ortho, Matrix4};
+use image::GenericImageView;
+use wgpu::{
+    util::DeviceExt, BindGroup, BindGroupLayout, Buffer, BufferDescriptor, BufferUsages, Device,
+    Queue, RenderPass, RenderPipeline, TextureFormat,
+};
+
+use crate::{
+    atlas::{extrude, ShelfPacker},
+    camera::camera::OPENGL_TO_WGPU_MATRIX,
+    reflection::ShaderReflection,
+    texture::Texture,
+    uniform::{ShaderType, UniformBuffer},
+    vertex::{TexturedVertex, Vertex},
+};
+
+/// The unit quad every sprite is drawn from, with the origin at the top-left
+/// corner and y pointing down like screen pixels.
+const QUAD_VERTICES: &[TexturedVertex] = &[
+    TexturedVertex::new([0.0, 0.0, 0.0], [0.0, 0.0]),
+    TexturedVertex::new([1.0, 0.0, 0.0], [1.0, 0.0]),
+    TexturedVertex::new([0.0, 1.0, 0.0], [0.0, 1.0]),
+    TexturedVertex::new([1.0, 1.0, 0.0], [1.0, 1.0]),
+];
+
+const QUAD_INDICES: &[u16] = &[0, 2, 1, 1, 2, 3];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Vertex)]
+#[vertex(instance)]
+pub struct SpriteInstance {
+    #[location(2)]
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    pub origin: [f32; 2],
+    pub rotation: f32,
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    pub color: [f32; 4],
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct SpriteCameraUniform {
+    view_proj: Matrix4<f32>,
+}
+
+/// A texture packed into one of the batch's atlases.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpriteTexture {
+    page: usize,
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    width: u32,
+    height: u32,
+}
+
+impl SpriteTexture {
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+}
+
+/// One textured quad. Positions and sizes are in pixels of the 2D camera,
+/// with y pointing down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+    pub texture: SpriteTexture,
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    /// The point of the sprite placed at `position` and rotated around, in
+    /// fractions of its size.
+    pub origin: [f32; 2],
+    /// Clockwise rotation in radians.
+    pub rotation: f32,
+    pub color: [f32; 4],
+    /// Sprites on higher layers are drawn over lower ones. Within a layer,
+    /// sprites keep the order they were drawn in.
+    pub layer: i32,
+}
+
+impl Sprite {
+    /// A sprite showing `texture` at its own size, centered on `position`.
+    pub fn new(texture: SpriteTexture, position: [f32; 2]) -> Self {
+        Self {
+            texture,
+            position,
+            size: [texture.width as f32, texture.height as f32],
+            origin: [0.5, 0.5],
+            rotation: 0.0,
+            color: [1.0; 4],
+            layer: 0,
+        }
+    }
+
+    pub fn with_size(mut self, size: [f32; 2]) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.size = [self.size[0] * scale, self.size[1] * scale];
+        self
+    }
+
+    pub fn with_origin(mut self, origin: [f32; 2]) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+}
+
+/// Pans and zooms the 2D scene. At the default, one unit is one pixel and
+/// the top-left corner of the surface is at the origin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpriteCamera {
+    pub position: [f32; 2],
+    pub zoom: f32,
+}
+
+impl Default for SpriteCamera {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0],
+            zoom: 1.0,
+        }
+    }
+}
+
+impl SpriteCamera {
+    /// The orthographic projection for a surface of `width` x `height`
+    /// pixels.
+    pub fn calc_matrix(&self, width: u32, height: u32) -> Matrix4<f32> {
+        let (width, height) = (width as f32 / self.zoom, height as f32 / self.zoom);
+        let [x, y] = self.position;
+        OPENGL_TO_WGPU_MATRIX * ortho(x, x + width, y + height, y, -1.0, 1.0)
+    }
+}
+
+struct AtlasPage {
+    texture: Texture,
+    bind_group: BindGroup,
+    packer: ShelfPacker,
+}
+
+/// Draws large numbers of textured, tinted, rotated and scaled quads.
+///
+/// Textures are packed into atlas pages when added, so sprites sharing a
+/// page are drawn together. Sprites are sorted by layer and then by page,
+/// which gives one instanced draw call per page on each layer.
+pub struct SpriteBatch {
+    pub camera: SpriteCamera,
+    sprites: Vec<Sprite>,
+    pages: Vec<AtlasPage>,
+    texture_layout: BindGroupLayout,
+    camera_uniform: UniformBuffer<SpriteCameraUniform>,
+    quad_vertices: Buffer,
+    quad_indices: Buffer,
+    instances: Buffer,
+    capacity: usize,
+    /// Instance ranges per atlas page uploaded by the last
+    /// [`prepare`](Self::prepare).
+    batches: Vec<(usize, Range<u32>)>,
+    pipeline: RenderPipeline,
+}
+
+impl SpriteBatch {
+    const PAGE_SIZE: u32 = 2048;
+    const PADDING: u32 = 1;
+
+    pub fn new(device: &Device, color_format: TextureFormat) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(include_str!("../shaders/sprite.wgsl"))?;
+        reflection.validate_vertex_layouts(
+            "vs_main",
+            &[TexturedVertex::desc(), SpriteInstance::desc()],
+        )?;
+
+        let mut layout = reflection.create_layout(device, "Sprite Pipeline Layout")?;
+        let pipeline = create_sprite_pipeline(device, &layout.pipeline_layout, color_format);
+        let texture_layout = layout.bind_group_layouts.remove(1);
+        let camera_uniform = UniformBuffer::new(
+            device,
+            &layout.bind_group_layouts[0],
+            SpriteCameraUniform {
+                view_proj: cgmath::SquareMatrix::identity(),
+            },
+            "sprite_camera",
+        );
+
+        let quad_vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Quad Vertex Buffer"),
+            contents: bytemuck::cast_slice(QUAD_VERTICES),
+            usage: BufferUsages::VERTEX,
+        });
+        let quad_indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Quad Index Buffer"),
+            contents: bytemuck::cast_slice(QUAD_INDICES),
+            usage: BufferUsages::INDEX,
+        });
+
+        let capacity = 1024;
+        Ok(Self {
+            camera: SpriteCamera::default(),
+            sprites: Vec::new(),
+            pages: Vec::new(),
+            texture_layout,
+            camera_uniform,
+            quad_vertices,
+            quad_indices,
+            instances: create_instance_buffer(device, capacity),
+            capacity,
+            batches: Vec::new(),
+            pipeline,
+        })
+    }
+
+    /// Packs `image` into an atlas page, starting a new page when it fits in
+    /// none of the existing ones.
+    pub fn add_texture(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        image: &image::DynamicImage,
+    ) -> Result<SpriteTexture> {
+        let (width, height) = image.dimensions();
+        let (padded_width, padded_height) = (width + 2 * Self::PADDING, height + 2 * Self::PADDING);
+        if padded_width > Self::PAGE_SIZE || padded_height > Self::PAGE_SIZE {
+            bail!(
+                "{}x{} sprite texture is larger than the {}x{} atlas",
+                width,
+                height,
+                Self::PAGE_SIZE,
+                Self::PAGE_SIZE
+            );
+        }
+
+        let found = self.pages.iter_mut().enumerate().find_map(|(index, page)| {
+            page.packer
+                .allocate(padded_width, padded_height)
+                .map(|position| (index, position))
+        });
+        let (page, (x, y)) = match found {
+            Some(found) => found,
+            None => {
+                self.pages.push(self.create_page(device));
+                let index = self.pages.len() - 1;
+                let position = self.pages[index]
+                    .packer
+                    .allocate(padded_width, padded_height)
+                    .context("sprite texture does not fit an empty atlas page")?;
+                (index, position)
+            }
+        };
+
+        // The padding repeats the edge pixels so linear filtering at the
+        // sprite's border does not pick up its neighbours.
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.pages[page].texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &extrude(&image.to_rgba8(), Self::PADDING),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(4 * padded_width),
+                rows_per_image: std::num::NonZeroU32::new(padded_height),
+            },
+            wgpu::Extent3d {
+                width: padded_width,
+                height: padded_height,
+                depth_or_array_layers: 1,
+            },
+        );
+        let (x, y) = (x + Self::PADDING, y + Self::PADDING);
+
+        let size = Self::PAGE_SIZE as f32;
+        Ok(SpriteTexture {
+            page,
+            uv_min: [x as f32 / size, y as f32 / size],
+            uv_max: [(x + width) as f32 / size, (y + height) as f32 / size],
+            width,
+            height,
+        })
+    }
+
+    fn create_page(&self, device: &Device) -> AtlasPage {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("sprite_atlas"),
+            size: wgpu::Extent3d {
+                width: Self::PAGE_SIZE,
+                height: Self::PAGE_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sprite_atlas_bind_group"),
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        AtlasPage {
+            texture: Texture {
+                texture,
+                view,
+                sampler,
+            },
+            bind_group,
+            packer: ShelfPacker::new(Self::PAGE_SIZE, Self::PAGE_SIZE),
+        }
+    }
+
+    /// Queues `sprite` for this frame.
+    pub fn draw(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    /// Sorts and uploads the queued sprites and forgets them.
+    pub(crate) fn prepare(&mut self, device: &Device, queue: &Queue, surface_size: (u32, u32)) {
+        self.camera_uniform.set(SpriteCameraUniform {
+            view_proj: self.camera.calc_matrix(surface_size.0, surface_size.1),
+        });
+        self.camera_uniform.update(queue);
+
+        let mut sprites = std::mem::take(&mut self.sprites);
+        self.batches = sort_into_batches(&mut sprites);
+
+        let instances = sprites
+            .iter()
+            .map(|sprite| SpriteInstance {
+                position: sprite.position,
+                size: sprite.size,
+                origin: sprite.origin,
+                rotation: sprite.rotation,
+                uv_min: sprite.texture.uv_min,
+                uv_max: sprite.texture.uv_max,
+                color: sprite.color,
+            })
+            .collect::<Vec<_>>();
+
+        if instances.len() > self.capacity {
+            self.capacity = instances.len().next_power_of_two();
+            self.instances = create_instance_buffer(device, self.capacity);
+        }
+        if !instances.is_empty() {
+            queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&instances));
+        }
+
+        // Keep the allocation for next frame.
+        sprites.clear();
+        self.sprites = sprites;
+    }
+
+    /// Draws the sprites uploaded by the last [`prepare`](Self::prepare)
+    /// into a pass over the whole surface.
+    pub(crate) fn render<'a>(&'a self, pass: &mut RenderPass<'a>) {
+        if self.batches.is_empty() {
+            return;
+        }
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, self.camera_uniform.bind_group(), &[]);
+        pass.set_vertex_buffer(0, self.quad_vertices.slice(..));
+        pass.set_vertex_buffer(1, self.instances.slice(..));
+        pass.set_index_buffer(self.quad_indices.slice(..), wgpu::IndexFormat::Uint16);
+        for (page, range) in &self.batches {
+            pass.set_bind_group(1, &self.pages[*page].bind_group, &[]);
+            pass.draw_indexed(0..QUAD_INDICES.len() as u32, 0, range.clone());
+        }
+    }
+}
+
+/// Sorts `sprites` by layer and atlas page, keeping the submission order
+/// otherwise, and returns the instance range of each page run.
+fn sort_into_batches(sprites: &mut [Sprite]) -> Vec<(usize, Range<u32>)> {
+    sprites.sort_by_key(|sprite| (sprite.layer, sprite.texture.page));
+
+    let mut batches: Vec<(usize, Range<u32>)> = Vec::new();
+    for (index, sprite) in sprites.iter().enumerate() {
+        let index = index as u32;
+        match batches.last_mut() {
+            Some((page, range)) if *page == sprite.texture.page => range.end = index + 1,
+            _ => batches.push((sprite.texture.page, index..index + 1)),
+        }
+    }
+    batches
+}
+
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Sprite Instance Buffer"),
+        size: (capacity * std::mem::size_of::<SpriteInstance>()) as u64,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_sprite_pipeline(
+    device: &Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: TextureFormat,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Sprite Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/sprite.wgsl").into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Sprite Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[TexturedVertex::desc(), SpriteInstance::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        // Drawn in the overlay pass, which keeps the scene's depth buffer
+        // attached; sprites ignore it.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite(page: usize, layer: i32) -> Sprite {
+        let texture = SpriteTexture {
+            page,
+            uv_min: [0.0; 2],
+            uv_max: [1.0; 2],
+            width: 1,
+            height: 1,
+        };
+        Sprite::new(texture, [0.0; 2]).with_layer(layer)
+    }
+
+    #[test]
+    fn batches_group_pages_within_layers() {
+        let mut sprites = [sprite(1, 0), sprite(0, 1), sprite(0, 0), sprite(1, 0)];
+        let batches = sort_into_batches(&mut sprites);
+        assert_eq!(batches, [(0, 0..1), (1, 1..3), (0, 3..4)]);
+    }
+
+    #[test]
+    fn sprite_shader_matches_vertex_layouts() {
+        ShaderReflection::from_wgsl(include_str!("../shaders/sprite.wgsl"))
+            .unwrap()
+            .validate_vertex_layouts("vs_main", &[TexturedVertex::desc(), SpriteInstance::desc()])
+            .unwrap();
+    }
+}
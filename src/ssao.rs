@@ -0,0 +1,690 @@
+//! Screen-space ambient occlusion, which darkens the ambient light in
+//! creases and corners.
+//!
+//! Before each view's pass, the model's opaque meshes are drawn once more
+//! into the view's own depth target, and with
+//! [`SsaoSettings::normal_prepass`] their normals into a second target. A
+//! full-screen pass then tests a hemisphere of samples around every pixel
+//! against that depth, rotating the kernel built by [`kernel`] per pixel
+//! with a 4x4 noise texture, and a bilateral blur averages the noise out without crossing
+//! depth edges. `shader.wgsl` multiplies its ambient light by the result.
+
+use std::collections::HashMap;
+
+use anyhow::*;
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use wgpu::{
+    util::DeviceExt, BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, CommandEncoder,
+    CompareFunction, Device, Operations, PipelineLayout, Queue, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, TextureFormat,
+    TextureView,
+};
+
+use crate::{
+    camera::view::CameraView,
+    instance::InstanceRaw,
+    model::{Model, ModelVertex},
+    reflection::{ReflectedLayout, ShaderReflection},
+    texture::{TargetId, Texture},
+    uniform::{self, Array, MemoryLayout, ShaderType},
+    vertex::Vertex,
+};
+
+/// Tuning for [`State::ssao`](crate::state::State::ssao).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsaoSettings {
+    /// Off by default, as it draws the opaque meshes a second time.
+    pub enabled: bool,
+    /// World-space radius of the hemisphere searched for occluders.
+    pub radius: f32,
+    /// How far the scene must be in front of a sample to occlude it, which
+    /// keeps flat surfaces from occluding themselves.
+    pub bias: f32,
+    /// Samples per pixel, up to [`Ssao::MAX_SAMPLES`].
+    pub sample_count: u32,
+    /// Draws vertex normals in the prepass rather than reconstructing them
+    /// from depth, which is smoother on curved surfaces and at silhouettes.
+    pub normal_prepass: bool,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 0.5,
+            bias: 0.025,
+            sample_count: 16,
+            normal_prepass: true,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct SsaoUniform {
+    view_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+    eye: [f32; 3],
+    radius: f32,
+    viewport: [f32; 4],
+    bias: f32,
+    sample_count: u32,
+    far_depth: f32,
+    use_normals: u32,
+    kernel: Array<[f32; 3], { Ssao::MAX_SAMPLES as usize }>,
+}
+
+/// The targets and bind groups of one view.
+struct SsaoTargets {
+    size: (u32, u32),
+    normal_prepass: bool,
+    depth: Texture,
+    normals: Option<Texture>,
+    occlusion: Texture,
+    blurred: Texture,
+    params: Buffer,
+    occlusion_bind_group: BindGroup,
+    blur_bind_group: BindGroup,
+}
+
+pub(crate) struct Ssao {
+    prepass_layout: PipelineLayout,
+    occlusion_layout: ReflectedLayout,
+    blur_layout: ReflectedLayout,
+    prepass_pipelines: HashMap<(CompareFunction, bool), RenderPipeline>,
+    occlusion_pipeline: RenderPipeline,
+    blur_pipeline: RenderPipeline,
+    noise: Texture,
+    unoccluded: Texture,
+    /// Indexed like `State::views`.
+    views: Vec<SsaoTargets>,
+    /// Replaced whenever `views` changes.
+    target_id: TargetId,
+}
+
+impl Ssao {
+    pub const OCCLUSION_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+    pub const NORMAL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+    pub const MAX_SAMPLES: u32 = 64;
+
+    /// `camera_layout` and `camera_entries` describe the camera bind group
+    /// shared with the main shader.
+    pub(crate) fn new(
+        device: &Device,
+        queue: &Queue,
+        camera_layout: &BindGroupLayout,
+        camera_entries: &[BindGroupLayoutEntry],
+    ) -> Result<Self> {
+        let prepass = ShaderReflection::from_wgsl(include_str!("../shaders/ssao_prepass.wgsl"))?;
+        prepass.validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])?;
+        prepass
+            .validate_bind_group_layouts(&[camera_entries])
+            .context("ssao_prepass.wgsl")?;
+        let prepass_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Prepass Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+
+        let occlusion_layout = ShaderReflection::from_wgsl(include_str!("../shaders/ssao.wgsl"))?
+            .create_layout(device, "SSAO Pipeline Layout")?;
+        let blur_layout = ShaderReflection::from_wgsl(include_str!("../shaders/ssao_blur.wgsl"))?
+            .create_layout(device, "SSAO Blur Pipeline Layout")?;
+        let occlusion_pipeline = create_fullscreen_pipeline(
+            device,
+            &occlusion_layout.pipeline_layout,
+            "SSAO",
+            include_str!("../shaders/ssao.wgsl"),
+        );
+        let blur_pipeline = create_fullscreen_pipeline(
+            device,
+            &blur_layout.pipeline_layout,
+            "SSAO Blur",
+            include_str!("../shaders/ssao_blur.wgsl"),
+        );
+
+        let noise = texture_with_data(
+            device,
+            queue,
+            (4, 4),
+            TextureFormat::Rgba8Snorm,
+            &noise_texels(),
+            "ssao_noise",
+        );
+        let unoccluded = texture_with_data(
+            device,
+            queue,
+            (1, 1),
+            Self::OCCLUSION_FORMAT,
+            &[u8::MAX],
+            "ssao_unoccluded",
+        );
+
+        Ok(Self {
+            prepass_layout,
+            occlusion_layout,
+            blur_layout,
+            prepass_pipelines: HashMap::new(),
+            occlusion_pipeline,
+            blur_pipeline,
+            noise,
+            unoccluded,
+            views: Vec::new(),
+            target_id: TargetId::next(),
+        })
+    }
+
+    /// Makes sure every one of `views` has targets of its size and uploads
+    /// its camera and `settings`.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        settings: &SsaoSettings,
+        views: &[CameraView],
+        surface_size: (u32, u32),
+    ) {
+        if self.views.len() > views.len() {
+            self.views.truncate(views.len());
+            self.target_id = TargetId::next();
+        }
+        let sample_count = settings.sample_count.clamp(1, Self::MAX_SAMPLES);
+        let mut kernel = Array([[0.0; 3]; Self::MAX_SAMPLES as usize]);
+        for (i, sample) in kernel.0.iter_mut().take(sample_count as usize).enumerate() {
+            *sample = kernel_sample(i as u32, sample_count).into();
+        }
+        for (index, view) in views.iter().enumerate() {
+            let compare = view.projection.depth_compare();
+            let key = (compare, settings.normal_prepass);
+            if !self.prepass_pipelines.contains_key(&key) {
+                let pipeline =
+                    create_prepass_pipeline(device, &self.prepass_layout, compare, key.1);
+                self.prepass_pipelines.insert(key, pipeline);
+            }
+
+            let size = view.target_size(surface_size.0, surface_size.1);
+            let stale = self.views.get(index).map_or(true, |targets| {
+                targets.size != size || targets.normal_prepass != settings.normal_prepass
+            });
+            if stale {
+                let targets = self.create_targets(device, size, settings.normal_prepass);
+                if index < self.views.len() {
+                    self.views[index] = targets;
+                } else {
+                    self.views.push(targets);
+                }
+                self.target_id = TargetId::next();
+            }
+
+            let view_proj = view.projection.calc_matrix() * view.camera.calc_matrix();
+            let viewport = view.pixel_viewport(surface_size.0, surface_size.1);
+            let params = SsaoUniform {
+                view_proj: view_proj.into(),
+                inv_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity).into(),
+                eye: view.camera.position.into(),
+                radius: settings.radius.max(1e-3),
+                viewport: [
+                    viewport.x as f32,
+                    viewport.y as f32,
+                    viewport.width as f32,
+                    viewport.height as f32,
+                ],
+                bias: settings.bias,
+                sample_count,
+                far_depth: view.projection.depth_clear_value(),
+                use_normals: settings.normal_prepass as u32,
+                kernel,
+            };
+            queue.write_buffer(
+                &self.views[index].params,
+                0,
+                &uniform::to_bytes(&params, MemoryLayout::Std140),
+            );
+        }
+    }
+
+    fn create_targets(
+        &self,
+        device: &Device,
+        size: (u32, u32),
+        normal_prepass: bool,
+    ) -> SsaoTargets {
+        let (width, height) = size;
+        let depth = Texture::create_depth_texture_sized(device, width, height, "ssao_depth");
+        let normals = normal_prepass.then(|| {
+            Texture::create_render_target(
+                device,
+                width,
+                height,
+                Self::NORMAL_FORMAT,
+                "ssao_normals",
+            )
+        });
+        let occlusion = Texture::create_render_target(
+            device,
+            width,
+            height,
+            Self::OCCLUSION_FORMAT,
+            "ssao_occlusion",
+        );
+        let blurred = Texture::create_render_target(
+            device,
+            width,
+            height,
+            Self::OCCLUSION_FORMAT,
+            "ssao_blurred",
+        );
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SSAO Params"),
+            size: SsaoUniform::STD140.size as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Without a prepass the normals are never read, but the binding
+        // still needs a texture.
+        let normal_view = normals.as_ref().unwrap_or(&self.unoccluded);
+        let occlusion_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ssao_bind_group"),
+            layout: &self.occlusion_layout.bind_group_layouts[0],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&depth.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_view.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.noise.view),
+                },
+            ],
+        });
+        let blur_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ssao_blur_bind_group"),
+            layout: &self.blur_layout.bind_group_layouts[0],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&depth.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&occlusion.view),
+                },
+            ],
+        });
+
+        SsaoTargets {
+            size,
+            normal_prepass,
+            depth,
+            normals,
+            occlusion,
+            blurred,
+            params,
+            occlusion_bind_group,
+            blur_bind_group,
+        }
+    }
+
+    /// Records the prepass, occlusion and blur of view `index`, which
+    /// [`prepare`](Self::prepare) has set up.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        index: usize,
+        view: &CameraView,
+        surface_size: (u32, u32),
+        model: &Model,
+        instance_buffer: &Buffer,
+        instance_count: u32,
+    ) {
+        let targets = &self.views[index];
+        let (width, height) = surface_size;
+        let viewport = view.pixel_viewport(width, height);
+        let scissor = view.pixel_scissor(width, height);
+        let limit = |pass: &mut wgpu::RenderPass| {
+            pass.set_viewport(
+                viewport.x as f32,
+                viewport.y as f32,
+                viewport.width as f32,
+                viewport.height as f32,
+                0.0,
+                1.0,
+            );
+            pass.set_scissor_rect(scissor.x, scissor.y, scissor.width, scissor.height);
+        };
+
+        {
+            let normals = targets
+                .normals
+                .iter()
+                .map(|normals| {
+                    Some(RenderPassColorAttachment {
+                        view: &normals.view,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("SSAO Prepass"),
+                color_attachments: &normals,
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &targets.depth.view,
+                    depth_ops: Some(Operations {
+                        load: wgpu::LoadOp::Clear(view.projection.depth_clear_value()),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            limit(&mut pass);
+            let key = (view.projection.depth_compare(), targets.normal_prepass);
+            pass.set_pipeline(&self.prepass_pipelines[&key]);
+            pass.set_bind_group(0, view.camera_uniform().bind_group(), &[]);
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for (mesh, _) in model.meshes_by_transparency(false) {
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, 0..instance_count);
+            }
+        }
+
+        for (label, target, pipeline, bind_group) in [
+            (
+                "SSAO Pass",
+                &targets.occlusion,
+                &self.occlusion_pipeline,
+                &targets.occlusion_bind_group,
+            ),
+            (
+                "SSAO Blur Pass",
+                &targets.blurred,
+                &self.blur_pipeline,
+                &targets.blur_bind_group,
+            ),
+        ] {
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            limit(&mut pass);
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+
+    /// The blurred occlusion of every view, in order.
+    pub(crate) fn occlusions(&self) -> impl Iterator<Item = &TextureView> {
+        self.views.iter().map(|targets| &targets.blurred.view)
+    }
+
+    /// Changes whenever the textures of [`Ssao::occlusions`] do.
+    pub(crate) fn target_id(&self) -> TargetId {
+        self.target_id
+    }
+
+    /// A single white texel, for drawing without occlusion.
+    pub(crate) fn unoccluded(&self) -> &TextureView {
+        &self.unoccluded.view
+    }
+}
+
+/// Sample `i` of `sample_count` in the hemisphere around +Z, within the
+/// unit sphere and denser close to the centre, where occluders matter more.
+fn kernel_sample(i: u32, sample_count: u32) -> Vector3<f32> {
+    // The Van der Corput sequence spreads the samples around the normal,
+    // and the golden ratio their elevation.
+    let phi = std::f32::consts::TAU * i.reverse_bits() as f32 * 2f32.powi(-32);
+    let cos_theta = (1.0 - (i as f32 * 0.618034).fract()).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let t = (i as f32 + 0.5) / sample_count as f32;
+    let scale = 0.1 + 0.9 * t * t;
+    Vector3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta) * scale
+}
+
+/// Sixteen rotations around +Z, evenly spread but shuffled so neighbouring
+/// pixels do not turn in order.
+fn noise_texels() -> Vec<u8> {
+    (0..16)
+        .flat_map(|i| {
+            let angle = ((i * 7) % 16) as f32 / 16.0 * std::f32::consts::TAU;
+            let snorm = |value: f32| (value * 127.0).round() as i8 as u8;
+            [snorm(angle.cos()), snorm(angle.sin()), 0, 0]
+        })
+        .collect()
+}
+
+fn texture_with_data(
+    device: &Device,
+    queue: &Queue,
+    (width, height): (u32, u32),
+    format: TextureFormat,
+    data: &[u8],
+    label: &str,
+) -> Texture {
+    let texture = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        },
+        data,
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+fn create_prepass_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    depth_compare: CompareFunction,
+    normals: bool,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("SSAO Prepass Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/ssao_prepass.wgsl").into()),
+    });
+    let targets = [Some(wgpu::ColorTargetState {
+        format: Ssao::NORMAL_FORMAT,
+        blend: None,
+        write_mask: wgpu::ColorWrites::ALL,
+    })];
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("SSAO Prepass Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: normals.then_some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+/// A pipeline drawing a full-screen triangle into an occlusion target.
+fn create_fullscreen_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    label: &str,
+    source: &str,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: Ssao::OCCLUSION_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::*;
+
+    #[test]
+    fn kernel_fills_the_hemisphere_towards_the_centre() {
+        for sample_count in 1..=Ssao::MAX_SAMPLES {
+            let kernel = (0..sample_count)
+                .map(|i| kernel_sample(i, sample_count))
+                .collect::<Vec<_>>();
+            let mut previous = 0.0;
+            for (i, sample) in kernel.iter().enumerate() {
+                assert!(sample.z >= 0.0, "{sample_count}: {sample:?}");
+                let length = sample.magnitude();
+                assert!((0.1..=1.0).contains(&length), "{sample_count}: {sample:?}");
+                assert!(length > previous);
+                previous = length;
+                for other in &kernel[..i] {
+                    let cos = sample.normalize().dot(other.normalize());
+                    assert!(cos < 0.9999, "{sample_count}: {sample:?} {other:?}");
+                }
+            }
+        }
+
+        // Enough samples reach around the normal on every side.
+        let kernel = (0..16).map(|i| kernel_sample(i, 16)).collect::<Vec<_>>();
+        for (x, y) in [(1.0, 1.0), (-1.0, 1.0), (-1.0, -1.0), (1.0, -1.0)] {
+            assert!(kernel
+                .iter()
+                .any(|sample| sample.x * x > 0.0 && sample.y * y > 0.0));
+        }
+    }
+
+    #[test]
+    fn ssao_shaders_validate() {
+        let scene = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER)
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        let prepass =
+            ShaderReflection::from_wgsl(include_str!("../shaders/ssao_prepass.wgsl")).unwrap();
+        prepass
+            .validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])
+            .unwrap();
+        prepass.validate_bind_group_layouts(&[&scene[1]]).unwrap();
+
+        let occlusion = ShaderReflection::from_wgsl(include_str!("../shaders/ssao.wgsl"))
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        match occlusion[0][0].ty {
+            wgpu::BindingType::Buffer {
+                min_binding_size, ..
+            } => assert_eq!(
+                min_binding_size.unwrap().get() as usize,
+                SsaoUniform::STD140.size
+            ),
+            _ => unreachable!(),
+        }
+
+        for source in [
+            include_str!("../shaders/ssao.wgsl"),
+            include_str!("../shaders/ssao_blur.wgsl"),
+        ] {
+            let entries = ShaderReflection::from_wgsl(source)
+                .unwrap()
+                .bind_group_layout_entries()
+                .unwrap();
+            assert!(matches!(
+                entries[0][1].ty,
+                wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    ..
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn noise_rotations_are_unit_and_distinct() {
+        let texels = noise_texels();
+        let rotations = texels
+            .chunks(4)
+            .map(|texel| (texel[0] as i8, texel[1] as i8))
+            .collect::<Vec<_>>();
+        for (i, &(x, y)) in rotations.iter().enumerate() {
+            let length = ((x as f32).powi(2) + (y as f32).powi(2)).sqrt() / 127.0;
+            assert!((length - 1.0).abs() < 0.01);
+            assert!(!rotations[..i].contains(&(x, y)));
+        }
+    }
+}
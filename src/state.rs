@@ -1,64 +1,143 @@
-use std::time::Duration;
+use std::{collections::HashMap, task::Poll, time::Duration};
 
 use cgmath::{InnerSpace, Rotation3, Zero};
 use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
-    Backends, BindGroup, BindGroupEntry, Buffer, BufferBindingType, Color,
-    CommandEncoderDescriptor, CompositeAlphaMode, Device, DeviceDescriptor, Features, Limits,
-    Operations, PowerPreference, PresentMode, Queue, RenderPassColorAttachment,
+    util::DeviceExt, Backends, Buffer, Color, CommandEncoderDescriptor, CompareFunction,
+    CompositeAlphaMode, Device, DeviceDescriptor, Features, Limits, Operations, PipelineLayout,
+    PowerPreference, PresentMode, Queue, RenderPassColorAttachment,
     RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, RequestAdapterOptions,
-    ShaderStages, Surface, SurfaceConfiguration, TextureSampleType, TextureUsages,
-    TextureViewDimension,
+    Surface, SurfaceConfiguration, TextureFormat, TextureUsages,
 };
 use winit::{
-    dpi::PhysicalSize,
-    event::{ElementState, KeyboardInput, MouseButton, WindowEvent},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
     window::Window,
 };
 
 use crate::{
     camera::{
-        camera::Camera, controller::CameraController, projection::Projection,
-        uniform::CameraUniform,
+        camera::Camera,
+        controller::CameraController,
+        projection::Projection,
+        view::{CameraView, PixelRect, ViewTarget},
     },
+    clusters::LightClusters,
+    debug_draw::DebugDraw,
+    debug_view::{DebugViewMode, DebugViews},
+    decals::{DecalTexturesId, Decals},
+    deferred::{self, Deferred, ShadingPath},
+    fog::{self, Fog, FogSettings},
+    ibl::Environment,
     instance::{Instance, InstanceRaw},
-    light::LightUniform,
+    light::{DirectionalLight, LightUniform, PointLight, SCENE_SHADER},
     model::{DrawModel, Model, ModelVertex},
+    particles::{EmitterId, ParticleEmitter, ParticleSystem},
+    picking::{PickResult, Picker},
+    point_shadows::{PointShadowSettings, PointShadows},
+    reflection::{self, ReflectedLayout, ShaderReflection},
     resources,
+    shadows::{ShadowSettings, Shadows},
+    sprite::SpriteBatch,
+    ssao::{Ssao, SsaoSettings},
+    terrain::{Terrain, TerrainBuilder},
+    text::renderer::TextRenderer,
     texture::Texture,
+    transparency::{self, Oit, TransparencyMode},
+    uniform::UniformBuffer,
     vertex::Vertex,
 };
 
 const NUM_INSTANCES_PER_ROW: u32 = 20;
 
+/// The pipelines needed to draw the scene into one kind of target. Views with
+/// different color formats or depth directions each get their own set.
+pub(crate) struct ScenePipelines {
+    pub(crate) scene: RenderPipeline,
+    pub(crate) light: RenderPipeline,
+    pub(crate) clear: RenderPipeline,
+    /// Blended materials, sorted.
+    pub(crate) transparent: RenderPipeline,
+    /// Blended materials into the weighted blended targets.
+    pub(crate) oit: RenderPipeline,
+    pub(crate) oit_composite: RenderPipeline,
+    /// Opaque materials into the G-buffer.
+    pub(crate) gbuffer: RenderPipeline,
+    pub(crate) deferred_lighting: RenderPipeline,
+    pub(crate) fog: RenderPipeline,
+}
+
 pub struct State {
     pub(crate) surface: Surface,
     pub(crate) device: Device,
     pub(crate) queue: Queue,
     pub(crate) config: SurfaceConfiguration,
     pub(crate) size: PhysicalSize<u32>,
-    pub(crate) pipeline: RenderPipeline,
-    pub(crate) camera_buffer: wgpu::Buffer,
-    pub(crate) camera_bind_group: wgpu::BindGroup,
+    pub(crate) pipelines: HashMap<(TextureFormat, CompareFunction), ScenePipelines>,
+    pub(crate) layout: ReflectedLayout,
+    pub(crate) clear_layout: ReflectedLayout,
     pub(crate) instances: Vec<Instance>,
     pub(crate) instance_buffer: Buffer,
+    /// The instances back to front for each view, when blended materials
+    /// are sorted.
+    pub(crate) sorted_instance_buffer: Option<Buffer>,
     pub(crate) depth_texture: Texture,
     pub(crate) model: Model,
-    pub(crate) light_uniform: LightUniform,
-    pub(crate) light_buffer: Buffer,
-    pub(crate) light_pipeline: RenderPipeline,
-    pub(crate) light_bind_group: BindGroup,
+    pub(crate) light_uniform: UniformBuffer<LightUniform>,
+    /// Lights shaded by clustered forward shading, on top of the main
+    /// light. Hundreds are fine as long as few reach any one spot.
+    pub point_lights: Vec<PointLight>,
+    pub(crate) light_clusters: LightClusters,
+    /// A light infinitely far away, such as the sun, casting shadows
+    /// across the whole scene.
+    pub sun: Option<DirectionalLight>,
+    /// The sun's cascaded shadow map.
+    pub shadows: ShadowSettings,
+    pub(crate) shadow_maps: Shadows,
+    /// Cube shadows of the main light and of point lights that cast them.
+    pub point_shadows: PointShadowSettings,
+    pub(crate) point_shadow_maps: PointShadows,
+    /// Fog and atmospheric scattering over each view's opaque scene.
+    pub fog: FogSettings,
+    pub(crate) fog_renderer: Fog,
+    pub(crate) light_pipeline_layout: PipelineLayout,
+    pub(crate) picker: Picker,
+    pub debug: DebugDraw,
+    pub debug_views: DebugViews,
+    pub text: TextRenderer,
+    pub sprites: SpriteBatch,
+    pub terrain: Option<Terrain>,
+    /// How materials with [`AlphaMode::Blend`](crate::model::AlphaMode::Blend)
+    /// are drawn.
+    pub transparency: TransparencyMode,
+    pub(crate) oit: Oit,
+    pub particles: ParticleSystem,
+    /// The ambient light. Replace it with
+    /// [`set_environment_map`](Self::set_environment_map).
+    pub environment: Environment,
+    /// Screen-space ambient occlusion of the model's opaque meshes.
+    pub ssao: SsaoSettings,
+    pub(crate) ambient_occlusion: Ssao,
+    /// Whether opaque meshes and the terrain are lit as they are drawn or
+    /// from a G-buffer.
+    pub shading: ShadingPath,
+    pub(crate) deferred: Deferred,
+    /// Projected onto the opaque meshes and the terrain, with
+    /// [`ShadingPath::Deferred`] only. Blended materials never get them.
+    pub decals: Decals,
+    #[cfg(feature = "egui")]
+    pub gui: crate::gui::Gui,
 
     // camera stuff
-    pub camera_uniform: CameraUniform,
-    pub camera: Camera,
-    pub projection: Projection,
-    pub camera_controller: CameraController,
+    pub views: Vec<CameraView>,
+    /// The view whose camera `camera_controller` drives.
+    pub controlled_view: usize,
+    pub camera_controller: Box<dyn CameraController>,
     pub mouse_pressed: bool,
+    pub cursor_position: Option<PhysicalPosition<f64>>,
 }
 
 impl State {
-    pub async fn new(window: &Window) -> Self {
+    pub async fn new<C: CameraController + 'static>(window: &Window, camera_controller: C) -> Self {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(Backends::all());
@@ -77,7 +156,9 @@ impl State {
             .request_device(
                 &DeviceDescriptor {
                     label: None,
-                    features: Features::empty(),
+                    // Optional: the wireframe overlay falls back to a
+                    // shader when lines are unsupported.
+                    features: adapter.features() & Features::POLYGON_MODE_LINE,
                     limits: Limits::default(),
                 },
                 None,
@@ -129,187 +210,271 @@ impl State {
         let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
         let projection =
             Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
-        let camera_controller = CameraController::new(7.48341, 1.4);
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: TextureSampleType::Float { filterable: true },
-                            view_dimension: TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
+
+        let shader_reflection = ShaderReflection::from_wgsl(SCENE_SHADER).unwrap();
+        let reflected = shader_reflection
+            .create_layout(&device, "Render Pipeline Layout")
+            .unwrap();
+        let texture_bind_group_layout = &reflected.bind_group_layouts[0];
+        let camera_bind_group_layout = &reflected.bind_group_layouts[1];
+        // The scene's light group also holds each view's point light
+        // clusters. The light itself gets a group of its own for the light
+        // shader, which only needs it.
+        let light_bind_group_layout =
+            &device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("light_bind_group_layout"),
+                entries: &reflected.entries[2][..1],
             });
 
-        let camera_uniform = CameraUniform::new();
+        let light_uniform = UniformBuffer::new(
+            &device,
+            light_bind_group_layout,
+            LightUniform {
+                position: [2.0, 2.0, 2.0],
+                color: [1.0, 1.0, 1.0],
+            },
+            "light_bind_group",
+        );
 
-        let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("camera-buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        // The light shader shares the camera and light bind groups with the
+        // main shader, just at different group indices.
+        ShaderReflection::from_wgsl(include_str!("../shaders/light.wgsl"))
+            .and_then(|reflection| {
+                reflection.validate_bind_group_layouts(&[
+                    &reflected.entries[1],
+                    &reflected.entries[2][..1],
+                ])
+            })
+            .unwrap();
 
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("camera_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
-                    count: None,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                }],
+        let light_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Light Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, light_bind_group_layout],
+                push_constant_ranges: &[],
             });
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            label: Some("camera_bind_group"),
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-        });
+        let clear_layout = ShaderReflection::from_wgsl(include_str!("../shaders/clear.wgsl"))
+            .and_then(|reflection| reflection.create_layout(&device, "Clear Pipeline Layout"))
+            .unwrap();
 
-        let light_uniform = LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
+        let main_view = CameraView::new(
+            &device,
+            camera_bind_group_layout,
+            &clear_layout.bind_group_layouts[0],
+            camera,
+            projection,
+        );
 
-        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Light VB"),
-            contents: bytemuck::cast_slice(&[light_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        let picker = Picker::new(
+            &device,
+            camera_bind_group_layout,
+            &reflected.entries[1],
+            config.width,
+            config.height,
+        )
+        .unwrap();
 
-        let light_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: None,
-            });
+        let debug =
+            DebugDraw::new(&device, camera_bind_group_layout, &reflected.entries[1]).unwrap();
 
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-            label: None,
-        });
+        let debug_views = DebugViews::new(&device, &reflected).unwrap();
 
-        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[
-                &texture_bind_group_layout,
-                &camera_bind_group_layout,
-                &light_bind_group_layout,
-            ],
-            push_constant_ranges: &[],
-        });
+        let text =
+            TextRenderer::new(&device, camera_bind_group_layout, &reflected.entries[1]).unwrap();
 
-        let pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shader.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &layout,
-                config.format,
-                Some(Texture::DEPTH_FORMAT),
-                &[ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-            )
-        };
+        let sprites = SpriteBatch::new(&device, config.format).unwrap();
 
-        let light_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/light.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &layout,
-                config.format,
-                Some(Texture::DEPTH_FORMAT),
-                &[ModelVertex::desc()],
-                shader,
-            )
-        };
+        let oit = Oit::new(&device).unwrap();
+
+        let particles =
+            ParticleSystem::new(&device, camera_bind_group_layout, &reflected.entries[1]).unwrap();
+
+        // A dim gray sky, which lights like the old constant ambient term
+        // until an environment map is set.
+        let environment = Environment::uniform(&device, &queue, [0.1; 3]).unwrap();
+
+        let ambient_occlusion = Ssao::new(
+            &device,
+            &queue,
+            camera_bind_group_layout,
+            &reflected.entries[1],
+        )
+        .unwrap();
 
-        let model = resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
+        let deferred = Deferred::new(&device, &reflected).unwrap();
+        let decals = Decals::new(&device, &queue).unwrap();
+
+        let light_clusters = LightClusters::new(&device).unwrap();
+        let shadow_maps = Shadows::new(&device).unwrap();
+        let point_shadow_maps = PointShadows::new(&device).unwrap();
+        let fog_renderer = Fog::new(&device).unwrap();
+
+        #[cfg(feature = "egui")]
+        let gui =
+            crate::gui::Gui::new(&device, config.format, size, window.scale_factor()).unwrap();
+
+        let model = resources::load_model("cube.obj", &device, &queue, texture_bind_group_layout)
             .await
             .unwrap();
 
-        Self {
+        let mut state = Self {
             surface,
             device,
             queue,
             config,
             size,
-            pipeline,
-            camera_uniform,
-            camera_buffer,
-            camera_bind_group,
-            camera,
+            pipelines: HashMap::new(),
+            layout: reflected,
+            clear_layout,
             instances,
             instance_buffer,
+            sorted_instance_buffer: None,
             depth_texture,
             model,
             light_uniform,
-            light_buffer,
-            light_pipeline,
-            light_bind_group,
-            camera_controller,
-            projection,
+            point_lights: Vec::new(),
+            light_clusters,
+            sun: None,
+            shadows: ShadowSettings::default(),
+            shadow_maps,
+            point_shadows: PointShadowSettings::default(),
+            point_shadow_maps,
+            fog: FogSettings::default(),
+            fog_renderer,
+            light_pipeline_layout,
+            picker,
+            debug,
+            debug_views,
+            text,
+            sprites,
+            terrain: None,
+            transparency: TransparencyMode::default(),
+            oit,
+            particles,
+            environment,
+            ssao: SsaoSettings::default(),
+            ambient_occlusion,
+            shading: ShadingPath::default(),
+            deferred,
+            decals,
+            #[cfg(feature = "egui")]
+            gui,
+            views: Vec::new(),
+            controlled_view: 0,
+            camera_controller: Box::new(camera_controller),
             mouse_pressed: false,
+            cursor_position: None,
+        };
+        state.add_view(main_view);
+        state
+    }
+
+    /// Creates a full-surface view with its own camera uniform. Configure it
+    /// with the `with_*` builders and hand it to [`add_view`](Self::add_view).
+    pub fn create_view(&self, camera: Camera, projection: Projection) -> CameraView {
+        CameraView::new(
+            &self.device,
+            &self.layout.bind_group_layouts[1],
+            &self.clear_layout.bind_group_layouts[0],
+            camera,
+            projection,
+        )
+    }
+
+    /// Adds a view to the frame and returns its index in `views`.
+    pub fn add_view(&mut self, mut view: CameraView) -> usize {
+        view.fit_projection(self.config.width, self.config.height);
+        self.ensure_pipelines(self.pipeline_key(&view));
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    /// The view driven by the camera controller.
+    pub fn main_view(&self) -> &CameraView {
+        &self.views[self.controlled_view]
+    }
+
+    pub fn main_view_mut(&mut self) -> &mut CameraView {
+        &mut self.views[self.controlled_view]
+    }
+
+    /// Switches the projection of a view, building pipelines for its depth
+    /// direction if no other view uses it yet.
+    pub fn set_projection(&mut self, index: usize, projection: Projection) {
+        let (width, height) = (self.config.width, self.config.height);
+        let view = &mut self.views[index];
+        view.projection = projection;
+        view.fit_projection(width, height);
+
+        let key = self.pipeline_key(&self.views[index]);
+        self.ensure_pipelines(key);
+    }
+
+    fn pipeline_key(&self, view: &CameraView) -> (TextureFormat, CompareFunction) {
+        let format = match view.target() {
+            ViewTarget::Surface => self.config.format,
+            ViewTarget::Texture { format, .. } => *format,
+        };
+        (format, view.projection.depth_compare())
+    }
+
+    fn ensure_pipelines(&mut self, key: (TextureFormat, CompareFunction)) {
+        if !self.pipelines.contains_key(&key) {
+            let pipelines = self.create_pipelines(key);
+            self.pipelines.insert(key, pipelines);
+        }
+    }
+
+    fn create_pipelines(
+        &self,
+        (color_format, depth_compare): (TextureFormat, CompareFunction),
+    ) -> ScenePipelines {
+        let (scene, light) = create_scene_pipelines(
+            &self.device,
+            color_format,
+            depth_compare,
+            &self.layout.pipeline_layout,
+            &self.light_pipeline_layout,
+        );
+        let clear = create_clear_pipeline(
+            &self.device,
+            &self.clear_layout.pipeline_layout,
+            color_format,
+        );
+
+        let (transparent, oit, oit_composite) = transparency::create_transparent_pipelines(
+            &self.device,
+            &self.layout.pipeline_layout,
+            &self.oit.composite_layout.pipeline_layout,
+            color_format,
+            depth_compare,
+        );
+
+        let (gbuffer, deferred_lighting) = deferred::create_deferred_pipelines(
+            &self.device,
+            &self.layout.pipeline_layout,
+            &self.deferred.lighting_layout,
+            color_format,
+            depth_compare,
+        );
+
+        let fog = fog::create_fog_pipeline(
+            &self.device,
+            &self.fog_renderer.layout.pipeline_layout,
+            color_format,
+        );
+
+        ScenePipelines {
+            scene,
+            light,
+            clear,
+            transparent,
+            oit,
+            oit_composite,
+            gbuffer,
+            deferred_lighting,
+            fog,
         }
     }
 
@@ -319,14 +484,88 @@ impl State {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
-            self.projection.resize(new_size.width, new_size.height);
+            for view in &mut self.views {
+                view.fit_projection(new_size.width, new_size.height);
+            }
             self.depth_texture =
                 Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.picker
+                .resize(&self.device, new_size.width, new_size.height);
+            self.fog_renderer.resize();
+            #[cfg(feature = "egui")]
+            self.gui.resize(new_size);
+        }
+    }
+
+    /// Requests the object under window pixel (`x`, `y`), as seen by the
+    /// topmost surface view covering it. Returns `false` if no view does.
+    /// The result arrives through [`poll_pick`](Self::poll_pick).
+    pub fn request_pick(&mut self, x: f64, y: f64) -> bool {
+        let (width, height) = (self.config.width, self.config.height);
+        let view = (0..self.views.len())
+            .filter(|&index| {
+                let view = &self.views[index];
+                view.target().is_surface() && view.pixel_scissor(width, height).contains(x, y)
+            })
+            .max_by_key(|&index| self.views[index].priority);
+
+        match view {
+            Some(view) => {
+                self.picker.request(view, x as u32, y as u32);
+                true
+            }
+            None => false,
         }
     }
 
+    pub fn pick_at_cursor(&mut self) -> bool {
+        match self.cursor_position {
+            Some(position) => self.request_pick(position.x, position.y),
+            None => false,
+        }
+    }
+
+    /// Returns the result of the last requested pick once the GPU has
+    /// delivered it; `Ready(None)` means nothing was under the pixel.
+    pub fn poll_pick(&mut self) -> Poll<Option<PickResult>> {
+        self.device.poll(wgpu::Maintain::Poll);
+        self.picker.poll()
+    }
+
     pub fn input(&mut self, event: &WindowEvent) -> bool {
+        // egui gets first pick, so typing into a text field does not move
+        // the camera.
+        #[cfg(feature = "egui")]
+        if self.gui.on_event(event) {
+            return true;
+        }
+
         match event {
+            // F1 cycles through the debug views, F2 toggles the wireframe.
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::F1),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.debug_views.mode = self.debug_views.mode.next();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::F2),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.debug_views.wireframe = !self.debug_views.wireframe;
+                true
+            }
             WindowEvent::KeyboardInput {
                 input:
                     KeyboardInput {
@@ -340,40 +579,113 @@ impl State {
                 self.camera_controller.process_scroll(delta);
                 true
             }
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state,
-                ..
-            } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
+            WindowEvent::MouseInput { button, state, .. } => {
+                if *button == MouseButton::Left {
+                    self.mouse_pressed = *state == ElementState::Pressed;
+                }
+                self.camera_controller.process_mouse_button(*button, *state);
                 true
             }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some(*position);
+                false
+            }
             _ => false,
         }
     }
 
+    /// Packs `image` into the sprite batch's atlases for use in
+    /// [`Sprite`](crate::sprite::Sprite)s.
+    pub fn add_sprite_texture(
+        &mut self,
+        image: &image::DynamicImage,
+    ) -> anyhow::Result<crate::sprite::SpriteTexture> {
+        self.sprites.add_texture(&self.device, &self.queue, image)
+    }
+
+    /// Adds textures for [`Decal`](crate::decals::Decal)s, with an
+    /// optional tangent-space normal map.
+    pub fn add_decal_textures(
+        &mut self,
+        albedo: &image::DynamicImage,
+        normal: Option<&image::DynamicImage>,
+    ) -> anyhow::Result<DecalTexturesId> {
+        let albedo = Texture::from_image(&self.device, &self.queue, albedo, Some("decal"), false)?;
+        let normal = normal
+            .map(|normal| {
+                Texture::from_image(
+                    &self.device,
+                    &self.queue,
+                    normal,
+                    Some("decal_normal"),
+                    true,
+                )
+            })
+            .transpose()?;
+        Ok(self.decals.add_textures(&self.device, albedo, normal))
+    }
+
+    /// Builds the terrain drawn by every view, replacing any previous one.
+    pub fn set_terrain(&mut self, builder: TerrainBuilder) -> anyhow::Result<()> {
+        self.terrain = Some(builder.build(&self.device, &self.queue, &self.layout)?);
+        Ok(())
+    }
+
+    /// Lights the scene with an equirectangular HDR environment map. With a
+    /// `cache` path the computed maps are reused from there, or written
+    /// there for next time.
+    pub fn set_environment_map(
+        &mut self,
+        hdr: &[u8],
+        cache: Option<&std::path::Path>,
+    ) -> anyhow::Result<()> {
+        self.environment = match cache {
+            Some(cache) => Environment::from_hdr_cached(&self.device, &self.queue, hdr, cache)?,
+            None => Environment::from_hdr(&self.device, &self.queue, hdr)?,
+        };
+        Ok(())
+    }
+
+    /// Adds an emitter to `particles`.
+    pub fn add_particle_emitter(&mut self, emitter: ParticleEmitter) -> EmitterId {
+        self.particles.add_emitter(&self.device, emitter)
+    }
+
+    /// Installs the hook that builds the egui UI, run once per frame.
+    #[cfg(feature = "egui")]
+    pub fn set_ui(&mut self, ui: impl FnMut(&mut egui::Context) + 'static) {
+        self.gui.set_ui(ui);
+    }
+
+    /// Whether raw mouse motion belongs to the UI rather than the camera.
+    pub fn ui_wants_pointer(&self) -> bool {
+        #[cfg(feature = "egui")]
+        return self.gui.wants_pointer_input();
+        #[cfg(not(feature = "egui"))]
+        false
+    }
+
     pub fn update(&mut self, duration: Duration) {
-        self.camera_controller
-            .update_camera(&mut self.camera, duration);
-        self.camera_uniform
-            .update_view_proj(&self.camera, &self.projection);
-
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
+        self.debug.update(duration);
+        self.particles.update(duration.as_secs_f32());
+        self.camera_controller.observe_scene(&self.instances);
+        if let Some(view) = self.views.get_mut(self.controlled_view) {
+            self.camera_controller
+                .update_camera(&mut view.camera, duration);
+        }
+        for view in &mut self.views {
+            // Viewports may have been moved since the last frame.
+            view.fit_projection(self.config.width, self.config.height);
+            view.update(&self.queue);
+        }
 
-        let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position =
+        let light = self.light_uniform.get_mut();
+        let old_position: cgmath::Vector3<_> = light.position.into();
+        light.position =
             (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
                 * old_position)
                 .into();
-        self.queue.write_buffer(
-            &self.light_buffer,
-            0,
-            bytemuck::cast_slice(&[self.light_uniform]),
-        );
+        self.light_uniform.update(&self.queue);
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -387,14 +699,467 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        for index in 0..self.views.len() {
+            self.ensure_pipelines(self.pipeline_key(&self.views[index]));
+        }
+        let keys = self.pipelines.keys().copied().collect::<Vec<_>>();
+        self.debug
+            .prepare(&self.device, &self.queue, keys.iter().copied());
+        if let Some(terrain) = &mut self.terrain {
+            terrain.prepare(&self.device, keys.iter().copied());
+        }
+        self.particles
+            .prepare(&self.device, &self.queue, keys.iter().copied());
+        self.debug_views
+            .prepare(&self.device, &self.queue, &self.model, keys);
+
+        let (width, height) = (self.config.width, self.config.height);
+        let main_view = self.main_view();
+        let text_key = (self.config.format, self.pipeline_key(main_view).1);
+        let text_viewport = main_view.pixel_viewport(width, height);
+        self.text.prepare(
+            &self.device,
+            &self.queue,
+            text_key,
+            (width, height),
+            (text_viewport.width, text_viewport.height),
+        );
+        self.sprites
+            .prepare(&self.device, &self.queue, (width, height));
+
+        // Collisions use the main view's depth from the previous frame,
+        // before the passes below clear it.
+        let main_view = &self.views[self.controlled_view];
+        let main_depth = match main_view.target() {
+            ViewTarget::Surface => &self.depth_texture.view,
+            ViewTarget::Texture { depth, .. } => &depth.view,
+        };
+        self.particles.simulate(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            main_view,
+            main_depth,
+            (width, height),
+        );
+        self.shadow_maps.prepare(
+            &self.device,
+            &self.queue,
+            &self.shadows,
+            self.sun.as_ref(),
+            &self.views,
+        );
+        self.point_shadow_maps.prepare(
+            &self.device,
+            &self.queue,
+            &self.point_shadows,
+            self.light_uniform.get(),
+            &self.point_lights,
+        );
+        self.light_clusters.prepare(
+            &self.device,
+            &self.queue,
+            &self.layout.bind_group_layouts[2],
+            self.light_uniform.buffer(),
+            &self.shadow_maps,
+            &self.point_shadow_maps,
+            &self.point_lights,
+            &self.views,
+            (width, height),
+        );
+        self.light_clusters.assign(&mut encoder);
+        self.point_shadow_maps.render(
+            &mut encoder,
+            &self.model,
+            &self.instance_buffer,
+            self.instances.len() as u32,
+            self.terrain.as_ref(),
+        );
+
+        let transparent =
+            self.model.has_transparency() && self.debug_views.mode == DebugViewMode::Lit;
+        let instance_stride = std::mem::size_of::<InstanceRaw>() as u64;
+        let instance_bytes = self.instances.len() as u64 * instance_stride;
+        match self.transparency {
+            TransparencyMode::Sorted if transparent => {
+                let size = instance_bytes * self.views.len() as u64;
+                if !matches!(&self.sorted_instance_buffer, Some(buffer) if buffer.size() >= size) {
+                    self.sorted_instance_buffer =
+                        Some(self.device.create_buffer(&wgpu::BufferDescriptor {
+                            label: Some("Sorted Instance Buffer"),
+                            size,
+                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        }));
+                }
+                let buffer = self.sorted_instance_buffer.as_ref().unwrap();
+                for (index, view) in self.views.iter().enumerate() {
+                    let sorted =
+                        transparency::sort_back_to_front(&self.instances, view.camera.position);
+                    self.queue.write_buffer(
+                        buffer,
+                        index as u64 * instance_bytes,
+                        bytemuck::cast_slice(&sorted),
+                    );
+                }
+            }
+            TransparencyMode::WeightedBlended if transparent => {
+                let sizes = self
+                    .views
+                    .iter()
+                    .map(|view| view.target_size(width, height))
+                    .collect::<Vec<_>>();
+                self.oit.prepare(&self.device, sizes);
+            }
+            _ => {}
+        }
+
+        let occluded = self.ssao.enabled && self.debug_views.mode == DebugViewMode::Lit;
+        if occluded {
+            self.ambient_occlusion.prepare(
+                &self.device,
+                &self.queue,
+                &self.ssao,
+                &self.views,
+                (width, height),
+            );
+        }
+        let deferred =
+            self.shading == ShadingPath::Deferred && self.debug_views.mode == DebugViewMode::Lit;
+        let fogged =
+            self.fog.is_active(self.sun.as_ref()) && self.debug_views.mode == DebugViewMode::Lit;
+        if fogged {
+            self.fog_renderer.prepare(
+                &self.device,
+                &self.queue,
+                &self.fog,
+                self.sun.as_ref(),
+                &self.views,
+                &self.depth_texture.view,
+                (width, height),
+            );
+        }
+        if deferred {
+            self.deferred
+                .prepare(&self.device, &self.queue, &self.views, (width, height));
+            self.decals.prepare(
+                &self.device,
+                &self.queue,
+                &self.views,
+                &self.deferred,
+                (width, height),
+            );
+        }
+        self.environment.prepare(
+            &self.device,
+            &self.layout.bind_group_layouts[3],
+            &self.ambient_occlusion,
+        );
+        // Blended surfaces go without occlusion, as the prepass only sees
+        // what is behind them.
+        let unoccluded = self.environment.unoccluded_bind_group();
+
+        let mut order = (0..self.views.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&index| self.views[index].priority);
+
+        let mut surface_cleared = false;
+        for index in order {
+            let camera_view = &self.views[index];
+            let lights = self.light_clusters.bind_group(index);
+            let key = self.pipeline_key(camera_view);
+            let pipelines = &self.pipelines[&key];
+
+            let (color_view, depth_view) = match camera_view.target() {
+                ViewTarget::Surface => (&view, &self.depth_texture.view),
+                ViewTarget::Texture { color, depth, .. } => (&color.view, &depth.view),
+            };
+
+            // Load ops clear the whole attachment, so only the first view
+            // drawn into a target may use them. Later views clear their own
+            // rectangle with a full-screen triangle instead.
+            let first = match camera_view.target() {
+                ViewTarget::Surface => !std::mem::replace(&mut surface_cleared, true),
+                ViewTarget::Texture { .. } => true,
+            };
+            let (color_load, depth_load) = if first {
+                (
+                    wgpu::LoadOp::Clear(camera_view.clear_color.unwrap_or(Color::BLACK)),
+                    wgpu::LoadOp::Clear(camera_view.projection.depth_clear_value()),
+                )
+            } else {
+                (wgpu::LoadOp::Load, wgpu::LoadOp::Load)
+            };
+
+            self.shadow_maps.render(
+                &mut encoder,
+                index,
+                &self.model,
+                &self.instance_buffer,
+                self.instances.len() as u32,
+                self.terrain.as_ref(),
+                camera_view.camera.position,
+            );
+            let lighting = if occluded {
+                self.ambient_occlusion.render(
+                    &mut encoder,
+                    index,
+                    camera_view,
+                    (width, height),
+                    &self.model,
+                    &self.instance_buffer,
+                    self.instances.len() as u32,
+                );
+                self.environment.bind_group(index)
+            } else {
+                unoccluded
+            };
+            if deferred {
+                self.deferred.render(
+                    &mut encoder,
+                    index,
+                    camera_view,
+                    (width, height),
+                    &pipelines.gbuffer,
+                    &self.model,
+                    &self.instance_buffer,
+                    self.instances.len() as u32,
+                    self.terrain.as_ref(),
+                    lights,
+                    unoccluded,
+                );
+            }
+
+            let viewport = camera_view.pixel_viewport(width, height);
+            let scissor = camera_view.pixel_scissor(width, height);
+            if deferred {
+                self.decals.render(
+                    &mut encoder,
+                    index,
+                    self.deferred.views(index),
+                    viewport,
+                    scissor,
+                );
+            }
+            let mut pass = begin_view_pass(
+                &mut encoder,
+                (color_view, color_load),
+                (depth_view, depth_load),
+                viewport,
+                scissor,
+            );
+
+            if !first {
+                // A blend constant of zero keeps the color that is already
+                // there, for views without a clear color.
+                let keep = if camera_view.clear_color.is_some() {
+                    1.0
+                } else {
+                    0.0
+                };
+                pass.set_blend_constant(Color {
+                    r: keep,
+                    g: keep,
+                    b: keep,
+                    a: keep,
+                });
+                pass.set_pipeline(&pipelines.clear);
+                pass.set_bind_group(0, camera_view.clear_uniform.bind_group(), &[]);
+                pass.draw(0..3, 0..1);
+            }
+
+            pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+
+            use crate::model::DrawLight;
+            pass.set_pipeline(&pipelines.light);
+            pass.draw_light_model(
+                &self.model,
+                camera_view.camera_uniform.bind_group(),
+                self.light_uniform.bind_group(),
+            );
+
+            let instances = 0..self.instances.len() as u32;
+            if !self.debug_views.draw_scene(
+                &mut pass,
+                key,
+                &self.model,
+                instances.clone(),
+                camera_view.camera_uniform.bind_group(),
+                lights,
+            ) {
+                if deferred {
+                    pass.set_pipeline(&pipelines.deferred_lighting);
+                    pass.set_bind_group(0, self.deferred.bind_group(index), &[]);
+                    pass.set_bind_group(1, lighting, &[]);
+                    pass.set_bind_group(2, lights, &[]);
+                    pass.draw(0..3, 0..1);
+                } else {
+                    pass.set_pipeline(&pipelines.scene);
+                    pass.set_bind_group(3, lighting, &[]);
+                    for (mesh, material) in self.model.meshes_by_transparency(false) {
+                        pass.draw_mesh_instanced(
+                            mesh,
+                            material,
+                            instances.clone(),
+                            camera_view.camera_uniform.bind_group(),
+                            lights,
+                        );
+                    }
+                }
+            }
+            // With deferred shading the terrain is in the G-buffer.
+            if let Some(terrain) = self.terrain.as_ref().filter(|_| !deferred) {
+                terrain.draw(
+                    &mut pass,
+                    key,
+                    camera_view.camera.position,
+                    camera_view.camera_uniform.bind_group(),
+                    lights,
+                );
+            }
+
+            if fogged {
+                drop(pass);
+                self.fog_renderer.render(
+                    &mut encoder,
+                    index,
+                    &pipelines.fog,
+                    color_view,
+                    viewport,
+                    scissor,
+                );
+                pass = begin_view_pass(
+                    &mut encoder,
+                    (color_view, wgpu::LoadOp::Load),
+                    (depth_view, wgpu::LoadOp::Load),
+                    viewport,
+                    scissor,
+                );
+                pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            }
+
+            if transparent {
+                match self.transparency {
+                    TransparencyMode::Sorted => {
+                        let offset = index as u64 * instance_bytes;
+                        let buffer = self.sorted_instance_buffer.as_ref().unwrap();
+                        pass.set_vertex_buffer(1, buffer.slice(offset..offset + instance_bytes));
+                        pass.set_pipeline(&pipelines.transparent);
+                        pass.set_bind_group(3, unoccluded, &[]);
+                        for (mesh, material) in self.model.meshes_by_transparency(true) {
+                            pass.draw_mesh_instanced(
+                                mesh,
+                                material,
+                                instances.clone(),
+                                camera_view.camera_uniform.bind_group(),
+                                lights,
+                            );
+                        }
+                        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+                    }
+                    TransparencyMode::WeightedBlended => {
+                        let targets = self
+                            .oit
+                            .targets(camera_view.target_size(width, height))
+                            .unwrap();
+                        drop(pass);
+
+                        let mut oit_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                            label: Some("OIT Accumulate Pass"),
+                            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                                view: depth_view,
+                                stencil_ops: None,
+                                depth_ops: Some(wgpu::Operations {
+                                    load: wgpu::LoadOp::Load,
+                                    store: true,
+                                }),
+                            }),
+                            color_attachments: &[
+                                Some(RenderPassColorAttachment {
+                                    view: &targets.accum.view,
+                                    resolve_target: None,
+                                    ops: Operations {
+                                        store: true,
+                                        load: wgpu::LoadOp::Clear(Color::TRANSPARENT),
+                                    },
+                                }),
+                                Some(RenderPassColorAttachment {
+                                    view: &targets.revealage.view,
+                                    resolve_target: None,
+                                    ops: Operations {
+                                        store: true,
+                                        load: wgpu::LoadOp::Clear(Color::WHITE),
+                                    },
+                                }),
+                            ],
+                        });
+                        oit_pass.set_viewport(
+                            viewport.x as f32,
+                            viewport.y as f32,
+                            viewport.width as f32,
+                            viewport.height as f32,
+                            0.0,
+                            1.0,
+                        );
+                        oit_pass.set_scissor_rect(
+                            scissor.x,
+                            scissor.y,
+                            scissor.width,
+                            scissor.height,
+                        );
+                        oit_pass.set_pipeline(&pipelines.oit);
+                        oit_pass.set_bind_group(3, unoccluded, &[]);
+                        oit_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+                        for (mesh, material) in self.model.meshes_by_transparency(true) {
+                            oit_pass.draw_mesh_instanced(
+                                mesh,
+                                material,
+                                instances.clone(),
+                                camera_view.camera_uniform.bind_group(),
+                                lights,
+                            );
+                        }
+                        drop(oit_pass);
+
+                        pass = begin_view_pass(
+                            &mut encoder,
+                            (color_view, wgpu::LoadOp::Load),
+                            (depth_view, wgpu::LoadOp::Load),
+                            viewport,
+                            scissor,
+                        );
+                        pass.set_pipeline(&pipelines.oit_composite);
+                        pass.set_bind_group(0, &targets.bind_group, &[]);
+                        pass.draw(0..3, 0..1);
+                        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+                    }
+                }
+            }
+
+            self.particles
+                .draw(&mut pass, key, camera_view.camera_uniform.bind_group());
+            self.debug_views.draw_wireframe(
+                &mut pass,
+                key,
+                &self.model,
+                instances,
+                &self.instance_buffer,
+                camera_view.camera_uniform.bind_group(),
+            );
+
+            self.debug
+                .draw(&mut pass, key, camera_view.camera_uniform.bind_group());
+        }
+
+        // Sprites and text go over every view. World text is seen through the main
+        // view and tested against whatever depth the surface ended up with.
         {
             let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Render Pass"),
+                label: Some("Text Pass"),
                 depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     stencil_ops: None,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: wgpu::LoadOp::Load,
                         store: true,
                     }),
                 }),
@@ -403,46 +1168,211 @@ impl State {
                     resolve_target: None,
                     ops: Operations {
                         store: true,
-                        load: wgpu::LoadOp::Clear(Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Load,
                     },
                 })],
             });
 
-            pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-
-            use crate::model::DrawLight;
-            pass.set_pipeline(&self.light_pipeline);
-            pass.draw_light_model(&self.model, &self.camera_bind_group, &self.light_bind_group);
+            let main_view = self.main_view();
+            let camera_bind_group = main_view.camera_uniform.bind_group();
+            if main_view.target().is_surface() {
+                pass.set_viewport(
+                    text_viewport.x as f32,
+                    text_viewport.y as f32,
+                    text_viewport.width as f32,
+                    text_viewport.height as f32,
+                    0.0,
+                    1.0,
+                );
+                self.text.draw_world(&mut pass, text_key, camera_bind_group);
+            }
 
-            pass.set_pipeline(&self.pipeline);
-            pass.draw_model_instanced(
-                &self.model,
-                0..self.instances.len() as u32,
-                &self.camera_bind_group,
-                &self.light_bind_group,
-            );
+            pass.set_viewport(0.0, 0.0, width as f32, height as f32, 0.0, 1.0);
+            self.sprites.render(&mut pass);
+            self.text
+                .draw_screen(&mut pass, text_key, camera_bind_group);
         }
 
+        #[cfg(feature = "egui")]
+        self.gui.render(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &view,
+            (width, height),
+        );
+
+        self.picker.encode(
+            &self.device,
+            &mut encoder,
+            &self.views,
+            (width, height),
+            &self.model,
+            &self.instance_buffer,
+            self.instances.len() as u32,
+        );
+
         self.queue.submit(std::iter::once(encoder.finish()));
+        self.picker.after_submit();
         output.present();
 
         Ok(())
     }
 }
 
+/// Starts a pass drawing into a view's rectangle of its target.
+fn begin_view_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    (color_view, color_load): (&'a wgpu::TextureView, wgpu::LoadOp<Color>),
+    (depth_view, depth_load): (&'a wgpu::TextureView, wgpu::LoadOp<f32>),
+    viewport: PixelRect,
+    scissor: PixelRect,
+) -> wgpu::RenderPass<'a> {
+    let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("Render Pass"),
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+            view: depth_view,
+            stencil_ops: None,
+            depth_ops: Some(wgpu::Operations {
+                load: depth_load,
+                store: true,
+            }),
+        }),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: color_view,
+            resolve_target: None,
+            ops: Operations {
+                store: true,
+                load: color_load,
+            },
+        })],
+    });
+    pass.set_viewport(
+        viewport.x as f32,
+        viewport.y as f32,
+        viewport.width as f32,
+        viewport.height as f32,
+        0.0,
+        1.0,
+    );
+    pass.set_scissor_rect(scissor.x, scissor.y, scissor.width, scissor.height);
+    pass
+}
+
+fn create_scene_pipelines(
+    device: &wgpu::Device,
+    color_format: wgpu::TextureFormat,
+    depth_compare: wgpu::CompareFunction,
+    layout: &wgpu::PipelineLayout,
+    light_layout: &wgpu::PipelineLayout,
+) -> (RenderPipeline, RenderPipeline) {
+    let pipeline = {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Normal Shader"),
+            source: wgpu::ShaderSource::Wgsl(SCENE_SHADER.into()),
+        };
+        create_render_pipeline(
+            device,
+            layout,
+            color_format,
+            Some(Texture::DEPTH_FORMAT),
+            depth_compare,
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+            shader,
+        )
+    };
+
+    let light_pipeline = {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Light Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/light.wgsl").into()),
+        };
+        create_render_pipeline(
+            device,
+            light_layout,
+            color_format,
+            Some(Texture::DEPTH_FORMAT),
+            depth_compare,
+            &[ModelVertex::desc()],
+            shader,
+        )
+    };
+
+    (pipeline, light_pipeline)
+}
+
+/// Draws `clear.wgsl` over the viewport. Color is
+/// blended by the pass's blend constant so a view can reset depth only.
+fn create_clear_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Clear Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/clear.wgsl").into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Clear Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Constant,
+                        dst_factor: wgpu::BlendFactor::OneMinusConstant,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Constant,
+                        dst_factor: wgpu::BlendFactor::OneMinusConstant,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
 fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
     color_format: wgpu::TextureFormat,
     depth_format: Option<wgpu::TextureFormat>,
+    depth_compare: wgpu::CompareFunction,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
 ) -> wgpu::RenderPipeline {
+    // Catch mismatches between the Rust vertex layouts and the shader's
+    // `@location` inputs before wgpu turns them into garbage on screen.
+    if let wgpu::ShaderSource::Wgsl(source) = &shader.source {
+        if let Err(e) = reflection::parse_wgsl(source).and_then(|module| {
+            reflection::validate_vertex_layouts(&module, "vs_main", vertex_layouts)
+        }) {
+            panic!("{:?}: {}", shader.label, e);
+        }
+    }
+
     let shader = device.create_shader_module(shader);
 
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -477,7 +1407,7 @@ fn create_render_pipeline(
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
             depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
+            depth_compare,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
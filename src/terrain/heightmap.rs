@@ -0,0 +1,87 @@
+use anyhow::*;
+use image::DynamicImage;
+
+/// Heights sampled on a regular grid, between 0 and 1.
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    width: u32,
+    depth: u32,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// `heights` holds `width` samples along X for each of `depth` rows
+    /// along Z.
+    pub fn new(width: u32, depth: u32, heights: Vec<f32>) -> Result<Self> {
+        if width < 2 || depth < 2 {
+            bail!("a {}x{} heightmap has no cells", width, depth);
+        }
+        if heights.len() != (width * depth) as usize {
+            bail!(
+                "{} heights given for a {}x{} heightmap",
+                heights.len(),
+                width,
+                depth
+            );
+        }
+        Ok(Self {
+            width,
+            depth,
+            heights,
+        })
+    }
+
+    /// Reads the image as 16-bit grayscale, so 8-bit images work too but
+    /// step visibly.
+    pub fn from_image(image: &DynamicImage) -> Result<Self> {
+        let luma = image.to_luma16();
+        let heights = luma
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32 / u16::MAX as f32)
+            .collect();
+        Self::new(luma.width(), luma.height(), heights)
+    }
+
+    /// Number of samples along X.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Number of samples along Z.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// The sample at `x`, `z`, clamped to the edges.
+    pub fn get(&self, x: u32, z: u32) -> f32 {
+        let x = x.min(self.width - 1);
+        let z = z.min(self.depth - 1);
+        self.heights[(z * self.width + x) as usize]
+    }
+
+    /// The height at fractional sample coordinates, interpolated over the
+    /// same two triangles per cell the terrain mesh uses at full detail.
+    /// Returns it with its slope along X and Z, in heights per sample.
+    pub fn interpolate(&self, x: f32, z: f32) -> (f32, [f32; 2]) {
+        let x = x.clamp(0.0, (self.width - 1) as f32);
+        let z = z.clamp(0.0, (self.depth - 1) as f32);
+        let (cell_x, cell_z) = (
+            (x.floor() as u32).min(self.width - 2),
+            (z.floor() as u32).min(self.depth - 2),
+        );
+        let (fx, fz) = (x - cell_x as f32, z - cell_z as f32);
+
+        let h00 = self.get(cell_x, cell_z);
+        let h01 = self.get(cell_x, cell_z + 1);
+        let h10 = self.get(cell_x + 1, cell_z);
+        let h11 = self.get(cell_x + 1, cell_z + 1);
+        // Cells are split along the diagonal from (0, 1) to (1, 0).
+        if fx + fz <= 1.0 {
+            let slope = [h10 - h00, h01 - h00];
+            (h00 + slope[0] * fx + slope[1] * fz, slope)
+        } else {
+            let slope = [h11 - h01, h11 - h10];
+            (h11 - slope[0] * (1.0 - fx) - slope[1] * (1.0 - fz), slope)
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Index buffers for terrain chunks at every level of detail, and the choice
+//! of level per chunk.
+
+/// Bits of an edge mask, naming the edges whose neighbour is one level
+/// coarser. North is the edge at the lowest Z.
+pub(crate) const NORTH: u32 = 1;
+pub(crate) const EAST: u32 = 2;
+pub(crate) const SOUTH: u32 = 4;
+pub(crate) const WEST: u32 = 8;
+
+/// Number of distinct edge masks.
+pub(crate) const MASKS: u32 = 16;
+
+/// Triangles of a chunk of `size` x `size` quads using every `step`-th
+/// vertex of its `(size + 1)²` vertex grid, indexed row by row.
+///
+/// The edges in `coarser` meet a neighbour using twice the step. Their odd
+/// vertices are collapsed onto the previous even one, so the edge follows
+/// the neighbour's exactly and no cracks open between them.
+pub(crate) fn chunk_indices(size: u32, step: u32, coarser: u32) -> Vec<u32> {
+    let odd = |c: u32| c % (2 * step) != 0;
+    let snap = |mut x: u32, mut z: u32| {
+        if (z == 0 && coarser & NORTH != 0 || z == size && coarser & SOUTH != 0) && odd(x) {
+            x -= step;
+        }
+        if (x == 0 && coarser & WEST != 0 || x == size && coarser & EAST != 0) && odd(z) {
+            z -= step;
+        }
+        (x as i64, z as i64)
+    };
+
+    let mut indices = Vec::new();
+    for z in (0..size).step_by(step as usize) {
+        for x in (0..size).step_by(step as usize) {
+            let a = snap(x, z);
+            let b = snap(x, z + step);
+            let c = snap(x + step, z);
+            let d = snap(x + step, z + step);
+            for [a, b, c] in [[a, b, c], [c, b, d]] {
+                // Collapsed edges leave some triangles without area, even
+                // where two coarser edges meet in a corner.
+                let area = (b.1 - a.1) * (c.0 - a.0) - (b.0 - a.0) * (c.1 - a.1);
+                if area != 0 {
+                    indices.extend([a, b, c].map(|(x, z)| (z * (size as i64 + 1) + x) as u32));
+                }
+            }
+        }
+    }
+    indices
+}
+
+/// Picks a level for each of `width` x `depth` chunks, stored row by row.
+/// Each chunk starts at `preferred(index)` and is refined until no
+/// neighbour is more than one level finer, which the edge masks rely on.
+pub(crate) fn select_levels(
+    (width, depth): (u32, u32),
+    preferred: impl Fn(usize) -> u32,
+) -> Vec<u32> {
+    let mut levels = (0..(width * depth) as usize)
+        .map(preferred)
+        .collect::<Vec<_>>();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for z in 0..depth {
+            for x in 0..width {
+                let finest = neighbours((width, depth), x, z)
+                    .map(|(_, index)| levels[index])
+                    .min()
+                    .unwrap_or(u32::MAX);
+                let level = &mut levels[(z * width + x) as usize];
+                if *level > finest.saturating_add(1) {
+                    *level = finest + 1;
+                    changed = true;
+                }
+            }
+        }
+    }
+    levels
+}
+
+/// The edge mask of the chunk at `x`, `z` for `levels` from
+/// [`select_levels`].
+pub(crate) fn coarser_edges(levels: &[u32], (width, depth): (u32, u32), x: u32, z: u32) -> u32 {
+    let level = levels[(z * width + x) as usize];
+    neighbours((width, depth), x, z)
+        .filter(|&(_, index)| levels[index] > level)
+        .fold(0, |mask, (edge, _)| mask | edge)
+}
+
+/// The edge bit and index of each neighbour of the chunk at `x`, `z`.
+fn neighbours((width, depth): (u32, u32), x: u32, z: u32) -> impl Iterator<Item = (u32, usize)> {
+    let index = move |x: u32, z: u32| (z * width + x) as usize;
+    [
+        (z > 0).then(|| (NORTH, index(x, z - 1))),
+        (x + 1 < width).then(|| (EAST, index(x + 1, z))),
+        (z + 1 < depth).then(|| (SOUTH, index(x, z + 1))),
+        (x > 0).then(|| (WEST, index(x - 1, z))),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stitched_chunks_tile_without_t_junctions() {
+        let size = 8;
+        for step in [1, 2, 4] {
+            for coarser in 0..MASKS {
+                let indices = chunk_indices(size, step, coarser);
+                let point = |i: u32| ((i % (size + 1)) as f32, (i / (size + 1)) as f32);
+
+                // Every triangle faces up, and together they cover the chunk
+                // exactly once.
+                let mut area = 0.0;
+                for triangle in indices.chunks(3) {
+                    let [a, b, c] = [0, 1, 2].map(|i| point(triangle[i]));
+                    let cross = (b.1 - a.1) * (c.0 - a.0) - (b.0 - a.0) * (c.1 - a.1);
+                    assert!(cross > 0.0, "step {} mask {}", step, coarser);
+                    area += cross / 2.0;
+                }
+                assert_eq!(area, (size * size) as f32, "step {} mask {}", step, coarser);
+
+                // Coarser edges only use the neighbour's vertices.
+                for &i in &indices {
+                    let (x, z) = (i % (size + 1), i / (size + 1));
+                    let on = |edge: u32, along: bool| coarser & edge != 0 && along;
+                    if on(NORTH, z == 0) || on(SOUTH, z == size) {
+                        assert_eq!(x % (2 * step), 0);
+                    }
+                    if on(WEST, x == 0) || on(EAST, x == size) {
+                        assert_eq!(z % (2 * step), 0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbouring_levels_differ_by_at_most_one() {
+        // One fine chunk in a corner of a coarse field.
+        let levels = select_levels((4, 3), |index| if index == 0 { 0 } else { 3 });
+        #[rustfmt::skip]
+        assert_eq!(levels, [
+            0, 1, 2, 3,
+            1, 2, 3, 3,
+            2, 3, 3, 3,
+        ]);
+        assert_eq!(coarser_edges(&levels, (4, 3), 0, 0), EAST | SOUTH);
+        assert_eq!(coarser_edges(&levels, (4, 3), 1, 1), EAST | SOUTH);
+        assert_eq!(coarser_edges(&levels, (4, 3), 3, 2), 0);
+    }
+}
@@ -0,0 +1,697 @@
+//! Heightmap terrain split into chunks that each pick a level of detail
+//! from their distance to the camera.
+//!
+//! ```ignore
+//! let heightmap = Heightmap::from_image(&image::open("island.png")?)?;
+//! state.set_terrain(
+//!     TerrainBuilder::new(heightmap)
+//!         .with_height_scale(40.0)
+//!         .with_layer(grass, 64.0)
+//!         .with_layer(rock, 32.0)
+//!         .with_splat_map(image::open("island_splat.png")?),
+//! )?;
+//! let ground = state.terrain.as_ref().and_then(|terrain| terrain.height_at(x, z));
+//! ```
+
+pub mod heightmap;
+mod lod;
+
+use std::{collections::HashMap, ops::Range};
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Point3, Vector3};
+use image::{DynamicImage, Rgba, RgbaImage};
+use wgpu::{
+    util::DeviceExt, BindGroup, Buffer, BufferUsages, CompareFunction, Device, PipelineLayout,
+    Queue, RenderPass, RenderPipeline, TextureFormat,
+};
+
+use crate::{
+    deferred::ShadingPath,
+    reflection::{ReflectedLayout, ShaderReflection},
+    texture::Texture,
+    uniform::{ShaderType, UniformBuffer},
+    vertex::Vertex,
+};
+
+pub use heightmap::Heightmap;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Vertex)]
+pub struct TerrainVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    /// Position on the terrain from 0 to 1, where the splat map is sampled.
+    pub tex_coords: [f32; 2],
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct TerrainUniform {
+    layer_scales: [f32; 4],
+    decal_layer: u32,
+}
+
+const TERRAIN_SHADER: &str = concat!(
+    include_str!("../../shaders/gbuffer.wgsl"),
+    include_str!("../../shaders/lighting.wgsl"),
+    include_str!("../../shaders/terrain.wgsl")
+);
+
+/// Describes a terrain for [`State::set_terrain`](crate::state::State::set_terrain).
+pub struct TerrainBuilder {
+    heightmap: Heightmap,
+    cell_size: f32,
+    height_scale: f32,
+    origin: Option<Point3<f32>>,
+    chunk_size: u32,
+    lod_levels: u32,
+    lod_distance: f32,
+    layers: Vec<(Texture, f32)>,
+    splat_map: Option<DynamicImage>,
+    decal_layer: u32,
+}
+
+impl TerrainBuilder {
+    pub const MAX_LAYERS: usize = 4;
+
+    /// One unit between samples and one unit of height, centered on the
+    /// origin, in chunks of 32 x 32 cells with four levels of detail.
+    pub fn new(heightmap: Heightmap) -> Self {
+        Self {
+            heightmap,
+            cell_size: 1.0,
+            height_scale: 1.0,
+            origin: None,
+            chunk_size: 32,
+            lod_levels: 4,
+            lod_distance: 64.0,
+            layers: Vec::new(),
+            splat_map: None,
+            decal_layer: 0,
+        }
+    }
+
+    /// Distance between neighbouring samples along X and Z.
+    pub fn with_cell_size(mut self, cell_size: f32) -> Self {
+        self.cell_size = cell_size;
+        self
+    }
+
+    /// Height of a sample at full brightness.
+    pub fn with_height_scale(mut self, height_scale: f32) -> Self {
+        self.height_scale = height_scale;
+        self
+    }
+
+    /// Where the first sample of the heightmap is placed at height zero,
+    /// instead of centering the terrain on the origin.
+    pub fn with_origin(mut self, origin: Point3<f32>) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// Number of cells along each side of a chunk, rounded up to a power of
+    /// two. Heightmaps of `n * chunk_size + 1` samples fit exactly.
+    pub fn with_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = chunk_size.max(2).next_power_of_two();
+        self
+    }
+
+    /// Each level halves the vertices along a chunk edge. Limited to what
+    /// the chunk size allows.
+    pub fn with_lod_levels(mut self, lod_levels: u32) -> Self {
+        self.lod_levels = lod_levels.max(1);
+        self
+    }
+
+    /// Chunks closer than this are drawn in full detail. Each level after
+    /// that starts at twice the distance of the one before.
+    pub fn with_lod_distance(mut self, lod_distance: f32) -> Self {
+        self.lod_distance = lod_distance;
+        self
+    }
+
+    /// Adds a texture layer repeated `scale` times across the terrain.
+    pub fn with_layer(mut self, texture: Texture, scale: f32) -> Self {
+        self.layers.push((texture, scale));
+        self
+    }
+
+    /// Weights of the layers in the red, green, blue and alpha channels,
+    /// stretched over the whole terrain. Without an alpha channel the fourth
+    /// layer gets no weight. Without a splat map only the first layer shows.
+    pub fn with_splat_map(mut self, splat_map: DynamicImage) -> Self {
+        self.splat_map = Some(splat_map);
+        self
+    }
+
+    /// Which of the 32 layers of [decals](crate::decals) the terrain is
+    /// on, as [`Material::decal_layer`](crate::model::Material::decal_layer)
+    /// for meshes. Defaults to 0.
+    pub fn with_decal_layer(mut self, decal_layer: u32) -> Self {
+        self.decal_layer = decal_layer.min(31);
+        self
+    }
+
+    pub fn build(self, device: &Device, queue: &Queue, scene: &ReflectedLayout) -> Result<Terrain> {
+        if self.layers.len() > Self::MAX_LAYERS {
+            bail!(
+                "{} terrain layers given, at most {} are supported",
+                self.layers.len(),
+                Self::MAX_LAYERS
+            );
+        }
+
+        let reflection = ShaderReflection::from_wgsl(TERRAIN_SHADER)?;
+        reflection.validate_vertex_layouts("vs_main", &[TerrainVertex::desc()])?;
+        let mut reflected = reflection.create_layout(device, "Terrain Pipeline Layout")?;
+        reflection
+            .validate_bind_group_layouts(&[
+                &reflected.entries[0],
+                &scene.entries[1],
+                &scene.entries[2],
+                &reflected.entries[3],
+            ])
+            .context("terrain.wgsl")?;
+        let params_layout = reflected.bind_group_layouts.remove(3);
+        let material_layout = reflected.bind_group_layouts.remove(0);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Terrain Pipeline Layout"),
+            bind_group_layouts: &[
+                &material_layout,
+                &scene.bind_group_layouts[1],
+                &scene.bind_group_layouts[2],
+                &params_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let splat = match &self.splat_map {
+            Some(image) => {
+                let mut weights = image.to_rgba8();
+                if !image.color().has_alpha() {
+                    weights.pixels_mut().for_each(|pixel| pixel.0[3] = 0);
+                }
+                weights
+            }
+            None => RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 0])),
+        };
+        // Weights are not colors, so the splat map is stored linearly.
+        let splat = Texture::from_image(
+            device,
+            queue,
+            &DynamicImage::ImageRgba8(splat),
+            Some("terrain_splat_map"),
+            true,
+        )?;
+
+        let mut layer_scales = [1.0; 4];
+        let mut layers = Vec::with_capacity(Self::MAX_LAYERS);
+        for (index, (texture, scale)) in self.layers.into_iter().enumerate() {
+            layer_scales[index] = scale;
+            layers.push(texture);
+        }
+        while layers.len() < Self::MAX_LAYERS {
+            // Unused layers have no weight, but still need a texture bound.
+            layers.push(Texture::from_image(
+                device,
+                queue,
+                &DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([255; 4]))),
+                Some("terrain_empty_layer"),
+                false,
+            )?);
+        }
+        // The layers' own samplers may clamp, but layers tile.
+        let layer_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("terrain_layer_sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let material = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("terrain_material_bind_group"),
+            layout: &material_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&splat.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&splat.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&layers[0].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&layers[1].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&layers[2].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&layers[3].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&layer_sampler),
+                },
+            ],
+        });
+        let params = UniformBuffer::new(
+            device,
+            &params_layout,
+            TerrainUniform {
+                layer_scales,
+                decal_layer: self.decal_layer,
+            },
+            "terrain_params",
+        );
+
+        let heightmap = self.heightmap;
+        let (width, depth) = (heightmap.width(), heightmap.depth());
+        let origin = self.origin.unwrap_or_else(|| {
+            Point3::new(
+                -((width - 1) as f32) * self.cell_size / 2.0,
+                0.0,
+                -((depth - 1) as f32) * self.cell_size / 2.0,
+            )
+        });
+        let chunk_size = self.chunk_size;
+        let chunks = (
+            (width - 1).div_ceil(chunk_size),
+            (depth - 1).div_ceil(chunk_size),
+        );
+        // A level at the chunk size leaves a single quad per chunk.
+        let lod_levels = self.lod_levels.min(chunk_size.trailing_zeros() + 1);
+
+        let (vertices, heights) = chunk_vertices(
+            &heightmap,
+            origin,
+            self.cell_size,
+            self.height_scale,
+            chunk_size,
+            chunks,
+        );
+        let mut indices = Vec::new();
+        let mut index_ranges = Vec::new();
+        for level in 0..lod_levels {
+            for mask in 0..lod::MASKS {
+                let start = indices.len() as u32;
+                indices.extend(lod::chunk_indices(chunk_size, 1 << level, mask));
+                index_ranges.push(start..indices.len() as u32);
+            }
+        }
+
+        Ok(Terrain {
+            heightmap,
+            origin,
+            cell_size: self.cell_size,
+            height_scale: self.height_scale,
+            chunk_size,
+            chunks,
+            heights,
+            lod_levels,
+            lod_distance: self.lod_distance,
+            vertices: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Terrain Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: BufferUsages::VERTEX,
+            }),
+            indices: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Terrain Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: BufferUsages::INDEX,
+            }),
+            index_ranges,
+            material,
+            params,
+            _textures: (splat, layers),
+            layout,
+            pipelines: HashMap::new(),
+            gbuffer_pipelines: HashMap::new(),
+        })
+    }
+}
+
+/// A heightmap drawn as a grid of chunks, blending up to four texture
+/// layers by a splat map.
+///
+/// Every view picks the level of each chunk from the distance to its
+/// camera. Neighbouring chunks differ by at most one level, and the finer
+/// one stitches its edge to the coarser one so no cracks show.
+pub struct Terrain {
+    heightmap: Heightmap,
+    origin: Point3<f32>,
+    cell_size: f32,
+    height_scale: f32,
+    chunk_size: u32,
+    chunks: (u32, u32),
+    /// Lowest and highest point of each chunk.
+    heights: Vec<(f32, f32)>,
+    lod_levels: u32,
+    /// Chunks closer than this are drawn in full detail. Each level after
+    /// that starts at twice the distance of the one before.
+    pub lod_distance: f32,
+    /// The vertices of every chunk at full detail, one chunk after another.
+    vertices: Buffer,
+    /// Index ranges in `indices` per level and edge mask, shared by all
+    /// chunks.
+    indices: Buffer,
+    index_ranges: Vec<Range<u32>>,
+    material: BindGroup,
+    params: UniformBuffer<TerrainUniform>,
+    _textures: (Texture, Vec<Texture>),
+    layout: PipelineLayout,
+    pipelines: HashMap<(TextureFormat, CompareFunction), RenderPipeline>,
+    /// Into the G-buffer, per depth comparison.
+    gbuffer_pipelines: HashMap<CompareFunction, RenderPipeline>,
+}
+
+impl Terrain {
+    /// Height of the surface at world `x`, `z`, as drawn in full detail, or
+    /// `None` outside the terrain.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (sx, sz) = self.sample_coordinates(x, z)?;
+        let (height, _) = self.heightmap.interpolate(sx, sz);
+        Some(self.origin.y + height * self.height_scale)
+    }
+
+    /// Normal of the surface at world `x`, `z`, or `None` outside the
+    /// terrain.
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<Vector3<f32>> {
+        let (sx, sz) = self.sample_coordinates(x, z)?;
+        let (_, [dx, dz]) = self.heightmap.interpolate(sx, sz);
+        let scale = self.height_scale / self.cell_size;
+        Some(Vector3::new(-dx * scale, 1.0, -dz * scale).normalize())
+    }
+
+    pub fn heightmap(&self) -> &Heightmap {
+        &self.heightmap
+    }
+
+    /// The corners of the terrain with the lowest and highest X and Z, at
+    /// height zero.
+    pub fn extent(&self) -> (Point3<f32>, Point3<f32>) {
+        let size = Vector3::new(
+            (self.heightmap.width() - 1) as f32 * self.cell_size,
+            0.0,
+            (self.heightmap.depth() - 1) as f32 * self.cell_size,
+        );
+        (self.origin, self.origin + size)
+    }
+
+    fn sample_coordinates(&self, x: f32, z: f32) -> Option<(f32, f32)> {
+        let sx = (x - self.origin.x) / self.cell_size;
+        let sz = (z - self.origin.z) / self.cell_size;
+        let inside = |s: f32, samples: u32| (0.0..=(samples - 1) as f32).contains(&s);
+        (inside(sx, self.heightmap.width()) && inside(sz, self.heightmap.depth()))
+            .then_some((sx, sz))
+    }
+
+    /// The level a chunk would like to be drawn at, seen from `eye`.
+    fn preferred_level(&self, chunk: usize, eye: Point3<f32>) -> u32 {
+        let chunk_x = chunk as u32 % self.chunks.0;
+        let chunk_z = chunk as u32 / self.chunks.0;
+        let extent = self.chunk_size as f32 * self.cell_size;
+        let min = Point3::new(
+            self.origin.x + chunk_x as f32 * extent,
+            self.heights[chunk].0,
+            self.origin.z + chunk_z as f32 * extent,
+        );
+        let max = Point3::new(min.x + extent, self.heights[chunk].1, min.z + extent);
+
+        // Distance to the chunk's bounding box.
+        let outside = |value: f32, min: f32, max: f32| (min - value).max(value - max).max(0.0);
+        let distance = Vector3::new(
+            outside(eye.x, min.x, max.x),
+            outside(eye.y, min.y, max.y),
+            outside(eye.z, min.z, max.z),
+        )
+        .magnitude();
+
+        let level = if distance < self.lod_distance {
+            0
+        } else {
+            (distance / self.lod_distance).log2().floor() as u32 + 1
+        };
+        level.min(self.lod_levels - 1)
+    }
+
+    /// Makes sure pipelines exist for every target in `keys`.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        keys: impl IntoIterator<Item = (TextureFormat, CompareFunction)>,
+    ) {
+        for (color_format, depth_compare) in keys {
+            self.pipelines
+                .entry((color_format, depth_compare))
+                .or_insert_with(|| {
+                    let target = wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    };
+                    create_terrain_pipeline(
+                        device,
+                        &self.layout,
+                        "fs_main",
+                        &[Some(target)],
+                        depth_compare,
+                    )
+                });
+            self.gbuffer_pipelines
+                .entry(depth_compare)
+                .or_insert_with(|| {
+                    let target = |format| {
+                        Some(wgpu::ColorTargetState {
+                            format,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })
+                    };
+                    create_terrain_pipeline(
+                        device,
+                        &self.layout,
+                        "fs_gbuffer",
+                        &[
+                            target(ShadingPath::ALBEDO_FORMAT),
+                            target(ShadingPath::NORMAL_FORMAT),
+                            target(ShadingPath::MATERIAL_FORMAT),
+                        ],
+                        depth_compare,
+                    )
+                });
+        }
+    }
+
+    /// Draws every chunk at the level it gets from `eye`.
+    pub(crate) fn draw<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        key: (TextureFormat, CompareFunction),
+        eye: Point3<f32>,
+        camera_bind_group: &'a BindGroup,
+        light_bind_group: &'a BindGroup,
+    ) {
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            self.draw_with(pass, pipeline, eye, camera_bind_group, light_bind_group);
+        }
+    }
+
+    /// Draws every chunk into the G-buffer pass of a view with
+    /// `depth_compare`, as [`draw`](Self::draw) does into its color.
+    pub(crate) fn draw_gbuffer<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        depth_compare: CompareFunction,
+        eye: Point3<f32>,
+        camera_bind_group: &'a BindGroup,
+        light_bind_group: &'a BindGroup,
+    ) {
+        if let Some(pipeline) = self.gbuffer_pipelines.get(&depth_compare) {
+            self.draw_with(pass, pipeline, eye, camera_bind_group, light_bind_group);
+        }
+    }
+
+    fn draw_with<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        pipeline: &'a RenderPipeline,
+        eye: Point3<f32>,
+        camera_bind_group: &'a BindGroup,
+        light_bind_group: &'a BindGroup,
+    ) {
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.material, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.set_bind_group(2, light_bind_group, &[]);
+        pass.set_bind_group(3, self.params.bind_group(), &[]);
+        self.draw_chunks(pass, eye);
+    }
+
+    /// Draws every chunk at the level it gets from `eye`, with whatever
+    /// pipeline and bind groups `pass` has set, such as a shadow caster's.
+    pub(crate) fn draw_chunks<'a>(&'a self, pass: &mut RenderPass<'a>, eye: Point3<f32>) {
+        let levels = lod::select_levels(self.chunks, |chunk| self.preferred_level(chunk, eye));
+        pass.set_vertex_buffer(0, self.vertices.slice(..));
+        pass.set_index_buffer(self.indices.slice(..), wgpu::IndexFormat::Uint32);
+
+        let chunk_vertices = (self.chunk_size + 1) * (self.chunk_size + 1);
+        for chunk_z in 0..self.chunks.1 {
+            for chunk_x in 0..self.chunks.0 {
+                let chunk = chunk_z * self.chunks.0 + chunk_x;
+                let mask = lod::coarser_edges(&levels, self.chunks, chunk_x, chunk_z);
+                let range =
+                    &self.index_ranges[(levels[chunk as usize] * lod::MASKS + mask) as usize];
+                pass.draw_indexed(range.clone(), (chunk * chunk_vertices) as i32, 0..1);
+            }
+        }
+    }
+}
+
+/// The vertices of every chunk at full detail, one chunk after another, and
+/// the lowest and highest point of each.
+fn chunk_vertices(
+    heightmap: &Heightmap,
+    origin: Point3<f32>,
+    cell_size: f32,
+    height_scale: f32,
+    size: u32,
+    (chunks_x, chunks_z): (u32, u32),
+) -> (Vec<TerrainVertex>, Vec<(f32, f32)>) {
+    let (width, depth) = (heightmap.width(), heightmap.depth());
+    let sample = |x: u32, z: u32| heightmap.get(x, z) * height_scale;
+
+    let mut vertices = Vec::with_capacity(((size + 1) * (size + 1) * chunks_x * chunks_z) as usize);
+    let mut heights = Vec::with_capacity((chunks_x * chunks_z) as usize);
+    for chunk_z in 0..chunks_z {
+        for chunk_x in 0..chunks_x {
+            let mut range = (f32::MAX, f32::MIN);
+            for z in 0..=size {
+                for x in 0..=size {
+                    // Chunks hanging over the edge repeat the last sample.
+                    let sx = (chunk_x * size + x).min(width - 1);
+                    let sz = (chunk_z * size + z).min(depth - 1);
+                    let height = sample(sx, sz);
+                    range = (range.0.min(height), range.1.max(height));
+
+                    // Central differences, one-sided at the edges.
+                    let (left, right) = (sx.saturating_sub(1), (sx + 1).min(width - 1));
+                    let (near, far) = (sz.saturating_sub(1), (sz + 1).min(depth - 1));
+                    let dx = (sample(right, sz) - sample(left, sz))
+                        / ((right - left) as f32 * cell_size);
+                    let dz =
+                        (sample(sx, far) - sample(sx, near)) / ((far - near) as f32 * cell_size);
+                    vertices.push(TerrainVertex {
+                        position: [
+                            origin.x + sx as f32 * cell_size,
+                            origin.y + height,
+                            origin.z + sz as f32 * cell_size,
+                        ],
+                        normal: Vector3::new(-dx, 1.0, -dz).normalize().into(),
+                        tex_coords: [
+                            sx as f32 / (width - 1) as f32,
+                            sz as f32 / (depth - 1) as f32,
+                        ],
+                    });
+                }
+            }
+            heights.push((origin.y + range.0, origin.y + range.1));
+        }
+    }
+    (vertices, heights)
+}
+
+fn create_terrain_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    fragment_entry: &str,
+    targets: &[Option<wgpu::ColorTargetState>],
+    depth_compare: CompareFunction,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Terrain Shader"),
+        source: wgpu::ShaderSource::Wgsl(TERRAIN_SHADER.into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Terrain Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[TerrainVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: fragment_entry,
+            targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn height_query_follows_the_mesh_triangles() {
+        #[rustfmt::skip]
+        let heightmap = Heightmap::new(3, 2, vec![
+            0.0, 1.0, 0.0,
+            1.0, 0.0, 0.5,
+        ])
+        .unwrap();
+        assert_eq!(heightmap.interpolate(1.0, 0.0).0, 1.0);
+        assert_eq!(heightmap.interpolate(2.0, 1.0).0, 0.5);
+        // Both triangles of the first cell contain its centre; the diagonal
+        // runs between the two corners at height one.
+        assert_eq!(heightmap.interpolate(0.5, 0.5).0, 1.0);
+        assert_eq!(heightmap.interpolate(0.25, 0.25), (0.5, [1.0, 1.0]));
+        assert_eq!(heightmap.interpolate(0.75, 0.75), (0.5, [-1.0, -1.0]));
+        assert!(Heightmap::new(3, 2, vec![0.0; 5]).is_err());
+    }
+
+    #[test]
+    fn terrain_shader_shares_scene_bind_groups() {
+        let reflection = ShaderReflection::from_wgsl(TERRAIN_SHADER).unwrap();
+        reflection
+            .validate_vertex_layouts("vs_main", &[TerrainVertex::desc()])
+            .unwrap();
+
+        let scene = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER)
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+        let own = reflection.bind_group_layout_entries().unwrap();
+        reflection
+            .validate_bind_group_layouts(&[&own[0], &scene[1], &scene[2], &own[3]])
+            .unwrap();
+    }
+}
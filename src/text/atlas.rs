@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use ab_glyph::{Font, FontVec, GlyphId, PxScale};
+use wgpu::{Device, Extent3d, FilterMode, Queue, TextureFormat, TextureUsages};
+
+use super::layout::FontId;
+use crate::{atlas::ShelfPacker, texture::Texture};
+
+/// Where a rasterized glyph lives in the atlas and how it sits relative to
+/// its pen position on the baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasGlyph {
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+}
+
+/// Returned when a glyph does not fit next to those already in the atlas.
+/// [`GlyphAtlas::clear`] makes room again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasFull;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: FontId,
+    id: GlyphId,
+    /// As [`TextStyle::pixel_size`](super::layout::TextStyle::pixel_size).
+    size: u32,
+}
+
+/// A single-channel texture caching rasterized glyph coverage.
+pub struct GlyphAtlas {
+    texture: Texture,
+    size: u32,
+    packer: ShelfPacker,
+    glyphs: HashMap<GlyphKey, Option<AtlasGlyph>>,
+}
+
+impl GlyphAtlas {
+    /// Empty pixels kept around each glyph so linear filtering does not
+    /// bleed neighbours in.
+    const PADDING: u32 = 1;
+
+    pub fn new(device: &Device, size: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("glyph_atlas"),
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture: Texture {
+                texture,
+                view,
+                sampler,
+            },
+            size,
+            packer: ShelfPacker::new(size, size),
+            glyphs: HashMap::new(),
+        }
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Forgets every glyph. Their pixels, padding included, are overwritten
+    /// as new glyphs are rasterized.
+    pub fn clear(&mut self) {
+        self.packer.clear();
+        self.glyphs.clear();
+    }
+
+    /// Looks up a glyph at `size` whole pixels, rasterizing it on first
+    /// use. Glyphs without an outline, such as spaces, have no entry.
+    pub fn glyph(
+        &mut self,
+        queue: &Queue,
+        font_id: FontId,
+        font: &FontVec,
+        id: GlyphId,
+        size: u32,
+    ) -> Result<Option<AtlasGlyph>, AtlasFull> {
+        let key = GlyphKey {
+            font: font_id,
+            id,
+            size,
+        };
+        if let Some(glyph) = self.glyphs.get(&key) {
+            return Ok(*glyph);
+        }
+
+        let glyph = self.rasterize(queue, font, key)?;
+        self.glyphs.insert(key, glyph);
+        Ok(glyph)
+    }
+
+    fn rasterize(
+        &mut self,
+        queue: &Queue,
+        font: &FontVec,
+        key: GlyphKey,
+    ) -> Result<Option<AtlasGlyph>, AtlasFull> {
+        let glyph = key.id.with_scale(PxScale::from(key.size as f32));
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            return Ok(None);
+        };
+        let bounds = outlined.px_bounds();
+        let width = bounds.width().ceil() as u32;
+        let height = bounds.height().ceil() as u32;
+        if width == 0 || height == 0 {
+            return Ok(None);
+        }
+
+        let (padded_width, padded_height) = (width + 2 * Self::PADDING, height + 2 * Self::PADDING);
+        let (x, y) = self
+            .packer
+            .allocate(padded_width, padded_height)
+            .ok_or(AtlasFull)?;
+
+        // The padding is written too, as a cleared atlas still holds the
+        // glyphs that were there before.
+        let mut pixels = vec![0u8; (padded_width * padded_height) as usize];
+        outlined.draw(|px, py, coverage| {
+            if px < width && py < height {
+                let index = (py + Self::PADDING) * padded_width + px + Self::PADDING;
+                pixels[index as usize] = (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+            }
+        });
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(padded_width),
+                rows_per_image: std::num::NonZeroU32::new(padded_height),
+            },
+            Extent3d {
+                width: padded_width,
+                height: padded_height,
+                depth_or_array_layers: 1,
+            },
+        );
+        let (x, y) = (x + Self::PADDING, y + Self::PADDING);
+
+        let size = self.size as f32;
+        Ok(Some(AtlasGlyph {
+            uv_min: [x as f32 / size, y as f32 / size],
+            uv_max: [(x + width) as f32 / size, (y + height) as f32 / size],
+            offset: [bounds.min.x, bounds.min.y],
+            size: [width as f32, height as f32],
+        }))
+    }
+}
@@ -0,0 +1,300 @@
+use ab_glyph::{Font, FontVec, GlyphId, ScaleFont};
+
+/// Horizontal placement of each line within the text block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Index of a font added to the [`TextRenderer`](super::renderer::TextRenderer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontId(pub(crate) usize);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextStyle {
+    pub font: FontId,
+    /// Font size in pixels.
+    pub size: f32,
+    pub color: [f32; 4],
+    /// Lines longer than this many pixels wrap, preferably at whitespace.
+    pub max_width: Option<f32>,
+    pub align: TextAlign,
+    /// Multiplier on the font's line height.
+    pub line_spacing: f32,
+}
+
+impl TextStyle {
+    pub fn new(font: FontId, size: f32) -> Self {
+        Self {
+            font,
+            size,
+            color: [1.0; 4],
+            max_width: None,
+            align: TextAlign::Left,
+            line_spacing: 1.0,
+        }
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_max_width(mut self, max_width: f32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    pub fn with_align(mut self, align: TextAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn with_line_spacing(mut self, line_spacing: f32) -> Self {
+        self.line_spacing = line_spacing;
+        self
+    }
+
+    /// `size` rounded to whole pixels, which text is both laid out and
+    /// rasterized at, so sizes that look the same share atlas entries.
+    pub fn pixel_size(&self) -> u32 {
+        self.size.round().max(1.0) as u32
+    }
+}
+
+/// A glyph placed relative to the top-left corner of the text block, with
+/// `x` at its pen position and `y` on its baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionedGlyph {
+    pub id: GlyphId,
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextLayout {
+    pub glyphs: Vec<PositionedGlyph>,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// The metrics layout needs from a font at one size.
+pub trait GlyphMetrics {
+    fn glyph_id(&self, c: char) -> GlyphId;
+    fn advance(&self, id: GlyphId) -> f32;
+    fn kern(&self, first: GlyphId, second: GlyphId) -> f32;
+    fn ascent(&self) -> f32;
+    fn line_height(&self) -> f32;
+}
+
+impl<F: Font> GlyphMetrics for ab_glyph::PxScaleFont<&F> {
+    fn glyph_id(&self, c: char) -> GlyphId {
+        ScaleFont::glyph_id(self, c)
+    }
+
+    fn advance(&self, id: GlyphId) -> f32 {
+        self.h_advance(id)
+    }
+
+    fn kern(&self, first: GlyphId, second: GlyphId) -> f32 {
+        ScaleFont::kern(self, first, second)
+    }
+
+    fn ascent(&self) -> f32 {
+        ScaleFont::ascent(self)
+    }
+
+    fn line_height(&self) -> f32 {
+        self.height() + self.line_gap()
+    }
+}
+
+/// Lays out `text` in `font` following `style`.
+pub fn layout(font: &FontVec, text: &str, style: &TextStyle) -> TextLayout {
+    layout_with(&font.as_scaled(style.pixel_size() as f32), text, style)
+}
+
+struct Line {
+    glyphs: Vec<(GlyphId, f32, char)>,
+    /// Width up to the end of the last visible glyph, ignoring trailing
+    /// whitespace.
+    width: f32,
+}
+
+impl Line {
+    fn finish(glyphs: Vec<(GlyphId, f32, char)>, metrics: &impl GlyphMetrics) -> Self {
+        let width = glyphs
+            .iter()
+            .rev()
+            .find(|(_, _, c)| !c.is_whitespace())
+            .map_or(0.0, |&(id, x, _)| x + metrics.advance(id));
+        Self { glyphs, width }
+    }
+}
+
+pub fn layout_with(metrics: &impl GlyphMetrics, text: &str, style: &TextStyle) -> TextLayout {
+    let mut lines = Vec::new();
+
+    for paragraph in text.split('\n') {
+        let mut line: Vec<(GlyphId, f32, char)> = Vec::new();
+        let mut x = 0.0;
+        let mut previous = None;
+        // Where the line may be broken: the first glyph after whitespace.
+        let mut break_at = None;
+
+        for c in paragraph.chars().filter(|c| !c.is_control()) {
+            let id = metrics.glyph_id(c);
+            if let Some(previous) = previous {
+                x += metrics.kern(previous, id);
+            }
+            let advance = metrics.advance(id);
+
+            let overflows = style
+                .max_width
+                .is_some_and(|max_width| x + advance > max_width);
+            if overflows && !c.is_whitespace() && !line.is_empty() {
+                let split = break_at.filter(|&at| at > 0).unwrap_or(line.len());
+                let rest = line.split_off(split);
+                lines.push(Line::finish(line, metrics));
+
+                let shift = rest.first().map_or(x, |&(_, start, _)| start);
+                line = rest
+                    .into_iter()
+                    .map(|(id, start, c)| (id, start - shift, c))
+                    .collect();
+                x -= shift;
+                break_at = None;
+            }
+
+            line.push((id, x, c));
+            x += advance;
+            previous = Some(id);
+            if c.is_whitespace() {
+                break_at = Some(line.len());
+            }
+        }
+
+        lines.push(Line::finish(line, metrics));
+    }
+
+    let block_width = lines.iter().map(|line| line.width).fold(0.0, f32::max);
+    let align_width = style.max_width.unwrap_or(block_width);
+    let line_height = metrics.line_height() * style.line_spacing;
+
+    let mut glyphs = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let offset = match style.align {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => (align_width - line.width) / 2.0,
+            TextAlign::Right => align_width - line.width,
+        };
+        let y = metrics.ascent() + index as f32 * line_height;
+
+        glyphs.extend(
+            line.glyphs
+                .iter()
+                .filter(|(_, _, c)| !c.is_whitespace())
+                .map(|&(id, x, _)| PositionedGlyph {
+                    id,
+                    x: x + offset,
+                    y,
+                }),
+        );
+    }
+
+    TextLayout {
+        glyphs,
+        width: if style.max_width.is_some() {
+            align_width
+        } else {
+            block_width
+        },
+        height: lines.len() as f32 * line_height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every glyph is 10 pixels wide, "AV" kerns by -2.
+    struct Monospace;
+
+    impl GlyphMetrics for Monospace {
+        fn glyph_id(&self, c: char) -> GlyphId {
+            GlyphId(c as u16)
+        }
+
+        fn advance(&self, _: GlyphId) -> f32 {
+            10.0
+        }
+
+        fn kern(&self, first: GlyphId, second: GlyphId) -> f32 {
+            if (first, second) == (GlyphId(b'A' as u16), GlyphId(b'V' as u16)) {
+                -2.0
+            } else {
+                0.0
+            }
+        }
+
+        fn ascent(&self) -> f32 {
+            8.0
+        }
+
+        fn line_height(&self) -> f32 {
+            12.0
+        }
+    }
+
+    fn style() -> TextStyle {
+        TextStyle::new(FontId(0), 12.0)
+    }
+
+    #[test]
+    fn kerning_and_newlines() {
+        let layout = layout_with(&Monospace, "AV\nA", &style());
+        let positions: Vec<_> = layout.glyphs.iter().map(|g| (g.x, g.y)).collect();
+        assert_eq!(positions, [(0.0, 8.0), (8.0, 8.0), (0.0, 20.0)]);
+        assert_eq!(layout.width, 18.0);
+        assert_eq!(layout.height, 24.0);
+    }
+
+    #[test]
+    fn wraps_at_whitespace_and_aligns() {
+        let style = style().with_max_width(55.0).with_align(TextAlign::Right);
+        let layout = layout_with(&Monospace, "abc defg", &style);
+
+        // "abc" is right-aligned on the first line, "defg" on the second.
+        let lines: Vec<_> = layout.glyphs.iter().map(|g| (g.x, g.y)).collect();
+        assert_eq!(
+            lines,
+            [
+                (25.0, 8.0),
+                (35.0, 8.0),
+                (45.0, 8.0),
+                (15.0, 20.0),
+                (25.0, 20.0),
+                (35.0, 20.0),
+                (45.0, 20.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn sizes_round_to_whole_pixels() {
+        let pixel_size = |size| TextStyle::new(FontId(0), size).pixel_size();
+        assert_eq!(pixel_size(12.4), 12);
+        assert_eq!(pixel_size(12.5), 13);
+        assert_eq!(pixel_size(0.2), 1);
+    }
+
+    #[test]
+    fn breaks_long_words() {
+        let layout = layout_with(&Monospace, "abcdefg", &style().with_max_width(30.0));
+        let rows: Vec<_> = layout.glyphs.iter().map(|g| g.y).collect();
+        assert_eq!(rows, [8.0, 8.0, 8.0, 20.0, 20.0, 20.0, 32.0]);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod atlas;
+pub mod layout;
+pub mod renderer;
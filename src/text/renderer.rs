@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+
+use ab_glyph::FontVec;
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use cgmath::Point3;
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, BufferDescriptor, BufferUsages,
+    CompareFunction, Device, PipelineLayout, Queue, RenderPass, RenderPipeline, TextureFormat,
+};
+
+use super::{
+    atlas::GlyphAtlas,
+    layout::{self, FontId, TextStyle},
+};
+use crate::{
+    reflection::ShaderReflection,
+    texture::Texture,
+    uniform::{ShaderType, UniformBuffer},
+    vertex::Vertex,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable, Vertex)]
+#[vertex(instance)]
+pub struct GlyphInstance {
+    /// Top-left pixel of the text block for screen text, world position of
+    /// the block's top-left corner for world text.
+    pub anchor: [f32; 3],
+    /// Pixel offset of the glyph's top-left corner from the anchor.
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    pub color: [f32; 4],
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct TextViewport {
+    size: [f32; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Anchor {
+    Screen([f32; 2]),
+    World(Point3<f32>),
+}
+
+struct QueuedText {
+    text: String,
+    anchor: Anchor,
+    style: TextStyle,
+}
+
+struct TextPipelines {
+    screen: RenderPipeline,
+    world: RenderPipeline,
+}
+
+/// Draws text from TTF/OTF fonts as instanced quads sampling a glyph atlas.
+///
+/// Text is queued every frame, like [`DebugDraw`](crate::debug_draw::DebugDraw)
+/// shapes, and drawn over the finished views. Screen text is positioned in
+/// surface pixels; world text is anchored to a point seen through the main
+/// view, keeps its pixel size and is hidden by geometry in front of it.
+pub struct TextRenderer {
+    fonts: Vec<FontVec>,
+    queued: Vec<QueuedText>,
+    atlas: GlyphAtlas,
+    atlas_bind_group: BindGroup,
+    screen_viewport: UniformBuffer<TextViewport>,
+    world_viewport: UniformBuffer<TextViewport>,
+    /// Instance ranges in `buffer` uploaded by the last
+    /// [`prepare`](Self::prepare).
+    screen: std::ops::Range<u32>,
+    world: std::ops::Range<u32>,
+    buffer: Buffer,
+    capacity: usize,
+    layout: PipelineLayout,
+    pipelines: HashMap<(TextureFormat, CompareFunction), TextPipelines>,
+}
+
+impl TextRenderer {
+    const ATLAS_SIZE: u32 = 1024;
+
+    pub fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        camera_entries: &[BindGroupLayoutEntry],
+    ) -> Result<Self> {
+        let reflection = ShaderReflection::from_wgsl(include_str!("../../shaders/text.wgsl"))?;
+        for entry_point in ["vs_screen", "vs_world"] {
+            reflection.validate_vertex_layouts(entry_point, &[GlyphInstance::desc()])?;
+        }
+
+        let mut reflected = reflection.create_layout(device, "Text Pipeline Layout")?;
+        reflection
+            .validate_bind_group_layouts(&[
+                camera_entries,
+                &reflected.entries[1],
+                &reflected.entries[2],
+            ])
+            .context("text.wgsl")?;
+        let atlas_layout = reflected.bind_group_layouts.remove(2);
+        let viewport_layout = reflected.bind_group_layouts.remove(1);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &viewport_layout, &atlas_layout],
+            push_constant_ranges: &[],
+        });
+
+        let atlas = GlyphAtlas::new(device, Self::ATLAS_SIZE);
+        let atlas_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("text_atlas_bind_group"),
+            layout: &atlas_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.texture().view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&atlas.texture().sampler),
+                },
+            ],
+        });
+
+        let viewport = TextViewport { size: [1.0, 1.0] };
+        let capacity = 256;
+        Ok(Self {
+            fonts: Vec::new(),
+            queued: Vec::new(),
+            atlas,
+            atlas_bind_group,
+            screen_viewport: UniformBuffer::new(
+                device,
+                &viewport_layout,
+                viewport,
+                "text_screen_viewport",
+            ),
+            world_viewport: UniformBuffer::new(
+                device,
+                &viewport_layout,
+                viewport,
+                "text_world_viewport",
+            ),
+            screen: 0..0,
+            world: 0..0,
+            buffer: create_instance_buffer(device, capacity),
+            capacity,
+            layout,
+            pipelines: HashMap::new(),
+        })
+    }
+
+    /// Loads a TrueType or OpenType font from its file contents.
+    pub fn add_font(&mut self, data: Vec<u8>) -> Result<FontId> {
+        let font = FontVec::try_from_vec(data).map_err(|e| anyhow!("invalid font: {}", e))?;
+        self.fonts.push(font);
+        Ok(FontId(self.fonts.len() - 1))
+    }
+
+    pub fn font(&self, id: FontId) -> &FontVec {
+        &self.fonts[id.0]
+    }
+
+    /// Lays out `text` without drawing it, e.g. to center it on something.
+    pub fn measure(&self, text: &str, style: &TextStyle) -> layout::TextLayout {
+        layout::layout(self.font(style.font), text, style)
+    }
+
+    /// Draws `text` this frame with its top-left corner at surface pixel
+    /// `position`.
+    pub fn queue_screen(&mut self, text: impl Into<String>, position: [f32; 2], style: TextStyle) {
+        self.queued.push(QueuedText {
+            text: text.into(),
+            anchor: Anchor::Screen(position),
+            style,
+        });
+    }
+
+    /// Draws `text` this frame with its top-left corner at `position` in the
+    /// world, as seen by the main view.
+    pub fn queue_world(
+        &mut self,
+        text: impl Into<String>,
+        position: Point3<f32>,
+        style: TextStyle,
+    ) {
+        self.queued.push(QueuedText {
+            text: text.into(),
+            anchor: Anchor::World(position),
+            style,
+        });
+    }
+
+    /// Rasterizes missing glyphs, uploads the queued text and forgets it.
+    /// `surface_size` positions screen text, `world_viewport_size` is the
+    /// pixel size of the viewport world text is seen through.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        key: (TextureFormat, CompareFunction),
+        surface_size: (u32, u32),
+        world_viewport_size: (u32, u32),
+    ) {
+        if !self.pipelines.contains_key(&key) {
+            let pipelines = TextPipelines {
+                screen: create_text_pipeline(device, &self.layout, "vs_screen", key.0, None),
+                world: create_text_pipeline(device, &self.layout, "vs_world", key.0, Some(key.1)),
+            };
+            self.pipelines.insert(key, pipelines);
+        }
+
+        self.screen_viewport.set(TextViewport {
+            size: [surface_size.0 as f32, surface_size.1 as f32],
+        });
+        self.screen_viewport.update(queue);
+        self.world_viewport.set(TextViewport {
+            size: [world_viewport_size.0 as f32, world_viewport_size.1 as f32],
+        });
+        self.world_viewport.update(queue);
+
+        let queued = std::mem::take(&mut self.queued);
+        let instances = match self.build_instances(queue, &queued) {
+            Some(instances) => instances,
+            None => {
+                // Start over with an empty atlas holding just this frame's
+                // glyphs. What still does not fit is left out.
+                self.atlas.clear();
+                self.build_instances(queue, &queued).unwrap_or_default()
+            }
+        };
+
+        let world_start = instances
+            .iter()
+            .position(|(world, _)| *world)
+            .unwrap_or(instances.len()) as u32;
+        self.screen = 0..world_start;
+        self.world = world_start..instances.len() as u32;
+
+        let instances = instances
+            .into_iter()
+            .map(|(_, instance)| instance)
+            .collect::<Vec<_>>();
+        if instances.len() > self.capacity {
+            self.capacity = instances.len().next_power_of_two();
+            self.buffer = create_instance_buffer(device, self.capacity);
+        }
+        if !instances.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&instances));
+        }
+    }
+
+    /// One instance per visible glyph, screen text first, each tagged with
+    /// whether it is world text. `None` when the atlas ran out of space.
+    fn build_instances(
+        &mut self,
+        queue: &Queue,
+        queued: &[QueuedText],
+    ) -> Option<Vec<(bool, GlyphInstance)>> {
+        let mut instances = Vec::new();
+        for world in [false, true] {
+            for text in queued
+                .iter()
+                .filter(|t| matches!(t.anchor, Anchor::World(_)) == world)
+            {
+                let anchor = match text.anchor {
+                    Anchor::Screen([x, y]) => [x, y, 0.0],
+                    Anchor::World(position) => position.into(),
+                };
+                let font = &self.fonts[text.style.font.0];
+                let layout = layout::layout(font, &text.text, &text.style);
+
+                for glyph in layout.glyphs {
+                    let entry = self
+                        .atlas
+                        .glyph(
+                            queue,
+                            text.style.font,
+                            font,
+                            glyph.id,
+                            text.style.pixel_size(),
+                        )
+                        .ok()?;
+                    if let Some(entry) = entry {
+                        instances.push((
+                            world,
+                            GlyphInstance {
+                                anchor,
+                                offset: [glyph.x + entry.offset[0], glyph.y + entry.offset[1]],
+                                size: entry.size,
+                                uv_min: entry.uv_min,
+                                uv_max: entry.uv_max,
+                                color: text.style.color,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+        Some(instances)
+    }
+
+    /// Draws world text uploaded by the last [`prepare`](Self::prepare). The
+    /// pass viewport must be the one world text is seen through.
+    pub(crate) fn draw_world<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        key: (TextureFormat, CompareFunction),
+        camera_bind_group: &'a BindGroup,
+    ) {
+        if let Some(pipelines) = self.pipelines.get(&key) {
+            self.draw(
+                pass,
+                &pipelines.world,
+                &self.world_viewport,
+                &self.world,
+                camera_bind_group,
+            );
+        }
+    }
+
+    /// Draws screen text uploaded by the last [`prepare`](Self::prepare)
+    /// into a pass whose viewport covers the whole surface.
+    pub(crate) fn draw_screen<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        key: (TextureFormat, CompareFunction),
+        camera_bind_group: &'a BindGroup,
+    ) {
+        if let Some(pipelines) = self.pipelines.get(&key) {
+            self.draw(
+                pass,
+                &pipelines.screen,
+                &self.screen_viewport,
+                &self.screen,
+                camera_bind_group,
+            );
+        }
+    }
+
+    fn draw<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        pipeline: &'a RenderPipeline,
+        viewport: &'a UniformBuffer<TextViewport>,
+        range: &std::ops::Range<u32>,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        pass.set_bind_group(1, viewport.bind_group(), &[]);
+        pass.set_bind_group(2, &self.atlas_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.buffer.slice(..));
+        pass.draw(0..4, range.clone());
+    }
+}
+
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Glyph Instance Buffer"),
+        size: (capacity * std::mem::size_of::<GlyphInstance>()) as u64,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// `depth_compare` is `None` for text drawn on top of everything.
+fn create_text_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    entry_point: &str,
+    color_format: TextureFormat,
+    depth_compare: Option<CompareFunction>,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Text Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/text.wgsl").into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Text Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point,
+            buffers: &[GlyphInstance::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: depth_compare.unwrap_or(CompareFunction::Always),
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_shader_matches_instance_layout() {
+        let reflection =
+            ShaderReflection::from_wgsl(include_str!("../../shaders/text.wgsl")).unwrap();
+        for entry_point in ["vs_screen", "vs_world"] {
+            reflection
+                .validate_vertex_layouts(entry_point, &[GlyphInstance::desc()])
+                .unwrap();
+        }
+
+        let entries = reflection.bind_group_layout_entries().unwrap();
+        match entries[1][0].ty {
+            wgpu::BindingType::Buffer {
+                min_binding_size, ..
+            } => assert!(min_binding_size.unwrap().get() as usize <= TextViewport::STD140.size),
+            _ => unreachable!(),
+        }
+    }
+}
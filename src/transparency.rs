@@ -0,0 +1,393 @@
+//! The pass that draws materials with [`AlphaMode::Blend`](crate::model::AlphaMode::Blend)
+//! after everything opaque.
+
+use std::collections::HashMap;
+
+use anyhow::*;
+use cgmath::{EuclideanSpace, MetricSpace, Point3};
+use wgpu::{BindGroup, CompareFunction, Device, PipelineLayout, RenderPipeline, TextureFormat};
+
+use crate::{
+    instance::{Instance, InstanceRaw},
+    light::SCENE_SHADER,
+    model::ModelVertex,
+    reflection::{ReflectedLayout, ShaderReflection},
+    texture::Texture,
+    vertex::Vertex,
+};
+
+/// How blended materials are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransparencyMode {
+    /// Instances are drawn back to front from each view's camera. Exact
+    /// for instances that do not overlap themselves.
+    #[default]
+    Sorted,
+    /// Weighted blended order-independent transparency. Nothing is sorted,
+    /// which suits many overlapping surfaces, but the result only
+    /// approximates the right order, favouring closer and more opaque
+    /// surfaces.
+    WeightedBlended,
+}
+
+impl TransparencyMode {
+    pub const ACCUM_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+    pub const REVEALAGE_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+}
+
+/// Sums the weighted colors and weights of the transparent fragments.
+const ACCUMULATE: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::One,
+    dst_factor: wgpu::BlendFactor::One,
+    operation: wgpu::BlendOperation::Add,
+};
+/// Multiplies in the light each transparent fragment lets through.
+const REVEAL: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::Zero,
+    dst_factor: wgpu::BlendFactor::OneMinusSrc,
+    operation: wgpu::BlendOperation::Add,
+};
+/// Keeps the revealed fraction of what is already there.
+const COMPOSITE: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+    dst_factor: wgpu::BlendFactor::SrcAlpha,
+    operation: wgpu::BlendOperation::Add,
+};
+
+/// The targets weighted blended transparency accumulates into for views
+/// of one size, and the bind group that resolves them.
+pub(crate) struct OitTargets {
+    pub(crate) accum: Texture,
+    pub(crate) revealage: Texture,
+    pub(crate) bind_group: BindGroup,
+}
+
+/// Targets for weighted blended transparency, one set per view size.
+pub(crate) struct Oit {
+    pub(crate) composite_layout: ReflectedLayout,
+    targets: HashMap<(u32, u32), OitTargets>,
+}
+
+impl Oit {
+    pub(crate) fn new(device: &Device) -> Result<Self> {
+        let composite_layout =
+            ShaderReflection::from_wgsl(include_str!("../shaders/oit_composite.wgsl"))?
+                .create_layout(device, "OIT Composite Pipeline Layout")?;
+        Ok(Self {
+            composite_layout,
+            targets: HashMap::new(),
+        })
+    }
+
+    /// Makes sure targets exist for each of `sizes` and drops any others.
+    pub(crate) fn prepare(&mut self, device: &Device, sizes: impl IntoIterator<Item = (u32, u32)>) {
+        let sizes = sizes.into_iter().collect::<Vec<_>>();
+        self.targets.retain(|size, _| sizes.contains(size));
+        for size in sizes {
+            let layout = &self.composite_layout.bind_group_layouts[0];
+            self.targets
+                .entry(size)
+                .or_insert_with(|| create_targets(device, layout, size));
+        }
+    }
+
+    pub(crate) fn targets(&self, size: (u32, u32)) -> Option<&OitTargets> {
+        self.targets.get(&size)
+    }
+}
+
+fn create_targets(
+    device: &Device,
+    layout: &wgpu::BindGroupLayout,
+    (width, height): (u32, u32),
+) -> OitTargets {
+    let accum = Texture::create_render_target(
+        device,
+        width,
+        height,
+        TransparencyMode::ACCUM_FORMAT,
+        "oit_accum",
+    );
+    let revealage = Texture::create_render_target(
+        device,
+        width,
+        height,
+        TransparencyMode::REVEALAGE_FORMAT,
+        "oit_revealage",
+    );
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("oit_composite_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&accum.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&revealage.view),
+            },
+        ],
+    });
+    OitTargets {
+        accum,
+        revealage,
+        bind_group,
+    }
+}
+
+/// `instances` ordered from the farthest to the closest to `eye`.
+pub(crate) fn sort_back_to_front(instances: &[Instance], eye: Point3<f32>) -> Vec<InstanceRaw> {
+    let distance = |instance: &Instance| Point3::from_vec(instance.position).distance2(eye);
+    let mut sorted = instances.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+    sorted.into_iter().map(Instance::to_raw).collect()
+}
+
+/// The pipelines drawing blended materials with `shader.wgsl`: sorted with
+/// alpha blending, into the weighted blended targets, and resolving those
+/// targets into a view.
+pub(crate) fn create_transparent_pipelines(
+    device: &Device,
+    layout: &PipelineLayout,
+    composite_layout: &PipelineLayout,
+    color_format: TextureFormat,
+    depth_compare: CompareFunction,
+) -> (RenderPipeline, RenderPipeline, RenderPipeline) {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Transparent Shader"),
+        source: wgpu::ShaderSource::Wgsl(SCENE_SHADER.into()),
+    });
+    // Transparent surfaces are hidden by opaque ones but not by each other.
+    let depth_stencil = Some(wgpu::DepthStencilState {
+        format: Texture::DEPTH_FORMAT,
+        depth_write_enabled: false,
+        depth_compare,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    });
+    let primitive = wgpu::PrimitiveState {
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        front_face: wgpu::FrontFace::Ccw,
+        cull_mode: Some(wgpu::Face::Back),
+        ..Default::default()
+    };
+    let vertex = wgpu::VertexState {
+        module: &shader,
+        entry_point: "vs_main",
+        buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+    };
+
+    let sorted = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Transparent Pipeline"),
+        layout: Some(layout),
+        vertex: vertex.clone(),
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive,
+        depth_stencil: depth_stencil.clone(),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    let accumulate = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("OIT Accumulate Pipeline"),
+        layout: Some(layout),
+        vertex,
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_oit",
+            targets: &[
+                Some(wgpu::ColorTargetState {
+                    format: TransparencyMode::ACCUM_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: ACCUMULATE,
+                        alpha: ACCUMULATE,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    format: TransparencyMode::REVEALAGE_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: REVEAL,
+                        alpha: REVEAL,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+            ],
+        }),
+        primitive,
+        depth_stencil,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("OIT Composite Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/oit_composite.wgsl").into()),
+    });
+    let composite = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("OIT Composite Pipeline"),
+        layout: Some(composite_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState {
+                    color: COMPOSITE,
+                    alpha: COMPOSITE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+
+    (sorted, accumulate, composite)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{One, Quaternion, Vector3};
+
+    use super::*;
+
+    #[test]
+    fn instances_sort_from_far_to_near() {
+        let instances = [2.0, -5.0, 1.0, 8.0].map(|x| Instance {
+            position: Vector3::new(x, 0.0, 0.0),
+            rotation: Quaternion::one(),
+        });
+        let sorted = sort_back_to_front(&instances, Point3::new(0.0, 0.0, 0.0));
+        let expected = [8.0, -5.0, 2.0, 1.0].map(|x| {
+            Instance {
+                position: Vector3::new(x, 0.0, 0.0),
+                rotation: Quaternion::one(),
+            }
+            .to_raw()
+        });
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&sorted),
+            bytemuck::cast_slice::<_, u8>(&expected)
+        );
+    }
+
+    /// What `component` makes of `src` over `dst`.
+    fn blend(component: wgpu::BlendComponent, src: f32, src_alpha: f32, dst: f32) -> f32 {
+        let factor = |factor| match factor {
+            wgpu::BlendFactor::Zero => 0.0,
+            wgpu::BlendFactor::One => 1.0,
+            wgpu::BlendFactor::OneMinusSrc => 1.0 - src,
+            wgpu::BlendFactor::SrcAlpha => src_alpha,
+            wgpu::BlendFactor::OneMinusSrcAlpha => 1.0 - src_alpha,
+            wgpu::BlendFactor::Src
+            | wgpu::BlendFactor::Dst
+            | wgpu::BlendFactor::OneMinusDst
+            | wgpu::BlendFactor::DstAlpha
+            | wgpu::BlendFactor::OneMinusDstAlpha
+            | wgpu::BlendFactor::SrcAlphaSaturated
+            | wgpu::BlendFactor::Constant
+            | wgpu::BlendFactor::OneMinusConstant => {
+                unreachable!("blend factor {factor:?} not used by OIT pipelines")
+            }
+        };
+        assert_eq!(component.operation, wgpu::BlendOperation::Add);
+        src * factor(component.src_factor) + dst * factor(component.dst_factor)
+    }
+
+    /// Resolves `(color, alpha, distance)` fragments over `background`
+    /// with the blend states above, `fs_oit` in `shader.wgsl` and
+    /// `oit_composite.wgsl`.
+    fn resolve(fragments: &[([f32; 3], f32, f32)], background: [f32; 3]) -> [f32; 3] {
+        let mut accum = [0.0; 4];
+        let mut revealage = 1.0;
+        for &(color, alpha, distance) in fragments {
+            let weight = alpha * (0.03 / (1e-5 + (distance / 200.0).powi(4))).clamp(1e-2, 3e3);
+            let src = [color[0] * alpha, color[1] * alpha, color[2] * alpha, alpha]
+                .map(|channel| channel * weight);
+            for (accum, channel) in accum.iter_mut().zip(src) {
+                *accum = blend(ACCUMULATE, channel, src[3], *accum);
+            }
+            revealage = blend(REVEAL, alpha, alpha, revealage);
+        }
+        if revealage >= 1.0 {
+            return background;
+        }
+        std::array::from_fn(|i| {
+            let average = accum[i] / accum[3].max(1e-5);
+            blend(COMPOSITE, average, revealage, background[i])
+        })
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for (a, b) in a.into_iter().zip(b) {
+            assert!((a - b).abs() < 1e-4, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn weighted_blending_resolves_like_alpha_blending() {
+        let background = [0.2, 0.4, 0.6];
+        assert_close(resolve(&[], background), background);
+
+        // A single surface is exact.
+        let red = [1.0, 0.0, 0.0];
+        assert_close(
+            resolve(&[(red, 0.25, 10.0)], background),
+            [0.25 + 0.2 * 0.75, 0.4 * 0.75, 0.6 * 0.75],
+        );
+
+        // Draw order does not matter, and the closer surface weighs more.
+        let blue = [0.0, 0.0, 1.0];
+        let near_red = (red, 0.5, 5.0);
+        let far_blue = (blue, 0.5, 50.0);
+        let result = resolve(&[near_red, far_blue], [0.0; 3]);
+        assert_close(result, resolve(&[far_blue, near_red], [0.0; 3]));
+        assert!(result[0] > result[2]);
+        // Together they cover three quarters of the background.
+        assert!((result[0] + result[2] - 0.75).abs() < 1e-4);
+    }
+
+    #[test]
+    fn transparency_shaders_validate() {
+        let reflection = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER).unwrap();
+        reflection
+            .validate_vertex_layouts("vs_main", &[ModelVertex::desc(), InstanceRaw::desc()])
+            .unwrap();
+        let entries = reflection.bind_group_layout_entries().unwrap();
+        assert!(matches!(
+            entries[0][4].ty,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                ..
+            }
+        ));
+
+        ShaderReflection::from_wgsl(include_str!("../shaders/oit_composite.wgsl"))
+            .unwrap()
+            .bind_group_layout_entries()
+            .unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+use cgmath::{Matrix3, Matrix4, Point3, Vector2, Vector3, Vector4};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, Buffer, BufferUsages, Device,
+    Queue,
+};
+
+pub use skygen_derive::ShaderType;
+
+/// The memory layout rules WGSL applies to host-shareable data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryLayout {
+    /// Rules for `var<uniform>`: structs additionally align to 16 bytes.
+    Std140,
+    /// Rules for `var<storage>`: natural alignment of every member.
+    Std430,
+}
+
+impl MemoryLayout {
+    pub const fn of<T: ShaderType + ?Sized>(self) -> TypeLayout {
+        match self {
+            Self::Std140 => T::STD140,
+            Self::Std430 => T::STD430,
+        }
+    }
+}
+
+/// Alignment and size of a type, in bytes, under one [`MemoryLayout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeLayout {
+    pub align: usize,
+    pub size: usize,
+}
+
+impl TypeLayout {
+    pub const fn new(align: usize, size: usize) -> Self {
+        Self { align, size }
+    }
+
+    /// Lays out a struct with the given members in declaration order.
+    pub const fn structure(fields: &[TypeLayout], layout: MemoryLayout) -> Self {
+        let mut align = 1;
+        let mut offset = 0;
+
+        let mut i = 0;
+        while i < fields.len() {
+            let field = fields[i];
+            if field.align > align {
+                align = field.align;
+            }
+            offset = align_to(offset, field.align) + field.size;
+            i += 1;
+        }
+
+        if let MemoryLayout::Std140 = layout {
+            align = align_to(align, 16);
+        }
+
+        Self {
+            align,
+            size: align_to(offset, align),
+        }
+    }
+
+    /// Lays out `count` columns of `column`, as used for matrices. WGSL
+    /// matrices align like their column vectors under both rule sets.
+    const fn columns(column: TypeLayout, count: usize) -> Self {
+        Self {
+            align: column.align,
+            size: align_to(column.size, column.align) * count,
+        }
+    }
+}
+
+pub const fn align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// A type that can be written into a uniform or storage buffer following the
+/// WGSL memory layout rules. Use `#[derive(ShaderType)]` for structs instead
+/// of padding them by hand.
+pub trait ShaderType {
+    const STD140: TypeLayout;
+    const STD430: TypeLayout;
+
+    /// Writes `self` into `out`, which is exactly `layout.of::<Self>().size`
+    /// bytes long and may contain stale padding bytes.
+    fn write(&self, layout: MemoryLayout, out: &mut [u8]);
+}
+
+/// Serializes `value` into a freshly allocated, correctly padded byte buffer.
+pub fn to_bytes<T: ShaderType>(value: &T, layout: MemoryLayout) -> Vec<u8> {
+    let mut bytes = vec![0; layout.of::<T>().size];
+    value.write(layout, &mut bytes);
+    bytes
+}
+
+macro_rules! impl_scalar {
+    ($($ty:ty),*) => {$(
+        impl ShaderType for $ty {
+            const STD140: TypeLayout = TypeLayout::new(4, 4);
+            const STD430: TypeLayout = TypeLayout::new(4, 4);
+
+            fn write(&self, _: MemoryLayout, out: &mut [u8]) {
+                out.copy_from_slice(&self.to_ne_bytes());
+            }
+        }
+    )*};
+}
+
+impl_scalar!(f32, u32, i32);
+
+macro_rules! impl_vector {
+    ($($ty:ty; $n:literal => $align:literal),*) => {$(
+        impl ShaderType for [$ty; $n] {
+            const STD140: TypeLayout = TypeLayout::new($align, 4 * $n);
+            const STD430: TypeLayout = TypeLayout::new($align, 4 * $n);
+
+            fn write(&self, _: MemoryLayout, out: &mut [u8]) {
+                out.copy_from_slice(bytemuck::cast_slice(self));
+            }
+        }
+    )*};
+}
+
+impl_vector!(
+    f32; 2 => 8, f32; 3 => 16, f32; 4 => 16,
+    u32; 2 => 8, u32; 3 => 16, u32; 4 => 16,
+    i32; 2 => 8, i32; 3 => 16, i32; 4 => 16
+);
+
+macro_rules! impl_matrix {
+    ($($columns:literal x $rows:literal),*) => {$(
+        impl ShaderType for [[f32; $rows]; $columns] {
+            const STD140: TypeLayout =
+                TypeLayout::columns(<[f32; $rows]>::STD140, $columns);
+            const STD430: TypeLayout =
+                TypeLayout::columns(<[f32; $rows]>::STD430, $columns);
+
+            fn write(&self, layout: MemoryLayout, out: &mut [u8]) {
+                let stride = layout.of::<Self>().size / $columns;
+                for (column, out) in self.iter().zip(out.chunks_exact_mut(stride)) {
+                    out[..4 * $rows].copy_from_slice(bytemuck::cast_slice(column));
+                }
+            }
+        }
+    )*};
+}
+
+impl_matrix!(2 x 2, 2 x 3, 2 x 4, 3 x 2, 3 x 3, 3 x 4, 4 x 2, 4 x 3, 4 x 4);
+
+macro_rules! impl_cgmath {
+    ($($ty:ident => $raw:ty),*) => {$(
+        impl ShaderType for $ty<f32> {
+            const STD140: TypeLayout = <$raw>::STD140;
+            const STD430: TypeLayout = <$raw>::STD430;
+
+            fn write(&self, layout: MemoryLayout, out: &mut [u8]) {
+                let raw: $raw = (*self).into();
+                raw.write(layout, out);
+            }
+        }
+    )*};
+}
+
+impl_cgmath!(
+    Vector2 => [f32; 2],
+    Vector3 => [f32; 3],
+    Vector4 => [f32; 4],
+    Point3 => [f32; 3],
+    Matrix3 => [[f32; 3]; 3],
+    Matrix4 => [[f32; 4]; 4]
+);
+
+/// A fixed-size WGSL array of `T`, as `[f32; 3]` and the like already stand
+/// for vectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Array<T, const N: usize>(pub [T; N]);
+
+impl<T: ShaderType, const N: usize> Array<T, N> {
+    /// Uniform arrays round their elements up to 16 bytes.
+    const fn stride(layout: MemoryLayout) -> usize {
+        let element = layout.of::<T>();
+        match layout {
+            MemoryLayout::Std140 => align_to(element.size, align_to(element.align, 16)),
+            MemoryLayout::Std430 => align_to(element.size, element.align),
+        }
+    }
+}
+
+impl<T: ShaderType, const N: usize> ShaderType for Array<T, N> {
+    const STD140: TypeLayout = TypeLayout::new(
+        align_to(T::STD140.align, 16),
+        Self::stride(MemoryLayout::Std140) * N,
+    );
+    const STD430: TypeLayout =
+        TypeLayout::new(T::STD430.align, Self::stride(MemoryLayout::Std430) * N);
+
+    fn write(&self, layout: MemoryLayout, out: &mut [u8]) {
+        let size = layout.of::<T>().size;
+        for (element, out) in self
+            .0
+            .iter()
+            .zip(out.chunks_exact_mut(Self::stride(layout)))
+        {
+            element.write(layout, &mut out[..size]);
+        }
+    }
+}
+
+/// A uniform buffer holding a single `T` together with the bind group that
+/// exposes it at binding 0.
+///
+/// The value is modified on the CPU through [`get_mut`](Self::get_mut) or
+/// [`set`](Self::set) and only uploaded by [`update`](Self::update) when its
+/// std140 bytes differ from what the GPU already has.
+pub struct UniformBuffer<T: ShaderType> {
+    value: T,
+    uploaded: Vec<u8>,
+    staging: Vec<u8>,
+    buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+impl<T: ShaderType> UniformBuffer<T> {
+    pub fn new(device: &Device, layout: &BindGroupLayout, value: T, label: &str) -> Self {
+        let uploaded = to_bytes(&value, MemoryLayout::Std140);
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(label),
+            contents: &uploaded,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some(label),
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            value,
+            staging: uploaded.clone(),
+            uploaded,
+            buffer,
+            bind_group,
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    pub fn set(&mut self, value: T) {
+        self.value = value;
+    }
+
+    /// Uploads the value if it changed since the last upload. Returns whether
+    /// a write was issued.
+    pub fn update(&mut self, queue: &Queue) -> bool {
+        self.value.write(MemoryLayout::Std140, &mut self.staging);
+        if self.staging == self.uploaded {
+            return false;
+        }
+
+        queue.write_buffer(&self.buffer, 0, &self.staging);
+        std::mem::swap(&mut self.staging, &mut self.uploaded);
+        true
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::uniform::CameraUniform, light::LightUniform, reflection::ShaderReflection,
+    };
+
+    #[derive(ShaderType)]
+    struct Packed {
+        direction: [f32; 3],
+        intensity: f32,
+        offset: [f32; 2],
+    }
+
+    #[test]
+    fn scalars_pack_after_vec3() {
+        assert_eq!(Packed::STD140, TypeLayout::new(16, 32));
+        assert_eq!(Packed::STD430, TypeLayout::new(16, 32));
+
+        let bytes = to_bytes(
+            &Packed {
+                direction: [1.0, 2.0, 3.0],
+                intensity: 4.0,
+                offset: [5.0, 6.0],
+            },
+            MemoryLayout::Std140,
+        );
+        let floats: &[f32] = bytemuck::cast_slice(&bytes);
+        assert_eq!(&floats[..6], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn uniform_arrays_pad_their_elements() {
+        assert_eq!(Array::<f32, 3>::STD140, TypeLayout::new(16, 48));
+        assert_eq!(Array::<f32, 3>::STD430, TypeLayout::new(4, 12));
+
+        let bytes = to_bytes(&Array([1.0f32, 2.0]), MemoryLayout::Std140);
+        let floats: &[f32] = bytemuck::cast_slice(&bytes);
+        assert_eq!((floats[0], floats[4]), (1.0, 2.0));
+    }
+
+    #[test]
+    fn uniforms_match_shader_struct_sizes() {
+        let reflection = ShaderReflection::from_wgsl(crate::light::SCENE_SHADER).unwrap();
+        let entries = reflection.bind_group_layout_entries().unwrap();
+
+        let size = |group: usize| match entries[group][0].ty {
+            wgpu::BindingType::Buffer {
+                min_binding_size, ..
+            } => min_binding_size.unwrap().get() as usize,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(CameraUniform::STD140.size, size(1));
+        assert_eq!(LightUniform::STD140.size, size(2));
+    }
+}